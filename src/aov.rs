@@ -0,0 +1,162 @@
+use crate::camera::Camera;
+use crate::image::Color;
+use crate::integrators::debug::id_color;
+use crate::material::Material;
+use crate::raytracing::HitRecord;
+
+/// Arbitrary output variables: quantities describing the first surface seen through each pixel, which are rendered
+/// alongside the radiance. They are averaged over the samples taken in each pixel, so ids are stored as false colours,
+/// which blend smoothly along the edges of objects.
+#[derive (Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum AovType {
+    /// The base colour of the material.
+    Albedo,
+    /// The shading normal, pointing outwards.
+    Normal,
+    /// The distance to the surface along the viewing direction of the camera.
+    Depth,
+    /// The position of the surface in world space.
+    Position,
+    /// A false colour identifying the primitive.
+    ObjectId,
+    /// A false colour identifying the material.
+    MaterialId
+}
+
+/// The value of every AOV for a single sample, paired with the name of the buffer it is added to.
+pub type AovSample = [(&'static str, Color); 6];
+
+impl AovType {
+    pub const ALL: [AovType; 6] = [AovType::Albedo, AovType::Normal, AovType::Depth, AovType::Position, AovType::ObjectId, AovType::MaterialId];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            AovType::Albedo => "Albedo",
+            AovType::Normal => "Normal",
+            AovType::Depth => "Depth",
+            AovType::Position => "Position",
+            AovType::ObjectId => "Object ID",
+            AovType::MaterialId => "Material ID"
+        }
+    }
+
+    /// Maps the values of an AOV into colours which can be shown on screen. Normals are mapped from [-1, 1] to [0, 1],
+    /// while depths and positions are scaled so that they span [0, 1] across the image. The other AOVs are already
+    /// colours.
+    pub fn to_display(&self, values: &[Color]) -> Vec<Color> {
+        match self {
+            AovType::Normal => values.iter().map(|normal| (normal + Color::new(1.0, 1.0, 1.0)) * 0.5).collect(),
+            AovType::Depth | AovType::Position => {
+                let min = values.iter().fold(Color::repeat(f64::INFINITY), |min, value| min.inf(value));
+                let max = values.iter().fold(Color::repeat(f64::NEG_INFINITY), |max, value| max.sup(value));
+                let (min, range) = match self {
+                    AovType::Depth => (Color::zeros(), Color::repeat(max[0].max(f64::MIN_POSITIVE))),
+                    _ => (min, (max - min).map(|range| range.max(f64::MIN_POSITIVE)))
+                };
+                values.iter().map(|value| (value - min).component_div(&range)).collect()
+            }
+            _ => values.to_vec()
+        }
+    }
+}
+
+/// Records the first surface hit along a camera ray, as the integrator traces it, so that the AOVs can be found
+/// without tracing the ray again. Surfaces which only mark the boundary of a medium are passed over, but nothing is
+/// recorded once the path has left the camera ray in any other way.
+#[derive (Copy, Clone, Default)]
+pub struct FirstHit {
+    hit: Option<(HitRecord, Material)>,
+    closed: bool
+}
+
+impl FirstHit {
+    /// Records a hit found by the path, unless a surface has already been recorded or the path has been closed.
+    pub fn record(&mut self, rec: &HitRecord, mat: &Material) {
+        if !self.closed && !mat.is_interface() {
+            self.hit = Some((*rec, *mat));
+            self.closed = true;
+        }
+    }
+
+    /// Stops recording hits, as the path has been scattered before reaching a surface.
+    pub fn close(&mut self) {
+        self.closed = true;
+    }
+
+    /// Returns the AOVs of the recorded surface, or `None` if the camera ray did not reach one.
+    pub fn aovs(&self, cam: &Camera) -> Option<AovSample> {
+        self.hit.map(|(rec, mat)| evaluate(&rec, &mat, cam))
+    }
+}
+
+/// Returns the AOVs of a surface seen by the camera.
+pub fn evaluate(rec: &HitRecord, mat: &Material, cam: &Camera) -> AovSample {
+    let normal = if rec.front_face { rec.normal } else { -rec.normal };
+    let depth = (rec.p - cam.origin).dot(&-cam.orientation.w.into_inner());
+    [(AovType::Albedo.name(), mat.albedo()), (AovType::Normal.name(), normal), (AovType::Depth.name(), Color::repeat(depth)),
+     (AovType::Position.name(), rec.p.coords), (AovType::ObjectId.name(), id_color(rec.primitive_id)),
+     (AovType::MaterialId.name(), id_color(mat.id() as usize))]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::camera::CameraSettings;
+    use crate::nalgebra::{Point3, Vector3};
+    use crate::primitives::{Primitive, Primitives};
+    use crate::raytracing::{Hit, Ray};
+
+    /// Records the hits found by tracing the ray straight through the world, as the integrators do on the first bounce.
+    fn first_hit(r: &Ray, world: &Primitives) -> FirstHit {
+        let mut first_hit = FirstHit::default();
+        let mut ray = *r;
+        while let Some((rec, mat)) = world.hit(&ray, 0.001, f64::INFINITY) {
+            first_hit.record(&rec, mat);
+            ray = Ray::new_at_time(rec.p, ray.direction(), ray.time());
+        }
+        first_hit
+    }
+
+    #[test]
+    fn test_first_hit(){
+        let cam = Camera::new(CameraSettings { v_fov: 40.0, image_height: 10, image_width: 10, shutter_close: 0.0, ..CameraSettings::default() });
+        let albedo = Color::new(0.2, 0.4, 0.6);
+        let mut world = Primitives::new();
+        world.add(Primitive::new_sphere(Point3::<f64>::new(0.0, 0.0, 0.0), 2.0, Material::new_interface()));
+        world.add(Primitive::new_sphere(Point3::<f64>::new(0.0, 0.0, 0.0), 1.0, Material::new_lambertian(albedo)));
+        world.add(Primitive::new_sphere(Point3::<f64>::new(5.0, 0.0, 0.0), 1.0, Material::new_lambertian(albedo)));
+
+        //Case 1: The AOVs describe the first surface behind the boundary of the medium
+        let aovs = first_hit(&Ray::new(cam.origin, Vector3::<f64>::new(0.0, 0.0, -1.0)), &world).aovs(&cam).unwrap();
+        assert_eq!(aovs.map(|(name, _)| name), AovType::ALL.map(|aov_type| aov_type.name()));
+        assert_eq!(aovs[0].1, albedo);
+        assert!((aovs[1].1 - Vector3::<f64>::new(0.0, 0.0, 1.0)).norm() < 1e-9);
+        assert!((aovs[2].1 - Color::repeat(9.0)).norm() < 1e-9);
+        assert!((aovs[3].1 - Color::new(0.0, 0.0, 1.0)).norm() < 1e-9);
+        assert_eq!(aovs[4].1, id_color(1));
+
+        //Case 2: Objects with the same material share a material id, but not an object id
+        let other = first_hit(&Ray::new(Point3::<f64>::new(5.0, 0.0, 10.0), Vector3::<f64>::new(0.0, 0.0, -1.0)), &world).aovs(&cam).unwrap();
+        assert_ne!(other[4], aovs[4]);
+        assert_eq!(other[5], aovs[5]);
+
+        //Case 3: Rays which miss record nothing
+        assert!(first_hit(&Ray::new(cam.origin, Vector3::<f64>::new(0.0, 1.0, 0.0)), &world).aovs(&cam).is_none());
+
+        //Case 4: Nothing is recorded after the path is closed
+        let mut closed = FirstHit::default();
+        closed.close();
+        let (rec, mat) = world.hit(&Ray::new(Point3::<f64>::new(5.0, 0.0, 10.0), Vector3::<f64>::new(0.0, 0.0, -1.0)), 0.001, f64::INFINITY).unwrap();
+        closed.record(&rec, mat);
+        assert!(closed.aovs(&cam).is_none());
+    }
+
+    #[test]
+    fn test_to_display(){
+        let depths = [Color::repeat(2.0), Color::repeat(4.0)];
+        assert_eq!(AovType::Depth.to_display(&depths), vec![Color::repeat(0.5), Color::repeat(1.0)]);
+        let positions = [Color::new(-1.0, 0.0, 2.0), Color::new(1.0, 0.0, 4.0)];
+        assert_eq!(AovType::Position.to_display(&positions), vec![Color::new(0.0, 0.0, 0.0), Color::new(1.0, 0.0, 1.0)]);
+        assert_eq!(AovType::Normal.to_display(&[Color::new(0.0, -1.0, 1.0)]), vec![Color::new(0.5, 0.0, 1.0)]);
+    }
+}
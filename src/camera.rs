@@ -3,6 +3,7 @@ use core::f64;
 use nalgebra::{Translation3, Unit, Transform3};
 
 use crate::sampler;
+use crate::sampler::Sampler;
 use crate::util::deg_to_rad;
 use crate::nalgebra::{Vector3, Point3, Rotation3};
 use crate::raytracing::Ray;
@@ -17,6 +18,8 @@ pub struct Camera {
     pub orientation: Orientation,
     pub lens_radius: f64,
     pub resoloution: (usize, usize),
+    pub shutter_open: f64,
+    pub shutter_close: f64,
     
     // These settings are used for calculation purposes only
     v_up: Unit<Vector3<f64>>,
@@ -27,7 +30,7 @@ pub struct Camera {
 }
 
 
-#[derive (Copy, Clone, Default)]
+#[derive (Copy, Clone)]
 pub struct CameraSettings {
     pub look_from: Point3<f64>,
     pub look_at: Point3<f64>,
@@ -38,6 +41,19 @@ pub struct CameraSettings {
     pub focus_dist: f64,
     pub image_height: usize,
     pub image_width: usize,
+    /// The times at which the shutter opens and closes. Rays are traced at times spread uniformly between them, so that
+    /// moving primitives are blurred along their path.
+    pub shutter_open: f64,
+    pub shutter_close: f64,
+}
+
+impl Default for CameraSettings {
+    /// Returns a pinhole camera ten units up the z-axis, looking at the origin, which renders a square image.
+    fn default() -> CameraSettings {
+        CameraSettings { look_from: Point3::<f64>::new(0.0, 0.0, 10.0), look_at: Point3::<f64>::origin(), v_up: Vector3::<f64>::new(0.0, 1.0, 0.0),
+                         v_fov: 20.0, aspect_ratio: 1.0, aperture: 0.0, focus_dist: 10.0, image_height: 100, image_width: 100,
+                         shutter_open: 0.0, shutter_close: 1.0 }
+    }
 }
 
 
@@ -71,14 +87,84 @@ impl Camera {
         let resoloution = (settings.image_width, settings.image_height);
 
         let lens_radius = settings.aperture/2.0;
-        Camera{origin, horizontal, vertical, lower_left_corner, orientation, lens_radius, resoloution, v_up, focus_dist, viewport_width, viewport_height, v_fov}
+        let (shutter_open, shutter_close) = (settings.shutter_open, settings.shutter_close);
+        Camera{origin, horizontal, vertical, lower_left_corner, orientation, lens_radius, resoloution, shutter_open, shutter_close, v_up, focus_dist, viewport_width, viewport_height, v_fov}
     }
 
-    pub fn get_ray(&self, s: f64, t:f64) -> Ray {
-        let rd = self.lens_radius * sampler::rand_in_unit_disk();
+    /// Returns the distance from the camera to the plane in perfect focus.
+    pub fn focus_dist(&self) -> f64 {
+        self.focus_dist
+    }
+
+    /// Returns a ray through the given position on the film, leaving from a point on the lens and at a time drawn from
+    /// the sampler.
+    pub fn get_ray<S: Sampler>(&self, s: f64, t:f64, sampler: &mut S) -> Ray {
+        let rd = self.lens_radius * sampler::uniform_disk(sampler.get_2d());
         let offset = self.orientation.u().into_inner() * rd[0] + self.orientation.v().into_inner() * rd[1];
 
-        Ray::new(self.origin + offset, Unit::new_normalize(self.lower_left_corner + s*self.horizontal + t*self.vertical - self.origin - offset).into_inner())
+        let direction = Unit::new_normalize(self.lower_left_corner + s*self.horizontal + t*self.vertical - self.origin - offset).into_inner();
+        Ray::new_at_time(self.origin + offset, direction, self.sample_time(sampler.get_1d()))
+    }
+
+    /// Maps a uniform random number in [0, 1) to a time while the shutter is open.
+    pub fn sample_time(&self, u: f64) -> f64 {
+        self.shutter_open + u * (self.shutter_close - self.shutter_open)
+    }
+
+    /// Returns the position on the film, in the same coordinates as the arguments of `get_ray`, of the ray leaving
+    /// the lens at `lens_point` in the given direction. Returns None if the ray does not pass through the film.
+    pub fn film_position(&self, lens_point: &Point3<f64>, direction: &Vector3<f64>) -> Option<(f64, f64)> {
+        let direction = direction.normalize();
+        let cos_theta = -direction.dot(&self.orientation.w);
+        if cos_theta <= 0.0 {
+            return None;
+        }
+        let focus_point = lens_point + direction * (self.focus_dist / cos_theta);
+        let offset = focus_point - self.lower_left_corner;
+        let s = offset.dot(&self.horizontal) / self.horizontal.norm_squared();
+        let t = offset.dot(&self.vertical) / self.vertical.norm_squared();
+        if (0.0..1.0).contains(&s) && (0.0..1.0).contains(&t) {
+            Some((s, t))
+        } else {
+            None
+        }
+    }
+
+    /// Returns the area of the lens, or one if the camera is a pinhole.
+    pub fn lens_area(&self) -> f64 {
+        if self.lens_radius > 0.0 {
+            f64::consts::PI * self.lens_radius * self.lens_radius
+        } else {
+            1.0
+        }
+    }
+
+    /// Returns the probability density, with respect to solid angle, of `get_ray` choosing the given direction from 
+    /// `lens_point` when the film position is chosen uniformly.
+    pub fn pdf_direction(&self, lens_point: &Point3<f64>, direction: &Vector3<f64>) -> f64 {
+        if self.film_position(lens_point, direction).is_none() {
+            return 0.0;
+        }
+        let cos_theta = -direction.normalize().dot(&self.orientation.w);
+        let film_area = self.horizontal.norm() * self.vertical.norm();
+        self.focus_dist * self.focus_dist / (film_area * cos_theta.powi(3))
+    }
+
+    /// Returns the importance emitted by the camera along the ray leaving the lens at `lens_point` in the given 
+    /// direction. This is normalised so that a ray generated by `get_ray` carries unit importance relative to its pdf.
+    pub fn importance(&self, lens_point: &Point3<f64>, direction: &Vector3<f64>) -> f64 {
+        let pdf = self.pdf_direction(lens_point, direction);
+        if pdf == 0.0 {
+            return 0.0;
+        }
+        let cos_theta = -direction.normalize().dot(&self.orientation.w);
+        pdf / (cos_theta * self.lens_area())
+    }
+
+    /// Samples a point uniformly on the lens, using a pair of uniform random numbers in [0, 1).
+    pub fn sample_lens(&self, u: [f64; 2]) -> Point3<f64> {
+        let rd = self.lens_radius * sampler::uniform_disk(u);
+        self.origin + self.orientation.u().into_inner() * rd[0] + self.orientation.v().into_inner() * rd[1]
     }
 
     pub fn translate(&mut self, forward: f64, right: f64, up: f64) {
@@ -125,3 +211,30 @@ impl Orientation{
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sampler::independent::IndependentSampler;
+
+    #[test]
+    fn test_film_position(){
+        let camera_settings = CameraSettings { look_from: Point3::<f64>::new(1.0, 2.0, 3.0), v_fov: 30.0, aspect_ratio: 1.5, aperture: 0.5, focus_dist: 3.0, image_width: 150, shutter_close: 0.0, ..CameraSettings::default() };
+        let cam = Camera::new(camera_settings);
+
+        //Case 1: The film position of a camera ray is the position it was generated from
+        let r = cam.get_ray(0.3, 0.8, &mut IndependentSampler::new(0));
+        let (s, t) = cam.film_position(&r.origin(), &r.direction()).unwrap();
+        assert!((s - 0.3).abs() < 1e-9 && (t - 0.8).abs() < 1e-9);
+
+        //Case 2: Rays which miss the film have no importance
+        assert!(cam.film_position(&cam.origin, &-cam.orientation.w()).is_some());
+        assert!(cam.film_position(&cam.origin, &cam.orientation.w()).is_none());
+        assert_eq!(cam.importance(&cam.origin, &cam.orientation.u()), 0.0);
+
+        //Case 3: The pdf of the direction through the centre of the film is the inverse of the film's solid angle there
+        let film_area = cam.horizontal.norm() * cam.vertical.norm();
+        let pdf = cam.pdf_direction(&cam.origin, &-cam.orientation.w());
+        assert!((pdf - 9.0 / film_area).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,387 @@
+use crate::gltf_import::{self, GltfScene};
+use crate::denoise::denoise;
+use crate::image::{write_pfm, Color, Image};
+use crate::pbrt;
+use crate::raytracing::Hit;
+use crate::scene_file::{self, SceneDescription};
+use crate::scenes;
+use crate::threads::{GlobalSettings, Instructions, ThreadCoordinator};
+use crate::nalgebra::{Point3, Vector3};
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
+
+pub const USAGE: &str = "\
+Usage: Ray_Trace render [options]
+
+Renders a scene without opening a window, and saves it to a PPM file, or to a PFM file if the output path ends in .pfm.
+The AOVs, render layers and light groups are saved next to it as PFM files, named after the output with their own
+names added to the end.
+Options which are left out take their values from the scene file, or from the defaults below for built-in scenes.
+
+Options:
+    --scene <name|path>     A built-in scene, or the path to a scene file, a pbrt-v3 .pbrt file or a glTF .gltf or .glb file
+                            (default: sphere_world)
+    --width <pixels>        The width of the image (default: 800)
+    --height <pixels>       The height of the image (default: keeps the aspect ratio, which is 3:2 by default)
+    --samples <count>       The number of samples per pixel (default: 1000)
+    --max-depth <count>     The maximum number of bounces along a path (default: 50)
+    --threads <count>       The number of raytracing threads (default: one per CPU)
+    --seed <seed>           The seed of the render and of randomly placed scenes (default: 0)
+    --look-from <x,y,z>     The position of the camera (default: set by the scene)
+    --look-at <x,y,z>       The point the camera looks at (default: set by the scene)
+    --fov <degrees>         The vertical field of view (default: 20)
+    --aperture <size>       The aperture of the lens (default: 0)
+    --focus-dist <distance> The distance to the plane in focus (default: 10)
+    --output <path>         The file the image is saved to (default: results.ppm)
+    --denoise <strength>    Also saves the image denoised at the given strength, to the output path with _denoised added
+                            to the end of its name (default: not denoised)
+    --help                  Prints this message";
+
+/// The options of a render, which can be read from the command line. Options which are `None` keep the values given
+/// by the scene.
+#[derive (Clone, Debug, PartialEq)]
+pub struct RenderOptions {
+    /// The name of a built-in scene, or the path to a scene file.
+    pub scene: String,
+    pub image_width: Option<usize>,
+    pub image_height: Option<usize>,
+    pub samples_per_pixel: Option<usize>,
+    pub max_depth: Option<i32>,
+    pub threads: usize,
+    pub seed: Option<u64>,
+    pub look_from: Option<Point3<f64>>,
+    pub look_at: Option<Point3<f64>>,
+    pub v_fov: Option<f64>,
+    pub aperture: Option<f64>,
+    pub focus_dist: Option<f64>,
+    pub output: String,
+    /// The strength the image is denoised at, if a denoised copy is saved too.
+    pub denoise: Option<f64>
+}
+
+impl Default for RenderOptions {
+    fn default() -> RenderOptions {
+        RenderOptions { scene: String::from("sphere_world"), image_width: None, image_height: None, samples_per_pixel: None, max_depth: None,
+                        threads: num_cpus::get(), seed: None, look_from: None, look_at: None, v_fov: None, aperture: None, focus_dist: None,
+                        output: String::from("results.ppm"), denoise: None }
+    }
+}
+
+impl RenderOptions {
+    /// Reads the options from command line arguments, which follow the name of the subcommand. Returns `None` if help
+    /// was asked for.
+    pub fn parse(args: &[String]) -> Result<Option<RenderOptions>, String> {
+        let mut options = RenderOptions::default();
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            if arg == "--help" || arg == "-h" {
+                return Ok(None);
+            }
+            let value = args.next().ok_or_else(|| format!("Missing value for '{}'", arg))?;
+            match arg.as_str() {
+                "--scene" => {
+                    if !scenes::SCENE_NAMES.contains(&value.as_str()) && !Path::new(value).is_file() {
+                        return Err(format!("'{}' is neither a scene file nor one of the built-in scenes: {}", value, scenes::SCENE_NAMES.join(", ")));
+                    }
+                    options.scene = value.clone();
+                }
+                "--width" => options.image_width = Some(parse_positive(arg, value)?),
+                "--height" => options.image_height = Some(parse_positive(arg, value)?),
+                "--samples" => options.samples_per_pixel = Some(parse_positive(arg, value)?),
+                "--max-depth" => options.max_depth = Some(parse_positive(arg, value)? as i32),
+                "--threads" => options.threads = parse_positive(arg, value)?,
+                "--seed" => options.seed = Some(parse_number(arg, value)?),
+                "--look-from" => options.look_from = Some(parse_point(arg, value)?),
+                "--look-at" => options.look_at = Some(parse_point(arg, value)?),
+                "--fov" => options.v_fov = Some(parse_number(arg, value)?),
+                "--aperture" => options.aperture = Some(parse_number(arg, value)?),
+                "--focus-dist" => options.focus_dist = Some(parse_number(arg, value)?),
+                "--output" => options.output = value.clone(),
+                "--denoise" => {
+                    let strength: f64 = parse_number(arg, value)?;
+                    if !(strength >= 0.0 && strength.is_finite()) {
+                        return Err(format!("Invalid value for '{}': expected a strength of at least zero, found '{}'", arg, value));
+                    }
+                    options.denoise = Some(strength);
+                }
+                _ => return Err(format!("Unknown option '{}'", arg))
+            }
+        }
+        Ok(Some(options))
+    }
+
+    /// Overrides the settings of a scene with the options which were given. Setting only one side of the image keeps
+    /// its aspect ratio.
+    pub fn apply(&self, description: &mut SceneDescription) {
+        let aspect_ratio = description.image_width as f64 / description.image_height as f64;
+        match (self.image_width, self.image_height) {
+            (Some(width), Some(height)) => (description.image_width, description.image_height) = (width, height),
+            (Some(width), None) => (description.image_width, description.image_height) = (width, ((width as f64 / aspect_ratio) as usize).max(1)),
+            (None, Some(height)) => (description.image_width, description.image_height) = (((height as f64 * aspect_ratio) as usize).max(1), height),
+            (None, None) => ()
+        }
+        let (settings, camera) = (&mut description.raytrace_settings, &mut description.camera);
+        settings.samples_per_pixel = self.samples_per_pixel.unwrap_or(settings.samples_per_pixel);
+        settings.max_depth = self.max_depth.unwrap_or(settings.max_depth);
+        settings.seed = self.seed.unwrap_or(settings.seed);
+        camera.look_from = self.look_from.unwrap_or(camera.look_from);
+        camera.look_at = self.look_at.unwrap_or(camera.look_at);
+        camera.v_fov = self.v_fov.unwrap_or(camera.v_fov);
+        camera.aperture = self.aperture.unwrap_or(camera.aperture);
+        camera.focus_dist = self.focus_dist.unwrap_or(camera.focus_dist);
+    }
+
+    /// Builds the scene and the settings it is rendered with. Built-in scenes are rendered with the default settings
+    /// of scene files, from the camera position they give.
+    pub fn to_settings(&self) -> Result<GlobalSettings, String> {
+        let seed = self.seed.unwrap_or(SceneDescription::default().raytrace_settings.seed);
+        match scenes::from_name(&self.scene, seed) {
+            Some((geometric_primitives, background, look_from, look_at)) => {
+                let mut description = SceneDescription { background, ..SceneDescription::default() };
+                (description.camera.look_from, description.camera.look_at) = (look_from, look_at);
+                self.apply(&mut description);
+                description.settings_for(geometric_primitives)
+            }
+            None if gltf_import::is_gltf(Path::new(&self.scene)) => {
+                let mut description = SceneDescription { background: Color::new(0.7, 0.8, 1.0), ..SceneDescription::default() };
+                let scene = gltf_import::import_gltf(Path::new(&self.scene), description.image_width, description.image_height)?;
+                for warning in &scene.warnings {
+                    eprintln!("Warning: {}", warning);
+                }
+                place_camera(&mut description, &scene);
+                self.apply(&mut description);
+                description.settings_for(scene.primitives)
+            }
+            None => {
+                let path = Path::new(&self.scene);
+                let mut description = load(path)?;
+                self.apply(&mut description);
+                description.to_settings(path.parent().unwrap_or_else(|| Path::new("")))
+            }
+        }
+    }
+}
+
+/// Places the camera of a glTF scene at its first camera. Scenes without a camera are viewed from in front, far enough
+/// back to see all of them.
+fn place_camera(description: &mut SceneDescription, scene: &GltfScene) {
+    let camera = &mut description.camera;
+    match scene.cameras.first() {
+        Some(settings) => {
+            (camera.look_from, camera.look_at, camera.v_up, camera.v_fov) = (settings.look_from, settings.look_at, settings.v_up, settings.v_fov);
+            description.image_height = settings.image_height;
+        }
+        None => if let Some(bounds) = scene.primitives.bounding_box() {
+            let center = Point3::from((bounds.min().coords + bounds.max().coords) / 2.0);
+            let radius = (bounds.max() - bounds.min()).norm() / 2.0;
+            let distance = 1.1 * radius / (camera.v_fov.to_radians() / 2.0).sin();
+            (camera.look_from, camera.look_at) = (center + Vector3::<f64>::z() * distance, center);
+        }
+    }
+}
+
+/// Reads a scene file, or imports a pbrt file, printing the warnings of the import to stderr.
+fn load(path: &Path) -> Result<SceneDescription, String> {
+    if path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("pbrt")) {
+        let (description, warnings) = pbrt::import(path)?;
+        for warning in warnings {
+            eprintln!("Warning: {}", warning);
+        }
+        return Ok(description);
+    }
+    scene_file::load(path)
+}
+
+fn parse_number<T: std::str::FromStr>(option: &str, value: &str) -> Result<T, String> {
+    value.parse().map_err(|_| format!("Invalid value '{}' for '{}'", value, option))
+}
+
+fn parse_positive(option: &str, value: &str) -> Result<usize, String> {
+    match parse_number(option, value)? {
+        0 => Err(format!("'{}' must be at least 1", option)),
+        number => Ok(number)
+    }
+}
+
+/// Parses a point written as three comma separated coordinates.
+fn parse_point(option: &str, value: &str) -> Result<Point3<f64>, String> {
+    let coordinates = value.split(',').map(|coordinate| parse_number(option, coordinate.trim())).collect::<Result<Vec<f64>, String>>()?;
+    match coordinates[..] {
+        [x, y, z] => Ok(Point3::<f64>::new(x, y, z)),
+        _ => Err(format!("Expected three coordinates for '{}', found '{}'", option, value))
+    }
+}
+
+/// Renders the scene described by the command line arguments, and returns the code the process should exit with.
+pub fn run(args: &[String]) -> i32 {
+    let options = match RenderOptions::parse(args) {
+        Ok(Some(options)) => options,
+        Ok(None) => {
+            println!("{}", USAGE);
+            return 0;
+        }
+        Err(error) => {
+            eprintln!("Error: {}\n\n{}", error, USAGE);
+            return 2;
+        }
+    };
+    match render(&options) {
+        Ok(()) => 0,
+        Err(error) => {
+            eprintln!("Error: {}", error);
+            1
+        }
+    }
+}
+
+/// Renders an image with the given options and saves it, printing the progress of the render to stderr.
+pub fn render(options: &RenderOptions) -> Result<(), String> {
+    //The outputs are opened first, so that a bad path is reported before rendering rather than after
+    let create = |path: &str| File::create(path).map_err(|error| format!("Could not create '{}': {}", path, error));
+    let file = create(&options.output)?;
+    let denoised = match options.denoise {
+        Some(strength) => {
+            let path = denoised_path(&options.output);
+            Some((create(&path)?, path, strength))
+        }
+        None => None
+    };
+    let settings = options.to_settings()?;
+    let samples_per_pixel = settings.raytrace_settings.samples_per_pixel;
+
+    let start = Instant::now();
+    let mut thread_coordinator = ThreadCoordinator::new(settings);
+    thread_coordinator.spin_up(options.threads + 1);
+    let mut reported = None;
+    //A thread which panics never finishes its tiles, so without the check the loop would wait forever
+    while !thread_coordinator.is_done() {
+        if let Err(error) = thread_coordinator.check_threads() {
+            thread_coordinator.transmit_instructions(Instructions::Terminate);
+            return Err(error);
+        }
+        let progress = thread_coordinator.get_progress();
+        if reported != Some(progress) {
+            eprint!("\rRendering: {}/{} samples per pixel", progress, samples_per_pixel);
+            reported = Some(progress);
+        }
+        thread::sleep(Duration::from_millis(250));
+    }
+    thread_coordinator.transmit_instructions(Instructions::Terminate);
+    eprintln!("\rRendered {} samples per pixel in {:.1}s", samples_per_pixel, start.elapsed().as_secs_f64());
+
+    let raytrace = thread_coordinator.output_image().raytrace;
+    save(&raytrace.to_image(), file, &options.output)?;
+    if let Some((file, path, strength)) = denoised {
+        save(&denoise(&raytrace, strength), file, &path)?;
+    }
+    for path in raytrace.save_buffers(&output_stem(&options.output))? {
+        eprintln!("Saved to {}", path);
+    }
+    Ok(())
+}
+
+/// Writes an image to the file opened at the given path, as a PFM file if the path ends in .pfm and a PPM file if not.
+fn save(image: &Image, file: File, path: &str) -> Result<(), String> {
+    let mut writer = BufWriter::new(file);
+    let written = if path.to_lowercase().ends_with(".pfm") {
+        let colors: Vec<_> = image.pixels.iter().map(|pixel| pixel.color).collect();
+        write_pfm(&mut writer, image.image_width, image.image_height, &colors)
+    } else {
+        image.write_ppm(&mut writer)
+    };
+    written.and_then(|_| writer.flush()).map_err(|error| format!("Could not write '{}': {}", path, error))?;
+    eprintln!("Saved to {}", path);
+    Ok(())
+}
+
+/// Returns the output path without its extension, which the AOVs, render layers and light groups are saved alongside.
+pub fn output_stem(output: &str) -> String {
+    Path::new(output).with_extension("").to_string_lossy().into_owned()
+}
+
+/// Returns the path a denoised copy of the output is saved to, which adds `_denoised` to the end of the name of the
+/// file, before its extension.
+pub fn denoised_path(output: &str) -> String {
+    let path = Path::new(output);
+    let stem = path.file_stem().map_or(String::new(), |stem| stem.to_string_lossy().into_owned());
+    let name = match path.extension() {
+        Some(extension) => format!("{}_denoised.{}", stem, extension.to_string_lossy()),
+        None => format!("{}_denoised", stem)
+    };
+    path.with_file_name(name).to_string_lossy().into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| String::from(*arg)).collect()
+    }
+
+    #[test]
+    fn test_parse(){
+        //Case 1: No arguments give the defaults
+        assert_eq!(RenderOptions::parse(&[]), Ok(Some(RenderOptions::default())));
+
+        //Case 2: Options override the defaults
+        let options = RenderOptions::parse(&args(&["--scene", "light_test", "--width", "300", "--samples", "16", "--look-from", "1, 2,3",
+                                                   "--output", "out.pfm"])).unwrap().unwrap();
+        assert_eq!(options.scene, "light_test");
+        assert_eq!((options.image_width, options.image_height), (Some(300), None));
+        assert_eq!(options.samples_per_pixel, Some(16));
+        assert_eq!(options.look_from, Some(Point3::<f64>::new(1.0, 2.0, 3.0)));
+        assert_eq!(options.output, "out.pfm");
+        assert_eq!(options.denoise, None);
+        let options = RenderOptions::parse(&args(&["--denoise", "0.5"])).unwrap().unwrap();
+        assert_eq!(options.denoise, Some(0.5));
+
+        //Case 3: Help
+        assert_eq!(RenderOptions::parse(&args(&["--width", "300", "--help"])), Ok(None));
+
+        //Case 4: Invalid arguments are reported
+        assert!(RenderOptions::parse(&args(&["--scene", "nowhere"])).is_err());
+        assert!(RenderOptions::parse(&args(&["--width", "0"])).is_err());
+        assert!(RenderOptions::parse(&args(&["--samples"])).is_err());
+        assert!(RenderOptions::parse(&args(&["--look-at", "1,2"])).is_err());
+        assert!(RenderOptions::parse(&args(&["--bounces", "4"])).is_err());
+        assert!(RenderOptions::parse(&args(&["--denoise", "-1"])).is_err());
+        assert!(RenderOptions::parse(&args(&["--denoise", "NaN"])).is_err());
+    }
+
+    #[test]
+    fn test_denoised_path(){
+        assert_eq!(denoised_path("results.ppm"), "results_denoised.ppm");
+        assert_eq!(denoised_path("renders/out.v2.pfm"), "renders/out.v2_denoised.pfm");
+        assert_eq!(denoised_path("out"), "out_denoised");
+    }
+
+    #[test]
+    fn test_output_stem(){
+        assert_eq!(output_stem("results.ppm"), "results");
+        assert_eq!(output_stem("renders/out.v2.pfm"), "renders/out.v2");
+        assert_eq!(output_stem("out"), "out");
+    }
+
+    #[test]
+    fn test_apply(){
+        let mut description = SceneDescription::default();
+        (description.image_width, description.image_height) = (400, 200);
+
+        //Case 1: Options which were left out keep the values of the scene
+        let mut options = RenderOptions::default();
+        options.apply(&mut description);
+        assert_eq!(description, SceneDescription { image_width: 400, image_height: 200, ..SceneDescription::default() });
+
+        //Case 2: Setting the width alone keeps the aspect ratio
+        options.image_width = Some(100);
+        options.samples_per_pixel = Some(8);
+        options.apply(&mut description);
+        assert_eq!((description.image_width, description.image_height), (100, 50));
+        assert_eq!(description.raytrace_settings.samples_per_pixel, 8);
+    }
+}
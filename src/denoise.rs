@@ -0,0 +1,133 @@
+use crate::aov::AovType;
+use crate::image::{Color, Image, RaytracedImage};
+
+/// The number of passes of the à-trous filter. Each pass doubles the spacing of the taps, so the filter reaches
+/// 2 * (2^ITERATIONS - 1) pixels in each direction.
+const ITERATIONS: usize = 5;
+
+/// The weights of the taps of the B3 spline kernel, which is applied along each axis.
+const KERNEL: [f64; 5] = [1.0 / 16.0, 1.0 / 4.0, 3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+
+/// The difference in gamma corrected colour at which the weight of a tap falls by a factor of e, on the first pass
+/// at a strength of one. It halves on every later pass, as the taps spread further apart.
+const COLOR_SIGMA: f64 = 0.5;
+
+/// The difference in normal at which the weight of a tap falls by a factor of e.
+const NORMAL_SIGMA: f64 = 0.3;
+
+/// The difference in albedo at which the weight of a tap falls by a factor of e.
+const ALBEDO_SIGMA: f64 = 0.1;
+
+/// The smallest albedo the colour is divided by when the texture is removed before filtering.
+const MIN_ALBEDO: f64 = 0.01;
+
+/// Filters the noise out of a raytraced image with an edge-avoiding à-trous wavelet filter (Dammertz et al. 2010).
+///
+/// The colour is divided by the albedo before filtering, so that textures are not blurred, and multiplied by it
+/// again afterwards. Each pass blurs the image with a B3 spline whose taps are spread ever further apart, and each tap
+/// is weighted down where its colour, normal or albedo differ from those of the pixel being filtered, so that edges
+/// are kept sharp. The normal and albedo come from the AOVs of the image, and are ignored if they were not rendered.
+/// Higher strengths blur across larger differences in colour, and a strength of zero leaves the image unchanged.
+pub fn denoise(image: &RaytracedImage, strength: f64) -> Image {
+    let mut output = image.to_image();
+    if strength <= 0.0 {
+        return output;
+    }
+
+    let (image_width, image_height) = (output.image_width, output.image_height);
+    let albedos = image.aov(AovType::Albedo).map(|albedos| albedos.iter().map(|albedo| albedo.map(|a| a.max(MIN_ALBEDO))).collect::<Vec<_>>());
+    let normals = image.aov(AovType::Normal);
+    let mut colors: Vec<Color> = match &albedos {
+        Some(albedos) => output.pixels.iter().zip(albedos.iter()).map(|(pixel, albedo)| pixel.color.component_div(albedo)).collect(),
+        None => output.pixels.iter().map(|pixel| pixel.color).collect()
+    };
+
+    for iteration in 0..ITERATIONS {
+        let step = 1 << iteration;
+        let sigma_color = strength * COLOR_SIGMA / step as f64;
+        let tonemapped: Vec<Color> = colors.iter().map(|color| color.map(|c| c.max(0.0).sqrt())).collect();
+        let mut filtered = vec![Color::zeros(); colors.len()];
+
+        for y in 0..image_height {
+            for x in 0..image_width {
+                let p = y * image_width + x;
+                let (mut sum, mut weight_sum) = (Color::zeros(), 0.0);
+                for (dy, kernel_y) in KERNEL.iter().enumerate() {
+                    for (dx, kernel_x) in KERNEL.iter().enumerate() {
+                        let qx = x as isize + (dx as isize - 2) * step;
+                        let qy = y as isize + (dy as isize - 2) * step;
+                        if qx < 0 || qy < 0 || qx >= image_width as isize || qy >= image_height as isize {
+                            continue;
+                        }
+                        let q = qy as usize * image_width + qx as usize;
+
+                        let mut exponent = (tonemapped[p] - tonemapped[q]).norm_squared() / (sigma_color * sigma_color);
+                        if let Some(normals) = &normals {
+                            exponent += (normals[p] - normals[q]).norm_squared() / (NORMAL_SIGMA * NORMAL_SIGMA);
+                        }
+                        if let Some(albedos) = &albedos {
+                            exponent += (albedos[p] - albedos[q]).norm_squared() / (ALBEDO_SIGMA * ALBEDO_SIGMA);
+                        }
+                        let weight = kernel_x * kernel_y * (-exponent).exp();
+                        sum += colors[q] * weight;
+                        weight_sum += weight;
+                    }
+                }
+                //The pixel itself always has a positive weight
+                filtered[p] = sum / weight_sum;
+            }
+        }
+        colors = filtered;
+    }
+
+    for (index, pixel) in output.pixels.iter_mut().enumerate() {
+        pixel.color = match &albedos {
+            Some(albedos) => colors[index].component_mul(&albedos[index]),
+            None => colors[index]
+        };
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_denoise(){
+        //The left half of the image is dark and the right half bright, and every sample is noisy
+        let (image_width, image_height) = (16, 16);
+        let rng = fastrand::Rng::with_seed(1);
+        let mut image = RaytracedImage::new(image_width, image_height);
+        let truth = |x: usize| if x < image_width / 2 { Color::repeat(0.1) } else { Color::repeat(0.4) };
+        for y in 0..image_height {
+            for x in 0..image_width {
+                let index = y * image_width + x;
+                let albedo = truth(x) * 2.0;
+                image.add_sample(index, truth(x) * (0.5 + rng.f64()));
+                image.add_aovs(index, &AovType::ALL.map(|aov_type| (aov_type.name(), match aov_type {
+                    AovType::Albedo => albedo,
+                    AovType::Normal => Color::new(0.0, 0.0, 1.0),
+                    _ => Color::zeros()
+                })));
+            }
+        }
+        image.samples = 1;
+        let error = |output: &Image| output.pixels.iter().enumerate().map(|(index, pixel)| (pixel.color - truth(index % image_width)).norm_squared()).sum::<f64>();
+
+        //Case 1: A strength of zero leaves the image unchanged
+        assert!(denoise(&image, 0.0) == image.to_image());
+
+        //Case 2: The noise is reduced
+        let denoised = denoise(&image, 1.0);
+        assert!(error(&denoised) < 0.1 * error(&image.to_image()));
+
+        //Case 3: The edge between the halves is not blurred
+        for y in 0..image_height {
+            for x in [image_width / 2 - 1, image_width / 2] {
+                let color = denoised.pixels[y * image_width + x].color;
+                assert!((color - truth(x)).norm() < 0.1 * truth(x).norm());
+            }
+        }
+    }
+}
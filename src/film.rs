@@ -0,0 +1,345 @@
+use std::f64::consts::PI;
+
+use crate::aov::AovSample;
+use crate::image::{Color, RaytracedImage};
+use crate::lpe::RenderLayer;
+
+/// The shapes of filter which can be used to reconstruct the image from its samples.
+#[derive (Copy, Clone, Debug, PartialEq)]
+pub enum FilterType {
+    /// Weights every sample within the radius equally. A radius of half a pixel gives each sample to its own pixel.
+    Box,
+    /// Weights samples by their distance from the pixel centre, falling linearly to zero at the radius.
+    Triangle,
+    /// A Gaussian whose standard deviation is a third of the radius, shifted down so that it reaches zero at the radius.
+    Gaussian,
+    /// The Mitchell-Netravali cubic with B = C = 1/3, which is sharper than the Gaussian, at the cost of slight ringing.
+    Mitchell,
+    /// A sinc windowed by a wider sinc, which is the sharpest of the filters and rings the most.
+    Lanczos
+}
+
+impl FilterType {
+    pub const ALL: [FilterType; 5] = [FilterType::Box, FilterType::Triangle, FilterType::Gaussian, FilterType::Mitchell, FilterType::Lanczos];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            FilterType::Box => "Box",
+            FilterType::Triangle => "Triangle",
+            FilterType::Gaussian => "Gaussian",
+            FilterType::Mitchell => "Mitchell-Netravali",
+            FilterType::Lanczos => "Lanczos"
+        }
+    }
+}
+
+/// A pixel reconstruction filter, which gives the weight of a sample in each pixel whose centre lies within `radius`
+/// pixels of it, along both axes.
+#[derive (Copy, Clone, Debug, PartialEq)]
+pub struct Filter {
+    pub filter_type: FilterType,
+    pub radius: f64
+}
+
+impl Filter {
+    pub fn new(filter_type: FilterType, radius: f64) -> Filter {
+        Filter { filter_type, radius }
+    }
+
+    /// Returns the weight of a sample offset from the centre of a pixel by the given number of pixels. The filters are
+    /// separable, so the weight is the product of the weights along each axis.
+    pub fn evaluate(&self, offset: (f64, f64)) -> f64 {
+        self.evaluate_1d(offset.0) * self.evaluate_1d(offset.1)
+    }
+
+    fn evaluate_1d(&self, x: f64) -> f64 {
+        let (x, radius) = (x.abs(), self.radius);
+        if x >= radius {
+            return 0.0;
+        }
+        match self.filter_type {
+            FilterType::Box => 1.0,
+            FilterType::Triangle => radius - x,
+            FilterType::Gaussian => {
+                let gaussian = |x: f64| (-4.5 * (x / radius).powi(2)).exp();
+                gaussian(x) - gaussian(radius)
+            }
+            FilterType::Mitchell => {
+                let (b, c) = (1.0 / 3.0, 1.0 / 3.0);
+                let x = 2.0 * x / radius;
+                if x > 1.0 {
+                    ((-b - 6.0*c) * x.powi(3) + (6.0*b + 30.0*c) * x.powi(2) + (-12.0*b - 48.0*c) * x + (8.0*b + 24.0*c)) / 6.0
+                } else {
+                    ((12.0 - 9.0*b - 6.0*c) * x.powi(3) + (-18.0 + 12.0*b + 6.0*c) * x.powi(2) + (6.0 - 2.0*b)) / 6.0
+                }
+            }
+            FilterType::Lanczos => sinc(x) * sinc(x / radius)
+        }
+    }
+}
+
+impl Default for Filter {
+    /// Gives each sample to its own pixel, as if there were no filter.
+    fn default() -> Filter {
+        Filter::new(FilterType::Box, 0.5)
+    }
+}
+
+fn sinc(x: f64) -> f64 {
+    if x.abs() < 1e-5 {
+        return 1.0;
+    }
+    (PI * x).sin() / (PI * x)
+}
+
+/// A rectangle of pixels within the image, measured in pixels from its top left corner.
+#[derive (Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Tile {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize
+}
+
+impl Tile {
+    pub fn new(x: usize, y: usize, width: usize, height: usize) -> Tile {
+        Tile { x, y, width, height }
+    }
+
+    /// Splits an image into tiles of the given size, row by row from the top left. The tiles along the right and
+    /// bottom edges are cut short by the edges of the image.
+    pub fn split(image_width: usize, image_height: usize, tile_size: usize) -> Vec<Tile> {
+        let mut tiles = Vec::new();
+        for y in (0..image_height).step_by(tile_size) {
+            for x in (0..image_width).step_by(tile_size) {
+                tiles.push(Tile::new(x, y, tile_size.min(image_width - x), tile_size.min(image_height - y)));
+            }
+        }
+        tiles
+    }
+
+    pub fn contains(&self, i: usize, j: usize) -> bool {
+        i >= self.x && j >= self.y && i < self.x + self.width && j < self.y + self.height
+    }
+
+    /// Returns the index of the pixel at column `i` and row `j` of the image, among the pixels of the tile.
+    pub fn local_index(&self, i: usize, j: usize) -> usize {
+        (j - self.y) * self.width + i - self.x
+    }
+
+    /// Returns the tile grown by `margin` pixels on every side, without crossing the edges of the image.
+    pub fn expand(&self, margin: usize, image_width: usize, image_height: usize) -> Tile {
+        let (x, y) = (self.x.saturating_sub(margin), self.y.saturating_sub(margin));
+        let right = (self.x + self.width + margin).min(image_width);
+        let bottom = (self.y + self.height + margin).min(image_height);
+        Tile::new(x, y, right - x, bottom - y)
+    }
+
+    pub fn overlaps(&self, other: &Tile) -> bool {
+        self.x < other.x + other.width && other.x < self.x + self.width && self.y < other.y + other.height && other.y < self.y + self.height
+    }
+}
+
+/// Collects the samples of a raytracing pass into an image. Each sample is spread over every pixel its filter reaches,
+/// and the filter weights are accumulated alongside the colours so that each pixel can be normalised by them. The light
+/// carried into each render layer and light group is filtered in the same way.
+/// 
+/// The film may cover only a region of the image, in which case its image holds just the pixels of the region. Samples
+/// must then be taken far enough inside the region that their filter does not reach beyond it. Light splatted onto 
+/// pixels outside the region is kept aside, by the index of the pixel within the whole image.
+pub struct Film {
+    pub image: RaytracedImage,
+    pub filter: Filter,
+    pub layers: Vec<RenderLayer>,
+    pub image_width: usize,
+    pub image_height: usize,
+    pub region: Tile,
+    pub outside_splats: Vec<(usize, Color)>
+}
+
+impl Film {
+    pub fn new(image_width: usize, image_height: usize, filter: Filter, layers: &[RenderLayer], light_groups: &[String]) -> Film {
+        Film::for_region(image_width, image_height, Tile::new(0, 0, image_width, image_height), filter, layers, light_groups)
+    }
+
+    /// Returns a film covering the given region of an image.
+    pub fn for_region(image_width: usize, image_height: usize, region: Tile, filter: Filter, layers: &[RenderLayer], light_groups: &[String]) -> Film {
+        let mut image = RaytracedImage::new(region.width, region.height);
+        for layer in layers {
+            image.add_layer(&layer.name);
+        }
+        for light_group in light_groups {
+            image.add_light_group(light_group);
+        }
+        Film { image, filter, layers: layers.to_vec(), image_width, image_height, region, outside_splats: Vec::new() }
+    }
+
+    /// Returns the number of pixels beyond its own pixel that a sample may reach with the given filter.
+    pub fn margin(filter: &Filter) -> usize {
+        ((filter.radius + 0.5).ceil() as usize).saturating_sub(1)
+    }
+
+    /// Returns the index among the pixels of the region of the pixel with the given index within the whole image.
+    fn local_index(&self, pixel_index: usize) -> usize {
+        self.region.local_index(pixel_index % self.image_width, pixel_index / self.image_width)
+    }
+
+    /// Adds a sample taken for the pixel with the given index, at the given film position, which is expressed in the
+    /// coordinates passed to `Camera::get_ray`, along with the light it carried into each render layer and from each
+    /// light group. The sample counts towards the error estimate of its own pixel only.
+    pub fn add_sample(&mut self, pixel_index: usize, film_position: (f64, f64), color: Color, layers: &[Color], light_groups: &[Color]) {
+        let local_index = self.local_index(pixel_index);
+        self.image.record_sample(local_index, color);
+
+        let (image_width, image_height, region) = (self.image_width, self.image_height, self.region);
+        let x = film_position.0 * image_width as f64;
+        let y = film_position.1 * image_height as f64;
+        let radius = self.filter.radius;
+        //Rows of the film are counted from the bottom of the image
+        let (left, right) = (region.x as f64, (region.x + region.width - 1) as f64);
+        let (bottom, top) = ((image_height - region.y - region.height) as f64, (image_height - 1 - region.y) as f64);
+        let x_range = ((x - 0.5 - radius).ceil().max(left) as usize)..=((x - 0.5 + radius).floor().min(right) as usize);
+        let y_range = ((y - 0.5 - radius).ceil().max(bottom) as usize)..=((y - 0.5 + radius).floor().min(top) as usize);
+        for row in y_range {
+            for column in x_range.clone() {
+                let weight = self.filter.evaluate((column as f64 + 0.5 - x, row as f64 + 0.5 - y));
+                if weight != 0.0 {
+                    let index = region.local_index(column, image_height - 1 - row);
+                    self.image.add_weighted(index, color, weight);
+                    self.image.add_weighted_layers(index, layers, weight);
+                    self.image.add_weighted_light_groups(index, light_groups, weight);
+                }
+            }
+        }
+    }
+
+    /// Adds the AOVs of a sample taken for the pixel with the given index. AOVs are not filtered.
+    pub fn add_aovs(&mut self, pixel_index: usize, aovs: &AovSample) {
+        let local_index = self.local_index(pixel_index);
+        self.image.add_aovs(local_index, aovs);
+    }
+
+    /// Adds light to the pixel containing the given film position, without filtering it. See `RaytracedImage::splat`.
+    pub fn splat(&mut self, film_position: (f64, f64), color: Color) {
+        let (s, t) = film_position;
+        let i = (s * self.image_width as f64).floor();
+        let j = (t * self.image_height as f64).floor();
+        if i < 0.0 || j < 0.0 || i >= self.image_width as f64 || j >= self.image_height as f64 {
+            return;
+        }
+        let (i, j) = (i as usize, self.image_height - 1 - j as usize);
+        if self.region.contains(i, j) {
+            let local_index = self.region.local_index(i, j);
+            self.image.splats[local_index] += color;
+            self.image.image.pixels[local_index].alpha = 1.0;
+        } else {
+            self.outside_splats.push((j * self.image_width + i, color));
+        }
+    }
+
+    pub fn into_image(self) -> RaytracedImage {
+        self.image
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_evaluate(){
+        //Case 1: Every filter peaks at the centre and vanishes at the radius
+        for filter_type in FilterType::ALL {
+            let filter = Filter::new(filter_type, 2.0);
+            assert!(filter.evaluate((0.0, 0.0)) > 0.0);
+            assert!(filter.evaluate((0.0, 0.0)) >= filter.evaluate((0.3, 0.0)));
+            assert_eq!(filter.evaluate((2.0, 0.0)), 0.0);
+            assert_eq!(filter.evaluate((0.5, -2.5)), 0.0);
+            if filter_type != FilterType::Box {
+                assert!(filter.evaluate((1.999, 0.0)).abs() < 1e-2);
+            }
+        }
+
+        //Case 2: The box and triangle filters
+        assert_eq!(Filter::new(FilterType::Box, 0.5).evaluate((0.49, -0.2)), 1.0);
+        assert_eq!(Filter::new(FilterType::Box, 0.5).evaluate((0.5, 0.0)), 0.0);
+        assert!((Filter::new(FilterType::Triangle, 1.0).evaluate((0.5, 0.25)) - 0.375).abs() < 1e-12);
+
+        //Case 3: The Mitchell-Netravali and Lanczos filters have negative lobes
+        assert!(Filter::new(FilterType::Mitchell, 2.0).evaluate((1.5, 0.0)) < 0.0);
+        assert!(Filter::new(FilterType::Lanczos, 3.0).evaluate((1.5, 0.0)) < 0.0);
+    }
+
+    #[test]
+    fn test_add_sample(){
+        //Case 1: With the default filter, a sample only lands on its own pixel
+        let mut film = Film::new(3, 3, Filter::default(), &[], &[]);
+        film.add_sample(4, (0.5, 0.5), Color::new(1.0, 1.0, 1.0), &[], &[]);
+        assert_eq!(film.image.weights, vec![0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0]);
+
+        //Case 2: A wider filter spreads the sample over the neighbouring pixels, but it only counts as a sample of its own
+        let mut film = Film::new(3, 3, Filter::new(FilterType::Triangle, 1.5), &[], &[]);
+        film.add_sample(4, (0.5, 0.5), Color::new(1.0, 1.0, 1.0), &[], &[]);
+        assert!(film.image.weights.iter().all(|weight| *weight > 0.0));
+        assert_eq!(film.image.pixel_samples, vec![0, 0, 0, 0, 1, 0, 0, 0, 0]);
+
+        //Case 3: The sample at the top left of the image is weighted towards the top left pixel
+        let mut film = Film::new(3, 3, Filter::new(FilterType::Triangle, 1.5), &[], &[]);
+        film.add_sample(0, (0.1, 0.9), Color::new(1.0, 1.0, 1.0), &[], &[]);
+        assert!(film.image.weights[0] > film.image.weights[1] && film.image.weights[1] > film.image.weights[2]);
+        assert!(film.image.weights[0] > film.image.weights[3] && film.image.weights[3] > film.image.weights[6]);
+
+        //Case 4: Filtering preserves a constant image
+        for filter_type in FilterType::ALL {
+            let mut film = Film::new(4, 4, Filter::new(filter_type, 2.0), &[], &[]);
+            for index in 0..16 {
+                let (i, j) = (index % 4, index / 4);
+                film.add_sample(index, ((i as f64 + 0.3) / 4.0, (3.6 - j as f64) / 4.0), Color::new(0.2, 0.4, 0.6), &[], &[]);
+            }
+            film.image.samples = 1;
+            for pixel in film.into_image().to_image().pixels {
+                assert!((pixel.color - Color::new(0.2, 0.4, 0.6)).norm() < 1e-12);
+            }
+        }
+
+        //Case 5: A film covering a region of the image weights its pixels as the film of the whole image does, and keeps
+        //light splatted outside the region aside
+        let filter = Filter::new(FilterType::Triangle, 1.5);
+        let region = Tile::new(1, 1, 1, 1).expand(Film::margin(&filter), 4, 4);
+        assert_eq!(region, Tile::new(0, 0, 3, 3));
+        let mut film = Film::new(4, 4, filter, &[], &[]);
+        let mut region_film = Film::for_region(4, 4, region, filter, &[], &[]);
+        for film in [&mut film, &mut region_film] {
+            film.add_sample(5, (0.4, 0.6), Color::new(1.0, 1.0, 1.0), &[], &[]);
+            film.splat((0.9, 0.9), Color::new(1.0, 1.0, 1.0));
+        }
+        for index in 0..9 {
+            assert_eq!(region_film.image.weights[index], film.image.weights[(index / 3) * 4 + index % 3]);
+        }
+        assert_eq!(region_film.outside_splats, vec![(3, Color::new(1.0, 1.0, 1.0))]);
+        assert_eq!(film.image.splats[3], Color::new(1.0, 1.0, 1.0));
+
+        //Case 6: A pixel reached only by the negative lobe of a filter has a negative weight, which still normalises it
+        let mut film = Film::new(4, 1, Filter::new(FilterType::Mitchell, 2.0), &[], &[]);
+        film.add_sample(0, (0.3 / 4.0, 0.5), Color::new(0.2, 0.4, 0.6), &[], &[]);
+        assert!(film.image.weights[1] < 0.0);
+        film.image.samples = 1;
+        let image = film.into_image().to_image();
+        assert!((image.pixels[1].color - Color::new(0.2, 0.4, 0.6)).norm() < 1e-12);
+        assert_eq!(image.pixels[2].color, Color::zeros());
+    }
+
+    #[test]
+    fn test_split(){
+        //Case 1: The tiles cover the image row by row, and are cut short by its edges
+        let tiles = Tile::split(5, 3, 2);
+        assert_eq!(tiles.len(), 6);
+        assert_eq!(tiles[2], Tile::new(4, 0, 1, 2));
+        assert_eq!(tiles[5], Tile::new(4, 2, 1, 1));
+        assert_eq!(tiles.iter().map(|tile| tile.width * tile.height).sum::<usize>(), 15);
+
+        //Case 2: Expanded tiles stop at the edges of the image
+        assert_eq!(tiles[4].expand(1, 5, 3), Tile::new(1, 1, 4, 2));
+        assert!(tiles[4].expand(1, 5, 3).overlaps(&tiles[0]) && !tiles[4].overlaps(&tiles[0]));
+    }
+}
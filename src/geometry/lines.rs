@@ -266,7 +266,7 @@ mod tests {
         let aspect_ratio = 1.0;
         let image_height = 100;
         let image_width = 100;
-        let camera_settings = CameraSettings { look_from, look_at, v_up, v_fov, aspect_ratio, aperture, focus_dist, image_height, image_width };
+        let camera_settings = CameraSettings { look_from, look_at, v_up, v_fov, aspect_ratio, aperture, focus_dist, image_height, image_width, shutter_open: 0.0, shutter_close: 0.0 };
         let cam = Camera::new(camera_settings);
         
         let line = Line3::new(Point3::<f64>::new(1.0, 1.0, 1.0), Point3::<f64>::new(1.0, 5.0, 4.0));
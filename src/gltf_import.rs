@@ -0,0 +1,354 @@
+use crate::camera::CameraSettings;
+use crate::image::Color;
+use crate::material::Material;
+use crate::primitives::{GeometricPrimitive, GeometricPrimitives};
+use crate::nalgebra::{Matrix3, Matrix4, Point3, Vector3};
+
+use gltf::buffer::Source;
+use gltf::camera::Projection;
+use gltf::mesh::Mode;
+
+use std::path::Path;
+
+/// The meshes and cameras of a glTF 2.0 file.
+pub struct GltfScene {
+    pub primitives: GeometricPrimitives,
+    /// The perspective cameras of the scene, in the order their nodes are found. Orthographic cameras are left out.
+    pub cameras: Vec<CameraSettings>,
+    /// Warnings about the parts of the file which were skipped.
+    pub warnings: Vec<String>
+}
+
+/// A parsed glTF file, along with the data of its buffers.
+struct Asset {
+    document: gltf::Document,
+    buffers: Vec<Vec<u8>>
+}
+
+/// Returns whether a path names a glTF file, either as JSON (.gltf) or binary (.glb).
+pub fn is_gltf(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("gltf") || extension.eq_ignore_ascii_case("glb"))
+}
+
+/// Imports the meshes and cameras of the default scene of a glTF file. Cameras keep the given image width, and take
+/// their image height from their aspect ratio if they have one.
+///
+/// Triangles are moved into the world by the transforms of their nodes. Materials are mapped from their
+/// metallic-roughness factors onto the closest material the renderer has; textures, and so texture coordinates, are
+/// not supported.
+pub fn import_gltf(path: &Path, image_width: usize, image_height: usize) -> Result<GltfScene, String> {
+    let asset = Asset::load(path)?;
+    let mut scene = GltfScene { primitives: GeometricPrimitives::new(), cameras: Vec::new(), warnings: Vec::new() };
+    asset.visit_nodes(&Matrix4::identity(), &mut |node, transform| {
+        if let Some(camera) = node.camera() {
+            match camera_settings(&camera, transform, image_width, image_height) {
+                Some(settings) => scene.cameras.push(settings),
+                None => scene.warnings.push(format!("Only perspective cameras are supported, so camera {} is skipped", camera.index()))
+            }
+        }
+        match node.mesh() {
+            Some(mesh) => asset.add_mesh(&mut scene.primitives, &mesh, transform, None),
+            None => Ok(())
+        }
+    }).map_err(|error| format!("{}: {}", path.display(), error))?;
+    Ok(scene)
+}
+
+/// Adds the triangles of a glTF file to the primitives, moved by the given transform. Triangles keep the materials of
+/// the file unless given one.
+pub fn add_gltf_meshes(primitives: &mut GeometricPrimitives, path: &Path, material: Option<Material>, transform: &Matrix4<f64>) -> Result<(), String> {
+    let asset = Asset::load(path)?;
+    asset.visit_nodes(transform, &mut |node, transform| match node.mesh() {
+        Some(mesh) => asset.add_mesh(primitives, &mesh, transform, material),
+        None => Ok(())
+    }).map_err(|error| format!("{}: {}", path.display(), error))
+}
+
+/// Decodes standard base64, as used by the data URIs of embedded buffers.
+fn decode_base64(encoded: &str) -> Result<Vec<u8>, String> {
+    let mut decoded = Vec::with_capacity(encoded.len() * 3 / 4);
+    let (mut bits, mut bit_count) = (0u32, 0);
+    for c in encoded.bytes().take_while(|c| *c != b'=') {
+        let value = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            _ => return Err(format!("Invalid character '{}' in base64 data", c as char))
+        };
+        bits = (bits << 6) | value as u32;
+        bit_count += 6;
+        if bit_count >= 8 {
+            bit_count -= 8;
+            decoded.push((bits >> bit_count) as u8);
+        }
+    }
+    Ok(decoded)
+}
+
+/// Decodes the escaped characters of a URI, such as `%20` for a space.
+fn decode_uri(uri: &str) -> String {
+    let bytes = uri.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match (bytes[i], uri.get(i + 1..i + 3).and_then(|hex| u8::from_str_radix(hex, 16).ok())) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+fn to_matrix(matrix: [[f32; 4]; 4]) -> Matrix4<f64> {
+    //glTF matrices are given a column at a time
+    Matrix4::from_fn(|row, column| matrix[column][row] as f64)
+}
+
+/// Maps a metallic-roughness material onto the closest of the renderer's materials, using its constant factors.
+/// Emissive materials become lights, transmissive ones glass, and mostly metallic ones metal.
+fn material(material: &gltf::Material) -> Material {
+    let emissive = material.emissive_factor();
+    let emissive = Color::new(emissive[0] as f64, emissive[1] as f64, emissive[2] as f64) * material.emissive_strength().unwrap_or(1.0) as f64;
+    if emissive != Color::zeros() {
+        return Material::new_diffuse_light(emissive);
+    }
+    if material.transmission().map_or(0.0, |transmission| transmission.transmission_factor()) >= 0.5 {
+        return Material::new_dielectric(material.ior().unwrap_or(1.5) as f64);
+    }
+    let pbr = material.pbr_metallic_roughness();
+    let [r, g, b, _] = pbr.base_color_factor();
+    let base_color = Color::new(r as f64, g as f64, b as f64);
+    if pbr.metallic_factor() >= 0.5 {
+        Material::new_metal(base_color, pbr.roughness_factor() as f64)
+    } else {
+        Material::new_lambertian(base_color)
+    }
+}
+
+/// Returns the settings of a perspective camera placed by the given transform, or `None` if the camera is orthographic.
+/// glTF cameras look along their -z axis.
+fn camera_settings(camera: &gltf::Camera, transform: &Matrix4<f64>, image_width: usize, image_height: usize) -> Option<CameraSettings> {
+    let perspective = match camera.projection() {
+        Projection::Perspective(perspective) => perspective,
+        Projection::Orthographic(_) => return None
+    };
+    let image_height = perspective.aspect_ratio().map_or(image_height, |aspect_ratio| ((image_width as f64 / aspect_ratio as f64).round() as usize).max(1));
+    let look_from = transform.transform_point(&Point3::<f64>::origin());
+    Some(CameraSettings { look_from, look_at: look_from + transform.transform_vector(&-Vector3::<f64>::z()).normalize(),
+                          v_up: transform.transform_vector(&Vector3::<f64>::y()).normalize(), v_fov: (perspective.yfov() as f64).to_degrees(),
+                          aspect_ratio: image_width as f64 / image_height as f64, aperture: 0.0, focus_dist: 10.0, image_height, image_width,
+                          shutter_open: 0.0, shutter_close: 1.0 })
+}
+
+impl Asset {
+    fn load(path: &Path) -> Result<Asset, String> {
+        let bytes = std::fs::read(path).map_err(|error| format!("Could not read '{}': {}", path.display(), error))?;
+        Asset::parse(&bytes, path.parent().unwrap_or_else(|| Path::new(""))).map_err(|error| format!("{}: {}", path.display(), error))
+    }
+
+    /// Parses a glTF file, reading external buffers relative to the given directory.
+    fn parse(bytes: &[u8], directory: &Path) -> Result<Asset, String> {
+        let gltf::Gltf { document, blob } = gltf::Gltf::from_slice(bytes).map_err(|error| format!("Invalid glTF: {}", error))?;
+        let buffers = document.buffers().map(|buffer| {
+            let data = match buffer.source() {
+                Source::Bin => blob.clone().ok_or_else(|| String::from("The file has no binary chunk"))?,
+                Source::Uri(uri) => match uri.strip_prefix("data:") {
+                    Some(data) => decode_base64(data.split_once(";base64,").ok_or_else(|| String::from("Data URIs must be base64 encoded"))?.1)?,
+                    None => {
+                        let path = directory.join(decode_uri(uri));
+                        std::fs::read(&path).map_err(|error| format!("Could not read '{}': {}", path.display(), error))?
+                    }
+                }
+            };
+            if data.len() < buffer.length() {
+                return Err(format!("Buffer {} is shorter than its length of {} bytes", buffer.index(), buffer.length()));
+            }
+            Ok(data)
+        }).collect::<Result<Vec<Vec<u8>>, String>>()?;
+        Ok(Asset { document, buffers })
+    }
+
+    /// Calls `visit` on every node of the default scene, or of the first scene if there is no default, along with the
+    /// transform from the node to the world. Nodes are visited depth first, in the order they are listed.
+    fn visit_nodes<F: FnMut(&gltf::Node, &Matrix4<f64>) -> Result<(), String>>(&self, root: &Matrix4<f64>, visit: &mut F) -> Result<(), String> {
+        fn visit_node<F: FnMut(&gltf::Node, &Matrix4<f64>) -> Result<(), String>>(node: &gltf::Node, parent: &Matrix4<f64>, visit: &mut F) -> Result<(), String> {
+            let transform = parent * to_matrix(node.transform().matrix());
+            visit(node, &transform)?;
+            for child in node.children() {
+                visit_node(&child, &transform, visit)?;
+            }
+            Ok(())
+        }
+
+        match self.document.default_scene().or_else(|| self.document.scenes().next()) {
+            Some(scene) => scene.nodes().try_for_each(|node| visit_node(&node, root, visit)),
+            None => Ok(())
+        }
+    }
+
+    /// Adds the triangles of a mesh, moved into the world by the given transform. Points and lines are skipped, since
+    /// they have no surface to render. Texture coordinates are not read, as triangles only hold positions and normals,
+    /// and none of the materials take a texture to look them up in.
+    fn add_mesh(&self, primitives: &mut GeometricPrimitives, mesh: &gltf::Mesh, transform: &Matrix4<f64>, material_override: Option<Material>) -> Result<(), String> {
+        let linear: Matrix3<f64> = transform.fixed_slice::<3, 3>(0, 0).into_owned();
+        let normal_transform = linear.try_inverse().unwrap_or_else(Matrix3::zeros).transpose();
+        //Transforms which mirror the mesh reverse its winding, which is turned back so that face normals point outwards
+        let rewind = linear.determinant() < 0.0;
+
+        for primitive in mesh.primitives() {
+            let reader = primitive.reader(|buffer| self.buffers.get(buffer.index()).map(Vec::as_slice));
+            let positions: Vec<Point3<f64>> = match reader.read_positions() {
+                Some(positions) => positions.map(|p| transform.transform_point(&Point3::<f64>::new(p[0] as f64, p[1] as f64, p[2] as f64))).collect(),
+                None => continue
+            };
+            let normals: Option<Vec<Vector3<f64>>> = reader.read_normals()
+                .map(|normals| normals.map(|n| (normal_transform * Vector3::<f64>::new(n[0] as f64, n[1] as f64, n[2] as f64)).normalize()).collect())
+                .filter(|normals: &Vec<Vector3<f64>>| normals.len() == positions.len());
+            //Only primitives without indices use their vertices in order. Indices which cannot be read are an error, since
+            //guessing at them would join up the wrong vertices
+            let indices: Vec<usize> = match (primitive.indices(), reader.read_indices()) {
+                (None, _) => (0..positions.len()).collect(),
+                (Some(_), Some(indices)) => indices.into_u32().map(|index| index as usize).collect(),
+                (Some(accessor), None) => return Err(format!("The indices of mesh {} could not be read from accessor {}", mesh.index(), accessor.index()))
+            };
+            let triangles: Vec<[usize; 3]> = match primitive.mode() {
+                Mode::Triangles => indices.chunks_exact(3).map(|triangle| [triangle[0], triangle[1], triangle[2]]).collect(),
+                Mode::TriangleStrip => (2..indices.len()).map(|i| if i % 2 == 0 {
+                    [indices[i - 2], indices[i - 1], indices[i]]
+                } else {
+                    [indices[i - 2], indices[i], indices[i - 1]]
+                }).collect(),
+                Mode::TriangleFan => (2..indices.len()).map(|i| [indices[0], indices[i - 1], indices[i]]).collect(),
+                _ => continue
+            };
+            if triangles.iter().flatten().any(|index| *index >= positions.len()) {
+                return Err(format!("A triangle of mesh {} refers to a vertex which does not exist", mesh.index()));
+            }
+
+            let material = material_override.unwrap_or_else(|| material(&primitive.material()));
+            for mut corners in triangles {
+                if rewind {
+                    corners.swap(1, 2);
+                }
+                let vertices = corners.map(|index| positions[index]);
+                let face_normal = (vertices[1] - vertices[0]).cross(&(vertices[2] - vertices[0]));
+                if face_normal.norm() == 0.0 {
+                    continue;
+                }
+                let normals = match &normals {
+                    Some(normals) => corners.map(|index| normals[index]),
+                    None => [face_normal.normalize(); 3]
+                };
+                primitives.add(GeometricPrimitive::new_triangle(vertices, normals, material));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::raytracing::Hit;
+
+    /// Builds a binary glTF file holding a single triangle, whose node has a child camera, with the given accessor for
+    /// the indices of the triangle.
+    fn triangle_glb(indices: &str) -> Vec<u8> {
+        let mut buffer = Vec::new();
+        for value in [0.0f32, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0] {
+            buffer.extend_from_slice(&value.to_le_bytes());
+        }
+        for index in [0u16, 1, 2, 0] {
+            buffer.extend_from_slice(&index.to_le_bytes());
+        }
+        let json = format!(r#"{{"asset": {{"version": "2.0"}}, "scene": 0, "scenes": [{{"nodes": [0]}}],
+            "nodes": [{{"translation": [0, 0, -2], "scale": [2, 2, 2], "mesh": 0, "children": [1, 2]}}, {{"camera": 0, "translation": [0, 0, 2]}}, {{"camera": 1}}],
+            "cameras": [{{"type": "perspective", "perspective": {{"yfov": 0.5, "aspectRatio": 2.0, "znear": 0.1}}}},
+                        {{"type": "orthographic", "orthographic": {{"xmag": 1, "ymag": 1, "zfar": 10, "znear": 0.1}}}}],
+            "meshes": [{{"primitives": [{{"attributes": {{"POSITION": 0}}, "indices": 1, "material": 0}}]}}],
+            "materials": [{{"pbrMetallicRoughness": {{"baseColorFactor": [1, 0.5, 0.25, 1], "metallicFactor": 1, "roughnessFactor": 0.25}}}}],
+            "buffers": [{{"byteLength": {}}}],
+            "bufferViews": [{{"buffer": 0, "byteOffset": 0, "byteLength": 36}}, {{"buffer": 0, "byteOffset": 36, "byteLength": 6}}],
+            "accessors": [{{"bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3", "min": [0, 0, 0], "max": [1, 1, 0]}},
+                          {}]}}"#, buffer.len(), indices);
+        let mut json = json.into_bytes();
+        while json.len() % 4 != 0 {
+            json.push(b' ');
+        }
+
+        let mut glb = Vec::new();
+        glb.extend_from_slice(b"glTF");
+        glb.extend_from_slice(&2u32.to_le_bytes());
+        glb.extend_from_slice(&((12 + 8 + json.len() + 8 + buffer.len()) as u32).to_le_bytes());
+        glb.extend_from_slice(&(json.len() as u32).to_le_bytes());
+        glb.extend_from_slice(b"JSON");
+        glb.extend_from_slice(&json);
+        glb.extend_from_slice(&(buffer.len() as u32).to_le_bytes());
+        glb.extend_from_slice(b"BIN\0");
+        glb.extend_from_slice(&buffer);
+        glb
+    }
+
+    const INDICES: &str = r#"{"bufferView": 1, "componentType": 5123, "count": 3, "type": "SCALAR"}"#;
+
+    #[test]
+    fn test_import(){
+        let path = std::env::temp_dir().join(format!("gltf_import_test_{}.glb", std::process::id()));
+        std::fs::write(&path, triangle_glb(INDICES)).unwrap();
+        let imported = import_gltf(&path, 200, 150);
+        let mut overridden = GeometricPrimitives::new();
+        let added = add_gltf_meshes(&mut overridden, &path, Some(Material::new_lambertian(Color::repeat(0.5))), &Matrix4::new_translation(&Vector3::<f64>::x()));
+        std::fs::remove_file(&path).unwrap();
+        let scene = imported.unwrap();
+
+        //Case 1: The triangle is moved by its node, and its material is mapped to metal
+        assert_eq!(scene.primitives.len(), 1);
+        let bounds = scene.primitives.get(0).bounding_box().unwrap();
+        assert!((bounds.min() - Point3::<f64>::new(0.0, 0.0, -2.0)).norm() < 1e-2);
+        assert!((bounds.max() - Point3::<f64>::new(2.0, 2.0, -2.0)).norm() < 1e-2);
+        assert!(scene.primitives.get(0).material() == Material::new_metal(Color::new(1.0, 0.5, 0.25), 0.25));
+
+        //Case 2: The camera is placed by its parent, and takes the image height from its aspect ratio. The orthographic
+        //camera is skipped with a warning
+        assert_eq!(scene.cameras.len(), 1);
+        assert_eq!(scene.warnings, vec![String::from("Only perspective cameras are supported, so camera 1 is skipped")]);
+        let camera = &scene.cameras[0];
+        assert!((camera.look_from - Point3::<f64>::new(0.0, 0.0, 2.0)).norm() < 1e-9);
+        assert!((camera.look_at - Point3::<f64>::new(0.0, 0.0, 1.0)).norm() < 1e-9);
+        assert_eq!((camera.image_width, camera.image_height), (200, 100));
+        assert!((camera.v_fov - 0.5f64.to_degrees()).abs() < 1e-6);
+
+        //Case 3: Meshes can be added with a material and transform of their own
+        added.unwrap();
+        assert_eq!(overridden.len(), 1);
+        assert!(overridden.get(0).material() == Material::new_lambertian(Color::repeat(0.5)));
+        assert!((overridden.get(0).bounding_box().unwrap().max() - Point3::<f64>::new(3.0, 2.0, -2.0)).norm() < 1e-2);
+
+        //Case 4: Indices which are given but cannot be read, here because they run past the end of their view, are an
+        //error rather than replaced by the vertices in order
+        let unreadable = Asset::parse(&triangle_glb(r#"{"bufferView": 1, "byteOffset": 4, "componentType": 5123, "count": 3, "type": "SCALAR"}"#), Path::new("")).unwrap();
+        let mesh = unreadable.document.meshes().next().unwrap();
+        let error = unreadable.add_mesh(&mut GeometricPrimitives::new(), &mesh, &Matrix4::identity(), None);
+        assert_eq!(error, Err(String::from("The indices of mesh 0 could not be read from accessor 1")));
+    }
+
+    #[test]
+    fn test_decode(){
+        //Case 1: Base64, with and without padding
+        assert_eq!(decode_base64("aGVsbG8="), Ok(b"hello".to_vec()));
+        assert_eq!(decode_base64("AAEC/w"), Ok(vec![0, 1, 2, 255]));
+        assert!(decode_base64("a$b").is_err());
+
+        //Case 2: Escaped URIs
+        assert_eq!(decode_uri("my%20mesh.bin"), "my mesh.bin");
+        assert_eq!(decode_uri("100%"), "100%");
+    }
+}
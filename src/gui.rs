@@ -2,7 +2,7 @@ pub mod progress_bar;
 
 use eframe::{egui::{self, Sense, panel::TopBottomSide, style::Margin, Ui, Context}, epaint::{ColorImage, Color32}};
 
-use crate::{nalgebra::{Vector2, Vector3, Point2, Point3, Rotation3, Unit}, image::PrimaryImageType, threads::{ThreadCoordinator, GlobalSettings}};
+use crate::{nalgebra::{Vector2, Vector3, Point2, Point3, Rotation3, Unit}, image::{Color, CompositeImage, Image, PrimaryImageType, RaytracedImage}, denoise::denoise, lpe::RenderLayer, sampler::SamplerType, film::FilterType, spectra::ColorMode, threads::{ThreadCoordinator, GlobalSettings}};
 use crate::*;
 
 use self::progress_bar::CustomProgressBar;
@@ -16,13 +16,47 @@ pub struct Gui {
     pub windows: Windows,
     pub renderers: Renderers,
     pub image_output: PrimaryImageType,
+    /// The integrator used for the raytraced image, which is restored when ambient occlusion is no longer shown.
+    pub render_mode: Integrator,
     pub outline: bool,
+    /// Whether the number of samples taken in each pixel is shown over the image.
+    pub sample_overlay: bool,
+    /// Whether the raytraced image is denoised before it is shown, and how strongly.
+    pub denoise: bool,
+    pub denoise_strength: f64,
+    /// The accumulated colours of the last image to be denoised, along with the strength it was denoised at and the
+    /// result, so that the image is only denoised again when it changes.
+    pub denoised: Option<(Image, f64, Image)>,
+    /// The gain and tint applied to each light group of the raytraced image, in the order of the groups.
+    pub light_mix: Vec<LightMix>,
     pub click_vector: Vector3<f64>,
     pub dragging: bool
 }
 
 pub struct Windows {
-    pub settings: bool
+    pub settings: bool,
+    pub light_mixer: bool
+}
+
+/// The adjustment made to the light from one light group of the raytraced image.
+#[derive (Copy, Clone, PartialEq)]
+pub struct LightMix {
+    pub gain: f64,
+    pub tint: [f32; 3]
+}
+
+impl LightMix {
+    /// The colour the light from the group is multiplied by.
+    pub fn scale(&self) -> Color {
+        Color::new(self.tint[0] as f64, self.tint[1] as f64, self.tint[2] as f64) * self.gain
+    }
+}
+
+impl Default for LightMix {
+    /// Leaves the light from the group unchanged.
+    fn default() -> LightMix {
+        LightMix { gain: 1.0, tint: [1.0, 1.0, 1.0] }
+    }
 }
 
 pub struct Renderers{
@@ -33,31 +67,104 @@ pub struct Renderers{
 impl Gui{
     pub fn new(settings: GlobalSettings, thread_coordinator: ThreadCoordinator) -> Gui {
         let camera_speed = 0.2;
+        let denoise_strength = 1.0;
 
         let image_width = settings.image_settings.image_width;
         let image_height = settings.image_settings.image_height;
         let samples_per_pixel = settings.raytrace_settings.samples_per_pixel;
 
-        let labels = Labels{width: image_width.to_string(), height: image_height.to_string(), samples: samples_per_pixel.to_string(), camera_speed: camera_speed.to_string()};
-        let windows = Windows { settings: false };
+        let ao_samples = settings.raytrace_settings.ao_samples;
+        let ao_distance = settings.raytrace_settings.ao_distance;
+        let noise_threshold = settings.raytrace_settings.noise_threshold;
+        let seed = settings.raytrace_settings.seed;
+        let filter_radius = settings.raytrace_settings.filter.radius;
+
+        let labels = Labels{width: image_width.to_string(), height: image_height.to_string(), samples: samples_per_pixel.to_string(), camera_speed: camera_speed.to_string(),
+                            ao_samples: ao_samples.to_string(), ao_distance: ao_distance.to_string(), noise_threshold: noise_threshold.to_string(), seed: seed.to_string(),
+                            filter_radius: filter_radius.to_string(), denoise_strength: denoise_strength.to_string(),
+                            layer: String::new(), layer_error: String::new()};
+        let windows = Windows { settings: false, light_mixer: false };
         let renderers = Renderers {raytracer: false, rasterizer: false};
         let expecting_data = true;
         let image_output = PrimaryImageType::Raytrace;
+        let render_mode = settings.raytrace_settings.integrator;
         let outline = false;
+        let sample_overlay = false;
+        let denoise = false;
+        let denoised = None;
+        let light_mix = Vec::new();
         let click_vector: Vector3::<f64> = Vector3::<f64>::default();
         let dragging = false;
 
-        Gui { thread_coordinator, settings, labels, camera_speed, expecting_data, windows, renderers, image_output, outline, click_vector, dragging }
+        Gui { thread_coordinator, settings, labels, camera_speed, expecting_data, windows, renderers, image_output, render_mode, outline, sample_overlay, denoise, denoise_strength, denoised, 
+              light_mix, click_vector, dragging }
     }
 
-    pub fn show_image(&self, ctx: &Context, ui: &mut Ui) {
-        let image = self.thread_coordinator.output_image();
-        let rgbas = image.output(self.image_output, self.outline).output_rgba();
+    pub fn show_image(&mut self, ctx: &Context, ui: &mut Ui) {
+        let mut image = self.thread_coordinator.output_image();
+        self.mix_lights(&mut image);
+        let shows_raytrace = matches!(self.image_output, PrimaryImageType::Raytrace | PrimaryImageType::AmbientOcclusion);
+        let mut output = if self.denoise && shows_raytrace && image.raytrace.samples > 0 {
+            let denoised = self.denoised_image(&image.raytrace);
+            if self.outline { image.outline.image.over(&denoised) } else { denoised }
+        } else {
+            image.output(self.image_output, self.outline)
+        };
+        if self.sample_overlay {
+            output = image.raytrace.sample_count_overlay(self.settings.raytrace_settings.samples_per_pixel).over(&output);
+        }
+        let rgbas = output.output_rgba();
         let raw_image = ColorImage::from_rgba_unmultiplied([image.image_width, image.image_height], &rgbas);
         let texture_handle = egui::Context::load_texture(&ctx, "output_image", raw_image);
         ui.image(texture_handle.id(), [image.image_width as f32, image.image_height as f32]);
     }
 
+    /// Relights the raytraced image with the gain and tint set for each light group in the light mixer.
+    fn mix_lights(&self, image: &mut CompositeImage) {
+        if self.settings.raytrace_settings.integrator.renders_light_groups() && self.light_mix.iter().any(|mix| *mix != LightMix::default()) {
+            let scales: Vec<Color> = self.light_mix.iter().map(LightMix::scale).collect();
+            image.raytrace = image.raytrace.mix_light_groups(&scales);
+        }
+    }
+
+    /// Shows a slider for the gain and a colour picker for the tint of each light group, which are applied to the 
+    /// raytraced image as soon as they change, without rendering it again. The mixer is disabled for integrators which
+    /// do not render light groups.
+    pub fn show_light_mixer_window(&mut self, ui: &mut Ui) {
+        let names = self.settings.scene.light_group_names();
+        self.light_mix.resize(names.len(), LightMix::default());
+        let integrator = self.settings.raytrace_settings.integrator;
+        if !integrator.renders_light_groups() {
+            ui.label(format!("Light groups are only rendered by path tracing, not by {}.", integrator.name().to_lowercase()));
+        }
+        let light_mix = &mut self.light_mix;
+        ui.add_enabled_ui(integrator.renders_light_groups(), |ui| {
+            egui::Grid::new("light_mixer").show(ui, |ui| {
+                for (name, mix) in names.iter().zip(light_mix.iter_mut()) {
+                    ui.label(name);
+                    ui.add(egui::Slider::new(&mut mix.gain, 0.0..=10.0).text("Gain"));
+                    ui.color_edit_button_rgb(&mut mix.tint);
+                    ui.end_row();
+                }
+            });
+            if ui.button("Reset").clicked() {
+                light_mix.iter_mut().for_each(|mix| *mix = LightMix::default());
+            }
+        });
+    }
+
+    /// Returns the raytraced image denoised at the current strength, reusing the last result if the image is unchanged.
+    fn denoised_image(&mut self, raytrace: &RaytracedImage) -> Image {
+        match &self.denoised {
+            Some((input, strength, output)) if *input == raytrace.image && *strength == self.denoise_strength => output.clone(),
+            _ => {
+                let output = denoise(raytrace, self.denoise_strength);
+                self.denoised = Some((raytrace.image.clone(), self.denoise_strength, output.clone()));
+                output
+            }
+        }
+    }
+
     pub fn show_settings_window(&mut self, ctx: &Context, ui: &mut Ui) {
         //Image Settings
         if self.windows.settings {
@@ -95,7 +202,153 @@ impl Gui{
                 ui.checkbox( &mut self.renderers.raytracer, "Raytracer");
                 ui.checkbox( &mut self.renderers.rasterizer, "Rasterizer");
             });
-            ui.checkbox(&mut self.outline, "Outline");
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut self.outline, "Outline");
+                ui.checkbox(&mut self.sample_overlay, "Sample counts");
+            });
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut self.denoise, "Denoise");
+                ui.label("Strength:");
+                let denoise_strength_response =  ui.add_sized(egui::Vec2::new(30f32, 20f32), egui::TextEdit::singleline(&mut self.labels.denoise_strength));
+                if denoise_strength_response.lost_focus() && ui.input().key_pressed(egui::Key::Enter) {
+                    match self.labels.denoise_strength.parse::<f64>(){
+                        Ok(num) if num >= 0.0 => {
+                            self.denoise_strength = num;
+                        }
+                        _ => {
+                            self.labels.denoise_strength = self.denoise_strength.to_string();
+                        }
+                    }
+                }
+            });
+            ui.horizontal(|ui| {
+                ui.label("Image:");
+                let mut image_output = self.image_output;
+                let layers = &self.settings.layers;
+                let name = |option: PrimaryImageType| match option {
+                    PrimaryImageType::Layer(index) => layers.get(index).map_or("Layer", |layer| layer.name.as_str()),
+                    option => option.name()
+                };
+                egui::ComboBox::from_id_source("image_output").selected_text(name(image_output)).show_ui(ui, |ui| {
+                    for option in PrimaryImageType::ALL.iter().copied().chain((0..layers.len()).map(PrimaryImageType::Layer)) {
+                        ui.selectable_value(&mut image_output, option, name(option));
+                    }
+                });
+                if image_output != self.image_output {
+                    self.image_output = image_output;
+                    self.update_integrator();
+                }
+            });
+            ui.horizontal(|ui| {
+                ui.label("Add layer:");
+                let layer_response = ui.add_sized(egui::Vec2::new(120f32, 20f32), egui::TextEdit::singleline(&mut self.labels.layer).hint_text("name = C D L"));
+                if layer_response.lost_focus() && ui.input().key_pressed(egui::Key::Enter) {
+                    match RenderLayer::parse(&self.labels.layer, &self.settings.layers) {
+                        Ok(layer) => {
+                            self.settings.layers.push(layer);
+                            self.labels.layer.clear();
+                            self.labels.layer_error.clear();
+                            self.thread_coordinator.update_settings(self.settings.clone());
+                        }
+                        Err(error) => self.labels.layer_error = error
+                    }
+                }
+                if ui.button("Reset layers").clicked() {
+                    self.settings.layers = RenderLayer::defaults();
+                    if let PrimaryImageType::Layer(_) = self.image_output {
+                        self.image_output = PrimaryImageType::Raytrace;
+                    }
+                    self.thread_coordinator.update_settings(self.settings.clone());
+                }
+            });
+            if !self.labels.layer_error.is_empty() {
+                ui.label(&self.labels.layer_error);
+            }
+            ui.horizontal(|ui| {
+                ui.label("Render mode:");
+                let mut render_mode = self.render_mode;
+                egui::ComboBox::from_id_source("render_mode").selected_text(render_mode.name()).show_ui(ui, |ui| {
+                    for option in Integrator::ALL {
+                        ui.selectable_value(&mut render_mode, option, option.name());
+                    }
+                });
+                if render_mode != self.render_mode {
+                    self.render_mode = render_mode;
+                    self.update_integrator();
+                }
+                let mut spectral = self.settings.raytrace_settings.color_mode == ColorMode::Spectral;
+                if ui.checkbox(&mut spectral, "Spectral").changed() {
+                    self.settings.raytrace_settings.color_mode = if spectral { ColorMode::Spectral } else { ColorMode::Rgb };
+                    self.thread_coordinator.update_settings(self.settings.clone());
+                }
+            });
+            ui.horizontal(|ui| {
+                ui.label("Sampler:");
+                let mut sampler = self.settings.raytrace_settings.sampler;
+                egui::ComboBox::from_id_source("sampler").selected_text(sampler.name()).show_ui(ui, |ui| {
+                    for option in SamplerType::ALL {
+                        ui.selectable_value(&mut sampler, option, option.name());
+                    }
+                });
+                if sampler != self.settings.raytrace_settings.sampler {
+                    self.settings.raytrace_settings.sampler = sampler;
+                    self.thread_coordinator.update_settings(self.settings.clone());
+                }
+            });
+            ui.horizontal(|ui| {
+                ui.label("Filter:");
+                let mut filter_type = self.settings.raytrace_settings.filter.filter_type;
+                egui::ComboBox::from_id_source("filter").selected_text(filter_type.name()).show_ui(ui, |ui| {
+                    for option in FilterType::ALL {
+                        ui.selectable_value(&mut filter_type, option, option.name());
+                    }
+                });
+                if filter_type != self.settings.raytrace_settings.filter.filter_type {
+                    self.settings.raytrace_settings.filter.filter_type = filter_type;
+                    self.thread_coordinator.update_settings(self.settings.clone());
+                }
+                ui.label("Radius:");
+                let filter_radius_response =  ui.add_sized(egui::Vec2::new(30f32, 20f32), egui::TextEdit::singleline(&mut self.labels.filter_radius));
+                if filter_radius_response.lost_focus() && ui.input().key_pressed(egui::Key::Enter) {
+                    match self.labels.filter_radius.parse::<f64>(){
+                        Ok(num) if num > 0.0 => {
+                            self.settings.raytrace_settings.filter.radius = num;
+                            self.thread_coordinator.update_settings(self.settings.clone());
+                        }
+                        _ => {
+                            self.labels.filter_radius = self.settings.raytrace_settings.filter.radius.to_string();
+                        }
+                    }
+                }
+            });
+            ui.horizontal(|ui| {
+                ui.label("AO samples:");
+                let ao_samples_response =  ui.add_sized(egui::Vec2::new(30f32, 20f32), egui::TextEdit::singleline(&mut self.labels.ao_samples));
+                if ao_samples_response.lost_focus() && ui.input().key_pressed(egui::Key::Enter) {
+                    match self.labels.ao_samples.parse::<usize>(){
+                        Ok(num) if num > 0 => {
+                            self.settings.raytrace_settings.ao_samples = num;
+                            self.thread_coordinator.update_settings(self.settings.clone());
+                        }
+                        _ => {
+                            self.labels.ao_samples = self.settings.raytrace_settings.ao_samples.to_string();
+                        }
+                    }
+                }
+                ui.label("AO distance:");
+                let ao_distance_response =  ui.add_sized(egui::Vec2::new(30f32, 20f32), egui::TextEdit::singleline(&mut self.labels.ao_distance));
+                if ao_distance_response.lost_focus() && ui.input().key_pressed(egui::Key::Enter) {
+                    match self.labels.ao_distance.parse::<f64>(){
+                        Ok(num) if num > 0.0 => {
+                            self.settings.raytrace_settings.ao_distance = num;
+                            self.thread_coordinator.update_settings(self.settings.clone());
+                        }
+                        _ => {
+                            self.labels.ao_distance = self.settings.raytrace_settings.ao_distance.to_string();
+                        }
+                    }
+                }
+            });
             ui.horizontal(|ui| {
                 ui.label("Samples:");
                 let samples_response =  ui.add_sized(egui::Vec2::new(40f32, 20f32), egui::TextEdit::singleline(&mut self.labels.samples));
@@ -109,11 +362,50 @@ impl Gui{
                         }
                     }
                 }
+                ui.label("Noise threshold:");
+                let noise_threshold_response =  ui.add_sized(egui::Vec2::new(40f32, 20f32), egui::TextEdit::singleline(&mut self.labels.noise_threshold));
+                if noise_threshold_response.lost_focus() && ui.input().key_pressed(egui::Key::Enter) {
+                    match self.labels.noise_threshold.parse::<f64>(){
+                        Ok(num) if num >= 0.0 => {
+                            self.settings.raytrace_settings.noise_threshold = num;
+                            self.thread_coordinator.update_settings(self.settings.clone());
+                        }
+                        _ => {
+                            self.labels.noise_threshold = self.settings.raytrace_settings.noise_threshold.to_string();
+                        }
+                    }
+                }
+                ui.label("Seed:");
+                let seed_response =  ui.add_sized(egui::Vec2::new(40f32, 20f32), egui::TextEdit::singleline(&mut self.labels.seed));
+                if seed_response.lost_focus() && ui.input().key_pressed(egui::Key::Enter) {
+                    match self.labels.seed.parse::<u64>(){
+                        Ok(num) => {
+                            self.settings.raytrace_settings.seed = num;
+                            self.thread_coordinator.update_settings(self.settings.clone());
+                        }
+                        Err(_) => {
+                            self.labels.seed = self.settings.raytrace_settings.seed.to_string();
+                        }
+                    }
+                }
             });
         
         }
     }
 
+    /// Renders the raytraced image with the ambient occlusion integrator while ambient occlusion is shown, and with the
+    /// selected render mode otherwise.
+    fn update_integrator(&mut self) {
+        let integrator = match self.image_output {
+            PrimaryImageType::AmbientOcclusion => Integrator::AmbientOcclusion,
+            _ => self.render_mode
+        };
+        if integrator != self.settings.raytrace_settings.integrator {
+            self.settings.raytrace_settings.integrator = integrator;
+            self.thread_coordinator.update_settings(self.settings.clone());
+        }
+    }
+
     pub fn capture_mouse_input(&mut self, ctx: &egui::Context, response: Response) {
         if response.interact(Sense::drag()).dragged() {
             let cam = self.settings.camera;
@@ -180,7 +472,16 @@ pub struct Labels{
     width: String,
     height: String,
     samples: String,
-    camera_speed: String
+    camera_speed: String,
+    ao_samples: String,
+    ao_distance: String,
+    noise_threshold: String,
+    seed: String,
+    filter_radius: String,
+    denoise_strength: String,
+    /// A render layer being written, and the error found in the last one entered.
+    layer: String,
+    layer_error: String
 }
 
 
@@ -196,7 +497,15 @@ impl eframe::App for Gui {
                 ui.menu_button("File", |ui| {                    
                     if ui.button("Save Image").clicked() {
                         let path = "results.ppm";
-                        self.thread_coordinator.output_image().output(PrimaryImageType::Raytrace, true).save(path);
+                        let mut image = self.thread_coordinator.output_image();
+                        self.mix_lights(&mut image);
+                        image.output(PrimaryImageType::Raytrace, true).save(path);
+                        if let Err(error) = image.raytrace.save_buffers("results") {
+                            eprintln!("Error: {}", error);
+                        }
+                        if self.denoise {
+                            denoise(&image.raytrace, self.denoise_strength).save("results_denoised.ppm");
+                        }
                     }
                 });
 
@@ -204,6 +513,10 @@ impl eframe::App for Gui {
                    self.windows.settings = !self.windows.settings;
                 };
 
+                if ui.button("Light Mixer").clicked() {
+                   self.windows.light_mixer = !self.windows.light_mixer;
+                };
+
                 ui.with_layout(egui::Layout::right_to_left(), |ui| {
                     if ui.button("🗙").clicked() {
                         frame.quit();
@@ -218,6 +531,14 @@ impl eframe::App for Gui {
 
                     self.windows.settings = settings_clone;
                 }
+                if self.windows.light_mixer {
+                    let mut light_mixer_clone = self.windows.light_mixer;
+                    egui::Window::new("💡 Light Mixer").open(&mut light_mixer_clone)
+                                                           .collapsible(false)
+                                                           .show(ctx, |ui| { self.show_light_mixer_window(ui) });
+
+                    self.windows.light_mixer = light_mixer_clone;
+                }
             });
         });
             
@@ -232,7 +553,8 @@ impl eframe::App for Gui {
         
         let completed_samples = self.thread_coordinator.get_progress() as f32;
         let requested_samples = self.settings.raytrace_settings.samples_per_pixel as f32;
-        let progress = completed_samples / requested_samples;
+        //Adaptive sampling may finish before every pass has been taken
+        let progress = if self.thread_coordinator.is_done() { 1.0 } else { completed_samples / requested_samples };
         let bottom_frame = egui::Frame{inner_margin: Margin::symmetric(5.0, 5.0), stroke: Stroke::new(1.0, Color32::GRAY), fill: Color32::WHITE, ..Default::default()};
         egui::TopBottomPanel::new(TopBottomSide::Bottom, "bottom_panel").frame(bottom_frame).show(ctx, |ui| {
             ui.add(CustomProgressBar::new(progress).desired_width(200.0).text(completed_samples.to_string() + "/" + &requested_samples.to_string() + " samples").animate(true));
@@ -4,6 +4,7 @@ use std::ops;
 use impl_ops::*;
 
 use crate::{nalgebra::Vector3, util::bound_f64};
+use crate::aov::{AovSample, AovType};
 
 
 pub type Color = Vector3<f64>;
@@ -46,10 +47,10 @@ impl Pixel{
         [ir, ig, ib]
     }
 
-    pub fn write_color<T: std::io::Write>(&self, writer: &mut T)
+    pub fn write_color<T: std::io::Write>(&self, writer: &mut T) -> std::io::Result<()>
     {
         let [ir, ig, ib] = self.to_rgb();
-        writeln!(writer, "{} {} {}", ir, ig, ib).unwrap();
+        writeln!(writer, "{} {} {}", ir, ig, ib)
     }
 
     /// Overlay another pixel with this pixel using alpha blending
@@ -116,41 +117,193 @@ impl Image {
                                                 .open(path)
                                                 .unwrap();
 
-        write!(file, "P3\n{} {} \n255\n", self.image_width, self.image_height).unwrap();
+        self.write_ppm(&mut file).unwrap();
+    }
+
+    /// Writes the image to the given writer in the PPM format
+    pub fn write_ppm<T: std::io::Write>(&self, writer: &mut T) -> std::io::Result<()> {
+        write!(writer, "P3\n{} {} \n255\n", self.image_width, self.image_height)?;
         for pixel in &self.pixels {
-            pixel.write_color(&mut file);
+            pixel.write_color(writer)?;
+        }
+        Ok(())
+    }
+}
+
+/// Saves a buffer of colours to a PFM file, which keeps their full range. PFM files list their rows from the bottom
+/// of the image up.
+pub fn save_pfm(path: &str, image_width: usize, image_height: usize, values: &[Color]) -> Result<(), String> {
+    let mut file = OpenOptions::new().create(true)
+                                            .write(true)
+                                            .truncate(true)
+                                            .open(path)
+                                            .map_err(|error| format!("Could not create '{}': {}", path, error))?;
+
+    write_pfm(&mut file, image_width, image_height, values).map_err(|error| format!("Could not write '{}': {}", path, error))
+}
+
+/// Writes a buffer of colours to the given writer in the PFM format.
+pub fn write_pfm<T: std::io::Write>(writer: &mut T, image_width: usize, image_height: usize, values: &[Color]) -> std::io::Result<()> {
+    write!(writer, "PF\n{} {}\n-1.0\n", image_width, image_height)?;
+    let mut bytes = Vec::<u8>::with_capacity(values.len() * 12);
+    for row in values.chunks(image_width).rev() {
+        for value in row {
+            for channel in value.iter() {
+                bytes.extend_from_slice(&(*channel as f32).to_le_bytes());
+            }
         }
     }
+    writer.write_all(&bytes)
+}
+
+/// Divides a filtered sum by the sum of the filter weights which went into it. Filters with negative lobes can leave a
+/// pixel with a negative total weight, which still normalises its sum, so only a pixel without any weight is black.
+fn normalise(value: &Color, weight: f64) -> Color {
+    if weight != 0.0 { value / weight } else { Color::zeros() }
+}
+
+/// A set of buffers, each holding a value for every pixel of an image, which are identified by name and kept in the
+/// order they were added in.
+#[derive (Clone, PartialEq)]
+pub struct NamedBuffers {
+    pixel_count: usize,
+    buffers: Vec<(String, Vec<Color>)>
+}
+
+impl NamedBuffers {
+    pub fn new(pixel_count: usize) -> NamedBuffers {
+        NamedBuffers { pixel_count, buffers: Vec::new() }
+    }
+
+    pub fn len(&self) -> usize {
+        self.buffers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buffers.is_empty()
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.buffers.iter().map(|(name, _)| name.as_str())
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &[Color]> {
+        self.buffers.iter().map(|(_, values)| values.as_slice())
+    }
+
+    /// Returns the index of the buffer with the given name, adding an empty buffer with that name if there is none.
+    pub fn add(&mut self, name: &str) -> usize {
+        self.index(name).unwrap_or_else(|| {
+            self.buffers.push((String::from(name), vec![Color::zeros(); self.pixel_count]));
+            self.buffers.len() - 1
+        })
+    }
+
+    pub fn index(&self, name: &str) -> Option<usize> {
+        self.buffers.iter().position(|(existing, _)| existing == name)
+    }
+
+    pub fn get(&self, index: usize) -> Option<&[Color]> {
+        self.buffers.get(index).map(|(_, values)| values.as_slice())
+    }
+
+    /// Adds a value to the pixel with the given index in each buffer, in order, multiplied by the weight.
+    pub fn add_weighted(&mut self, pixel_index: usize, values: &[Color], weight: f64) {
+        for ((_, buffer), value) in self.buffers.iter_mut().zip(values.iter()) {
+            buffer[pixel_index] += value * weight;
+        }
+    }
+
+    /// Adds a value to the pixel with the given index in the buffer with the given name, which is added if need be.
+    pub fn add_named(&mut self, pixel_index: usize, name: &str, value: &Color) {
+        let index = self.add(name);
+        self.buffers[index].1[pixel_index] += value;
+    }
+
+    /// Adds the buffers of another set, matched by name, to these. Each pair holds the index of a pixel in these
+    /// buffers and of the pixel added to it from the other buffers. Buffers missing from this set are added.
+    pub fn add_at(&mut self, other: &NamedBuffers, pixels: &[(usize, usize)]) {
+        for (name, values) in other.buffers.iter() {
+            let index = self.add(name);
+            let buffer = &mut self.buffers[index].1;
+            for (pixel_index, other_index) in pixels.iter().copied() {
+                buffer[pixel_index] += values[other_index];
+            }
+        }
+    }
+
+    /// Returns the buffer with the given index, with the value of each pixel divided by the divisor given for it.
+    /// Pixels with a divisor of zero are black.
+    pub fn normalised(&self, index: usize, divisors: &[f64]) -> Option<Vec<Color>> {
+        let values = self.get(index)?;
+        Some(values.iter().zip(divisors.iter()).map(|(value, divisor)| normalise(value, *divisor)).collect())
+    }
+
+    /// Saves every buffer, normalised by the given divisors, to a PFM file named after the given path, followed by the
+    /// prefix and the name of the buffer, in lower case with anything other than letters and digits replaced. Returns
+    /// the paths of the files saved.
+    pub fn save(&self, path_stem: &str, prefix: &str, image_width: usize, image_height: usize, divisors: &[f64]) -> Result<Vec<String>, String> {
+        let mut paths = Vec::new();
+        for (index, name) in self.names().enumerate() {
+            let name: String = name.chars().map(|c| if c.is_alphanumeric() { c.to_ascii_lowercase() } else { '_' }).collect();
+            let path = format!("{}_{}{}.pfm", path_stem, prefix, name);
+            save_pfm(&path, image_width, image_height, &self.normalised(index, divisors).unwrap())?;
+            paths.push(path);
+        }
+        Ok(paths)
+    }
 }
 
 #[derive (Clone, PartialEq)]
 /// An image produced via raytracing. Wraps the Image struct, but also contains some additional
 /// information required to compose multiple raytraced images together.
+/// 
+/// Samples may be spread over several pixels by a reconstruction filter, so the colour of each pixel holds a weighted
+/// sum of samples, and the weights are summed alongside it. Light splatted onto the image is kept apart, since it is
+/// not filtered.
+/// 
+/// Pixels may receive different numbers of samples when sampling adaptively, so the number of samples taken in each 
+/// pixel and the sums of their luminances and squared luminances are kept too. These give an estimate of the error 
+/// remaining in each pixel.
+/// 
+/// The AOVs of the first surface seen through each pixel are summed in buffers named after them, and averaged over the
+/// samples taken in the pixel. Each buffer is only allocated once the integrator first records its AOV.
+/// 
+/// Render layers hold the light arriving along particular kinds of path, and light groups the light emitted by each 
+/// group of lights. They are filtered like the image itself, and are kept in the order they were defined in.
 pub struct RaytracedImage {
     pub image: Image,
-    pub samples: usize
+    /// The number of passes over the image which have been added together.
+    pub samples: usize,
+    pub weights: Vec<f64>,
+    pub splats: Vec<Color>,
+    pub pixel_samples: Vec<usize>,
+    pub sums: Vec<f64>,
+    pub sum_squares: Vec<f64>,
+    pub aovs: NamedBuffers,
+    pub layers: NamedBuffers,
+    pub light_groups: NamedBuffers
 }
 
 impl RaytracedImage {
     pub fn new(image_width: usize, image_height: usize) -> RaytracedImage {
         let image = Image::new(image_width, image_height);
         let samples = 0;
-        RaytracedImage{ image, samples }
+        let pixel_count = image_width * image_height;
+        RaytracedImage{ image, samples, weights: vec![0.0; pixel_count], splats: vec![Color::zeros(); pixel_count], pixel_samples: vec![0; pixel_count],
+                        sums: vec![0.0; pixel_count], sum_squares: vec![0.0; pixel_count], aovs: NamedBuffers::new(pixel_count), layers: NamedBuffers::new(pixel_count), light_groups: NamedBuffers::new(pixel_count) }
     }
 
     pub fn clear(&mut self) {
-        self.image = Image::new(self.image.image_width, self.image.image_height);
-        self.samples = 0;
+        *self = RaytracedImage::new(self.image.image_width, self.image.image_height);
     }
 
+    /// Normalises each pixel by the filter weights of the samples it received, and adds the light splatted onto it,
+    /// averaged over the number of passes.
     pub fn to_image(&self) -> Image{
-        let samples = self.samples;
         let mut image = self.image.clone();
-        let scale = 1.0 / (samples as f64);
-        for pixel in &mut image.pixels {
-            pixel.color[0] *= scale;
-            pixel.color[1] *= scale;
-            pixel.color[2] *= scale;
+        for (index, pixel) in image.pixels.iter_mut().enumerate() {
+            pixel.color = normalise(&pixel.color, self.weights[index]) + self.splats[index] / self.samples.max(1) as f64;
         }
         image
     }
@@ -158,19 +311,232 @@ impl RaytracedImage {
     pub fn output_rgba(&self) -> Vec<u8> {
         self.to_image().output_rgba()
     }
+
+    /// Adds a sample to the pixel with the given index, without spreading it over any other pixels.
+    pub fn add_sample(&mut self, pixel_index: usize, color: Color) {
+        self.record_sample(pixel_index, color);
+        self.add_weighted(pixel_index, color, 1.0);
+    }
+
+    /// Counts a sample towards the error estimate of the pixel with the given index, without adding its colour.
+    pub fn record_sample(&mut self, pixel_index: usize, color: Color) {
+        self.pixel_samples[pixel_index] += 1;
+        self.sums[pixel_index] += luminance(&color);
+        self.sum_squares[pixel_index] += luminance(&color).powi(2);
+    }
+
+    /// Adds a sample to the pixel with the given index, weighted by the reconstruction filter.
+    pub fn add_weighted(&mut self, pixel_index: usize, color: Color, weight: f64) {
+        self.image.pixels[pixel_index].color += color * weight;
+        self.image.pixels[pixel_index].alpha = 1.0;
+        self.weights[pixel_index] += weight;
+    }
+
+    /// Adds an empty render layer with the given name.
+    pub fn add_layer(&mut self, name: &str) {
+        self.layers.add(name);
+    }
+
+    /// Adds the light a sample carried into each render layer to the pixel with the given index, weighted by the 
+    /// reconstruction filter.
+    pub fn add_weighted_layers(&mut self, pixel_index: usize, values: &[Color], weight: f64) {
+        self.layers.add_weighted(pixel_index, values, weight);
+    }
+
+    /// Returns the render layer with the given index, normalised by the filter weights in each pixel.
+    pub fn layer(&self, index: usize) -> Option<Vec<Color>> {
+        self.layers.normalised(index, &self.weights)
+    }
+
+    /// Returns an image showing the render layer with the given index, which is black if there is no such layer.
+    pub fn layer_image(&self, index: usize) -> Image {
+        let mut image = Image::new(self.image.image_width, self.image.image_height);
+        if let Some(values) = self.layer(index) {
+            for (pixel, color) in image.pixels.iter_mut().zip(values) {
+                *pixel = Pixel::new(color, 1.0);
+            }
+        }
+        image
+    }
+
+    /// Saves every render layer to a PFM file, named after the given path with the name of the layer added to the end.
+    pub fn save_layers(&self, path_stem: &str) -> Result<Vec<String>, String> {
+        self.layers.save(path_stem, "layer_", self.image.image_width, self.image.image_height, &self.weights)
+    }
+
+    /// Adds an empty light group with the given name.
+    pub fn add_light_group(&mut self, name: &str) {
+        self.light_groups.add(name);
+    }
+
+    /// Adds the light a sample received from each light group to the pixel with the given index, weighted by the 
+    /// reconstruction filter.
+    pub fn add_weighted_light_groups(&mut self, pixel_index: usize, values: &[Color], weight: f64) {
+        self.light_groups.add_weighted(pixel_index, values, weight);
+    }
+
+    /// Returns the light group with the given index, normalised by the filter weights in each pixel.
+    pub fn light_group(&self, index: usize) -> Option<Vec<Color>> {
+        self.light_groups.normalised(index, &self.weights)
+    }
+
+    /// Saves every light group to a PFM file, named after the given path with the name of the group added to the end.
+    pub fn save_light_groups(&self, path_stem: &str) -> Result<Vec<String>, String> {
+        self.light_groups.save(path_stem, "light_", self.image.image_width, self.image.image_height, &self.weights)
+    }
+
+    /// Returns the image relit by scaling the light from each light group by the colour given for it. Groups without a
+    /// scale are left unchanged, as is any light which was not sorted into a group. The render layers are not relit.
+    pub fn mix_light_groups(&self, scales: &[Color]) -> RaytracedImage {
+        let mut output = self.clone();
+        for (light_group, scale) in self.light_groups.values().zip(scales.iter()) {
+            let change = scale - Color::new(1.0, 1.0, 1.0);
+            for (pixel, value) in output.image.pixels.iter_mut().zip(light_group.iter()) {
+                pixel.color += change.component_mul(value);
+            }
+        }
+        output
+    }
+
+    /// Adds the pixels of another image, whose top left pixel lies at the given column and row of this one, to the 
+    /// pixels of this image they overlap. The number of passes is left unchanged.
+    pub fn add_at(&mut self, other: &RaytracedImage, offset: (isize, isize)) {
+        let (width, height) = (self.image.image_width as isize, self.image.image_height as isize);
+        let mut pixels = Vec::with_capacity(other.pixel_samples.len());
+        for y in 0..other.image.image_height {
+            for x in 0..other.image.image_width {
+                let (i, j) = (x as isize + offset.0, y as isize + offset.1);
+                if i >= 0 && j >= 0 && i < width && j < height {
+                    pixels.push(((j * width + i) as usize, y * other.image.image_width + x));
+                }
+            }
+        }
+
+        for (index, other_index) in pixels.iter().copied() {
+            let pixel = &mut self.image.pixels[index];
+            *pixel = Pixel::new(pixel.color + other.image.pixels[other_index].color, 1.0);
+            self.weights[index] += other.weights[other_index];
+            self.splats[index] += other.splats[other_index];
+            self.pixel_samples[index] += other.pixel_samples[other_index];
+            self.sums[index] += other.sums[other_index];
+            self.sum_squares[index] += other.sum_squares[other_index];
+        }
+        self.layers.add_at(&other.layers, &pixels);
+        self.light_groups.add_at(&other.light_groups, &pixels);
+        self.aovs.add_at(&other.aovs, &pixels);
+    }
+
+    /// Adds the AOVs of a sample to the pixel with the given index.
+    pub fn add_aovs(&mut self, pixel_index: usize, aovs: &AovSample) {
+        for (name, value) in aovs.iter() {
+            self.aovs.add_named(pixel_index, name, value);
+        }
+    }
+
+    /// Returns the values of the AOV averaged over the samples in each pixel, or `None` if it has not been rendered.
+    pub fn aov(&self, aov_type: AovType) -> Option<Vec<Color>> {
+        let samples: Vec<f64> = self.pixel_samples.iter().map(|samples| *samples as f64).collect();
+        self.aovs.normalised(self.aovs.index(aov_type.name())?, &samples)
+    }
+
+    /// Returns an image showing the AOV, which is black if it has not been rendered.
+    pub fn aov_image(&self, aov_type: AovType) -> Image {
+        let mut image = Image::new(self.image.image_width, self.image.image_height);
+        if let Some(values) = self.aov(aov_type) {
+            for (pixel, color) in image.pixels.iter_mut().zip(aov_type.to_display(&values)) {
+                *pixel = Pixel::new(color, 1.0);
+            }
+        }
+        image
+    }
+
+    /// Saves every AOV which has been rendered to a PFM file, named after the given path with the name of the AOV 
+    /// added to the end.
+    pub fn save_aovs(&self, path_stem: &str) -> Result<Vec<String>, String> {
+        let samples: Vec<f64> = self.pixel_samples.iter().map(|samples| *samples as f64).collect();
+        self.aovs.save(path_stem, "", self.image.image_width, self.image.image_height, &samples)
+    }
+
+    /// Saves the AOVs, render layers and light groups, as `save_aovs`, `save_layers` and `save_light_groups` do.
+    /// Returns the paths of the files saved.
+    pub fn save_buffers(&self, path_stem: &str) -> Result<Vec<String>, String> {
+        let mut paths = self.save_aovs(path_stem)?;
+        paths.extend(self.save_layers(path_stem)?);
+        paths.extend(self.save_light_groups(path_stem)?);
+        Ok(paths)
+    }
+
+    /// Adds a contribution to the pixel containing the given film position, which is expressed in the coordinates 
+    /// passed to `Camera::get_ray`. Contributions falling outside of the image are discarded. Splats do not count as
+    /// samples of the pixel they land on.
+    pub fn splat(&mut self, film_position: (f64, f64), color: Color) {
+        let (s, t) = film_position;
+        let i = (s * self.image.image_width as f64).floor();
+        let j = (t * self.image.image_height as f64).floor();
+        if i < 0.0 || j < 0.0 || i >= self.image.image_width as f64 || j >= self.image.image_height as f64 {
+            return;
+        }
+        let pixel_index = (self.image.image_height - 1 - j as usize) * self.image.image_width + i as usize;
+        self.splats[pixel_index] += color;
+        self.image.pixels[pixel_index].alpha = 1.0;
+    }
+
+    /// Returns an estimate of the standard error of the pixel with the given index, after gamma correction. The error
+    /// is infinite until the pixel has been sampled `MIN_ADAPTIVE_SAMPLES` times, since too few samples give an 
+    /// unreliable estimate.
+    pub fn pixel_error(&self, pixel_index: usize) -> f64 {
+        let n = self.pixel_samples[pixel_index];
+        if n < MIN_ADAPTIVE_SAMPLES {
+            return f64::INFINITY;
+        }
+        let n = n as f64;
+        let mean = self.sums[pixel_index] / n;
+        let variance = ((self.sum_squares[pixel_index] / n - mean * mean) * n / (n - 1.0)).max(0.0);
+        let standard_error = (variance / n).sqrt();
+        if standard_error == 0.0 {
+            return 0.0;
+        }
+
+        //The displayed value is the square root of the mean, whose error is found by propagating the standard error
+        standard_error / (2.0 * mean.max(0.0).sqrt())
+    }
+
+    /// Returns whether each pixel still needs sampling, because its estimated error is above the threshold.
+    pub fn active_pixels(&self, noise_threshold: f64) -> Vec<bool> {
+        (0..self.pixel_samples.len()).map(|index| self.pixel_error(index) > noise_threshold).collect()
+    }
+
+    /// Returns true if the estimated error of every pixel is below the threshold.
+    pub fn is_converged(&self, noise_threshold: f64) -> bool {
+        (0..self.pixel_samples.len()).all(|index| self.pixel_error(index) <= noise_threshold)
+    }
+
+    /// Returns a translucent image showing the number of samples taken in each pixel, running from blue for none to 
+    /// red for `max_samples`, which can be laid over the rendered image.
+    pub fn sample_count_overlay(&self, max_samples: usize) -> Image {
+        let mut image = Image::new(self.image.image_width, self.image.image_height);
+        for (pixel, pixel_samples) in image.pixels.iter_mut().zip(self.pixel_samples.iter()) {
+            let t = (*pixel_samples as f64 / max_samples.max(1) as f64).min(1.0);
+            *pixel = Pixel::new(Color::new(t, 1.0 - (2.0 * t - 1.0).abs(), 1.0 - t), 0.6);
+        }
+        image
+    }
+}
+
+/// The number of samples a pixel must receive before its error is estimated, and it may stop being sampled.
+pub const MIN_ADAPTIVE_SAMPLES: usize = 16;
+
+/// Returns the luminance of a linear RGB color.
+pub fn luminance(color: &Color) -> f64 {
+    0.2126 * color[0] + 0.7152 * color[1] + 0.0722 * color[2]
 }
 
 impl<'a> Add for &'a RaytracedImage {
     type Output = RaytracedImage;
 
     fn add(self, other: &'a RaytracedImage) -> RaytracedImage {
-        if self.image.image_height != other.image.image_height || self.image.image_width != other.image.image_width {
-            panic!("Image dimensions do not match");
-        }
-        
-        let mut output = RaytracedImage::new(self.image.image_width, self.image.image_height);
-        output.samples = self.samples + other.samples;
-        output.image.pixels = self.image.pixels.iter().zip(other.image.pixels.iter()).map(|(a,b)| Pixel::new(a.color + b.color, 1.0)).collect();
+        let mut output = self.clone();
+        output += other;
         output
     }
 }
@@ -182,7 +548,7 @@ impl AddAssign<&RaytracedImage> for RaytracedImage {
         }
 
         self.samples += other.samples;
-        self.image.pixels = self.image.pixels.iter().zip(other.image.pixels.iter()).map(|(a,b)| Pixel::new(a.color + b.color, 1.0)).collect();
+        self.add_at(other, (0, 0));
     }
 }
 
@@ -265,10 +631,34 @@ pub struct CompositeImage {
     pub image_height: usize
 }
 
+/// The images which can be shown beneath the outline.
 #[derive (Copy, Clone, PartialEq)]
 pub enum PrimaryImageType {
     Raster,
-    Raytrace
+    Raytrace,
+    /// The raytraced image, rendered with the ambient occlusion integrator rather than the selected render mode.
+    AmbientOcclusion,
+    /// One of the AOVs rendered alongside the raytraced image.
+    Aov(AovType),
+    /// The render layer with the given index.
+    Layer(usize)
+}
+
+impl PrimaryImageType {
+    pub const ALL: [PrimaryImageType; 9] = [PrimaryImageType::Raytrace, PrimaryImageType::AmbientOcclusion, PrimaryImageType::Raster,
+                                            PrimaryImageType::Aov(AovType::Albedo), PrimaryImageType::Aov(AovType::Normal), 
+                                            PrimaryImageType::Aov(AovType::Depth), PrimaryImageType::Aov(AovType::Position),
+                                            PrimaryImageType::Aov(AovType::ObjectId), PrimaryImageType::Aov(AovType::MaterialId)];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            PrimaryImageType::Raster => "Raster",
+            PrimaryImageType::Raytrace => "Raytrace",
+            PrimaryImageType::AmbientOcclusion => "Ambient occlusion",
+            PrimaryImageType::Aov(aov_type) => aov_type.name(),
+            PrimaryImageType::Layer(_) => "Layer"
+        }
+    }
 }
 
 #[derive (Clone, PartialEq)]
@@ -296,7 +686,7 @@ impl CompositeImage {
         let mut image: Image;
         match primary_image {
             PrimaryImageType::Raster => image = self.raster.to_image(),
-            PrimaryImageType::Raytrace => {
+            PrimaryImageType::Raytrace | PrimaryImageType::AmbientOcclusion => {
                 if self.raytrace.samples == 0 {
                     image = self.outline.to_image();
                  }
@@ -304,6 +694,8 @@ impl CompositeImage {
                      image = self.raytrace.to_image();
                  }
             }
+            PrimaryImageType::Aov(aov_type) => image = self.raytrace.aov_image(aov_type),
+            PrimaryImageType::Layer(index) => image = self.raytrace.layer_image(index)
         }
         
         if outlining_on {
@@ -364,3 +756,101 @@ impl_op_ex!(+= |lhs: &mut CompositeImage, rhs: &CompositeImage| {
 
 
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_adaptive_sampling(){
+        let mut image = RaytracedImage::new(2, 1);
+        for n in 0..MIN_ADAPTIVE_SAMPLES {
+            image.add_sample(0, Color::new(0.5, 0.5, 0.5));
+            let noise = if n % 2 == 0 { 1.0 } else { 0.0 };
+            image.add_sample(1, Color::new(noise, noise, noise));
+        }
+        image.samples = MIN_ADAPTIVE_SAMPLES;
+
+        //Case 1: Pixels are averaged over their own samples
+        image.add_sample(0, Color::new(0.5, 0.5, 0.5));
+        let output = image.to_image();
+        assert!((output.pixels[0].color - Color::new(0.5, 0.5, 0.5)).norm() < 1e-12);
+        assert!((output.pixels[1].color - Color::new(0.5, 0.5, 0.5)).norm() < 1e-12);
+
+        //Case 2: A constant pixel has converged, while a noisy one has not
+        assert_eq!(image.pixel_error(0), 0.0);
+        assert!(image.pixel_error(1) > 0.05);
+        assert_eq!(image.active_pixels(0.01), vec![false, true]);
+        assert!(!image.is_converged(0.01));
+        assert!(image.is_converged(1.0));
+
+        //Case 3: Pixels with too few samples are never converged
+        let mut image = RaytracedImage::new(1, 1);
+        image.add_sample(0, Color::new(0.5, 0.5, 0.5));
+        assert_eq!(image.pixel_error(0), f64::INFINITY);
+
+        //Case 4: Adding images adds their sample counts
+        let sum = &image + &image;
+        assert_eq!(sum.pixel_samples, vec![2]);
+        assert!((sum.sum_squares[0] - 0.5).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_aovs(){
+        let mut image = RaytracedImage::new(2, 1);
+        assert!(image.aov(AovType::Albedo).is_none());
+
+        //Case 1: AOVs are averaged over the samples in each pixel
+        for albedo in [0.2, 0.4] {
+            image.add_sample(0, Color::zeros());
+            image.add_aovs(0, &AovType::ALL.map(|aov_type| (aov_type.name(), Color::repeat(albedo))));
+        }
+        let albedos = image.aov(AovType::Albedo).unwrap();
+        assert!((albedos[0] - Color::repeat(0.3)).norm() < 1e-12);
+        assert_eq!(albedos[1], Color::zeros());
+
+        //Case 2: Adding images adds their AOVs
+        let sum = &image + &image;
+        assert!((sum.aov(AovType::Depth).unwrap()[0] - Color::repeat(0.3)).norm() < 1e-12);
+        assert!((sum.aovs.get(sum.aovs.index("Depth").unwrap()).unwrap()[0] - Color::repeat(1.2)).norm() < 1e-12);
+    }
+
+    #[test]
+    fn test_light_groups(){
+        let mut image = RaytracedImage::new(2, 1);
+        image.add_light_group("Key");
+        image.add_light_group("Background");
+        for index in 0..2 {
+            let (key, background) = (Color::new(0.4, 0.2, 0.2), Color::repeat(0.1 * index as f64));
+            image.add_weighted(index, key + background, 2.0);
+            image.add_weighted_light_groups(index, &[key, background], 2.0);
+        }
+        image.samples = 1;
+
+        //Case 1: Light groups are normalised by the filter weights
+        assert!((image.light_group(0).unwrap()[1] - Color::new(0.4, 0.2, 0.2)).norm() < 1e-12);
+        assert!(image.light_group(2).is_none());
+
+        //Case 2: Mixing with unit scales leaves the image unchanged
+        assert!(image.mix_light_groups(&[Color::repeat(1.0), Color::repeat(1.0)]) == image);
+
+        //Case 3: Each group is scaled independently
+        let mixed = image.mix_light_groups(&[Color::new(0.0, 1.0, 2.0), Color::repeat(3.0)]).to_image();
+        assert!((mixed.pixels[0].color - Color::new(0.0, 0.2, 0.4)).norm() < 1e-12);
+        assert!((mixed.pixels[1].color - Color::new(0.3, 0.5, 0.7)).norm() < 1e-12);
+
+        //Case 4: Adding images adds their light groups
+        let sum = &image + &image;
+        assert!((sum.light_group(1).unwrap()[1] - Color::repeat(0.1)).norm() < 1e-12);
+
+        //Case 5: Light groups are matched by name when images are added, and those missing are added
+        let mut other = RaytracedImage::new(2, 1);
+        other.add_light_group("Background");
+        other.add_light_group("Fill");
+        other.add_weighted_light_groups(0, &[Color::repeat(1.0), Color::repeat(2.0)], 1.0);
+        let sum = &image + &other;
+        assert_eq!(sum.light_groups.names().collect::<Vec<_>>(), vec!["Key", "Background", "Fill"]);
+        assert_eq!(sum.light_groups.get(1).unwrap()[0], image.light_groups.get(1).unwrap()[0] + Color::repeat(1.0));
+        assert_eq!(sum.light_groups.get(2).unwrap()[0], Color::repeat(2.0));
+    }
+}
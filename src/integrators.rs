@@ -0,0 +1,52 @@
+pub mod ambient_occlusion;
+pub mod bdpt;
+pub mod debug;
+pub mod photon_mapping;
+
+use debug::DebugMode;
+
+/// The algorithms available for estimating the light arriving at each pixel.
+#[derive (Copy, Clone, Debug, PartialEq)]
+pub enum Integrator {
+    /// Traces paths from the camera, sampling the lights directly at each bounce. This is the only integrator which 
+    /// renders participating media; the others pass straight through the boundaries of media and ignore them.
+    PathTracing,
+    /// Traces subpaths from both the camera and the lights, and connects every pair of their vertices.
+    Bidirectional,
+    /// Gathers photons shot from the lights at the first diffuse surface seen by the camera, using a fixed radius.
+    PhotonMapping,
+    /// Photon mapping with a new set of photons on each pass, gathered within a radius that shrinks from pass to pass.
+    ProgressivePhotonMapping,
+    /// Renders the fraction of the hemisphere above the first surface seen by the camera which is not blocked by
+    /// nearby geometry, regardless of the materials in the scene.
+    AmbientOcclusion,
+    /// Renders a property of the first surface seen by the camera instead of the light arriving from it.
+    Debug(DebugMode)
+}
+
+impl Integrator {
+    /// The integrators offered as render modes for the raytraced image. Ambient occlusion is selected through its own
+    /// primary image type instead.
+    pub const ALL: [Integrator; 10] = [Integrator::PathTracing, Integrator::Bidirectional, Integrator::PhotonMapping, 
+                                       Integrator::ProgressivePhotonMapping, Integrator::Debug(DebugMode::ShadingNormal), 
+                                       Integrator::Debug(DebugMode::GeometricNormal), Integrator::Debug(DebugMode::Depth), 
+                                       Integrator::Debug(DebugMode::PrimitiveId), Integrator::Debug(DebugMode::Albedo), 
+                                       Integrator::Debug(DebugMode::BvhHeatmap)];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Integrator::PathTracing => "Path tracing",
+            Integrator::Bidirectional => "Bidirectional path tracing",
+            Integrator::PhotonMapping => "Photon mapping",
+            Integrator::ProgressivePhotonMapping => "Progressive photon mapping",
+            Integrator::AmbientOcclusion => "Ambient occlusion",
+            Integrator::Debug(mode) => mode.name()
+        }
+    }
+
+    /// Returns true if the integrator sorts the light it finds into render layers and light groups. Only the path 
+    /// tracer does, so the others leave them black.
+    pub fn renders_light_groups(&self) -> bool {
+        *self == Integrator::PathTracing
+    }
+}
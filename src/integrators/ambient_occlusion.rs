@@ -0,0 +1,113 @@
+use crate::aov::FirstHit;
+use crate::image::Color;
+use crate::material::ShadingFrame;
+use crate::raytracing::{Hit, Ray};
+use crate::sampler::{cosine_hemisphere, Sampler};
+use crate::threads::RayTraceSettings;
+
+/// Returns the fraction of the hemisphere above the first surface hit by the ray which is not blocked by other
+/// surfaces within `settings.ao_distance`, weighted by the cosine of its angle to the normal. The fraction is
+/// estimated from `settings.ao_samples` occlusion rays, whose directions are drawn from `sampler`. Materials are 
+/// ignored, apart from surfaces which only mark the boundary of a medium, which are invisible. Rays which miss every 
+/// surface are white. If `first_hit` is given, the surface is recorded in it.
+pub fn li<T, S>(r: &Ray, world: &T, settings: &RayTraceSettings, sampler: &mut S, first_hit: Option<&mut FirstHit>) -> Color where T: Hit, S: Sampler {
+    let mut ray = *r;
+    let rec = loop {
+        match world.hit(&ray, 0.001, f64::INFINITY) {
+            Some((rec, mat)) if mat.is_interface() => ray = Ray::new_at_time(rec.p, ray.direction(), ray.time()),
+            Some((rec, mat)) => {
+                if let Some(first_hit) = first_hit {
+                    first_hit.record(&rec, mat);
+                }
+                break rec;
+            }
+            None => return Color::new(1.0, 1.0, 1.0)
+        }
+    };
+
+    let samples = settings.ao_samples.max(1);
+    let frame = ShadingFrame::from_normal(&rec.normal);
+    let unoccluded = (0..samples).filter(|_| {
+        let wi = frame.to_world(&cosine_hemisphere(sampler.get_2d()));
+        !is_occluded(&Ray::new_at_time(rec.p, wi, rec.time), world, settings.ao_distance)
+    }).count();
+
+    Color::new(1.0, 1.0, 1.0) * (unoccluded as f64 / samples as f64)
+}
+
+/// Returns true if the ray hits a surface, other than the boundary of a medium, within the given distance.
+fn is_occluded<T>(r: &Ray, world: &T, max_distance: f64) -> bool where T: Hit {
+    let mut ray = *r;
+    let mut remaining = max_distance;
+    loop {
+        match world.hit(&ray, 0.001, f64::INFINITY) {
+            Some((rec, mat)) => {
+                let distance = (rec.p - ray.origin()).norm();
+                if distance >= remaining {
+                    return false;
+                }
+                if !mat.is_interface() {
+                    return true;
+                }
+                remaining -= distance;
+                ray = Ray::new_at_time(rec.p, ray.direction(), ray.time());
+            }
+            None => return false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::integrators::Integrator;
+    use crate::material::Material;
+    use crate::nalgebra::{Point3, Vector3};
+    use crate::primitives::{GeometricPrimitive, GeometricPrimitives, Primitive, Primitives};
+    use crate::raytracing::MisHeuristic;
+    use crate::sampler::SamplerType;
+    use crate::film::Filter;
+    use crate::sampler::independent::IndependentSampler;
+    use crate::spectra::ColorMode;
+
+    fn settings(ao_samples: usize, ao_distance: f64) -> RayTraceSettings {
+        RayTraceSettings { max_depth: 1, rr_start_depth: 0, integrator: Integrator::AmbientOcclusion, ao_samples, ao_distance, ..RayTraceSettings::for_test() }
+    }
+
+    #[test]
+    fn test_li(){
+        let mat = Material::new_lambertian(Color::new(0.5, 0.5, 0.5));
+        let mut geometric_primitives = GeometricPrimitives::new();
+        geometric_primitives.add(GeometricPrimitive::new_sphere(Point3::<f64>::new(0.0, 0.0, 0.0), 5.0, mat));
+        let mut world = Primitives::new();
+        world.add(Primitive::new_bvh(geometric_primitives.to_bvh()));
+        let r = Ray::new(Point3::<f64>::new(0.0, 0.0, 0.0), Vector3::<f64>::new(0.0, 0.0, -1.0));
+        let mut sampler = IndependentSampler::new(0);
+
+        //Case 1: Every direction from the inside of a sphere is blocked by the far side of the sphere
+        assert_eq!(li(&r, &world, &settings(16, 20.0), &mut sampler, None), Color::new(0.0, 0.0, 0.0));
+
+        //Case 2: The far side of the sphere is out of reach
+        assert_eq!(li(&r, &world, &settings(16, 1e-3), &mut sampler, None), Color::new(1.0, 1.0, 1.0));
+
+        //Case 3: Rays which miss are unoccluded
+        let miss = Ray::new(Point3::<f64>::new(0.0, 10.0, 0.0), Vector3::<f64>::new(0.0, 1.0, 0.0));
+        assert_eq!(li(&miss, &world, &settings(16, 20.0), &mut sampler, None), Color::new(1.0, 1.0, 1.0));
+    }
+
+    #[test]
+    fn test_li_between_planes(){
+        //Two large spheres approximate a floor and a ceiling one unit above it. The cosine-weighted fraction of
+        //directions which escape the ceiling within a distance d is (1/d)^2.
+        let mat = Material::new_lambertian(Color::new(0.5, 0.5, 0.5));
+        let radius = 1e5;
+        let mut world = Primitives::new();
+        world.add(Primitive::new_sphere(Point3::<f64>::new(0.0, -radius, 0.0), radius, mat));
+        world.add(Primitive::new_sphere(Point3::<f64>::new(0.0, 1.0 + radius, 0.0), radius, mat));
+        let r = Ray::new(Point3::<f64>::new(0.0, 0.5, 0.0), Vector3::<f64>::new(0.0, -1.0, 0.0));
+        let mut sampler = IndependentSampler::new(0);
+
+        let ao = li(&r, &world, &settings(20000, 2.0), &mut sampler, None);
+        assert!((ao[0] - 0.25).abs() < 0.02);
+    }
+}
@@ -0,0 +1,563 @@
+use crate::aov::FirstHit;
+use crate::camera::Camera;
+use crate::film::Film;
+use crate::image::Color;
+use crate::lights::Lights;
+use crate::material::*;
+use crate::nalgebra::{Point3, Vector3};
+use crate::raytracing::{Hit, Ray, MisHeuristic};
+use crate::threads::RayTraceSettings;
+use crate::sampler::Sampler;
+
+/// The number of sampler dimensions reserved for each bounce of a subpath: three to sample the BSDF, and one for
+/// Russian roulette.
+const BOUNCE_DIMENSIONS: usize = 4;
+
+/// Whether a subpath carries radiance from the lights towards the camera, or importance from the camera towards the 
+/// lights. Some BSDFs are not symmetric, and so must be evaluated differently for each.
+#[derive (Copy, Clone, Debug, PartialEq)]
+enum TransportMode {
+    Radiance,
+    Importance
+}
+
+#[derive (Copy, Clone, Debug, PartialEq)]
+enum VertexKind {
+    Camera,
+    Light,
+    Surface
+}
+
+/// A vertex on a camera or light subpath.
+#[derive (Copy, Clone)]
+struct Vertex {
+    kind: VertexKind,
+    p: Point3<f64>,
+    /// The geometric normal at the vertex. The camera has no normal.
+    normal: Vector3<f64>,
+    /// The direction towards the previous vertex on the subpath.
+    wo: Vector3<f64>,
+    bsdf: Option<Bsdf>,
+    emission: Color,
+    /// The product of the BSDFs, cosines and inverse pdfs along the subpath, up to and including this vertex.
+    beta: Color,
+    /// True if the vertex was scattered by a specular lobe, so it cannot be connected to.
+    delta: bool,
+    /// The density, with respect to area, of sampling this vertex from the previous vertex on its subpath.
+    pdf_fwd: f64,
+    /// The density, with respect to area, of sampling this vertex from the next vertex on its subpath, were the path
+    /// traced in the opposite direction.
+    pdf_rev: f64,
+    /// The id of the primitive the vertex lies on, which finds the light it lies on if it is emissive. Vertices which
+    /// are not on a surface have an id of zero, which is never used.
+    primitive_id: usize
+}
+
+/// The first sampler dimension used by each part of a sample. Each part is drawn from the same dimensions however many
+/// vertices the subpaths before it have, so that a sampler can spread every part evenly over the samples of a pixel.
+struct Dimensions {
+    camera_walk: usize,
+    light: usize,
+    light_walk: usize,
+    /// The lens point of the strategy which connects `s` light vertices to the camera is drawn from the two dimensions
+    /// at `lens + 2 * s`.
+    lens: usize,
+    /// The light point of the strategy which connects `t` camera vertices to a light is drawn from the three 
+    /// dimensions at `lights + 3 * t`.
+    lights: usize
+}
+
+impl Dimensions {
+    /// Lays out the dimensions of a sample from `start`, for subpaths of at most `max_depth` bounces.
+    fn new(start: usize, max_depth: usize) -> Dimensions {
+        let camera_walk = start;
+        let light = camera_walk + BOUNCE_DIMENSIONS * (max_depth + 1);
+        let light_walk = light + 5;
+        let lens = light_walk + BOUNCE_DIMENSIONS * max_depth;
+        let lights = lens + 2 * (max_depth + 2);
+        Dimensions { camera_walk, light, light_walk, lens, lights }
+    }
+}
+
+impl Vertex {
+    fn camera(p: Point3<f64>, beta: Color) -> Vertex {
+        Vertex { kind: VertexKind::Camera, p, normal: Vector3::<f64>::zeros(), wo: Vector3::<f64>::zeros(), bsdf: None, 
+                 emission: Color::zeros(), beta, delta: false, pdf_fwd: 0.0, pdf_rev: 0.0, primitive_id: 0 }
+    }
+
+    fn light(p: Point3<f64>, normal: Vector3<f64>, emission: Color, beta: Color, pdf_fwd: f64) -> Vertex {
+        Vertex { kind: VertexKind::Light, p, normal, wo: Vector3::<f64>::zeros(), bsdf: None, emission, beta, delta: false,
+                 pdf_fwd, pdf_rev: 0.0, primitive_id: 0 }
+    }
+
+    fn surface(p: Point3<f64>, normal: Vector3<f64>, wo: Vector3<f64>, bsdf: Bsdf, emission: Color, beta: Color) -> Vertex {
+        Vertex { kind: VertexKind::Surface, p, normal, wo, bsdf: Some(bsdf), emission, beta, delta: false, pdf_fwd: 0.0, 
+                 pdf_rev: 0.0, primitive_id: 0 }
+    }
+
+    fn is_connectible(&self) -> bool {
+        match self.bsdf {
+            Some(bsdf) => bsdf.flags().is_non_specular(),
+            None => true
+        }
+    }
+
+    fn is_emissive(&self) -> bool {
+        self.emission != Color::zeros()
+    }
+
+    /// Evaluates the BSDF at the vertex for light travelling between the previous vertex and `next`.
+    fn f(&self, next: &Vertex, mode: TransportMode) -> Color {
+        let bsdf = match self.bsdf {
+            Some(bsdf) => bsdf,
+            None => return Color::zeros()
+        };
+        let wi = next.p - self.p;
+        match mode {
+            TransportMode::Radiance => bsdf.eval(&self.wo, &wi),
+            TransportMode::Importance => bsdf.eval(&wi, &self.wo)
+        }
+    }
+
+    /// Converts a density with respect to solid angle at this vertex into a density with respect to area at `next`.
+    fn convert_density(&self, pdf: f64, next: &Vertex) -> f64 {
+        let w = next.p - self.p;
+        let dist_squared = w.norm_squared();
+        if dist_squared == 0.0 {
+            return 0.0;
+        }
+        let mut pdf = pdf / dist_squared;
+        if next.kind != VertexKind::Camera {
+            pdf *= next.normal.dot(&(w / dist_squared.sqrt())).abs();
+        }
+        pdf
+    }
+
+    /// Returns the density, with respect to area, of sampling `next` from this vertex, having arrived from `prev`.
+    fn pdf(&self, cam: &Camera, prev: Option<&Vertex>, next: &Vertex) -> f64 {
+        match self.kind {
+            VertexKind::Camera => self.convert_density(cam.pdf_direction(&self.p, &(next.p - self.p)), next),
+            VertexKind::Light => self.pdf_light(next),
+            VertexKind::Surface => {
+                let (bsdf, prev) = match (self.bsdf, prev) {
+                    (Some(bsdf), Some(prev)) => (bsdf, prev),
+                    _ => return 0.0
+                };
+                self.convert_density(bsdf.pdf(&(prev.p - self.p), &(next.p - self.p)), next)
+            }
+        }
+    }
+
+    /// Returns the density, with respect to area, of light leaving this vertex towards `next`, were this vertex the
+    /// start of a light subpath.
+    fn pdf_light(&self, next: &Vertex) -> f64 {
+        self.convert_density(Lights::pdf_direction(&self.normal, &(next.p - self.p)), next)
+    }
+}
+
+/// Estimates the radiance arriving along the camera ray by bidirectional path tracing.
+///
+/// A subpath is traced from the camera, and another from a point sampled on one of the lights. Every prefix of the 
+/// camera subpath is connected to every prefix of the light subpath, giving a family of strategies which can each
+/// generate paths of a given length. Their contributions are combined by multiple importance sampling. Connections 
+/// to the camera itself land on an arbitrary pixel, and so are splatted onto `film` rather than returned. Paths are 
+/// limited to `max_depth` bounces, and subpaths are terminated by Russian roulette after `rr_start_depth` bounces.
+/// The random numbers are drawn from `sampler`, from the dimension it has reached onwards. If `first_hit` is given, the
+/// first surface the camera subpath reaches is recorded in it.
+#[allow(clippy::too_many_arguments)]
+pub fn li<T, S>(r: &Ray, cam: &Camera, background: Color, world: &T, lights: &Lights, settings: &RayTraceSettings, film: &mut Film, sampler: &mut S, first_hit: Option<&mut FirstHit>) -> Color where T: Hit, S: Sampler {
+    let max_depth = settings.max_depth.max(0) as usize;
+    let dimensions = Dimensions::new(sampler.dimension(), max_depth);
+    let mut camera_path = Vec::with_capacity(max_depth + 2);
+    let mut light_path = Vec::with_capacity(max_depth + 1);
+
+    let mut radiance = Color::zeros();
+    if let Some(beta) = generate_camera_subpath(r, cam, world, settings, sampler, &dimensions, &mut camera_path, first_hit) {
+        //The background cannot be sampled, so it is only found by camera subpaths
+        radiance += beta.component_mul(&background);
+    }
+    generate_light_subpath(r.time(), world, lights, settings, sampler, &dimensions, &mut light_path);
+
+    for t in 1..=camera_path.len() {
+        for s in 0..=light_path.len() {
+            let depth = s as isize + t as isize - 2;
+            if (s == 1 && t == 1) || depth < 0 || depth > max_depth as isize {
+                continue;
+            }
+            if t == 1 {
+                sampler.set_dimension(dimensions.lens + 2 * s);
+            } else if s == 1 {
+                sampler.set_dimension(dimensions.lights + 3 * t);
+            }
+            let (contribution, film_position) = connect(s, t, &light_path, &camera_path, r.time(), cam, world, lights, settings.mis_heuristic, sampler);
+            match film_position {
+                Some(film_position) => film.splat(film_position, contribution),
+                None => radiance += contribution
+            }
+        }
+    }
+    radiance
+}
+
+/// Traces a subpath from the camera along `r`, recording the first surface it reaches in `first_hit`, if given. Returns
+/// the throughput of the subpath if it escapes the scene.
+#[allow(clippy::too_many_arguments)]
+fn generate_camera_subpath<T, S>(r: &Ray, cam: &Camera, world: &T, settings: &RayTraceSettings, sampler: &mut S, dimensions: &Dimensions, path: &mut Vec<Vertex>, first_hit: Option<&mut FirstHit>) -> Option<Color> where T: Hit, S: Sampler {
+    let beta = Color::new(1.0, 1.0, 1.0);
+    let pdf = cam.pdf_direction(&r.origin(), &r.direction());
+    path.push(Vertex::camera(r.origin(), beta));
+    random_walk(*r, beta, pdf, world, settings, TransportMode::Radiance, settings.max_depth.max(0) as usize + 1, sampler, dimensions.camera_walk, path, first_hit)
+}
+
+/// Traces a subpath from a point sampled on one of the lights, at the same time as the camera subpath.
+fn generate_light_subpath<T, S>(time: f64, world: &T, lights: &Lights, settings: &RayTraceSettings, sampler: &mut S, dimensions: &Dimensions, path: &mut Vec<Vertex>) where T: Hit, S: Sampler {
+    sampler.set_dimension(dimensions.light);
+    let u_light = sampler.get_1d();
+    let u_position = sampler.get_2d();
+    let u_direction = sampler.get_2d();
+    let sample = match lights.sample_emission(u_light, u_position, u_direction) {
+        Some(sample) => sample,
+        None => return
+    };
+
+    path.push(Vertex::light(sample.p, sample.normal, sample.emission, sample.emission / sample.pdf_position, sample.pdf_position));
+    let cos_theta = sample.normal.dot(&sample.direction).abs();
+    let beta = sample.emission * (cos_theta / (sample.pdf_position * sample.pdf_direction));
+    let r = Ray::new_at_time(sample.p, sample.direction, time);
+    random_walk(r, beta, sample.pdf_direction, world, settings, TransportMode::Importance, settings.max_depth.max(0) as usize, sampler, dimensions.light_walk, path, None);
+}
+
+/// Extends a subpath by repeatedly sampling the BSDF, adding at most `max_vertices` vertices. `pdf` is the density,
+/// with respect to solid angle, of sampling the direction of `r`. Each bounce draws from its own `BOUNCE_DIMENSIONS`
+/// dimensions of `sampler`, counting from `dimension`. The surfaces reached are offered to `first_hit`, if given.
+/// Returns the throughput of the subpath if it escapes the scene.
+#[allow(clippy::too_many_arguments)]
+fn random_walk<T, S>(mut r: Ray, mut beta: Color, pdf: f64, world: &T, settings: &RayTraceSettings, mode: TransportMode, max_vertices: usize, sampler: &mut S, dimension: usize, path: &mut Vec<Vertex>, mut first_hit: Option<&mut FirstHit>) -> Option<Color> where T: Hit, S: Sampler {
+    let mut pdf_fwd = pdf;
+    let mut bounces = 0;
+    while bounces < max_vertices {
+        let (rec, mat) = match world.hit(&r, 0.001, f64::INFINITY) {
+            Some(hit) => hit,
+            None => return Some(beta)
+        };
+        if let Some(first_hit) = first_hit.as_mut() {
+            first_hit.record(&rec, mat);
+        }
+
+        let bsdf = mat.bsdf(&rec);
+        let wo = -r.direction().normalize();
+        let prev = path[path.len() - 1];
+        let mut vertex = Vertex::surface(rec.p, rec.normal, wo, bsdf, mat.emit(), beta);
+        vertex.pdf_fwd = prev.convert_density(pdf_fwd, &vertex);
+        vertex.primitive_id = rec.primitive_id;
+        path.push(vertex);
+        bounces += 1;
+        if bounces >= max_vertices {
+            break;
+        }
+
+        sampler.set_dimension(dimension + BOUNCE_DIMENSIONS * (bounces - 1));
+        let u_direction = sampler.get_2d();
+        let u_scatter = [u_direction[0], u_direction[1], sampler.get_1d()];
+        let u_survival = sampler.get_1d();
+        let sample = match bsdf.sample(&wo, u_scatter) {
+            Some(sample) => sample,
+            None => break
+        };
+        let f = if mode == TransportMode::Importance && !sample.flags.is_specular() {
+            bsdf.eval(&sample.wi, &wo)
+        } else {
+            sample.f
+        };
+        beta.component_mul_assign(&(f * (sample.wi.dot(&rec.normal).abs() / sample.pdf)));
+
+        let pdf_rev = if sample.flags.is_specular() {
+            path.last_mut().unwrap().delta = true;
+            pdf_fwd = 0.0;
+            0.0
+        } else {
+            pdf_fwd = sample.pdf;
+            bsdf.pdf(&sample.wi, &wo)
+        };
+        let n = path.len();
+        path[n - 2].pdf_rev = path[n - 1].convert_density(pdf_rev, &path[n - 2]);
+
+        if bounces as i32 >= settings.rr_start_depth {
+            let survival_probability = beta.max().min(1.0);
+            if u_survival >= survival_probability {
+                break;
+            }
+            beta /= survival_probability;
+        }
+        r = Ray::new_at_time(rec.p, sample.wi, r.time());
+    }
+    None
+}
+
+/// Returns true if nothing in the world lies between the two points at the given time.
+fn unoccluded<T>(world: &T, a: &Point3<f64>, b: &Point3<f64>, time: f64) -> bool where T: Hit {
+    let direction = b - a;
+    let dist = direction.norm();
+    world.hit(&Ray::new_at_time(*a, direction / dist, time), 0.001, dist - 0.001).is_none()
+}
+
+/// Connects the first `s` vertices of the light subpath to the first `t` vertices of the camera subpath, and returns
+/// the MIS-weighted contribution of the resulting path. When `t` is one, a new point is sampled on the camera lens,
+/// and the position on the film that the contribution should be splatted to is also returned. Similarly, when `s` is
+/// one a new point is sampled on the lights. New points are drawn from the next dimensions of `sampler`.
+#[allow(clippy::too_many_arguments)]
+fn connect<T, S>(s: usize, t: usize, light_path: &[Vertex], camera_path: &[Vertex], time: f64, cam: &Camera, world: &T, lights: &Lights, heuristic: MisHeuristic, sampler: &mut S) -> (Color, Option<(f64, f64)>) where T: Hit, S: Sampler {
+    let zero = (Color::zeros(), None);
+    let mut sampled = None;
+    let mut film_position = None;
+
+    let contribution = if s == 0 {
+        //The camera subpath has found a light by itself
+        let pt = &camera_path[t - 1];
+        if pt.kind != VertexKind::Surface || !pt.is_emissive() {
+            return zero;
+        }
+        pt.emission.component_mul(&pt.beta)
+    } else if t == 1 {
+        //Connect the light subpath to a point sampled on the lens
+        let qs = &light_path[s - 1];
+        if !qs.is_connectible() {
+            return zero;
+        }
+        let lens_point = cam.sample_lens(sampler.get_2d());
+        let direction = qs.p - lens_point;
+        let dist = direction.norm();
+        film_position = match cam.film_position(&lens_point, &direction) {
+            Some(film_position) => Some(film_position),
+            None => return zero
+        };
+        let cos_lens = -(direction / dist).dot(&cam.orientation.w);
+        let pdf = dist * dist / (cos_lens * cam.lens_area());
+        let importance = cam.importance(&lens_point, &direction);
+        let camera_vertex = Vertex::camera(lens_point, Color::new(1.0, 1.0, 1.0) * (importance / pdf));
+
+        let cos_theta = qs.normal.dot(&(direction / dist)).abs();
+        sampled = Some(camera_vertex);
+        qs.beta.component_mul(&qs.f(&camera_vertex, TransportMode::Importance)).component_mul(&camera_vertex.beta) * cos_theta
+    } else if s == 1 {
+        //Connect the camera subpath to a point sampled on the lights
+        let pt = &camera_path[t - 1];
+        if !pt.is_connectible() {
+            return zero;
+        }
+        let u_light = sampler.get_1d();
+        let (sample, emission, _, primitive_id) = match lights.sample(&pt.p, u_light, sampler.get_2d()) {
+            Some(sample) => sample,
+            None => return zero
+        };
+        let pdf_position = lights.pdf_position(primitive_id);
+        let light_vertex = Vertex::light(sample.p, sample.normal, emission, emission / sample.pdf, pdf_position);
+
+        let cos_theta = pt.normal.dot(&(sample.p - pt.p).normalize()).abs();
+        sampled = Some(light_vertex);
+        pt.beta.component_mul(&pt.f(&light_vertex, TransportMode::Radiance)).component_mul(&light_vertex.beta) * cos_theta
+    } else {
+        //Connect the two subpaths with a new edge
+        let qs = &light_path[s - 1];
+        let pt = &camera_path[t - 1];
+        if !qs.is_connectible() || !pt.is_connectible() {
+            return zero;
+        }
+        let w = pt.p - qs.p;
+        let dist_squared = w.norm_squared();
+        let w = w / dist_squared.sqrt();
+        let g = qs.normal.dot(&w).abs() * pt.normal.dot(&w).abs() / dist_squared;
+        qs.beta.component_mul(&qs.f(pt, TransportMode::Importance)).component_mul(&pt.f(qs, TransportMode::Radiance)).component_mul(&pt.beta) * g
+    };
+
+    if contribution == Color::zeros() {
+        return zero;
+    }
+
+    //Check that the new edge is not blocked
+    let visible = match (s, t) {
+        (0, _) => true,
+        (_, 1) => unoccluded(world, &light_path[s - 1].p, &sampled.unwrap().p, time),
+        (1, _) => unoccluded(world, &camera_path[t - 1].p, &sampled.unwrap().p, time),
+        _ => unoccluded(world, &light_path[s - 1].p, &camera_path[t - 1].p, time)
+    };
+    if !visible {
+        return zero;
+    }
+
+    let weight = mis_weight(s, t, light_path, camera_path, sampled, cam, lights, heuristic);
+    (contribution * weight, film_position)
+}
+
+/// Returns the MIS weight of the path formed by connecting the first `s` vertices of the light subpath to the first
+/// `t` vertices of the camera subpath, relative to every other strategy that could have generated the same path.
+/// `sampled` replaces the endpoint of the subpath which was sampled during the connection, if any. Strategies which
+/// would need to connect to a specular vertex are excluded.
+#[allow(clippy::too_many_arguments)]
+fn mis_weight(s: usize, t: usize, light_path: &[Vertex], camera_path: &[Vertex], sampled: Option<Vertex>, cam: &Camera, lights: &Lights, heuristic: MisHeuristic) -> f64 {
+    if s + t == 2 {
+        return 1.0;
+    }
+
+    let mut camera_path = camera_path[..t].to_vec();
+    let mut light_path = light_path[..s].to_vec();
+    if let Some(vertex) = sampled {
+        if s == 1 {
+            light_path[0] = vertex;
+        } else if t == 1 {
+            camera_path[0] = vertex;
+        }
+    }
+
+    //The connected vertices are never specular, as they were evaluated rather than sampled
+    camera_path[t - 1].delta = false;
+    if s > 0 {
+        light_path[s - 1].delta = false;
+    }
+
+    //Find the reverse densities of the vertices either side of the connection
+    let pt = camera_path[t - 1];
+    let qs = if s > 0 { Some(light_path[s - 1]) } else { None };
+    camera_path[t - 1].pdf_rev = match qs {
+        Some(qs) => qs.pdf(cam, if s > 1 { Some(&light_path[s - 2]) } else { None }, &pt),
+        None => lights.pdf_position(pt.primitive_id)
+    };
+    if t > 1 {
+        let pt_minus = camera_path[t - 2];
+        camera_path[t - 2].pdf_rev = match qs {
+            Some(qs) => pt.pdf(cam, Some(&qs), &pt_minus),
+            None => pt.pdf_light(&pt_minus)
+        };
+    }
+    if let Some(qs) = qs {
+        light_path[s - 1].pdf_rev = pt.pdf(cam, if t > 1 { Some(&camera_path[t - 2]) } else { None }, &qs);
+        if s > 1 {
+            let qs_minus = light_path[s - 2];
+            light_path[s - 2].pdf_rev = qs.pdf(cam, Some(&pt), &qs_minus);
+        }
+    }
+
+    //Specular vertices have no density, but their ratios cancel, so they are mapped to one
+    let remap = |pdf: f64| if pdf != 0.0 { pdf } else { 1.0 };
+    let power = |ratio: f64| match heuristic {
+        MisHeuristic::Balance => ratio,
+        MisHeuristic::Power => ratio * ratio
+    };
+
+    //Sum the ratios of the densities of the other strategies to that of this one
+    let mut sum = 0.0;
+    let mut ratio = 1.0;
+    for i in (1..t).rev() {
+        ratio *= remap(camera_path[i].pdf_rev) / remap(camera_path[i].pdf_fwd);
+        if !camera_path[i].delta && !camera_path[i - 1].delta {
+            sum += power(ratio);
+        }
+    }
+
+    ratio = 1.0;
+    for i in (0..s).rev() {
+        ratio *= remap(light_path[i].pdf_rev) / remap(light_path[i].pdf_fwd);
+        let delta_light_vertex = i > 0 && light_path[i - 1].delta;
+        if !light_path[i].delta && !delta_light_vertex {
+            sum += power(ratio);
+        }
+    }
+    1.0 / (1.0 + sum)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::camera::CameraSettings;
+    use crate::primitives::{GeometricPrimitive, GeometricPrimitives, Primitive, Primitives};
+    use crate::raytracing::raytrace_pixel;
+    use crate::scenes::SceneData;
+    use crate::integrators::Integrator;
+    use crate::spectra::ColorMode;
+    use crate::sampler::{Sampler, SamplerType};
+    use crate::film::Filter;
+
+    #[test]
+    fn test_li(){
+        //A diffuse sphere inside a larger emissive sphere is lit uniformly from every direction, so its radiance is 
+        //the product of its albedo and the emission
+        let albedo = Color::new(0.5, 0.5, 0.5);
+        let emission = Color::new(1.0, 1.0, 1.0);
+        let mut world = GeometricPrimitives::new();
+        world.add(GeometricPrimitive::new_sphere(Point3::<f64>::new(0.0, 0.0, 0.0), 1.0, Material::new_lambertian(albedo)));
+        world.add(GeometricPrimitive::new_sphere(Point3::<f64>::new(0.0, 0.0, 0.0), 10.0, Material::new_diffuse_light(emission)));
+        let lights = Lights::from_primitives(&world);
+        let mut raytracing_primitives = Primitives::new();
+        for index in 0..world.len() {
+            raytracing_primitives.add(Primitive::new_geometric_primitive(world.get(index)));
+        }
+        let scene = SceneData { raytracing_primitives, rasterization_primitives: world, lights, background: Color::zeros(), atmosphere: None, light_groups: Vec::new() };
+
+        let (image_width, image_height) = (4, 4);
+        let camera_settings = CameraSettings { look_from: Point3::<f64>::new(0.0, 0.0, 4.0), v_fov: 10.0, focus_dist: 4.0, image_height, image_width, shutter_close: 0.0, ..CameraSettings::default() };
+        let cam = Camera::new(camera_settings);
+        let settings = RayTraceSettings { max_depth: 5, rr_start_depth: 3, integrator: Integrator::Bidirectional, ..RayTraceSettings::for_test() };
+
+        let passes = 200;
+        let mut film = Film::new(image_width, image_height, settings.filter, &[], &[]);
+        let mut sampler = settings.sampler.sampler(passes, 0);
+        for pass in 0..passes {
+            for j in 0..image_height {
+                for i in 0..image_width {
+                    sampler.start_pixel_sample((i, j), pass, 0);
+                    raytrace_pixel(&mut film, cam, &scene, &settings, None, (i, j), &mut sampler);
+                }
+            }
+        }
+        film.image.samples = passes;
+        let mean = film.into_image().to_image().pixels.iter().fold(Color::zeros(), |sum, pixel| sum + pixel.color) / (image_width * image_height) as f64;
+        assert!((mean - albedo.component_mul(&emission)).norm() < 0.02);
+    }
+
+    #[test]
+    fn test_mis_weight(){
+        let cam = Camera::new(CameraSettings { look_from: Point3::<f64>::new(0.0, 0.0, 4.0), v_fov: 40.0, focus_dist: 4.0, image_height: 10, image_width: 10, shutter_close: 0.0, ..CameraSettings::default() });
+        let lights = Lights::new();
+
+        //Case 1: Paths with a single edge can only be found by the camera
+        let camera_path = [Vertex::camera(cam.origin, Color::new(1.0, 1.0, 1.0))];
+        assert_eq!(mis_weight(0, 2, &[], &camera_path, None, &cam, &lights, MisHeuristic::Power), 1.0);
+
+        //Case 2: The weights of the strategies for a longer path sum to one
+        let floor = Material::new_lambertian(Color::new(0.5, 0.5, 0.5));
+        let mut world = GeometricPrimitives::new();
+        world.add(GeometricPrimitive::new_sphere(Point3::<f64>::new(0.0, 0.0, 0.0), 1.0, floor));
+        let light = GeometricPrimitive::new_sphere(Point3::<f64>::new(0.0, 3.0, 0.0), 0.5, Material::new_diffuse_light(Color::new(1.0, 1.0, 1.0)));
+        world.add(light);
+        let lights = Lights::from_primitives(&world);
+
+        let r = Ray::new(cam.origin, Vector3::<f64>::new(0.0, 0.0, -1.0));
+        let (rec, mat) = world.hit(&r, 0.001, f64::INFINITY).unwrap();
+        let wo = -r.direction();
+        let p_light = Point3::<f64>::new(0.0, 2.5, 0.0);
+        let light_normal = Vector3::<f64>::new(0.0, -1.0, 0.0);
+
+        let mut camera_vertex = Vertex::camera(cam.origin, Color::new(1.0, 1.0, 1.0));
+        let mut surface = Vertex::surface(rec.p, rec.normal, wo, mat.bsdf(&rec), Color::zeros(), Color::new(1.0, 1.0, 1.0));
+        surface.pdf_fwd = camera_vertex.pdf(&cam, None, &surface);
+        let mut light_vertex = Vertex::light(p_light, light_normal, Color::new(1.0, 1.0, 1.0), Color::new(1.0, 1.0, 1.0), lights.pdf_position(1));
+        let mut hit_light = light_vertex;
+        hit_light.kind = VertexKind::Surface;
+        hit_light.primitive_id = 1;
+        hit_light.pdf_fwd = surface.pdf(&cam, Some(&camera_vertex), &hit_light);
+        camera_vertex.pdf_rev = 0.0;
+        light_vertex.pdf_rev = 0.0;
+
+        let mut light_surface = surface;
+        light_surface.wo = (p_light - rec.p).normalize();
+        light_surface.pdf_fwd = light_vertex.pdf_light(&surface);
+
+        let camera_path = [camera_vertex, surface, hit_light];
+        let light_path = [light_vertex, light_surface];
+        let mut sum = 0.0;
+        sum += mis_weight(0, 3, &light_path, &camera_path, None, &cam, &lights, MisHeuristic::Balance);
+        sum += mis_weight(1, 2, &light_path, &camera_path, Some(light_vertex), &cam, &lights, MisHeuristic::Balance);
+        sum += mis_weight(2, 1, &light_path, &camera_path, Some(camera_vertex), &cam, &lights, MisHeuristic::Balance);
+        assert!((sum - 1.0).abs() < 1e-9);
+    }
+}
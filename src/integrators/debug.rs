@@ -0,0 +1,157 @@
+use crate::aov::FirstHit;
+use crate::camera::Camera;
+use crate::image::Color;
+use crate::nalgebra::Vector3;
+use crate::raytracing::{Hit, Ray};
+
+/// The number of nodes visited by a ray which is shown at the hot end of the BVH heatmap. Counts are mapped onto the
+/// heatmap logarithmically, so that both cheap and expensive regions remain distinguishable.
+const HEATMAP_MAX_NODES: f64 = 1024.0;
+
+/// Quantities which can be rendered in place of the radiance, to help diagnose problems with a scene.
+#[derive (Copy, Clone, Debug, PartialEq)]
+pub enum DebugMode {
+    /// The normal used for shading, which may be interpolated across the surface, pointing outwards.
+    ShadingNormal,
+    /// The true normal of the surface, pointing outwards.
+    GeometricNormal,
+    /// The distance to the surface along the viewing direction of the camera. The focus plane is shown in mid-grey.
+    Depth,
+    /// A distinct colour for each primitive.
+    PrimitiveId,
+    /// The base colour of the material.
+    Albedo,
+    /// The number of acceleration structure nodes visited by the ray, from blue for few to red for many.
+    BvhHeatmap
+}
+
+impl DebugMode {
+    pub const ALL: [DebugMode; 6] = [DebugMode::ShadingNormal, DebugMode::GeometricNormal, DebugMode::Depth,
+                                     DebugMode::PrimitiveId, DebugMode::Albedo, DebugMode::BvhHeatmap];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            DebugMode::ShadingNormal => "Shading normal",
+            DebugMode::GeometricNormal => "Geometric normal",
+            DebugMode::Depth => "Depth",
+            DebugMode::PrimitiveId => "Primitive ID",
+            DebugMode::Albedo => "Albedo",
+            DebugMode::BvhHeatmap => "BVH heatmap"
+        }
+    }
+}
+
+/// Returns the quantity shown by the debug mode for the first surface hit by the ray. Surfaces which only mark the
+/// boundary of a medium are passed through, although the nodes visited to find them still count towards the heatmap.
+/// Rays which miss every surface are black, except in the heatmap. If `first_hit` is given, the surface is recorded in it.
+pub fn li<T>(r: &Ray, cam: &Camera, world: &T, mode: DebugMode, first_hit: Option<&mut FirstHit>) -> Color where T: Hit {
+    let mut ray = *r;
+    let mut nodes_visited = 0;
+    let hit = loop {
+        let (nodes, hit) = world.hit_debug(&ray, 0.001, f64::INFINITY);
+        nodes_visited += nodes;
+        match hit {
+            Some((rec, mat)) if mat.is_interface() => ray = Ray::new_at_time(rec.p, ray.direction(), ray.time()),
+            hit => break hit
+        }
+    };
+
+    if let (Some(first_hit), Some((rec, mat))) = (first_hit, hit) {
+        first_hit.record(&rec, mat);
+    }
+    if mode == DebugMode::BvhHeatmap {
+        return heatmap(nodes_visited);
+    }
+    let (rec, mat) = match hit {
+        Some(hit) => hit,
+        None => return Color::new(0.0, 0.0, 0.0)
+    };
+
+    match mode {
+        DebugMode::ShadingNormal => {
+            let outward_normal = if rec.front_face { rec.normal } else { -rec.normal };
+            normal_color(&outward_normal)
+        }
+        DebugMode::GeometricNormal => normal_color(&rec.geometric_normal),
+        DebugMode::Depth => {
+            let depth = (rec.p - cam.origin).dot(&-cam.orientation.w.into_inner());
+            Color::new(1.0, 1.0, 1.0) * (0.5 * depth / cam.focus_dist())
+        }
+        DebugMode::PrimitiveId => id_color(rec.primitive_id),
+        DebugMode::Albedo => mat.albedo(),
+        DebugMode::BvhHeatmap => unreachable!()
+    }
+}
+
+/// Maps the components of a normal from [-1, 1] to [0, 1].
+fn normal_color(normal: &Vector3<f64>) -> Color {
+    (normal.normalize() + Color::new(1.0, 1.0, 1.0)) * 0.5
+}
+
+/// Returns a colour which is well separated from the colours of nearby ids.
+pub fn id_color(id: usize) -> Color {
+    let mut hash = (id as u64).wrapping_add(1).wrapping_mul(0x9E37_79B9_7F4A_7C15);
+    hash ^= hash >> 29;
+    let channel = |shift: u64| 0.2 + 0.8 * ((hash >> shift) & 0xFF) as f64 / 255.0;
+    Color::new(channel(0), channel(8), channel(16))
+}
+
+/// Maps a count of visited nodes onto a false colour scale running through blue, cyan, green, yellow and red.
+fn heatmap(nodes_visited: i32) -> Color {
+    let scale = [Color::new(0.0, 0.0, 1.0), Color::new(0.0, 1.0, 1.0), Color::new(0.0, 1.0, 0.0),
+                 Color::new(1.0, 1.0, 0.0), Color::new(1.0, 0.0, 0.0)];
+    let t = ((1.0 + nodes_visited.max(0) as f64).ln() / (1.0 + HEATMAP_MAX_NODES).ln()).min(1.0);
+    let position = t * (scale.len() - 1) as f64;
+    let index = (position as usize).min(scale.len() - 2);
+    let fraction = position - index as f64;
+    scale[index] * (1.0 - fraction) + scale[index + 1] * fraction
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::camera::CameraSettings;
+    use crate::material::Material;
+    use crate::nalgebra::Point3;
+    use crate::primitives::{GeometricPrimitive, GeometricPrimitives, Primitive, Primitives};
+
+    #[test]
+    fn test_li(){
+        let cam = Camera::new(CameraSettings { v_fov: 40.0, image_height: 10, image_width: 10, shutter_close: 0.0, ..CameraSettings::default() });
+        let albedo = Color::new(0.2, 0.4, 0.6);
+        let mut geometric_primitives = GeometricPrimitives::new();
+        geometric_primitives.add(GeometricPrimitive::new_sphere(Point3::<f64>::new(0.0, 10.0, 0.0), 1.0, Material::new_lambertian(albedo)));
+        geometric_primitives.add(GeometricPrimitive::new_sphere(Point3::<f64>::new(0.0, 0.0, 0.0), 2.0, Material::new_lambertian(albedo)));
+        let mut world = Primitives::new();
+        world.add(Primitive::new_bvh(geometric_primitives.to_bvh()));
+        let r = Ray::new(cam.origin, Vector3::<f64>::new(0.0, 0.0, -1.0));
+
+        //Case 1: The normal faces the camera
+        assert_eq!(li(&r, &cam, &world, DebugMode::ShadingNormal, None), Color::new(0.5, 0.5, 1.0));
+        assert_eq!(li(&r, &cam, &world, DebugMode::GeometricNormal, None), Color::new(0.5, 0.5, 1.0));
+
+        //Case 2: The surface is 8 units away, in front of the focus plane
+        assert!((li(&r, &cam, &world, DebugMode::Depth, None) - Color::new(0.4, 0.4, 0.4)).norm() < 1e-9);
+
+        //Case 3: The primitive is identified by its index in the scene
+        assert_eq!(li(&r, &cam, &world, DebugMode::PrimitiveId, None), id_color(1));
+        assert_ne!(id_color(0), id_color(1));
+
+        //Case 4: The albedo of the material is returned
+        assert_eq!(li(&r, &cam, &world, DebugMode::Albedo, None), albedo);
+
+        //Case 5: Rays which visit more nodes are hotter
+        let miss = Ray::new(cam.origin, Vector3::<f64>::new(0.0, 1.0, 0.0));
+        assert_eq!(li(&miss, &cam, &world, DebugMode::Albedo, None), Color::new(0.0, 0.0, 0.0));
+        let cold = li(&miss, &cam, &world, DebugMode::BvhHeatmap, None);
+        let hot = li(&r, &cam, &world, DebugMode::BvhHeatmap, None);
+        assert!(hot[1] > cold[1]);
+    }
+
+    #[test]
+    fn test_heatmap(){
+        assert_eq!(heatmap(0), Color::new(0.0, 0.0, 1.0));
+        assert_eq!(heatmap(HEATMAP_MAX_NODES as i32), Color::new(1.0, 0.0, 0.0));
+        assert_eq!(heatmap(100000), Color::new(1.0, 0.0, 0.0));
+    }
+}
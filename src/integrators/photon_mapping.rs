@@ -0,0 +1,374 @@
+use std::f64::consts::PI;
+
+use crate::aov::FirstHit;
+use crate::image::Color;
+use crate::integrators::Integrator;
+use crate::lights::Lights;
+use crate::material::*;
+use crate::media::MediumTracker;
+use crate::nalgebra::{Point3, Vector3};
+use crate::raytracing::{Hit, HitRecord, Ray, sample_lights};
+use crate::threads::RayTraceSettings;
+use crate::sampler::{hash, Sampler};
+
+/// The fraction of new photons kept by progressive photon mapping on each pass. Smaller values shrink the radius 
+/// faster, at the expense of more noise.
+const PROGRESSIVE_ALPHA: f64 = 2.0 / 3.0;
+
+/// A packet of light deposited on a diffuse surface.
+#[derive (Copy, Clone, Debug, PartialEq)]
+pub struct Photon {
+    pub p: Point3<f64>,
+    /// The direction the photon arrived from, pointing away from the surface.
+    pub wi: Vector3<f64>,
+    pub power: Color
+}
+
+/// Photons stored in a balanced kd-tree, together with the radius within which they are gathered.
+/// 
+/// The tree is stored implicitly: the photon at the middle of each subrange of `photons` splits the rest of the 
+/// subrange in two along the axis recorded at the same index of `axes`.
+#[derive (Clone, Default)]
+pub struct PhotonMap {
+    photons: Vec<Photon>,
+    axes: Vec<usize>,
+    pub radius: f64
+}
+
+impl PhotonMap {
+    pub fn new(mut photons: Vec<Photon>, radius: f64) -> PhotonMap {
+        let mut axes = vec![0; photons.len()];
+        PhotonMap::build(&mut photons, &mut axes);
+        PhotonMap { photons, axes, radius }
+    }
+
+    /// Shoots `photon_count` photons from the lights, and stores them at every diffuse surface they hit after their 
+    /// first bounce. Light arriving directly from the lights is instead found by sampling them. The gather radius is 
+    /// `gather_radius`, or for progressive photon mapping, the radius refined for the given pass. Photons are traced at
+    /// time zero, so the map does not blur moving primitives. Each photon is drawn from its own sample of a sampler of
+    /// the type the image is rendered with, seeded from the seed of the render and the pass, so the same pass always 
+    /// traces the same map.
+    pub fn trace<T>(world: &T, lights: &Lights, settings: &RayTraceSettings, pass: usize) -> PhotonMap where T: Hit {
+        let mut sampler = settings.sampler.sampler(settings.photon_count, hash(&[settings.seed, pass as u64]));
+        let radius = match settings.integrator {
+            Integrator::ProgressivePhotonMapping => progressive_radius(settings.gather_radius, pass),
+            _ => settings.gather_radius
+        };
+
+        let mut photons = Vec::new();
+        if lights.is_empty() {
+            return PhotonMap::new(photons, radius);
+        }
+
+        for index in 0..settings.photon_count {
+            sampler.start_pixel_sample((0, 0), index, 0);
+            let u_light = sampler.get_1d();
+            let u_position = sampler.get_2d();
+            let u_direction = sampler.get_2d();
+            let sample = match lights.sample_emission(u_light, u_position, u_direction) {
+                Some(sample) => sample,
+                None => continue
+            };
+            let cos_theta = sample.normal.dot(&sample.direction).abs();
+            let mut power = sample.emission * (cos_theta / (sample.pdf_position * sample.pdf_direction * settings.photon_count as f64));
+            let mut r = Ray::new(sample.p, sample.direction);
+
+            for depth in 0..settings.max_depth {
+                let u_direction = sampler.get_2d();
+                let u_scatter = [u_direction[0], u_direction[1], sampler.get_1d()];
+                let u_survival = sampler.get_1d();
+                let (rec, mat) = match world.hit(&r, 0.001, f64::INFINITY) {
+                    Some(hit) => hit,
+                    None => break
+                };
+                let bsdf = mat.bsdf(&rec);
+                let wo = -r.direction().normalize();
+                if depth > 0 && bsdf.flags().contains(BsdfFlags::DIFFUSE) {
+                    photons.push(Photon { p: rec.p, wi: wo, power });
+                }
+
+                let sample = match bsdf.sample(&wo, u_scatter) {
+                    Some(sample) => sample,
+                    None => break
+                };
+
+                //Photons carry importance, so the BSDF is evaluated with its directions swapped
+                let f = if sample.flags.is_specular() { sample.f } else { bsdf.eval(&sample.wi, &wo) };
+                let new_power = power.component_mul(&(f * (sample.wi.dot(&rec.normal).abs() / sample.pdf)));
+
+                //Terminate photons whose power drops, so that the surviving photons keep roughly the same power
+                if depth >= settings.rr_start_depth {
+                    let survival_probability = (new_power.max() / power.max()).min(1.0);
+                    if u_survival >= survival_probability {
+                        break;
+                    }
+                    power = new_power / survival_probability;
+                } else {
+                    power = new_power;
+                }
+                r = Ray::new(rec.p, sample.wi);
+            }
+        }
+        PhotonMap::new(photons, radius)
+    }
+
+    pub fn len(&self) -> usize {
+        self.photons.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.photons.is_empty()
+    }
+
+    /// Calls `f` on every photon within `radius` of `p`.
+    pub fn for_each_within<F>(&self, p: &Point3<f64>, radius: f64, mut f: F) where F: FnMut(&Photon) {
+        PhotonMap::query(&self.photons, &self.axes, p, radius * radius, &mut f);
+    }
+
+    /// Estimates the radiance scattered along `wo` from the density of the photons around `p`.
+    pub fn estimate(&self, p: &Point3<f64>, wo: &Vector3<f64>, bsdf: &Bsdf) -> Color {
+        let mut flux = Color::zeros();
+        self.for_each_within(p, self.radius, |photon| {
+            flux += bsdf.eval(wo, &photon.wi).component_mul(&photon.power);
+        });
+        flux / (PI * self.radius * self.radius)
+    }
+
+    fn build(photons: &mut [Photon], axes: &mut [usize]) {
+        if photons.is_empty() {
+            return;
+        }
+
+        //Split along the axis in which the photons are most spread out
+        let mut min = photons[0].p;
+        let mut max = photons[0].p;
+        for photon in photons.iter() {
+            min = min.inf(&photon.p);
+            max = max.sup(&photon.p);
+        }
+        let axis = (max - min).imax();
+
+        let mid = photons.len() / 2;
+        photons.select_nth_unstable_by(mid, |a, b| a.p[axis].partial_cmp(&b.p[axis]).unwrap());
+        axes[mid] = axis;
+
+        let (left_photons, right_photons) = photons.split_at_mut(mid);
+        let (left_axes, right_axes) = axes.split_at_mut(mid);
+        PhotonMap::build(left_photons, left_axes);
+        PhotonMap::build(&mut right_photons[1..], &mut right_axes[1..]);
+    }
+
+    fn query<F>(photons: &[Photon], axes: &[usize], p: &Point3<f64>, radius_squared: f64, f: &mut F) where F: FnMut(&Photon) {
+        if photons.is_empty() {
+            return;
+        }
+        let mid = photons.len() / 2;
+        let photon = &photons[mid];
+        if (photon.p - p).norm_squared() <= radius_squared {
+            f(photon);
+        }
+
+        //Search the side containing the point first, and the other side only if it is within the radius
+        let axis = axes[mid];
+        let offset = p[axis] - photon.p[axis];
+        let (near, far) = if offset <= 0.0 {
+            ((&photons[..mid], &axes[..mid]), (&photons[mid + 1..], &axes[mid + 1..]))
+        } else {
+            ((&photons[mid + 1..], &axes[mid + 1..]), (&photons[..mid], &axes[..mid]))
+        };
+        PhotonMap::query(near.0, near.1, p, radius_squared, f);
+        if offset * offset <= radius_squared {
+            PhotonMap::query(far.0, far.1, p, radius_squared, f);
+        }
+    }
+}
+
+/// Returns the gather radius for the given pass of progressive photon mapping. The area of the gather disk shrinks by 
+/// a factor of `(i + alpha) / (i + 1)` after the `i`th pass, which keeps the bias and the variance of the average
+/// over all passes tending to zero.
+pub fn progressive_radius(initial_radius: f64, pass: usize) -> f64 {
+    let mut radius_squared = initial_radius * initial_radius;
+    for i in 1..=pass {
+        radius_squared *= (i as f64 + PROGRESSIVE_ALPHA) / (i as f64 + 1.0);
+    }
+    radius_squared.sqrt()
+}
+
+/// Estimates the radiance arriving along the camera ray using the photon map.
+/// 
+/// The camera path follows specular and glossy bounces until it reaches a diffuse surface. There, light arriving 
+/// directly from the lights is found by sampling them, and all other light from the lights is found by gathering the 
+/// photons around the hit. The background is not a source of photons, so the path is continued from the surface to 
+/// find the light arriving from it. The random numbers used by the path are drawn from `sampler`. If `first_hit` is 
+/// given, the first surface the path reaches is recorded in it.
+#[allow(clippy::too_many_arguments)]
+pub fn li<T, S>(r: &Ray, background: Color, world: &T, lights: &Lights, settings: &RayTraceSettings, photon_map: &PhotonMap, sampler: &mut S, mut first_hit: Option<&mut FirstHit>) -> Color where T: Hit, S: Sampler {
+    let mut radiance = Color::zeros();
+    let mut throughput = Color::new(1.0, 1.0, 1.0);
+    let mut ray = *r;
+
+    for _ in 0..settings.max_depth {
+        let u_direction = sampler.get_2d();
+        let u_scatter = [u_direction[0], u_direction[1], sampler.get_1d()];
+        let u_light_position = sampler.get_2d();
+        let u_light = [sampler.get_1d(), u_light_position[0], u_light_position[1]];
+        let (rec, mat) = match world.hit(&ray, 0.001, f64::INFINITY) {
+            Some(hit) => hit,
+            None => {
+                radiance += throughput.component_mul(&background);
+                break;
+            }
+        };
+        if let Some(first_hit) = first_hit.as_mut() {
+            first_hit.record(&rec, mat);
+        }
+        radiance += throughput.component_mul(&mat.emit());
+
+        let bsdf = mat.bsdf(&rec);
+        let wo = -ray.direction().normalize();
+        if bsdf.flags().contains(BsdfFlags::DIFFUSE) {
+            let (direct, _) = sample_lights(&wo, &rec, &bsdf, world, lights, &MediumTracker::default(), None, None, u_light);
+            let indirect = photon_map.estimate(&rec.p, &wo, &bsdf);
+            radiance += throughput.component_mul(&(direct + indirect));
+            if background != Color::zeros() {
+                radiance += throughput.component_mul(&background_radiance(&wo, &rec, &bsdf, background, world, settings, sampler));
+            }
+            break;
+        }
+
+        match bsdf.sample(&wo, u_scatter) {
+            Some(sample) => {
+                throughput.component_mul_assign(&(sample.f * (sample.wi.dot(&rec.normal).abs() / sample.pdf)));
+                ray = Ray::new_at_time(rec.p, sample.wi, ray.time());
+            }
+            None => break
+        }
+    }
+    radiance
+}
+
+/// Estimates the radiance scattered back along `wo` due to light from the background alone, by tracing a path from 
+/// the hit. Emission from the lights is ignored, as it is accounted for by the photon map.
+#[allow(clippy::too_many_arguments)]
+fn background_radiance<T, S>(wo: &Vector3<f64>, rec: &HitRecord, bsdf: &Bsdf, background: Color, world: &T, settings: &RayTraceSettings, sampler: &mut S) -> Color where T: Hit, S: Sampler {
+    let mut throughput = Color::new(1.0, 1.0, 1.0);
+    let (mut wo, mut rec, mut bsdf) = (*wo, *rec, *bsdf);
+
+    for depth in 0..settings.max_depth {
+        let u_direction = sampler.get_2d();
+        let u_scatter = [u_direction[0], u_direction[1], sampler.get_1d()];
+        let u_survival = sampler.get_1d();
+        let sample = match bsdf.sample(&wo, u_scatter) {
+            Some(sample) => sample,
+            None => break
+        };
+        throughput.component_mul_assign(&(sample.f * (sample.wi.dot(&rec.normal).abs() / sample.pdf)));
+        let ray = Ray::new_at_time(rec.p, sample.wi, rec.time);
+
+        let (next_rec, mat) = match world.hit(&ray, 0.001, f64::INFINITY) {
+            Some(hit) => hit,
+            None => return throughput.component_mul(&background)
+        };
+
+        if depth >= settings.rr_start_depth {
+            let survival_probability = throughput.max().min(1.0);
+            if u_survival >= survival_probability {
+                break;
+            }
+            throughput /= survival_probability;
+        }
+        wo = -ray.direction().normalize();
+        rec = next_rec;
+        bsdf = mat.bsdf(&rec);
+    }
+    Color::zeros()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::film::Filter;
+    use crate::primitives::{GeometricPrimitive, GeometricPrimitives};
+    use crate::primitives::rect::RectAxes;
+    use crate::raytracing::{MisHeuristic, ray_color};
+    use crate::sampler::SamplerType;
+    use crate::sampler::independent::IndependentSampler;
+    use crate::spectra::ColorMode;
+
+    #[test]
+    fn test_for_each_within(){
+        let mut sampler = IndependentSampler::new(0);
+        let mut rand_point = || Point3::from(Vector3::<f64>::from_fn(|_, _| sampler.get_1d() * 2.0 - 1.0));
+        let photons: Vec<Photon> = (0..500).map(|_| Photon { p: rand_point(), 
+                                                              wi: Vector3::<f64>::new(0.0, 1.0, 0.0), power: Color::new(1.0, 1.0, 1.0) }).collect();
+        let photon_map = PhotonMap::new(photons.clone(), 0.3);
+        assert_eq!(photon_map.len(), 500);
+
+        //Case 1: The kd-tree finds the same photons as a brute force search
+        for _ in 0..20 {
+            let p = rand_point();
+            let mut found = Vec::new();
+            photon_map.for_each_within(&p, 0.3, |photon| found.push(photon.p));
+            let expected = photons.iter().filter(|photon| (photon.p - p).norm() <= 0.3).count();
+            assert_eq!(found.len(), expected);
+            assert!(found.iter().all(|q| (q - p).norm() <= 0.3));
+        }
+
+        //Case 2: An empty map finds nothing
+        let empty = PhotonMap::new(Vec::new(), 0.3);
+        assert!(empty.is_empty());
+        empty.for_each_within(&Point3::<f64>::origin(), 1.0, |_| panic!());
+    }
+
+    #[test]
+    fn test_estimate(){
+        //A single photon on a Lambertian surface contributes albedo/pi times its power, spread over the gather disk
+        let photon_map = PhotonMap::new(vec![Photon { p: Point3::<f64>::origin(), wi: Vector3::<f64>::new(0.0, 1.0, 0.0), power: Color::new(1.0, 1.0, 1.0) }], 0.5);
+        let rec = HitRecord::new(Point3::<f64>::origin(), Vector3::<f64>::new(0.0, 1.0, 0.0), 1.0, 
+                                 Ray::new(Point3::<f64>::new(0.0, 1.0, 0.0), Vector3::<f64>::new(0.0, -1.0, 0.0)), Vector3::<f64>::zeros());
+        let bsdf = Material::new_lambertian(Color::new(0.5, 0.5, 0.5)).bsdf(&rec);
+        let estimate = photon_map.estimate(&Point3::<f64>::new(0.1, 0.0, 0.0), &Vector3::<f64>::new(0.0, 1.0, 0.0), &bsdf);
+        assert!((estimate - Color::new(0.5, 0.5, 0.5) / (PI * PI * 0.25)).norm() < 1e-9);
+    }
+
+    #[test]
+    fn test_li(){
+        let settings = RayTraceSettings { max_depth: 10, rr_start_depth: 3, integrator: Integrator::PhotonMapping, photon_count: 20000, ..RayTraceSettings::for_test() };
+        let background = Color::new(0.2, 0.2, 0.2);
+        let mut world = GeometricPrimitives::new();
+        world.add(GeometricPrimitive::new_rect(RectAxes::XZ, -5.0, 5.0, -5.0, 5.0, 0.0, Material::new_lambertian(Color::new(0.5, 0.5, 0.5))));
+        world.add(GeometricPrimitive::new_rect(RectAxes::YZ, 0.0, 2.0, -2.0, 2.0, 1.0, Material::new_lambertian(Color::new(0.8, 0.2, 0.2))));
+        world.add(GeometricPrimitive::new_rect(RectAxes::XZ, -0.5, 0.5, -0.5, 0.5, 2.0, Material::new_diffuse_light(Color::new(4.0, 4.0, 4.0))));
+        let lights = Lights::from_primitives(&world);
+
+        //Case 1: Rays which miss, or which hit a light, need no photons
+        let photon_map = PhotonMap::trace(&world, &lights, &settings, 0);
+        let mut sampler = IndependentSampler::new(0);
+        let r = Ray::new(Point3::<f64>::new(-1.0, 1.0, 0.0), Vector3::<f64>::new(-1.0, 1.0, 0.0));
+        assert_eq!(li(&r, background, &world, &lights, &settings, &photon_map, &mut sampler, None), background);
+        let r = Ray::new(Point3::<f64>::new(0.0, 1.0, 0.0), Vector3::<f64>::new(0.0, 1.0, 0.0));
+        assert_eq!(li(&r, background, &world, &lights, &settings, &photon_map, &mut sampler, None), Color::new(4.0, 4.0, 4.0));
+
+        //Case 2: Light reflected onto the floor by the wall is gathered from the photons, and agrees with the path tracer
+        let r = Ray::new(Point3::<f64>::new(-1.0, 1.0, 0.0), Vector3::<f64>::new(1.5, -1.0, 0.0));
+        let passes = 8;
+        let photon_mapped = (0..passes).fold(Color::zeros(), |sum, pass| {
+            let photon_map = PhotonMap::trace(&world, &lights, &settings, pass);
+            sum + (0..500).fold(Color::zeros(), |sum, _| sum + li(&r, background, &world, &lights, &settings, &photon_map, &mut sampler, None)) / 500.0
+        }) / passes as f64;
+        let samples = 20000;
+        let path_traced = (0..samples).fold(Color::zeros(), |sum, _| sum + ray_color(&r, background, None, &world, &lights, &settings, None, &mut sampler, None, None)) / samples as f64;
+        assert!((photon_mapped - path_traced).norm() < 0.1 * path_traced.norm());
+        assert!(photon_mapped[0] > photon_mapped[1]);
+    }
+
+    #[test]
+    fn test_progressive_radius(){
+        //Case 1: The first pass uses the initial radius
+        assert_eq!(progressive_radius(0.1, 0), 0.1);
+
+        //Case 2: The radius shrinks on every pass
+        let radii: Vec<f64> = (0..10).map(|pass| progressive_radius(0.1, pass)).collect();
+        assert!(radii.windows(2).all(|pair| pair[1] < pair[0] && pair[1] > 0.0));
+    }
+}
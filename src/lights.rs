@@ -0,0 +1,231 @@
+use crate::enum_dispatch::*;
+use crate::image::Color;
+use crate::material::*;
+use crate::nalgebra::{Vector3, Point3};
+use crate::primitives::{GeometricPrimitive, GeometricPrimitives};
+use crate::sampler::cosine_hemisphere;
+use std::collections::HashMap;
+use std::f64::consts::PI;
+
+/// A point sampled on the surface of a primitive, as seen from some reference point.
+#[derive (Copy, Clone, Debug, PartialEq)]
+pub struct SurfaceSample {
+    pub p: Point3<f64>,
+    pub normal: Vector3<f64>,
+    /// The probability density of the sample, with respect to solid angle at the reference point.
+    pub pdf: f64
+}
+
+/// A ray of light leaving a point sampled on one of the lights.
+#[derive (Copy, Clone, Debug, PartialEq)]
+pub struct EmissionSample {
+    pub p: Point3<f64>,
+    pub normal: Vector3<f64>,
+    pub direction: Vector3<f64>,
+    /// The probability density of the point, with respect to area. This accounts for the choice of light.
+    pub pdf_position: f64,
+    /// The probability density of the direction, with respect to solid angle.
+    pub pdf_direction: f64,
+    pub emission: Color
+}
+
+/// Primitives which can be sampled by area or solid angle, so that they can be used as lights.
+#[enum_dispatch]
+pub trait Sample: Send + Sync {
+    /// Samples a point on the surface visible from `origin`, using a pair of uniform random numbers in [0, 1).
+    fn sample(&self, origin: &Point3<f64>, u: [f64; 2]) -> Option<SurfaceSample>;
+
+    /// Samples a point uniformly by area on the surface. The pdf of the sample is with respect to area.
+    fn sample_area(&self, u: [f64; 2]) -> SurfaceSample;
+
+    fn area(&self) -> f64;
+
+    /// Returns the probability density, with respect to solid angle at `origin`, of sampling the direction `direction`.
+    fn pdf(&self, origin: &Point3<f64>, direction: &Vector3<f64>) -> f64;
+}
+
+/// The emissive primitives in the scene, collected when the scene is built so that they can be sampled directly. Each
+/// light keeps the id its primitive is given in hit records, which is its index in the scene's primitives, so that the
+/// light a ray hits can be looked up without tracing the lights again.
+#[derive (Default, Clone)]
+pub struct Lights {
+    list: Vec<(usize, GeometricPrimitive)>,
+    /// The index in `list` of the light with each primitive id.
+    indices: HashMap<usize, usize>
+}
+
+impl Lights {
+
+    pub fn new() -> Lights {
+        Lights{list: Vec::new(), indices: HashMap::new()}
+    }
+
+    /// Collects every primitive with an emissive material. Moving primitives are left out, since their position depends
+    /// on the time of the path; their emission is still found by rays which hit them.
+    pub fn from_primitives(primitives: &GeometricPrimitives) -> Lights {
+        let mut lights = Lights::new();
+        for index in 0..primitives.len() {
+            let primitive = primitives.get(index);
+            if primitive.material().emit() != Color::new(0.0, 0.0, 0.0) && !primitive.is_moving() {
+                lights.add(index, primitive);
+            }
+        }
+        lights
+    }
+
+    /// Adds a light, which hit records identify by the given primitive id.
+    pub fn add(&mut self, primitive_id: usize, light: GeometricPrimitive) {
+        self.indices.insert(primitive_id, self.list.len());
+        self.list.push((primitive_id, light));
+    }
+
+    pub fn get(&self, index: usize) -> GeometricPrimitive {
+        self.list[index].1
+    }
+
+    pub fn len(&self) -> usize {
+        self.list.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.list.is_empty()
+    }
+
+    /// Picks a light uniformly using `u_light`, and then samples a point on it using `u`. Returns the sample
+    /// together with the emission of the light, the light group it belongs to and its primitive id. The pdf of the 
+    /// sample accounts for the choice of light.
+    pub fn sample(&self, origin: &Point3<f64>, u_light: f64, u: [f64; 2]) -> Option<(SurfaceSample, Color, usize, usize)> {
+        if self.is_empty() {
+            return None;
+        }
+        let index = ((u_light * self.len() as f64) as usize).min(self.len() - 1);
+        let (primitive_id, light) = &self.list[index];
+        let mut sample = light.sample(origin, u)?;
+        sample.pdf /= self.len() as f64;
+        //Only emissive materials are collected as lights, so every light has a group
+        Some((sample, light.material().emit(), light.material().light_group().unwrap_or(0), *primitive_id))
+    }
+
+    /// Picks a light uniformly using `u_light`, samples a point uniformly by area on it using `u_position`, and then 
+    /// samples a direction for light to leave the point in using `u_direction`. Lights emit from both sides of their 
+    /// surface, so the direction is cosine-distributed about a randomly chosen side.
+    pub fn sample_emission(&self, u_light: f64, u_position: [f64; 2], u_direction: [f64; 2]) -> Option<EmissionSample> {
+        if self.is_empty() {
+            return None;
+        }
+        let index = ((u_light * self.len() as f64) as usize).min(self.len() - 1);
+        let light = &self.list[index].1;
+        let sample = light.sample_area(u_position);
+
+        let (side, u0) = if u_direction[0] < 0.5 {(1.0, 2.0 * u_direction[0])} else {(-1.0, 2.0 * u_direction[0] - 1.0)};
+        let frame = ShadingFrame::from_normal(&(side * sample.normal));
+        let direction = frame.to_world(&cosine_hemisphere([u0, u_direction[1]]));
+        let pdf_direction = Lights::pdf_direction(&sample.normal, &direction);
+        if pdf_direction == 0.0 {
+            return None;
+        }
+
+        let pdf_position = sample.pdf / self.len() as f64;
+        Some(EmissionSample { p: sample.p, normal: sample.normal, direction, pdf_position, pdf_direction, emission: light.material().emit() })
+    }
+
+    /// Returns the pdf, with respect to solid angle, with which `sample_emission` chooses the given direction from a 
+    /// point with the given normal.
+    pub fn pdf_direction(normal: &Vector3<f64>, direction: &Vector3<f64>) -> f64 {
+        normal.normalize().dot(&direction.normalize()).abs() / (2.0 * PI)
+    }
+
+    /// Returns the pdf, with respect to solid angle at `origin`, with which `sample` would choose the point `p` 
+    /// on the primitive with the given id. The pdf is zero if the primitive is not a light.
+    pub fn pdf(&self, origin: &Point3<f64>, p: &Point3<f64>, primitive_id: usize) -> f64 {
+        match self.find(primitive_id) {
+            Some(light) => light.pdf(origin, &(p - origin)) / self.len() as f64,
+            None => 0.0
+        }
+    }
+
+    /// Returns the pdf, with respect to area, with which `sample_emission` would choose a point on the primitive with
+    /// the given id. The pdf is zero if the primitive is not a light.
+    pub fn pdf_position(&self, primitive_id: usize) -> f64 {
+        match self.find(primitive_id) {
+            Some(light) => 1.0 / (light.area() * self.len() as f64),
+            None => 0.0
+        }
+    }
+
+    /// Returns the light with the given primitive id, if there is one.
+    fn find(&self, primitive_id: usize) -> Option<&GeometricPrimitive> {
+        self.indices.get(&primitive_id).map(|index| &self.list[*index].1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::primitives::rect::RectAxes;
+
+    #[test]
+    fn test_from_primitives(){
+        let mut primitives = GeometricPrimitives::new();
+        let diffuse = Material::new_lambertian(Color::new(0.5, 0.5, 0.5));
+        let light = Material::new_diffuse_light(Color::new(4.0, 4.0, 4.0));
+        primitives.add(GeometricPrimitive::new_sphere(Point3::<f64>::new(0.0, 0.0, 0.0), 1.0, diffuse));
+        primitives.add(GeometricPrimitive::new_rect(RectAxes::XY, -1.0, 1.0, -1.0, 1.0, 4.0, light));
+        primitives.add(GeometricPrimitive::new_sphere(Point3::<f64>::new(0.0, 5.0, 0.0), 1.0, light));
+
+        let lights = Lights::from_primitives(&primitives);
+        assert_eq!(lights.len(), 2);
+
+        //The lights are found by the index of their primitive
+        assert_eq!(lights.pdf_position(0), 0.0);
+        assert_eq!(lights.pdf_position(1), 1.0 / (4.0 * 2.0));
+        assert_eq!(lights.pdf_position(2), 1.0 / (4.0 * PI * 2.0));
+    }
+
+    #[test]
+    fn test_sample(){
+        let mut lights = Lights::new();
+        let origin = Point3::<f64>::new(0.0, 0.0, 0.0);
+        assert!(lights.sample(&origin, 0.5, [0.5, 0.5]).is_none());
+
+        let light = Material::new_diffuse_light(Color::new(4.0, 4.0, 4.0));
+        let grouped_light = Material::new_diffuse_light_in_group(Color::new(4.0, 4.0, 4.0), 1);
+        lights.add(3, GeometricPrimitive::new_rect(RectAxes::XY, -1.0, 1.0, -1.0, 1.0, 4.0, light));
+        lights.add(7, GeometricPrimitive::new_rect(RectAxes::XY, -1.0, 1.0, -1.0, 1.0, -4.0, grouped_light));
+
+        //The pdf of the sample must include the probability of choosing the light
+        let (sample, emission, light_group, primitive_id) = lights.sample(&origin, 0.9, [0.5, 0.5]).unwrap();
+        assert_eq!(sample.p, Point3::<f64>::new(0.0, 0.0, -4.0));
+        assert_eq!(sample.pdf, 0.5 * 16.0 / 4.0);
+        assert_eq!(emission, Color::new(4.0, 4.0, 4.0));
+        assert_eq!(light_group, 1);
+        assert_eq!(primitive_id, 7);
+        assert_eq!(lights.pdf(&origin, &sample.p, primitive_id), sample.pdf);
+
+        //Primitives which are not lights have zero density
+        assert_eq!(lights.pdf(&origin, &sample.p, 5), 0.0);
+        assert_eq!(lights.pdf_position(5), 0.0);
+    }
+
+    #[test]
+    fn test_sample_emission(){
+        let mut lights = Lights::new();
+        assert!(lights.sample_emission(0.5, [0.5, 0.5], [0.5, 0.5]).is_none());
+
+        let light = Material::new_diffuse_light(Color::new(4.0, 4.0, 4.0));
+        lights.add(0, GeometricPrimitive::new_rect(RectAxes::XY, -1.0, 1.0, -1.0, 1.0, 4.0, light));
+        lights.add(1, GeometricPrimitive::new_rect(RectAxes::XY, -1.0, 1.0, -1.0, 1.0, -4.0, light));
+
+        //Case 1: Light leaves from the front of the chosen light
+        let sample = lights.sample_emission(0.1, [0.5, 0.5], [0.2, 0.0]).unwrap();
+        assert_eq!(sample.p, Point3::<f64>::new(0.0, 0.0, 4.0));
+        assert_eq!(sample.pdf_position, 0.5 / 4.0);
+        assert!(sample.direction.dot(&sample.normal) > 0.0);
+        assert!((sample.pdf_direction - Lights::pdf_direction(&sample.normal, &sample.direction)).abs() < 1e-12);
+        assert_eq!(lights.pdf_position(0), sample.pdf_position);
+
+        //Case 2: Light leaves from the back of the chosen light
+        let sample = lights.sample_emission(0.1, [0.5, 0.5], [0.7, 0.0]).unwrap();
+        assert!(sample.direction.dot(&sample.normal) < 0.0);
+    }
+}
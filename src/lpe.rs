@@ -0,0 +1,413 @@
+use crate::image::Color;
+use crate::material::BsdfFlags;
+
+/// The events along a light path, as they are written in light path expressions.
+#[derive (Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Event {
+    /// `C`: the camera, where every path starts.
+    Camera,
+    /// `D`: diffuse reflection from a surface.
+    Diffuse,
+    /// `G`: glossy or mirror reflection from a surface.
+    Glossy,
+    /// `T`: transmission through a surface.
+    Transmission,
+    /// `V`: scattering within a participating medium.
+    Volume,
+    /// `L`: emission from a light, where a path ends.
+    Light,
+    /// `B`: light from the background, where a path ends.
+    Background
+}
+
+impl Event {
+    pub const ALL: [Event; 7] = [Event::Camera, Event::Diffuse, Event::Glossy, Event::Transmission, Event::Volume, Event::Light, Event::Background];
+
+    pub fn symbol(&self) -> char {
+        match self {
+            Event::Camera => 'C',
+            Event::Diffuse => 'D',
+            Event::Glossy => 'G',
+            Event::Transmission => 'T',
+            Event::Volume => 'V',
+            Event::Light => 'L',
+            Event::Background => 'B'
+        }
+    }
+
+    pub fn from_symbol(symbol: char) -> Option<Event> {
+        Event::ALL.iter().copied().find(|event| event.symbol() == symbol)
+    }
+
+    /// Returns the event of scattering from a surface through the given lobe.
+    pub fn from_flags(flags: BsdfFlags) -> Event {
+        if flags.contains(BsdfFlags::TRANSMISSION) {
+            Event::Transmission
+        } else if flags.contains(BsdfFlags::DIFFUSE) {
+            Event::Diffuse
+        } else {
+            Event::Glossy
+        }
+    }
+
+    fn bit(&self) -> u8 {
+        1 << Event::ALL.iter().position(|event| event == self).unwrap()
+    }
+}
+
+/// A light path expression, which matches the paths whose sequence of events it describes.
+///
+/// Events are written with the symbols of `Event`, and `.` matches any event. A set of events is written in brackets,
+/// as in `[DG]`, or `[^DG]` for every other event. Expressions may be followed by `*`, `+` or `?` to repeat them,
+/// separated by `|` to match either, and grouped with parentheses. The expression must match the whole path, from
+/// the camera to the light, so `C D .+ [LB]` matches light which is reflected diffusely by the first surface seen,
+/// after bouncing at least once before reaching it.
+///
+/// Expressions are compiled to a nondeterministic finite automaton, which follows a path one event at a time.
+#[derive (Clone, Debug, PartialEq)]
+pub struct LightPathExpression {
+    states: Vec<State>,
+    /// The states reachable from each state without consuming an event, as bit masks.
+    closures: Vec<u64>,
+    start: PathState
+}
+
+/// The states of an automaton after following part of a path.
+#[derive (Copy, Clone, Debug, PartialEq, Eq)]
+pub struct PathState(u64);
+
+#[derive (Copy, Clone, Debug, PartialEq)]
+enum State {
+    Accept,
+    /// Consumes an event in the mask, and moves to the next state.
+    Event(u8, usize),
+    /// Moves to either state without consuming an event.
+    Split(usize, usize)
+}
+
+#[derive (Clone, Debug, PartialEq)]
+enum Node {
+    Events(u8),
+    Concat(Vec<Node>),
+    Alternation(Vec<Node>),
+    Star(Box<Node>),
+    Plus(Box<Node>),
+    Optional(Box<Node>)
+}
+
+/// The most states an automaton may have, so that a set of states fits into a single bit mask.
+const MAX_STATES: usize = 64;
+
+impl LightPathExpression {
+    /// Parses and compiles an expression, or returns a description of the error in it.
+    pub fn parse(source: &str) -> Result<LightPathExpression, String> {
+        let symbols: Vec<char> = source.chars().filter(|c| !c.is_whitespace()).collect();
+        let mut position = 0;
+        let node = parse_alternation(&symbols, &mut position)?;
+        if position < symbols.len() {
+            return Err(format!("Unexpected '{}' in light path expression", symbols[position]));
+        }
+
+        let mut states = vec![State::Accept];
+        let start = compile(&node, 0, &mut states);
+        if states.len() > MAX_STATES {
+            return Err(String::from("Light path expression is too long"));
+        }
+        let closures = (0..states.len()).map(|index| closure(&states, index)).collect::<Vec<_>>();
+        let start = PathState(closures[start]);
+        Ok(LightPathExpression { states, closures, start })
+    }
+
+    /// Returns the state before any events have been followed.
+    pub fn start(&self) -> PathState {
+        self.start
+    }
+
+    /// Returns the state after following an event from the given state.
+    pub fn step(&self, state: PathState, event: Event) -> PathState {
+        let mut next_state = 0;
+        let mut remaining = state.0;
+        while remaining != 0 {
+            let index = remaining.trailing_zeros() as usize;
+            remaining &= remaining - 1;
+            if let State::Event(mask, next) = self.states[index] {
+                if mask & event.bit() != 0 {
+                    next_state |= self.closures[next];
+                }
+            }
+        }
+        PathState(next_state)
+    }
+
+    /// Returns true if the path followed to reach the state matches the expression.
+    pub fn accepts(&self, state: PathState) -> bool {
+        state.0 & 1 != 0
+    }
+
+    /// Returns true if the whole sequence of events matches the expression.
+    pub fn matches(&self, events: &[Event]) -> bool {
+        self.accepts(events.iter().fold(self.start, |state, event| self.step(state, *event)))
+    }
+}
+
+fn parse_alternation(symbols: &[char], position: &mut usize) -> Result<Node, String> {
+    let mut options = vec![parse_concat(symbols, position)?];
+    while symbols.get(*position) == Some(&'|') {
+        *position += 1;
+        options.push(parse_concat(symbols, position)?);
+    }
+    Ok(if options.len() == 1 { options.pop().unwrap() } else { Node::Alternation(options) })
+}
+
+fn parse_concat(symbols: &[char], position: &mut usize) -> Result<Node, String> {
+    let mut sequence = Vec::new();
+    while let Some(symbol) = symbols.get(*position) {
+        if *symbol == '|' || *symbol == ')' {
+            break;
+        }
+        let mut node = parse_atom(symbols, position)?;
+        while let Some(symbol) = symbols.get(*position) {
+            node = match symbol {
+                '*' => Node::Star(Box::new(node)),
+                '+' => Node::Plus(Box::new(node)),
+                '?' => Node::Optional(Box::new(node)),
+                _ => break
+            };
+            *position += 1;
+        }
+        sequence.push(node);
+    }
+    if sequence.is_empty() {
+        return Err(String::from("Empty light path expression"));
+    }
+    Ok(if sequence.len() == 1 { sequence.pop().unwrap() } else { Node::Concat(sequence) })
+}
+
+fn parse_atom(symbols: &[char], position: &mut usize) -> Result<Node, String> {
+    let symbol = symbols[*position];
+    *position += 1;
+    match symbol {
+        '.' => Ok(Node::Events(u8::MAX)),
+        '(' => {
+            let node = parse_alternation(symbols, position)?;
+            if symbols.get(*position) != Some(&')') {
+                return Err(String::from("Unclosed '(' in light path expression"));
+            }
+            *position += 1;
+            Ok(node)
+        }
+        '[' => {
+            let negated = symbols.get(*position) == Some(&'^');
+            if negated {
+                *position += 1;
+            }
+            let mut mask = 0;
+            loop {
+                match symbols.get(*position) {
+                    Some(']') => break,
+                    Some(symbol) => mask |= parse_event(*symbol)?.bit(),
+                    None => return Err(String::from("Unclosed '[' in light path expression"))
+                }
+                *position += 1;
+            }
+            *position += 1;
+            Ok(Node::Events(if negated { !mask } else { mask }))
+        }
+        symbol => Ok(Node::Events(parse_event(symbol)?.bit()))
+    }
+}
+
+fn parse_event(symbol: char) -> Result<Event, String> {
+    Event::from_symbol(symbol).ok_or(format!("Unknown event '{}' in light path expression", symbol))
+}
+
+/// Adds the states matching the node to the automaton, leading on to the state `next`. Returns the first of them.
+fn compile(node: &Node, next: usize, states: &mut Vec<State>) -> usize {
+    let push = |state: State, states: &mut Vec<State>| {
+        states.push(state);
+        states.len() - 1
+    };
+    match node {
+        Node::Events(mask) => push(State::Event(*mask, next), states),
+        Node::Concat(sequence) => sequence.iter().rev().fold(next, |next, node| compile(node, next, states)),
+        Node::Alternation(options) => {
+            let mut starts = options.iter().map(|option| compile(option, next, states)).collect::<Vec<_>>();
+            let last = starts.pop().unwrap();
+            starts.iter().rev().fold(last, |other, start| push(State::Split(*start, other), states))
+        }
+        Node::Star(node) => {
+            let split = push(State::Split(0, next), states);
+            let start = compile(node, split, states);
+            states[split] = State::Split(start, next);
+            split
+        }
+        Node::Plus(node) => {
+            let split = push(State::Split(0, next), states);
+            let start = compile(node, split, states);
+            states[split] = State::Split(start, next);
+            start
+        }
+        Node::Optional(node) => {
+            let start = compile(node, next, states);
+            push(State::Split(start, next), states)
+        }
+    }
+}
+
+/// Returns the states reachable from the given state without consuming an event, including the state itself.
+fn closure(states: &[State], index: usize) -> u64 {
+    let mut reached = 0u64;
+    let mut stack = vec![index];
+    while let Some(index) = stack.pop() {
+        if reached & (1 << index) != 0 {
+            continue;
+        }
+        reached |= 1 << index;
+        if let State::Split(a, b) = states[index] {
+            stack.push(a);
+            stack.push(b);
+        }
+    }
+    reached
+}
+
+/// A named image built from the light arriving along the paths which match a light path expression.
+#[derive (Clone, Debug, PartialEq)]
+pub struct RenderLayer {
+    pub name: String,
+    pub source: String,
+    pub expression: LightPathExpression
+}
+
+impl RenderLayer {
+    pub fn new(name: &str, source: &str) -> Result<RenderLayer, String> {
+        Ok(RenderLayer { name: String::from(name), source: String::from(source), expression: LightPathExpression::parse(source)? })
+    }
+
+    /// Parses a layer written as `name = expression`. Its name must differ from those of the `existing` layers.
+    pub fn parse(definition: &str, existing: &[RenderLayer]) -> Result<RenderLayer, String> {
+        let (name, source) = definition.split_once('=').ok_or(String::from("Layers are written as 'name = expression'"))?;
+        let name = name.trim();
+        if name.is_empty() {
+            return Err(String::from("Layers must have a name"));
+        }
+        if existing.iter().any(|layer| layer.name == name) {
+            return Err(format!("There is already a layer named '{}'", name));
+        }
+        RenderLayer::new(name, source.trim())
+    }
+
+    /// Returns layers which split the image by the first event along each path. Every path is matched by exactly one
+    /// of them, so they sum to the whole image.
+    pub fn defaults() -> Vec<RenderLayer> {
+        [("Emission", "C L"), ("Background", "C B"), ("Direct diffuse", "C D [LB]"), ("Indirect diffuse", "C D .+ [LB]"),
+         ("Glossy", "C G .* [LB]"), ("Transmission", "C T .* [LB]"), ("Volume", "C V .* [LB]")]
+            .iter().map(|(name, source)| RenderLayer::new(name, source).unwrap()).collect()
+    }
+}
+
+/// Follows a path as it is traced, and sorts the light it carries into render layers and light groups. There is a light
+/// group for every group of lights in the scene, followed by one for the light from the background.
+pub struct LayerTracker<'a> {
+    layers: &'a [RenderLayer],
+    states: Vec<PathState>,
+    /// The light carried by the path into each layer.
+    pub values: Vec<Color>,
+    /// The light carried by the path from each light group.
+    pub light_groups: Vec<Color>
+}
+
+impl<'a> LayerTracker<'a> {
+    /// Starts following a path from the camera, sorting its light into the given layers and number of light groups.
+    pub fn new(layers: &'a [RenderLayer], light_group_count: usize) -> LayerTracker<'a> {
+        let states = layers.iter().map(|layer| layer.expression.step(layer.expression.start(), Event::Camera)).collect();
+        LayerTracker { layers, states, values: vec![Color::zeros(); layers.len()], light_groups: vec![Color::zeros(); light_group_count] }
+    }
+
+    /// Extends the path by an event.
+    pub fn scatter(&mut self, event: Event) {
+        for (state, layer) in self.states.iter_mut().zip(self.layers.iter()) {
+            *state = layer.expression.step(*state, event);
+        }
+    }
+
+    /// Adds light which reaches the path through the given events, to every layer whose expression matches the path
+    /// extended by them. The light is also added to the light group of the light it was emitted by, or to the last
+    /// group if it came from the background. Light from groups which are not being followed is left out of them.
+    pub fn add(&mut self, events: &[Event], light_group: Option<usize>, radiance: &Color) {
+        for ((state, layer), value) in self.states.iter().zip(self.layers.iter()).zip(self.values.iter_mut()) {
+            let state = events.iter().fold(*state, |state, event| layer.expression.step(state, *event));
+            if layer.expression.accepts(state) {
+                *value += radiance;
+            }
+        }
+
+        let group_count = self.light_groups.len();
+        let index = match light_group {
+            Some(group) if group + 1 < group_count => group,
+            None if group_count > 0 => group_count - 1,
+            _ => return
+        };
+        self.light_groups[index] += radiance;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn events(path: &str) -> Vec<Event> {
+        path.chars().map(|symbol| Event::from_symbol(symbol).unwrap()).collect()
+    }
+
+    #[test]
+    fn test_matches(){
+        //Case 1: Sequences, wildcards and sets
+        let expression = LightPathExpression::parse("C D [LB]").unwrap();
+        assert!(expression.matches(&events("CDL")));
+        assert!(expression.matches(&events("CDB")));
+        assert!(!expression.matches(&events("CGL")));
+        assert!(!expression.matches(&events("CDDL")));
+        assert!(!expression.matches(&events("CD")));
+
+        //Case 2: Repetition
+        let expression = LightPathExpression::parse("C D .+ L").unwrap();
+        assert!(expression.matches(&events("CDDL")));
+        assert!(expression.matches(&events("CDGTVL")));
+        assert!(!expression.matches(&events("CDL")));
+        let expression = LightPathExpression::parse("C G* D? L").unwrap();
+        assert!(expression.matches(&events("CL")));
+        assert!(expression.matches(&events("CGGDL")));
+        assert!(!expression.matches(&events("CDDL")));
+
+        //Case 3: Alternation, grouping and negated sets
+        let expression = LightPathExpression::parse("C (D|GT)+ [^B]").unwrap();
+        assert!(expression.matches(&events("CDGTDL")));
+        assert!(!expression.matches(&events("CDGL")));
+        assert!(!expression.matches(&events("CDB")));
+
+        //Case 4: Errors are reported
+        assert!(LightPathExpression::parse("C X L").is_err());
+        assert!(LightPathExpression::parse("C (D L").is_err());
+        assert!(LightPathExpression::parse("C [D L").is_err());
+        assert!(LightPathExpression::parse("C D)").is_err());
+        assert!(LightPathExpression::parse("").is_err());
+        assert!(LightPathExpression::parse(&"(D|G)".repeat(40)).is_err());
+        assert!(RenderLayer::parse("C D L", &[]).is_err());
+        assert_eq!(RenderLayer::parse("Mirror = C G+ L", &[]).unwrap().name, "Mirror");
+        assert_eq!(RenderLayer::parse(" Glossy = C G L", &RenderLayer::defaults()), Err(String::from("There is already a layer named 'Glossy'")));
+    }
+
+    #[test]
+    fn test_defaults(){
+        //Every path is matched by exactly one of the default layers
+        let layers = RenderLayer::defaults();
+        let scatters = ["", "D", "G", "T", "V", "DD", "DG", "GD", "TTV", "VDG", "DGTV"];
+        for scatter in scatters {
+            for end in ["L", "B"] {
+                let path = events(&format!("C{}{}", scatter, end));
+                assert_eq!(layers.iter().filter(|layer| layer.expression.matches(&path)).count(), 1);
+            }
+        }
+    }
+}
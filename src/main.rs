@@ -13,6 +13,7 @@ extern crate delegate;
 extern crate nalgebra;
 
 pub mod camera;
+pub mod cli;
 pub mod material;
 pub mod util;
 pub mod gui;
@@ -21,10 +22,20 @@ pub mod geometry;
 pub mod image;
 pub mod rasterizing;
 pub mod primitives;
+pub mod lights;
+pub mod media;
+pub mod integrators;
 pub mod scenes;
+pub mod scene_file;
 pub mod raytracing;
 pub mod spectra;
 pub mod sampler;
+pub mod film;
+pub mod aov;
+pub mod denoise;
+pub mod lpe;
+pub mod gltf_import;
+pub mod pbrt;
 pub mod vec;
 
 use eframe::egui::Vec2;
@@ -35,6 +46,13 @@ use threads::*;
 use geometry::*;
 use primitives::*;
 use scenes::*;
+use lights::*;
+use raytracing::MisHeuristic;
+use integrators::Integrator;
+use spectra::ColorMode;
+use sampler::SamplerType;
+use film::{Filter, FilterType};
+use lpe::RenderLayer;
 use eframe::egui::*;
 use nalgebra::{Vector3};
 
@@ -49,31 +67,15 @@ use std::thread::Thread;
 
 fn main() {
 
-    //Scene
-    let (geometric_primitives, background, look_from, look_at) = scenes::sphere_world();
-    let bvh = Primitive::new_bvh(geometric_primitives.clone().to_bvh());
-    let mut primitives = Primitives::new();
-    primitives.add(bvh);
+    //Headless rendering
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("render") {
+        std::process::exit(cli::run(&args[1..]));
+    }
 
-    //Image
-    let aspect_ratio = 3.0/2.0;
-    let image_width = 800;
-    let image_height=  ((image_width as f64)/aspect_ratio) as usize;
-    let samples_per_pixel = 1000;
-    let max_depth=  50;
-
-    //Camera
-    let v_up: Vector3<f64> = Vector3::<f64>::new(0.0, 1.0, 0.0);
-    let focus_dist = 10.0;
-    let aperture = 0.0;
-    let camera_settings = CameraSettings { look_from, look_at, v_up, v_fov: 20.0, aspect_ratio, aperture, focus_dist, image_height, image_width};
-    let camera = Camera::new(camera_settings);
-    
-    //Package data
-    let image_settings = ImageSettings { image_width, image_height };
-    let raytrace_settings = RayTraceSettings { max_depth, samples_per_pixel };
-    let scene = SceneData { raytracing_primitives: primitives, rasterization_primitives: geometric_primitives, background };
-    let settings = GlobalSettings { raytrace_settings, image_settings, camera, scene, id: 1 };
+    //Settings
+    let settings = cli::RenderOptions::default().to_settings().unwrap();
+    let (image_width, image_height) = (settings.image_settings.image_width, settings.image_settings.image_height);
 
     //Threading
     let mut thread_coordinator = ThreadCoordinator::new(settings.clone());
@@ -1,3 +1,8 @@
+pub mod dispersion;
+
+use std::f64::consts::PI;
+use std::ops::BitOr;
+
 use nalgebra::{Vector3, Unit};
 
 use crate::image::{Color};
@@ -6,6 +11,8 @@ use crate::{util::*, sampler};
 use crate::raytracing::{HitRecord, Ray};
 use crate::sampler::*;
 
+use dispersion::{RefractiveIndex, REFERENCE_WAVELENGTH};
+
 #[derive(Default, Clone, Copy, PartialEq)]
 pub struct Lambertian{
     pub albedo: Color
@@ -20,30 +27,184 @@ pub struct Metal{
 
 #[derive(Default, Clone, Copy, PartialEq)]
 pub struct Dielectric{
-    index_of_refraction :f64,
+    refractive_index: RefractiveIndex,
 }
 
 #[derive(Default, Clone, Copy, PartialEq)]
 pub struct DiffuseLights{
-    color: Color
+    color: Color,
+    light_group: usize
 }
 
+/// A surface which does not interact with light, used to mark the boundary of a participating medium.
+#[derive(Default, Clone, Copy, PartialEq)]
+pub struct Interface;
+
 
 #[derive(Clone, Copy, PartialEq)]
 pub enum Material{
     Lambertian(Lambertian),
     Metal(Metal),
     Dielectric(Dielectric),
-    DiffuseLights(DiffuseLights)
+    DiffuseLights(DiffuseLights),
+    Interface(Interface)
+}
+
+/// Describes the lobes of a BSDF, or the lobe from which a particular direction was sampled.
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BsdfFlags(u8);
+
+impl BsdfFlags {
+    pub const NONE: BsdfFlags = BsdfFlags(0);
+    pub const REFLECTION: BsdfFlags = BsdfFlags(1);
+    pub const TRANSMISSION: BsdfFlags = BsdfFlags(1 << 1);
+    pub const DIFFUSE: BsdfFlags = BsdfFlags(1 << 2);
+    pub const GLOSSY: BsdfFlags = BsdfFlags(1 << 3);
+    pub const SPECULAR: BsdfFlags = BsdfFlags(1 << 4);
+
+    /// Returns true if every flag set in `other` is also set in `self`.
+    pub fn contains(&self, other: BsdfFlags) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Returns true if any flag set in `other` is also set in `self`.
+    pub fn intersects(&self, other: BsdfFlags) -> bool {
+        self.0 & other.0 != 0
+    }
+
+    /// Returns true if the flags describe a delta distribution, which can only be found by sampling.
+    pub fn is_specular(&self) -> bool {
+        self.intersects(BsdfFlags::SPECULAR)
+    }
+
+    /// Returns true if the flags describe a lobe that can be evaluated for arbitrary pairs of directions.
+    pub fn is_non_specular(&self) -> bool {
+        self.intersects(BsdfFlags::DIFFUSE | BsdfFlags::GLOSSY)
+    }
+}
+
+impl BitOr for BsdfFlags {
+    type Output = BsdfFlags;
+
+    fn bitor(self, rhs: BsdfFlags) -> BsdfFlags {
+        BsdfFlags(self.0 | rhs.0)
+    }
+}
+
+/// A direction sampled from a BSDF.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BsdfSample {
+    /// The sampled direction, pointing away from the surface.
+    pub wi: Vector3<f64>,
+    /// The value of the BSDF for the pair of directions. This excludes the cosine foreshortening term.
+    pub f: Color,
+    /// The probability density of sampling `wi`, with respect to solid angle. For specular lobes this is the 
+    /// discrete probability of choosing the lobe.
+    pub pdf: f64,
+    /// The lobe from which `wi` was sampled.
+    pub flags: BsdfFlags
+}
+
+/// An orthonormal basis in which the normal is the z-axis.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ShadingFrame {
+    pub s: Vector3<f64>,
+    pub t: Vector3<f64>,
+    pub n: Vector3<f64>
+}
+
+impl ShadingFrame {
+    pub fn from_normal(normal: &Vector3<f64>) -> ShadingFrame {
+        let n = normal.normalize();
+        let (s, t) = n.coordinate_system();
+        ShadingFrame { s, t, n }
+    }
+
+    pub fn to_local(&self, v: &Vector3<f64>) -> Vector3<f64> {
+        Vector3::<f64>::new(v.dot(&self.s), v.dot(&self.t), v.dot(&self.n))
+    }
+
+    pub fn to_world(&self, v: &Vector3<f64>) -> Vector3<f64> {
+        self.s * v[0] + self.t * v[1] + self.n * v[2]
+    }
+}
+
+/// The scattering function of a material at a particular hit. Directions are given in world space, and are converted 
+/// to the shading frame of the hit before being passed to the material. Both `wo` and `wi` point away from the surface.
+#[derive(Clone, Copy, PartialEq)]
+pub struct Bsdf {
+    frame: ShadingFrame,
+    bxdf: Material
+}
+
+impl Bsdf {
+    pub fn new(frame: ShadingFrame, bxdf: Material) -> Bsdf {
+        Bsdf { frame, bxdf }
+    }
+
+    /// Samples an incident direction for light leaving along `wo`, using three uniform random numbers in [0, 1).
+    pub fn sample(&self, wo: &Vector3<f64>, u: [f64; 3]) -> Option<BsdfSample> {
+        let wo = self.frame.to_local(&wo.normalize());
+        let mut sample = self.bxdf.sample(&wo, u)?;
+        sample.wi = self.frame.to_world(&sample.wi);
+        Some(sample)
+    }
+
+    pub fn eval(&self, wo: &Vector3<f64>, wi: &Vector3<f64>) -> Color {
+        self.bxdf.eval(&self.frame.to_local(&wo.normalize()), &self.frame.to_local(&wi.normalize()))
+    }
+
+    pub fn pdf(&self, wo: &Vector3<f64>, wi: &Vector3<f64>) -> f64 {
+        self.bxdf.pdf(&self.frame.to_local(&wo.normalize()), &self.frame.to_local(&wi.normalize()))
+    }
+
+    pub fn flags(&self) -> BsdfFlags {
+        self.bxdf.flags()
+    }
+
+    pub fn frame(&self) -> ShadingFrame {
+        self.frame
+    }
 }
 
 impl Scatter for Material {
-    fn scatter(&self, r : &Ray, rec: &HitRecord) -> Option<(Color, Ray)> {
+    fn sample(&self, wo: &Vector3<f64>, u: [f64; 3]) -> Option<BsdfSample> {
+        match *self {
+            Material::Lambertian(material) => material.sample(wo, u),
+            Material::Metal(material) => material.sample(wo, u),
+            Material::Dielectric(material) => material.sample(wo, u),
+            Material::DiffuseLights(material) => material.sample(wo, u),
+            Material::Interface(material) => material.sample(wo, u)
+        }
+    }
+
+    fn eval(&self, wo: &Vector3<f64>, wi: &Vector3<f64>) -> Color {
+        match *self {
+            Material::Lambertian(material) => material.eval(wo, wi),
+            Material::Metal(material) => material.eval(wo, wi),
+            Material::Dielectric(material) => material.eval(wo, wi),
+            Material::DiffuseLights(material) => material.eval(wo, wi),
+            Material::Interface(material) => material.eval(wo, wi)
+        }
+    }
+
+    fn pdf(&self, wo: &Vector3<f64>, wi: &Vector3<f64>) -> f64 {
         match *self {
-            Material::Lambertian(material) => material.scatter(r, rec),
-            Material::Metal(material) => material.scatter(r, rec),
-            Material::Dielectric(material) => material.scatter(r, rec),
-            Material::DiffuseLights(material) => material.scatter(r, rec)
+            Material::Lambertian(material) => material.pdf(wo, wi),
+            Material::Metal(material) => material.pdf(wo, wi),
+            Material::Dielectric(material) => material.pdf(wo, wi),
+            Material::DiffuseLights(material) => material.pdf(wo, wi),
+            Material::Interface(material) => material.pdf(wo, wi)
+        }
+    }
+
+    fn flags(&self) -> BsdfFlags {
+        match *self {
+            Material::Lambertian(material) => material.flags(),
+            Material::Metal(material) => material.flags(),
+            Material::Dielectric(material) => material.flags(),
+            Material::DiffuseLights(material) => material.flags(),
+            Material::Interface(material) => material.flags()
         }
     }
 
@@ -52,7 +213,8 @@ impl Scatter for Material {
             Material::Lambertian(material) => material.emit(),
             Material::Metal(material) => material.emit(),
             Material::Dielectric(material) => material.emit(),
-            Material::DiffuseLights(material) => material.emit()
+            Material::DiffuseLights(material) => material.emit(),
+            Material::Interface(material) => material.emit()
         }
     }
 }
@@ -70,9 +232,94 @@ impl Material {
         Material::Dielectric(Dielectric::new(ir))
     }
 
+    /// Returns a dielectric whose index of refraction may vary with wavelength, such as one of the glass presets.
+    pub fn new_dispersive_dielectric(refractive_index: RefractiveIndex) -> Material{
+        Material::Dielectric(Dielectric::new_dispersive(refractive_index))
+    }
+
     pub fn new_diffuse_light(color: Color) -> Material{
         Material::DiffuseLights(DiffuseLights::new(color))
     }
+
+    /// Returns a light belonging to the light group with the given index, so that its contribution to the image can 
+    /// be adjusted after rendering.
+    pub fn new_diffuse_light_in_group(color: Color, light_group: usize) -> Material{
+        Material::DiffuseLights(DiffuseLights::new_in_group(color, light_group))
+    }
+
+    pub fn new_interface() -> Material{
+        Material::Interface(Interface)
+    }
+
+    /// Returns true if the direction of light scattered by the material depends on its wavelength.
+    pub fn is_dispersive(&self) -> bool {
+        matches!(self, Material::Dielectric(material) if material.refractive_index.is_dispersive())
+    }
+
+    /// Returns true if the material only marks the boundary of a medium, so that rays pass straight through it.
+    pub fn is_interface(&self) -> bool {
+        matches!(self, Material::Interface(_))
+    }
+
+    /// Returns a number identifying the material. Materials of the same kind with the same parameters share an id.
+    pub fn id(&self) -> u64 {
+        let (kind, color, parameter) = match *self {
+            Material::Lambertian(material) => (0, material.albedo, 0.0),
+            Material::Metal(material) => (1, material.albedo, material.fuzz),
+            Material::Dielectric(material) => (2, Color::zeros(), material.refractive_index.at(REFERENCE_WAVELENGTH)),
+            Material::DiffuseLights(material) => (3, material.color, material.light_group as f64),
+            Material::Interface(_) => (4, Color::zeros(), 0.0)
+        };
+        sampler::hash(&[kind, color[0].to_bits(), color[1].to_bits(), color[2].to_bits(), parameter.to_bits()])
+    }
+
+    /// Returns the index of the light group the material's emission belongs to, or `None` if the material cannot emit
+    /// light.
+    pub fn light_group(&self) -> Option<usize> {
+        match self {
+            Material::DiffuseLights(material) => Some(material.light_group),
+            _ => None
+        }
+    }
+
+    /// Returns the base colour of the material. Emissive materials return their emission, and materials which do not 
+    /// absorb light return white.
+    pub fn albedo(&self) -> Color {
+        match *self {
+            Material::Lambertian(material) => material.albedo,
+            Material::Metal(material) => material.albedo,
+            Material::Dielectric(_) | Material::Interface(_) => Color::new(1.0, 1.0, 1.0),
+            Material::DiffuseLights(material) => material.color
+        }
+    }
+
+    /// Returns the BSDF of the material at the hit. The shading frame is built from the normal of the hit, which 
+    /// faces the incoming ray, so a dielectric that is being exited has its index of refraction inverted. Dispersive
+    /// dielectrics are evaluated at the reference wavelength.
+    pub fn bsdf(&self, rec: &HitRecord) -> Bsdf {
+        self.bsdf_at_wavelength(rec, REFERENCE_WAVELENGTH)
+    }
+
+    /// Returns the BSDF of the material at the hit for light of the given wavelength in nanometres.
+    pub fn bsdf_at_wavelength(&self, rec: &HitRecord, wavelength: f64) -> Bsdf {
+        let bxdf = match *self {
+            Material::Dielectric(material) => {
+                let ir = material.refractive_index.at(wavelength);
+                Material::Dielectric(Dielectric::new(if rec.front_face { ir } else { 1.0 / ir }))
+            }
+            material => material
+        };
+        Bsdf::new(ShadingFrame::from_normal(&rec.normal), bxdf)
+    }
+}
+
+/// Mirrors a direction in the shading frame about the normal.
+fn reflect_local(w: &Vector3<f64>) -> Vector3<f64> {
+    Vector3::<f64>::new(-w[0], -w[1], w[2])
+}
+
+fn same_hemisphere(w: &Vector3<f64>, wp: &Vector3<f64>) -> bool {
+    w[2] * wp[2] > 0.0
 }
 
 impl Lambertian{
@@ -87,18 +334,42 @@ impl Lambertian{
         if scatter_direction.near_zero(){
             scatter_direction = rec.normal;
         }
-        let scattered = Ray::new(rec.p, scatter_direction);
+        let scattered = Ray::new_at_time(rec.p, scatter_direction, rec.time);
         let attenuation = self.albedo;
         Some((attenuation, scattered))
     }
 }
 
 impl Scatter for Lambertian {
-    fn scatter(&self, _: &Ray, rec: &HitRecord) -> Option<(Color, Ray)>{
+    /// Directions are found by offsetting the normal by a random unit vector, and so are cosine-distributed.
+    fn sample(&self, wo: &Vector3<f64>, u: [f64; 3]) -> Option<BsdfSample> {
+        let normal = Vector3::<f64>::new(0.0, 0.0, 1.0);
+        let mut wi = normal + uniform_sphere([u[0], u[1]]);
 
-        let reflect_dir = sampler::rand_unit_vec();
-        self.deterministic_scatter( rec, reflect_dir.into_inner())
+        // Catch degenerate Scatter direction
+        if wi.near_zero(){
+            wi = normal;
+        }
+        let wi = wi.normalize();
+        Some(BsdfSample { wi, f: self.eval(wo, &wi), pdf: self.pdf(wo, &wi), flags: self.flags() })
+    }
 
+    fn eval(&self, wo: &Vector3<f64>, wi: &Vector3<f64>) -> Color {
+        if !same_hemisphere(wo, wi) {
+            return Color::new(0.0, 0.0, 0.0);
+        }
+        self.albedo / PI
+    }
+
+    fn pdf(&self, wo: &Vector3<f64>, wi: &Vector3<f64>) -> f64 {
+        if !same_hemisphere(wo, wi) {
+            return 0.0;
+        }
+        wi[2].abs() / PI
+    }
+
+    fn flags(&self) -> BsdfFlags {
+        BsdfFlags::REFLECTION | BsdfFlags::DIFFUSE
     }
 }
 
@@ -111,7 +382,7 @@ impl Metal {
 
     fn deterministic_scatter(&self, r_in: &Ray, rec: &HitRecord, rand_in_unit_sphere: Vector3<f64>) -> Option<(Color, Ray)>{
         let reflected = Unit::new_normalize(r_in.direction()).reflect(&rec.normal);
-        let scattered = Ray::new(rec.p, reflected + self.fuzz*rand_in_unit_sphere);
+        let scattered = Ray::new_at_time(rec.p, reflected + self.fuzz*rand_in_unit_sphere, rec.time);
         let attenuation = self.albedo;
         if scattered.direction().dot(&rec.normal) > 0.0{
             Some((attenuation, scattered))
@@ -122,15 +393,77 @@ impl Metal {
 }
 
 impl Scatter for Metal {
-    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<(Color, Ray)>{
-        let fuzz_dir = sampler::rand_in_unit_sphere();
-        self.deterministic_scatter(r_in, rec, fuzz_dir)
+    /// Directions point from the hit point towards a point distributed uniformly within the ball of radius `fuzz` 
+    /// centred on the tip of the reflected direction. Directions below the surface are absorbed.
+    fn sample(&self, wo: &Vector3<f64>, u: [f64; 3]) -> Option<BsdfSample> {
+        let reflected = reflect_local(wo);
+        if self.fuzz == 0.0 {
+            if reflected[2] <= 0.0 {
+                return None;
+            }
+            let f = self.albedo / reflected[2];
+            return Some(BsdfSample { wi: reflected, f, pdf: 1.0, flags: BsdfFlags::REFLECTION | BsdfFlags::SPECULAR });
+        }
+
+        let direction = reflected + self.fuzz * uniform_ball(u);
+        if direction[2] <= 0.0 {
+            return None;
+        }
+        let wi = direction.normalize();
+        let pdf = self.pdf(wo, &wi);
+        if pdf <= 0.0 {
+            return None;
+        }
+        Some(BsdfSample { wi, f: self.eval(wo, &wi), pdf, flags: self.flags() })
+    }
+
+    fn eval(&self, wo: &Vector3<f64>, wi: &Vector3<f64>) -> Color {
+        if !same_hemisphere(wo, wi) {
+            return Color::new(0.0, 0.0, 0.0);
+        }
+        self.albedo * (self.pdf(wo, wi) / wi[2].abs())
+    }
+
+    /// The density with respect to solid angle is found by integrating the uniform density of the fuzz ball along 
+    /// the section of the direction that lies within it.
+    fn pdf(&self, wo: &Vector3<f64>, wi: &Vector3<f64>) -> f64 {
+        if self.fuzz == 0.0 || !same_hemisphere(wo, wi) {
+            return 0.0;
+        }
+        let b = wi.dot(&reflect_local(wo));
+        let discriminant = b * b - 1.0 + self.fuzz * self.fuzz;
+        if discriminant <= 0.0 {
+            return 0.0;
+        }
+        let t_far = b + discriminant.sqrt();
+        let t_near = (b - discriminant.sqrt()).max(0.0);
+        if t_far <= 0.0 {
+            return 0.0;
+        }
+        (t_far.powi(3) - t_near.powi(3)) / (4.0 * PI * self.fuzz.powi(3))
+    }
+
+    fn flags(&self) -> BsdfFlags {
+        if self.fuzz == 0.0 {
+            BsdfFlags::REFLECTION | BsdfFlags::SPECULAR
+        } else {
+            BsdfFlags::REFLECTION | BsdfFlags::GLOSSY
+        }
     }
 }
 
 impl Dielectric {
     pub fn new(ir: f64) -> Dielectric{
-        Dielectric{index_of_refraction: ir}
+        Dielectric{refractive_index: RefractiveIndex::Constant(ir)}
+    }
+
+    pub fn new_dispersive(refractive_index: RefractiveIndex) -> Dielectric{
+        Dielectric{refractive_index}
+    }
+
+    /// Returns the index of refraction at the reference wavelength.
+    fn index_of_refraction(&self) -> f64 {
+        self.refractive_index.at(REFERENCE_WAVELENGTH)
     }
 
     fn reflectance(cosine: f64, ref_idx: f64) -> f64{
@@ -141,9 +474,9 @@ impl Dielectric {
 
     fn deterministic_scatter(&self, r_in: &Ray, rec: &HitRecord, reflectance_test: f64) -> Option<(Color, Ray)>{
         let attenuation = Color::new(1.0, 1.0, 1.0);
-        let mut refraction_ratio = self.index_of_refraction;
+        let mut refraction_ratio = self.index_of_refraction();
         if rec.front_face{
-            refraction_ratio = 1.0/self.index_of_refraction;
+            refraction_ratio = 1.0/self.index_of_refraction();
         }
         
         let unit_dir = r_in.direction().normalize();
@@ -158,37 +491,101 @@ impl Dielectric {
         } else{
             direction = Vector3::<f64>::refract(&unit_dir, &rec.normal, refraction_ratio);
         }
-        let scattered = Ray::new(rec.p, direction);
+        let scattered = Ray::new_at_time(rec.p, direction, rec.time);
         Some((attenuation, scattered))
     }
 }
 
 impl Scatter for Dielectric {
-    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<(Color, Ray)>{
-        let rand = sampler::rand_double(0.0, 1.0);
-        self.deterministic_scatter(r_in, rec, rand)
+    /// The shading frame is assumed to face the incoming light, which is entering the dielectric. The reflected and 
+    /// refracted directions are chosen with probabilities given by the reflectance, which therefore cancels from the 
+    /// ratio of `f` to `pdf`.
+    fn sample(&self, wo: &Vector3<f64>, u: [f64; 3]) -> Option<BsdfSample> {
+        let refraction_ratio = 1.0 / self.index_of_refraction();
+        let cos_theta = wo[2].min(1.0);
+        let sin_theta = (1.0 - cos_theta*cos_theta).sqrt();
+
+        let reflectance = if refraction_ratio*sin_theta > 1.0 {
+            1.0
+        } else {
+            Dielectric::reflectance(cos_theta, refraction_ratio)
+        };
+
+        if reflectance > u[0] {
+            let wi = reflect_local(wo);
+            let f = Color::new(1.0, 1.0, 1.0) * (reflectance / wi[2].abs());
+            Some(BsdfSample { wi, f, pdf: reflectance, flags: BsdfFlags::REFLECTION | BsdfFlags::SPECULAR })
+        } else {
+            let wi = Vector3::<f64>::refract(&-wo, &Vector3::<f64>::new(0.0, 0.0, 1.0), refraction_ratio);
+            let f = Color::new(1.0, 1.0, 1.0) * ((1.0 - reflectance) / wi[2].abs());
+            Some(BsdfSample { wi, f, pdf: 1.0 - reflectance, flags: BsdfFlags::TRANSMISSION | BsdfFlags::SPECULAR })
+        }
+    }
+
+    fn flags(&self) -> BsdfFlags {
+        BsdfFlags::REFLECTION | BsdfFlags::TRANSMISSION | BsdfFlags::SPECULAR
     }
 }
 
 impl DiffuseLights{
     pub fn new(color: Color) -> DiffuseLights{
-        DiffuseLights{color}
+        DiffuseLights{color, light_group: 0}
+    }
+
+    pub fn new_in_group(color: Color, light_group: usize) -> DiffuseLights{
+        DiffuseLights{color, light_group}
     }
 }
 
 impl Scatter for DiffuseLights{
-    fn scatter(&self, _: &Ray, _: &HitRecord) -> Option<(Color, Ray)>{
+    fn sample(&self, _: &Vector3<f64>, _: [f64; 3]) -> Option<BsdfSample>{
         None
     }
 
+    fn flags(&self) -> BsdfFlags {
+        BsdfFlags::NONE
+    }
+
     fn emit(&self) -> Color{
         self.color
     }
 
 }
 
+impl Scatter for Interface{
+    /// Light is transmitted straight through the surface, unchanged.
+    fn sample(&self, wo: &Vector3<f64>, _: [f64; 3]) -> Option<BsdfSample>{
+        if wo[2] == 0.0 {
+            return None;
+        }
+        let f = Color::new(1.0, 1.0, 1.0) / wo[2].abs();
+        Some(BsdfSample { wi: -wo, f, pdf: 1.0, flags: self.flags() })
+    }
+
+    fn flags(&self) -> BsdfFlags {
+        BsdfFlags::TRANSMISSION | BsdfFlags::SPECULAR
+    }
+}
+
+/// The scattering function of a material, expressed in a local shading frame in which the normal is the z-axis. 
+/// The outgoing direction `wo` and the incident direction `wi` both point away from the surface.
 pub trait Scatter: Clone{
-    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<(Color, Ray)>;
+    /// Samples an incident direction using three uniform random numbers in [0, 1). Returns None if the light is absorbed.
+    fn sample(&self, wo: &Vector3<f64>, u: [f64; 3]) -> Option<BsdfSample>;
+
+    /// Evaluates the BSDF for the pair of directions. Specular lobes cannot be evaluated for arbitrary directions.
+    fn eval(&self, _wo: &Vector3<f64>, _wi: &Vector3<f64>) -> Color {
+        Color::new(0.0, 0.0, 0.0)
+    }
+
+    /// Returns the probability density, with respect to solid angle, of `sample` choosing `wi`. Specular lobes 
+    /// have no density.
+    fn pdf(&self, _wo: &Vector3<f64>, _wi: &Vector3<f64>) -> f64 {
+        0.0
+    }
+
+    fn flags(&self) -> BsdfFlags;
+
     fn emit(&self) -> Color{
         Color::new(0.0, 0.0, 0.0)
     }
@@ -200,6 +597,7 @@ mod tests {
     use crate::primitives::{GeometricPrimitive};
     use crate::raytracing::{Hit, Ray};
     use crate::nalgebra::{Point3};
+    use crate::sampler::independent::IndependentSampler;
 
     #[test]
     fn test_lambertian_deterministic_scatter(){
@@ -227,6 +625,42 @@ mod tests {
         let (color, reflected_ray) = scatter_result.unwrap();
         assert_eq!(color, Color::new(0.7, 0.6, 0.5));
         assert_eq!(reflected_ray, Ray::new(Point3::new(0.0, 0.0, 0.0), Vector3::<f64>::new( -1.0, 0.0, 0.0)));
+
+        //Case 3: Sampling the BSDF matches the deterministic scatter for the same random unit vector
+        let bsdf = Material::Lambertian(mat).bsdf(&rec);
+        let wo = -r.direction();
+        for u in [[0.1, 0.2, 0.0], [0.5, 0.9, 0.0], [0.95, 0.4, 0.0]] {
+            let unit_vec = bsdf.frame().to_world(&uniform_sphere([u[0], u[1]]));
+            let (_, reflected_ray) = mat.deterministic_scatter(&rec, unit_vec).unwrap();
+            let sample = bsdf.sample(&wo, u).unwrap();
+            assert!((sample.wi - reflected_ray.direction().normalize()).norm() < 1e-12);
+            assert_eq!(sample.flags, BsdfFlags::REFLECTION | BsdfFlags::DIFFUSE);
+            let attenuation = sample.f * (sample.wi.dot(&rec.normal) / sample.pdf);
+            assert!((attenuation - albedo).norm() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn test_lambertian_eval(){
+        let albedo = Color::new(0.7, 0.6, 0.5);
+        let mat = Material::new_lambertian(albedo);
+        let s = GeometricPrimitive::new_sphere(Point3::new(1.0,0.0,0.0), 1.0, mat);
+        let r = Ray::new(Point3::new(-10.0, -10.0, 0.0), Vector3::<f64>::new( 1.0, 1.0, 0.0));
+        let (rec, _) = s.hit(&r, 0.0, 100.0).unwrap();
+        let bsdf = mat.bsdf(&rec);
+        let wo = -r.direction();
+
+        //Case 1: Direction along the normal
+        let wi = Vector3::<f64>::new(-2.0, 0.0, 0.0);
+        assert!((bsdf.pdf(&wo, &wi) - 1.0 / PI).abs() < 1e-12);
+        assert!((bsdf.eval(&wo, &wi) - albedo / PI).norm() < 1e-12);
+
+        //Case 2: Direction below the surface
+        let wi = Vector3::<f64>::new(1.0, 0.0, 0.0);
+        assert_eq!(bsdf.pdf(&wo, &wi), 0.0);
+        assert_eq!(bsdf.eval(&wo, &wi), Color::new(0.0, 0.0, 0.0));
+        assert!(bsdf.flags().is_non_specular());
+        assert!(!bsdf.flags().is_specular());
     }
 
     #[test]
@@ -260,6 +694,58 @@ mod tests {
         //Case 2: Ray is absorbed
         let scatter_result = mat.deterministic_scatter(&r, &rec, Vector3::<f64>::new(1.0, 0.0, 0.0));
         assert!(scatter_result.is_none());
+
+        //Case 3: Sampling the BSDF matches the deterministic scatter for the same point in the unit ball
+        let mat = Metal::new(albedo, 0.5);
+        let bsdf = Material::Metal(mat).bsdf(&rec);
+        let wo = -r.direction();
+        for u in [[0.1, 0.2, 0.3], [0.5, 0.9, 0.7], [0.95, 0.4, 0.1], [0.99, 0.0, 0.5]] {
+            let fuzz_dir = bsdf.frame().to_world(&uniform_ball(u));
+            match (mat.deterministic_scatter(&r, &rec, fuzz_dir), bsdf.sample(&wo, u)) {
+                (Some((_, reflected_ray)), Some(sample)) => {
+                    assert!((sample.wi - reflected_ray.direction().normalize()).norm() < 1e-12);
+                    assert_eq!(sample.flags, BsdfFlags::REFLECTION | BsdfFlags::GLOSSY);
+                    let attenuation = sample.f * (sample.wi.dot(&rec.normal) / sample.pdf);
+                    assert!((attenuation - albedo).norm() < 1e-9);
+                }
+                (None, None) => {}
+                _ => panic!("Sampling the BSDF does not match the deterministic scatter")
+            }
+        }
+    }
+
+    #[test]
+    fn test_metal_pdf(){
+        let albedo = Color::new(0.7, 0.6, 0.5);
+        let wo = Vector3::<f64>::new(1.0, 0.0, 1.0).normalize();
+
+        //Case 1: A perfect mirror is specular
+        let mat = Metal::new(albedo, 0.0);
+        assert!(mat.flags().is_specular());
+        assert_eq!(mat.pdf(&wo, &Vector3::<f64>::new(-1.0, 0.0, 1.0).normalize()), 0.0);
+        let sample = mat.sample(&wo, [0.5, 0.5, 0.5]).unwrap();
+        assert_eq!(sample.wi, Vector3::<f64>::new(-wo[0], -wo[1], wo[2]));
+        assert_eq!(sample.pdf, 1.0);
+
+        //Case 2: The pdf of a fuzzy metal integrates to one over the sphere of directions
+        let mat = Metal::new(albedo, 0.5);
+        assert!(mat.flags().is_non_specular());
+        let n_theta = 400;
+        let n_phi = 800;
+        let mut integral = 0.0;
+        for i in 0..n_theta {
+            let theta = (i as f64 + 0.5) * PI / (n_theta as f64);
+            for j in 0..n_phi {
+                let phi = (j as f64 + 0.5) * 2.0 * PI / (n_phi as f64);
+                let wi = Vector3::<f64>::new(theta.sin() * phi.cos(), theta.sin() * phi.sin(), theta.cos());
+                integral += mat.pdf(&wo, &wi) * theta.sin() * (PI / n_theta as f64) * (2.0 * PI / n_phi as f64);
+            }
+        }
+        assert!((integral - 1.0).abs() < 1e-2);
+
+        //Case 3: Directions outside of the fuzz ball, or below the surface, are never scattered
+        assert_eq!(mat.pdf(&wo, &Vector3::<f64>::new(1.0, 0.0, 0.0)), 0.0);
+        assert_eq!(mat.eval(&wo, &Vector3::<f64>::new(-1.0, 0.0, -0.1)), Color::new(0.0, 0.0, 0.0));
     }
 
     #[test]
@@ -272,15 +758,19 @@ mod tests {
 
 
     #[test]
-    fn test_diffuse_light_scatter(){
+    fn test_diffuse_light_sample(){
         let mat = Material::new_diffuse_light(Color::new(0.7, 0.6, 0.5));
         let s = GeometricPrimitive::new_sphere(Point3::new(1.0,0.0,0.0), 1.0, mat);
         let r = Ray::new(Point3::new(-10.0, -10.0, 0.0), Vector3::<f64>::new( 1.0, 1.0, 0.0));
         let hit = s.hit(&r, 0.0, 100.0);
         let (rec, _) = hit.unwrap();
 
-        let scatter_result = mat.scatter(&r, &rec);
-        assert!(scatter_result.is_none());
+        let mut sampler = IndependentSampler::new(0);
+        let bsdf = mat.bsdf(&rec);
+        for _ in 0..10 {
+            let [u0, u1] = sampler.get_2d();
+            assert!(bsdf.sample(&-r.direction(), [u0, u1, sampler.get_1d()]).is_none());
+        }
     }
 
     #[test]
@@ -290,6 +780,78 @@ mod tests {
         assert_eq!(emission, Color::new(0.7, 0.6, 0.5));
     }
 
+    #[test]
+    fn test_light_group(){
+        //Case 1: Lights belong to the group they were given, which is the first by default
+        assert_eq!(Material::new_diffuse_light(Color::new(1.0, 1.0, 1.0)).light_group(), Some(0));
+        assert_eq!(Material::new_diffuse_light_in_group(Color::new(1.0, 1.0, 1.0), 2).light_group(), Some(2));
+
+        //Case 2: Materials which cannot emit light have no group
+        assert_eq!(Material::new_lambertian(Color::new(0.5, 0.5, 0.5)).light_group(), None);
+        assert_eq!(Material::new_interface().light_group(), None);
+    }
+
+    #[test]
+    fn test_dielectric_sample(){
+        let mat = Material::new_dielectric(1.5);
+        let s = GeometricPrimitive::new_sphere(Point3::new(0.0,0.0,0.0), 1.0, mat);
+        let dielectric = Dielectric::new(1.5);
+
+        //Case 1: Sampling the BSDF matches the deterministic scatter, both entering and leaving the dielectric
+        let entering = Ray::new(Point3::new(-2.0, 0.5, 0.0), Vector3::<f64>::new( 1.0, 0.1, 0.0));
+        let leaving = Ray::new(Point3::new(0.0, 0.0, 0.0), Vector3::<f64>::new( 1.0, 0.3, 0.2));
+        for r in [entering, leaving] {
+            let (rec, _) = s.hit(&r, 0.001, 100.0).unwrap();
+            let bsdf = mat.bsdf(&rec);
+            for u in [0.01, 0.5, 0.99] {
+                let (_, scattered) = dielectric.deterministic_scatter(&r, &rec, u).unwrap();
+                let sample = bsdf.sample(&-r.direction(), [u, 0.0, 0.0]).unwrap();
+                assert!((sample.wi - scattered.direction().normalize()).norm() < 1e-12);
+                assert!(sample.flags.is_specular());
+                let attenuation = sample.f * (sample.wi.dot(&rec.normal).abs() / sample.pdf);
+                assert!((attenuation - Color::new(1.0, 1.0, 1.0)).norm() < 1e-12);
+            }
+        }
+
+        //Case 2: Total internal reflection always reflects
+        let r = Ray::new(Point3::new(0.0, 0.9, 0.0), Vector3::<f64>::new( 1.0, 0.0, 0.0));
+        let (rec, _) = s.hit(&r, 0.001, 100.0).unwrap();
+        let sample = mat.bsdf(&rec).sample(&-r.direction(), [0.999, 0.0, 0.0]).unwrap();
+        assert_eq!(sample.flags, BsdfFlags::REFLECTION | BsdfFlags::SPECULAR);
+        assert_eq!(sample.pdf, 1.0);
+    }
+
+    #[test]
+    fn test_dispersive_dielectric(){
+        let mat = Material::new_dispersive_dielectric(RefractiveIndex::BK7);
+        let s = GeometricPrimitive::new_sphere(Point3::new(0.0,0.0,0.0), 1.0, mat);
+        let r = Ray::new(Point3::new(-2.0, 0.5, 0.0), Vector3::<f64>::new( 1.0, 0.0, 0.0));
+        let (rec, _) = s.hit(&r, 0.001, 100.0).unwrap();
+        assert!(mat.is_dispersive());
+        assert!(!Material::new_dielectric(1.5).is_dispersive());
+
+        //Case 1: Blue light is bent further from its original direction than red light
+        let refract = |bsdf: Bsdf| bsdf.sample(&-r.direction(), [0.999, 0.0, 0.0]).unwrap().wi;
+        let blue = refract(mat.bsdf_at_wavelength(&rec, 450.0));
+        let red = refract(mat.bsdf_at_wavelength(&rec, 650.0));
+        assert!(blue.dot(&r.direction()) < red.dot(&r.direction()));
+
+        //Case 2: Without a wavelength, the reference wavelength is used
+        let reference = Material::new_dielectric(RefractiveIndex::BK7.at(REFERENCE_WAVELENGTH));
+        assert_eq!(refract(mat.bsdf(&rec)), refract(reference.bsdf(&rec)));
+    }
+
+    #[test]
+    fn test_bsdf_flags(){
+        let flags = BsdfFlags::REFLECTION | BsdfFlags::GLOSSY;
+        assert!(flags.contains(BsdfFlags::REFLECTION));
+        assert!(!flags.contains(BsdfFlags::REFLECTION | BsdfFlags::TRANSMISSION));
+        assert!(flags.intersects(BsdfFlags::REFLECTION | BsdfFlags::TRANSMISSION));
+        assert!(flags.is_non_specular());
+        assert!(!flags.is_specular());
+        assert!(!BsdfFlags::NONE.is_non_specular());
+    }
+
     #[test]
     fn test_reflectance(){
         let unit_vec = Vector3::<f64>::new(1.0, 2.0, 3.0).normalize();
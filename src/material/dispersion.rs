@@ -0,0 +1,88 @@
+/// The wavelength in nanometres at which the index of refraction is quoted, and at which dispersive dielectrics are
+/// evaluated when rendering in RGB. This is the helium d-line, which is the usual reference for optical glasses.
+pub const REFERENCE_WAVELENGTH: f64 = 587.56;
+
+/// The index of refraction of a dielectric, which may vary with the wavelength of the light passing through it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RefractiveIndex {
+    /// The same index at every wavelength, so that the dielectric does not disperse light.
+    Constant(f64),
+    /// Cauchy's equation, n(λ) = a + b / λ², with λ in micrometres. This fits most glasses well across the visible
+    /// spectrum.
+    Cauchy { a: f64, b: f64 },
+    /// The Sellmeier equation, n(λ)² = 1 + Σ bᵢλ² / (λ² - cᵢ), with λ in micrometres and cᵢ in square micrometres.
+    Sellmeier { b: [f64; 3], c: [f64; 3] }
+}
+
+impl RefractiveIndex {
+    /// Schott N-BK7, a common borosilicate crown glass.
+    pub const BK7: RefractiveIndex = RefractiveIndex::Sellmeier {
+        b: [1.039_612_12, 0.231_792_344, 1.010_469_45],
+        c: [0.006_000_698_67, 0.020_017_914_4, 103.560_653]
+    };
+
+    /// Fused silica, which disperses light less than most glasses.
+    pub const FUSED_SILICA: RefractiveIndex = RefractiveIndex::Sellmeier {
+        b: [0.696_166_3, 0.407_942_6, 0.897_479_4],
+        c: [0.004_679_148_26, 0.013_512_063_1, 97.934_002_5]
+    };
+
+    /// Diamond, whose high index and strong dispersion give gems their fire.
+    pub const DIAMOND: RefractiveIndex = RefractiveIndex::Sellmeier {
+        b: [0.3306, 4.3356, 0.0],
+        c: [0.030_625, 0.011_236, 0.0]
+    };
+
+    /// The named presets, along with their names.
+    pub const PRESETS: [(&'static str, RefractiveIndex); 3] = [("BK7", RefractiveIndex::BK7), ("Fused silica", RefractiveIndex::FUSED_SILICA),
+                                                               ("Diamond", RefractiveIndex::DIAMOND)];
+
+    /// Returns the index of refraction for light of the given wavelength in nanometres.
+    pub fn at(&self, wavelength: f64) -> f64 {
+        let lambda_sq = (wavelength / 1000.0).powi(2);
+        match *self {
+            RefractiveIndex::Constant(n) => n,
+            RefractiveIndex::Cauchy { a, b } => a + b / lambda_sq,
+            RefractiveIndex::Sellmeier { b, c } => {
+                let n_sq = 1.0 + (0..3).map(|i| b[i] * lambda_sq / (lambda_sq - c[i])).sum::<f64>();
+                n_sq.sqrt()
+            }
+        }
+    }
+
+    /// Returns true if the index varies with wavelength.
+    pub fn is_dispersive(&self) -> bool {
+        !matches!(self, RefractiveIndex::Constant(_))
+    }
+}
+
+impl Default for RefractiveIndex {
+    fn default() -> RefractiveIndex {
+        RefractiveIndex::Constant(1.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_at(){
+        //Case 1: The presets match their published indices at the reference wavelength
+        assert!((RefractiveIndex::BK7.at(REFERENCE_WAVELENGTH) - 1.5168).abs() < 1e-4);
+        assert!((RefractiveIndex::FUSED_SILICA.at(REFERENCE_WAVELENGTH) - 1.4585).abs() < 1e-4);
+        assert!((RefractiveIndex::DIAMOND.at(REFERENCE_WAVELENGTH) - 2.417).abs() < 1e-3);
+
+        //Case 2: Blue light is refracted more strongly than red light
+        for (_, ior) in RefractiveIndex::PRESETS {
+            assert!(ior.is_dispersive());
+            assert!(ior.at(450.0) > ior.at(650.0));
+        }
+
+        //Case 3: Cauchy's equation, and constant indices
+        let cauchy = RefractiveIndex::Cauchy { a: 1.5, b: 0.01 };
+        assert!((cauchy.at(500.0) - 1.54).abs() < 1e-12);
+        assert_eq!(RefractiveIndex::Constant(1.33).at(400.0), 1.33);
+        assert!(!RefractiveIndex::Constant(1.33).is_dispersive());
+    }
+}
@@ -0,0 +1,297 @@
+use std::f64::consts::PI;
+
+use crate::image::Color;
+use crate::nalgebra::{Point3, Vector3};
+use crate::raytracing::{Hit, HitRecord, Ray};
+use crate::spectra::SampledWavelengths;
+use crate::vec::VecExtensionMethods;
+
+/// The Henyey-Greenstein phase function. The asymmetry parameter `g` lies in (-1, 1): positive values scatter light
+/// forwards, negative values scatter it backwards, and zero scatters it uniformly in every direction.
+#[derive (Copy, Clone, Debug, Default, PartialEq)]
+pub struct HenyeyGreenstein {
+    pub g: f64
+}
+
+impl HenyeyGreenstein {
+    pub fn new(g: f64) -> HenyeyGreenstein {
+        HenyeyGreenstein { g: g.clamp(-0.99, 0.99) }
+    }
+
+    /// Evaluates the phase function for light arriving along `wi` and leaving along `wo`. Both directions point away
+    /// from the scattering point. The phase function is normalised, so it is also the density of sampling `wi`.
+    pub fn p(&self, wo: &Vector3<f64>, wi: &Vector3<f64>) -> f64 {
+        let cos_theta = -wo.normalize().dot(&wi.normalize());
+        let denominator = 1.0 + self.g * self.g - 2.0 * self.g * cos_theta;
+        (1.0 - self.g * self.g) / (4.0 * PI * denominator * denominator.max(0.0).sqrt())
+    }
+
+    /// Samples an incident direction for light leaving along `wo`, using a pair of uniform random numbers in [0, 1).
+    /// Returns the direction along with its probability density.
+    pub fn sample(&self, wo: &Vector3<f64>, u: [f64; 2]) -> (Vector3<f64>, f64) {
+        //Find the cosine of the angle between the direction of propagation and the scattered direction
+        let g = self.g;
+        let cos_theta = if g.abs() < 1e-3 {
+            1.0 - 2.0 * u[0]
+        } else {
+            let term = (1.0 - g * g) / (1.0 - g + 2.0 * g * u[0]);
+            ((1.0 + g * g - term * term) / (2.0 * g)).clamp(-1.0, 1.0)
+        };
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = 2.0 * PI * u[1];
+
+        let w = -wo.normalize();
+        let (s, t) = w.coordinate_system();
+        let wi = s * (sin_theta * phi.cos()) + t * (sin_theta * phi.sin()) + w * cos_theta;
+        (wi, self.p(wo, &wi))
+    }
+}
+
+/// A homogeneous participating medium, which absorbs and scatters light at the same rate throughout its volume.
+/// The coefficients are given per unit distance.
+#[derive (Copy, Clone, Debug, Default, PartialEq)]
+pub struct Medium {
+    pub sigma_a: Color,
+    pub sigma_s: Color,
+    pub phase: HenyeyGreenstein
+}
+
+/// The distance sampled for a ray travelling through a medium.
+#[derive (Copy, Clone, Debug, PartialEq)]
+pub struct MediumSample {
+    /// The distance to the point at which the ray is scattered, or None if it passes through the medium unscattered.
+    pub distance: Option<f64>,
+    /// The transmittance up to the sampled point, multiplied by the scattering coefficient if the ray was scattered,
+    /// and divided by the probability density of the sample.
+    pub weight: Color
+}
+
+impl Medium {
+    pub fn new(sigma_a: Color, sigma_s: Color, g: f64) -> Medium {
+        Medium { sigma_a, sigma_s, phase: HenyeyGreenstein::new(g) }
+    }
+
+    /// Returns the medium with its coefficients upsampled to spectra and evaluated at the given wavelengths, for use in
+    /// spectral mode.
+    pub fn to_spectral(&self, wavelengths: &SampledWavelengths) -> Medium {
+        Medium { sigma_a: wavelengths.reflectance(&self.sigma_a), sigma_s: wavelengths.reflectance(&self.sigma_s), phase: self.phase }
+    }
+
+    /// Returns the extinction coefficient, the total rate at which light is absorbed or scattered away.
+    pub fn sigma_t(&self) -> Color {
+        self.sigma_a + self.sigma_s
+    }
+
+    /// Returns the fraction of light which passes unscattered through the given distance of the medium.
+    pub fn transmittance(&self, distance: f64) -> Color {
+        self.sigma_t().map(|sigma_t| if sigma_t == 0.0 { 1.0 } else { (-sigma_t * distance).exp() })
+    }
+
+    /// Samples the distance a ray travels before being scattered, using free-flight sampling. Distances are drawn from
+    /// the transmittance of a randomly chosen colour channel, and the density is averaged over the channels so that
+    /// channels with differing extinction are still estimated without bias. `distance_max` is the distance to the next
+    /// surface.
+    pub fn sample(&self, distance_max: f64, u: [f64; 2]) -> MediumSample {
+        let sigma_t = self.sigma_t();
+        let channel = ((u[0] * 3.0) as usize).min(2);
+        let distance = if sigma_t[channel] > 0.0 { -(1.0 - u[1]).ln() / sigma_t[channel] } else { f64::INFINITY };
+
+        if distance < distance_max {
+            let transmittance = self.transmittance(distance);
+            let pdf = sigma_t.component_mul(&transmittance).sum() / 3.0;
+            let weight = if pdf > 0.0 { transmittance.component_mul(&self.sigma_s) / pdf } else { Color::zeros() };
+            MediumSample { distance: Some(distance), weight }
+        } else {
+            let transmittance = self.transmittance(distance_max);
+            let pdf = transmittance.sum() / 3.0;
+            let weight = if pdf > 0.0 { transmittance / pdf } else { Color::zeros() };
+            MediumSample { distance: None, weight }
+        }
+    }
+}
+
+/// Tracks the medium through which a ray is travelling. Rays start out in the atmosphere, which fills all space outside
+/// other media, and may be empty. A ray enters the medium inside a primitive when it is transmitted through the
+/// primitive's surface from the front, and returns to the atmosphere when it leaves. Media cannot be nested. In
+/// spectral mode, media are converted to the wavelengths carried by the path as they are entered.
+#[derive (Copy, Clone, Debug, Default, PartialEq)]
+pub struct MediumTracker {
+    pub current: Option<Medium>,
+    pub atmosphere: Option<Medium>,
+    pub wavelengths: Option<SampledWavelengths>
+}
+
+impl MediumTracker {
+    pub fn new(atmosphere: Option<Medium>) -> MediumTracker {
+        MediumTracker { current: atmosphere, atmosphere, wavelengths: None }
+    }
+
+    /// Tracks the media along a path carrying the given wavelengths, or RGB values if there are none.
+    pub fn new_with_wavelengths(atmosphere: Option<Medium>, wavelengths: Option<SampledWavelengths>) -> MediumTracker {
+        let atmosphere = match wavelengths {
+            Some(wavelengths) => atmosphere.map(|medium| medium.to_spectral(&wavelengths)),
+            None => atmosphere
+        };
+        MediumTracker { current: atmosphere, atmosphere, wavelengths }
+    }
+
+    /// Updates the medium for a ray leaving the hit along `wi`. Surfaces without an interior medium leave the medium
+    /// unchanged.
+    pub fn cross(&mut self, rec: &HitRecord, wi: &Vector3<f64>) {
+        if rec.medium.is_none() || wi.dot(&rec.normal) >= 0.0 {
+            return;
+        }
+        self.current = if rec.front_face {
+            match self.wavelengths {
+                Some(wavelengths) => rec.medium.map(|medium| medium.to_spectral(&wavelengths)),
+                None => rec.medium
+            }
+        } else {
+            self.atmosphere
+        };
+    }
+}
+
+/// Returns the fraction of light which travels unscattered from `p0` to `p1` at the given time, where `media` holds
+/// the medium the light leaves `p0` in. Surfaces which only mark the boundary of a medium are passed through, while
+/// any other surface blocks the light entirely.
+pub fn transmittance<T>(world: &T, p0: &Point3<f64>, p1: &Point3<f64>, time: f64, mut media: MediumTracker) -> Color where T: Hit {
+    let mut transmittance = Color::new(1.0, 1.0, 1.0);
+    let mut origin = *p0;
+    loop {
+        let to_end = p1 - origin;
+        let dist = to_end.norm();
+        let direction = to_end / dist;
+        let (rec, mat) = match world.hit(&Ray::new_at_time(origin, direction, time), 0.001, dist - 0.001) {
+            Some(hit) => hit,
+            None => {
+                if let Some(medium) = media.current {
+                    transmittance.component_mul_assign(&medium.transmittance(dist));
+                }
+                return transmittance;
+            }
+        };
+
+        if !mat.is_interface() {
+            return Color::zeros();
+        }
+        if let Some(medium) = media.current {
+            transmittance.component_mul_assign(&medium.transmittance(rec.t));
+        }
+        if transmittance == Color::zeros() {
+            return transmittance;
+        }
+        media.cross(&rec, &direction);
+        origin = rec.p;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::Material;
+    use crate::primitives::{GeometricPrimitive, GeometricPrimitives};
+    use crate::sampler::Sampler;
+    use crate::sampler::independent::IndependentSampler;
+
+    #[test]
+    fn test_henyey_greenstein(){
+        let wo = Vector3::<f64>::new(0.0, 0.0, 1.0);
+        let mut sampler = IndependentSampler::new(0);
+        for g in [-0.5, 0.0, 0.7] {
+            let phase = HenyeyGreenstein::new(g);
+
+            //Case 1: The sampled density matches the phase function, and the mean cosine of the scattering angle is g
+            let samples = 20000;
+            let mut mean_cosine = 0.0;
+            for _ in 0..samples {
+                let (wi, pdf) = phase.sample(&wo, sampler.get_2d());
+                assert!((wi.norm() - 1.0).abs() < 1e-9);
+                assert!((pdf - phase.p(&wo, &wi)).abs() < 1e-9);
+                mean_cosine += -wi.dot(&wo) / samples as f64;
+            }
+            assert!((mean_cosine - g).abs() < 0.02);
+
+            //Case 2: The phase function integrates to one over the sphere
+            let mut integral = 0.0;
+            for _ in 0..samples {
+                let wi = crate::sampler::uniform_sphere(sampler.get_2d());
+                integral += phase.p(&wo, &wi) * 4.0 * PI / samples as f64;
+            }
+            assert!((integral - 1.0).abs() < 0.05);
+        }
+    }
+
+    #[test]
+    fn test_medium_sample(){
+        let medium = Medium::new(Color::new(0.1, 0.2, 0.3), Color::new(0.5, 0.5, 0.0), 0.0);
+
+        //Case 1: The transmittance falls off exponentially with the extinction coefficient
+        let transmittance = medium.transmittance(2.0);
+        assert!((transmittance - Color::new((-1.2f64).exp(), (-1.4f64).exp(), (-0.6f64).exp())).norm() < 1e-12);
+        assert_eq!(Medium::new(Color::zeros(), Color::zeros(), 0.0).transmittance(f64::INFINITY), Color::new(1.0, 1.0, 1.0));
+
+        //Case 2: The weights of rays passing through the medium estimate the transmittance, and the weights of rays
+        //scattered within it estimate the integral of the transmittance multiplied by the scattering coefficient
+        let samples = 100000;
+        let distance_max = 2.0;
+        let (mut passed, mut scattered) = (Color::zeros(), Color::zeros());
+        let mut sampler = IndependentSampler::new(0);
+        for _ in 0..samples {
+            let sample = medium.sample(distance_max, sampler.get_2d());
+            match sample.distance {
+                Some(distance) => {
+                    assert!(distance < distance_max);
+                    scattered += sample.weight / samples as f64;
+                }
+                None => passed += sample.weight / samples as f64
+            }
+        }
+        let sigma_t = medium.sigma_t();
+        let expected = medium.sigma_s.component_mul(&(Color::new(1.0, 1.0, 1.0) - transmittance)).component_div(&sigma_t);
+        assert!((passed - transmittance).norm() < 0.01);
+        assert!((scattered - expected).norm() < 0.01);
+    }
+
+    #[test]
+    fn test_transmittance(){
+        let medium = Medium::new(Color::new(0.5, 0.5, 0.5), Color::zeros(), 0.0);
+        let mut world = GeometricPrimitives::new();
+        world.add(GeometricPrimitive::new_sphere_with_medium(Point3::<f64>::new(0.0, 0.0, 0.0), 1.0, Material::new_interface(), medium));
+        world.add(GeometricPrimitive::new_sphere(Point3::<f64>::new(0.0, 0.0, -10.0), 1.0, Material::new_lambertian(Color::zeros())));
+
+        //Case 1: Light passing through the medium is attenuated over the distance travelled within it
+        let tr = transmittance(&world, &Point3::<f64>::new(0.0, 0.0, 5.0), &Point3::<f64>::new(0.0, 0.0, -5.0), 0.0, MediumTracker::default());
+        assert!((tr - Color::new(1.0, 1.0, 1.0) * (-1.0f64).exp()).norm() < 1e-9);
+
+        //Case 2: Light leaving a point inside the medium is only attenuated until it leaves
+        let media = MediumTracker { current: Some(medium), atmosphere: None, wavelengths: None };
+        let tr = transmittance(&world, &Point3::<f64>::new(0.0, 0.0, 0.0), &Point3::<f64>::new(0.0, 5.0, 0.0), 0.0, media);
+        assert!((tr - Color::new(1.0, 1.0, 1.0) * (-0.5f64).exp()).norm() < 1e-9);
+
+        //Case 3: Opaque surfaces block the light
+        let tr = transmittance(&world, &Point3::<f64>::new(0.0, 0.0, 5.0), &Point3::<f64>::new(0.0, 0.0, -20.0), 0.0, MediumTracker::default());
+        assert_eq!(tr, Color::zeros());
+    }
+
+    #[test]
+    fn test_cross(){
+        let medium = Medium::new(Color::new(0.5, 0.5, 0.5), Color::zeros(), 0.0);
+        let atmosphere = Medium::new(Color::zeros(), Color::new(0.1, 0.1, 0.1), 0.0);
+        let sphere = GeometricPrimitive::new_sphere_with_medium(Point3::<f64>::new(0.0, 0.0, 0.0), 1.0, Material::new_interface(), medium);
+        let direction = Vector3::<f64>::new(0.0, 0.0, -1.0);
+        let (rec, _) = sphere.hit(&Ray::new(Point3::<f64>::new(0.0, 0.0, 5.0), direction), 0.001, f64::INFINITY).unwrap();
+
+        //Case 1: Reflected rays stay in the atmosphere
+        let mut media = MediumTracker::new(Some(atmosphere));
+        media.cross(&rec, &-direction);
+        assert_eq!(media.current, Some(atmosphere));
+
+        //Case 2: Transmitted rays enter the medium, and return to the atmosphere when they leave it
+        media.cross(&rec, &direction);
+        assert_eq!(media.current, Some(medium));
+        let (rec, _) = sphere.hit(&Ray::new(rec.p, direction), 0.001, f64::INFINITY).unwrap();
+        media.cross(&rec, &direction);
+        assert_eq!(media.current, Some(atmosphere));
+    }
+}
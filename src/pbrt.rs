@@ -0,0 +1,927 @@
+pub mod ply;
+
+use crate::film::{Filter, FilterType};
+use crate::image::Color;
+use crate::integrators::Integrator;
+use crate::material::dispersion::RefractiveIndex;
+use crate::sampler::SamplerType;
+use crate::scene_file::{MaterialDescription, SceneDescription, ShapeDescription};
+use crate::util::deg_to_rad;
+use crate::nalgebra::{Matrix3, Matrix4, Point3, Rotation3, Unit, Vector3};
+
+use std::cell::Cell;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+/// The deepest that `Include` directives may be nested, which stops files which include themselves.
+const MAX_INCLUDE_DEPTH: usize = 32;
+
+const PARAMETER_TYPES: [&str; 17] = ["integer", "float", "point2", "vector2", "point", "point3", "vector", "vector3", "normal", "normal3",
+                                     "rgb", "color", "xyz", "blackbody", "spectrum", "bool", "string"];
+const NUMERIC_TYPES: [&str; 10] = ["integer", "float", "point2", "vector2", "point", "point3", "vector", "vector3", "normal", "normal3"];
+
+#[derive (Clone, Debug, PartialEq)]
+enum Token {
+    Word(String),
+    Text(String),
+    Number(f64),
+    Open,
+    Close
+}
+
+#[derive (Clone, Debug, PartialEq)]
+enum Value {
+    Number(f64),
+    Text(String),
+    Bool(bool)
+}
+
+/// A directive and its arguments, each of which is a single value or a list of values in brackets.
+struct Directive {
+    name: String,
+    line: usize,
+    args: Vec<Vec<Value>>
+}
+
+/// A named parameter of a directive, such as `"float radius" [ 2 ]`.
+struct Parameter {
+    type_name: String,
+    name: String,
+    values: Vec<Value>,
+    /// Whether the importer read the parameter. Parameters which are never read are reported as ignored.
+    used: Cell<bool>
+}
+
+#[derive (Default)]
+struct ParamSet(Vec<Parameter>);
+
+#[derive (Clone)]
+struct GraphicsState {
+    transform: Matrix4<f64>,
+    material: MaterialDescription,
+    named_materials: HashMap<String, MaterialDescription>,
+    area_light: Option<Color>,
+    reverse_orientation: bool
+}
+
+#[derive (Copy, Clone, Debug, PartialEq)]
+enum Block {
+    Attribute,
+    Transform,
+    Object
+}
+
+/// Builds a scene from the directives of a pbrt file, keeping the warnings it gives along the way.
+struct Importer {
+    /// The directory of the main file, which the paths of included files and meshes are relative to.
+    directory: PathBuf,
+    scene: SceneDescription,
+    warnings: Vec<String>,
+    /// The warnings given so far, without their locations, so that each is only given once.
+    reported: HashSet<String>,
+    state: GraphicsState,
+    stack: Vec<(Block, GraphicsState)>,
+    coordinate_systems: HashMap<String, Matrix4<f64>>,
+    /// The field of view along the shorter side of the image, which is only turned into a vertical field of view once
+    /// the size of the image is known.
+    fov: f64
+}
+
+/// Imports a scene written in the format of pbrt-v3, returning it along with warnings about the parts of the file which
+/// could not be imported.
+///
+/// Only part of the format is supported: perspective cameras, spheres, triangle meshes and PLY meshes, matte, metal,
+/// mirror and glass materials, diffuse area lights and infinite lights of a constant colour. Anything else, from
+/// unknown directives down to unsupported parameters, is skipped with a warning, so that as much of the scene as
+/// possible is kept. Syntax errors and files which cannot be read are errors.
+///
+/// pbrt uses a left-handed coordinate system, so the scene is mirrored in the x axis to give the same image in this
+/// renderer.
+pub fn import(path: &Path) -> Result<(SceneDescription, Vec<String>), String> {
+    let source = std::fs::read_to_string(path).map_err(|error| format!("Could not read '{}': {}", path.display(), error))?;
+    let mut importer = Importer::new(path.parent().unwrap_or_else(|| Path::new("")));
+    importer.read(&path.display().to_string(), &source, 0)?;
+    Ok(importer.finish())
+}
+
+/// Imports a scene from the text of a pbrt file, whose included files and meshes are read from the given directory.
+pub fn import_source(source: &str, directory: &Path) -> Result<(SceneDescription, Vec<String>), String> {
+    let mut importer = Importer::new(directory);
+    importer.read("<input>", source, 0)?;
+    Ok(importer.finish())
+}
+
+/// Splits a file into tokens, each given with the line it is on.
+fn tokenize(source: &str) -> Result<Vec<(Token, usize)>, (usize, String)> {
+    let mut tokens = Vec::new();
+    let mut chars = source.chars().peekable();
+    let mut line = 1;
+    while let Some(c) = chars.next() {
+        match c {
+            '\n' => line += 1,
+            '#' => while chars.next_if(|c| *c != '\n').is_some() {},
+            '[' => tokens.push((Token::Open, line)),
+            ']' => tokens.push((Token::Close, line)),
+            '"' => {
+                let mut text = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some('n') => text.push('\n'),
+                            Some('t') => text.push('\t'),
+                            Some(c) if c != '\n' => text.push(c),
+                            _ => return Err((line, String::from("Unterminated string")))
+                        },
+                        Some(c) if c != '\n' => text.push(c),
+                        _ => return Err((line, String::from("Unterminated string")))
+                    }
+                }
+                tokens.push((Token::Text(text), line));
+            }
+            c if c.is_whitespace() => (),
+            c => {
+                let mut word = String::from(c);
+                while let Some(c) = chars.next_if(|c| !c.is_whitespace() && !"[]\"#".contains(*c)) {
+                    word.push(c);
+                }
+                let token = if c.is_ascii_digit() || "+-.".contains(c) {
+                    Token::Number(word.parse().map_err(|_| (line, format!("Invalid number '{}'", word)))?)
+                } else {
+                    Token::Word(word)
+                };
+                tokens.push((token, line));
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+fn describe(token: &Token) -> String {
+    match token {
+        Token::Word(word) => format!("'{}'", word),
+        Token::Text(text) => format!("\"{}\"", text),
+        Token::Number(number) => format!("'{}'", number),
+        Token::Open => String::from("'['"),
+        Token::Close => String::from("']'")
+    }
+}
+
+/// Returns whether a token is the name of a directive. `true` and `false` are the only bare words which are values.
+fn is_directive(token: &Token) -> bool {
+    matches!(token, Token::Word(word) if word != "true" && word != "false")
+}
+
+fn value(token: Token, line: usize) -> Result<Value, (usize, String)> {
+    match token {
+        Token::Number(number) => Ok(Value::Number(number)),
+        Token::Text(text) => Ok(Value::Text(text)),
+        Token::Word(word) if word == "true" || word == "false" => Ok(Value::Bool(word == "true")),
+        token => Err((line, format!("Unexpected {}", describe(&token))))
+    }
+}
+
+/// Groups tokens into directives. The arguments of a directive run until the name of the next one.
+fn directives(tokens: Vec<(Token, usize)>) -> Result<Vec<Directive>, (usize, String)> {
+    let mut directives = Vec::new();
+    let mut tokens = tokens.into_iter().peekable();
+    while let Some((token, line)) = tokens.next() {
+        let name = match token {
+            Token::Word(name) if name != "true" && name != "false" => name,
+            token => return Err((line, format!("Expected a directive, found {}", describe(&token))))
+        };
+        let mut args = Vec::new();
+        while let Some((token, line)) = tokens.next_if(|(token, _)| !is_directive(token)) {
+            if token != Token::Open {
+                args.push(vec![value(token, line)?]);
+                continue;
+            }
+            let mut values = Vec::new();
+            loop {
+                match tokens.next() {
+                    Some((Token::Close, _)) => break,
+                    Some((token, line)) => values.push(value(token, line)?),
+                    None => return Err((line, String::from("Unterminated '['")))
+                }
+            }
+            args.push(values);
+        }
+        directives.push(Directive { name, line, args });
+    }
+    Ok(directives)
+}
+
+/// Returns the type and name of a parameter declaration, such as `"float radius"`.
+fn declaration(arg: &[Value]) -> Option<(&str, &str)> {
+    match arg {
+        [Value::Text(text)] => match text.split_whitespace().collect::<Vec<&str>>()[..] {
+            [type_name, name] if PARAMETER_TYPES.contains(&type_name) => Some((type_name, name)),
+            _ => None
+        },
+        _ => None
+    }
+}
+
+fn numbers(values: &[Value]) -> Vec<f64> {
+    values.iter().filter_map(|value| match value {
+        Value::Number(number) => Some(*number),
+        _ => None
+    }).collect()
+}
+
+/// Converts a colour from CIE XYZ to linear sRGB.
+fn xyz_to_rgb(xyz: [f64; 3]) -> Color {
+    Color::new(3.240479 * xyz[0] - 1.537150 * xyz[1] - 0.498535 * xyz[2],
+               -0.969256 * xyz[0] + 1.875991 * xyz[1] + 0.041556 * xyz[2],
+               0.055648 * xyz[0] - 0.204043 * xyz[1] + 1.057311 * xyz[2])
+}
+
+/// The matrix which mirrors pbrt's left-handed coordinates into right-handed ones.
+fn mirror() -> Matrix4<f64> {
+    Matrix4::new_nonuniform_scaling(&Vector3::<f64>::new(-1.0, 1.0, 1.0))
+}
+
+/// Returns pbrt's world to camera transform for a camera at `eye` looking at `look`.
+fn look_at(eye: Point3<f64>, look: Point3<f64>, up: Vector3<f64>) -> Result<Matrix4<f64>, String> {
+    let direction = (look - eye).normalize();
+    let right = up.normalize().cross(&direction);
+    if right.norm() == 0.0 || !right.norm().is_finite() {
+        return Err(String::from("LookAt must be given an up vector which is not parallel to the viewing direction"));
+    }
+    let right = right.normalize();
+    let up = direction.cross(&right);
+    let camera_to_world = Matrix4::new(right.x, up.x, direction.x, eye.x,
+                                       right.y, up.y, direction.y, eye.y,
+                                       right.z, up.z, direction.z, eye.z,
+                                       0.0, 0.0, 0.0, 1.0);
+    camera_to_world.try_inverse().ok_or_else(|| String::from("LookAt must be given distinct points"))
+}
+
+impl Parameter {
+    /// Checks that the values of a parameter suit its type.
+    fn new(type_name: &str, name: &str, values: &[Value]) -> Result<Parameter, String> {
+        let values: Vec<Value> = values.iter().map(|value| match value {
+            Value::Text(text) if type_name == "bool" && (text == "true" || text == "false") => Value::Bool(text == "true"),
+            value => value.clone()
+        }).collect();
+        let all_numbers = values.iter().all(|value| matches!(value, Value::Number(_)));
+        let valid = match type_name {
+            "string" => values.iter().all(|value| matches!(value, Value::Text(_))),
+            "bool" => values.iter().all(|value| matches!(value, Value::Bool(_))),
+            "spectrum" => all_numbers || matches!(values[..], [Value::Text(_)]),
+            "integer" => all_numbers && numbers(&values).iter().all(|number| number.fract() == 0.0),
+            _ => all_numbers
+        };
+        let width = match type_name {
+            "point2" | "vector2" => 2,
+            "rgb" | "color" | "xyz" | "point" | "point3" | "vector" | "vector3" | "normal" | "normal3" => 3,
+            _ => 1
+        };
+        if values.is_empty() || !valid || (all_numbers && !values.len().is_multiple_of(width)) {
+            return Err(format!("Invalid value for the parameter \"{} {}\"", type_name, name));
+        }
+        Ok(Parameter { type_name: String::from(type_name), name: String::from(name), values, used: Cell::new(false) })
+    }
+}
+
+impl ParamSet {
+    /// Returns the values of the parameter with the given name and one of the given types, marking it as used.
+    fn find(&self, name: &str, types: &[&str]) -> Option<&[Value]> {
+        let parameter = self.0.iter().find(|parameter| parameter.name == name && types.contains(&parameter.type_name.as_str()))?;
+        parameter.used.set(true);
+        Some(&parameter.values)
+    }
+
+    fn numbers(&self, name: &str) -> Option<Vec<f64>> {
+        self.find(name, &NUMERIC_TYPES).map(numbers)
+    }
+
+    fn number(&self, name: &str, default: f64) -> f64 {
+        self.numbers(name).map_or(default, |numbers| numbers[0])
+    }
+
+    fn string(&self, name: &str) -> Option<String> {
+        match self.find(name, &["string"])? {
+            [Value::Text(text), ..] => Some(text.clone()),
+            _ => None
+        }
+    }
+
+    fn boolean(&self, name: &str, default: bool) -> bool {
+        match self.find(name, &["bool"]) {
+            Some([Value::Bool(value), ..]) => *value,
+            _ => default
+        }
+    }
+
+    /// Returns a colour given in RGB or XYZ. Spectra and blackbody emitters are left unread, so they are reported as
+    /// ignored.
+    fn color(&self, name: &str, default: Color) -> Color {
+        if let Some(values) = self.find(name, &["rgb", "color"]) {
+            let rgb = numbers(values);
+            Color::new(rgb[0], rgb[1], rgb[2])
+        } else if let Some(values) = self.find(name, &["xyz"]) {
+            let xyz = numbers(values);
+            xyz_to_rgb([xyz[0], xyz[1], xyz[2]])
+        } else {
+            default
+        }
+    }
+
+    /// Marks every parameter as used, for directives which are skipped with a warning of their own.
+    fn ignore(&self) {
+        for parameter in &self.0 {
+            parameter.used.set(true);
+        }
+    }
+}
+
+impl Directive {
+    /// Splits the arguments into the values which come first, such as the kind of shape, and the named parameters.
+    fn split(&self) -> Result<(Vec<Value>, ParamSet), String> {
+        let mut args = self.args.iter().peekable();
+        let mut positional = Vec::new();
+        while let Some(arg) = args.next_if(|arg| declaration(arg).is_none()) {
+            positional.extend(arg.iter().cloned());
+        }
+        let mut parameters = Vec::new();
+        while let Some(arg) = args.next() {
+            let (type_name, name) = declaration(arg).ok_or_else(|| format!("Expected a parameter declaration in {}", self.name))?;
+            let values = args.next().ok_or_else(|| format!("Missing the value of the parameter \"{} {}\"", type_name, name))?;
+            parameters.push(Parameter::new(type_name, name, values)?);
+        }
+        Ok((positional, ParamSet(parameters)))
+    }
+}
+
+/// Returns the given number of values, which must all be numbers.
+fn positional_numbers(directive: &str, values: &[Value], count: usize) -> Result<Vec<f64>, String> {
+    let numbers = numbers(values);
+    if numbers.len() != count || values.len() != count {
+        return Err(format!("{} expects {} numbers, found {} values", directive, count, values.len()));
+    }
+    Ok(numbers)
+}
+
+/// Returns the single string a directive is given, such as the kind of shape.
+fn positional_string(directive: &str, values: &[Value]) -> Result<String, String> {
+    match values {
+        [Value::Text(text)] => Ok(text.clone()),
+        _ => Err(format!("{} expects a single string", directive))
+    }
+}
+
+impl Importer {
+    fn new(directory: &Path) -> Importer {
+        let mut scene = SceneDescription { background: Color::zeros(), ..SceneDescription::default() };
+        let settings = &mut scene.raytrace_settings;
+        //The defaults of pbrt, which are used for directives that are left out
+        (scene.image_width, scene.image_height) = (640, 480);
+        (settings.samples_per_pixel, settings.sampler) = (16, SamplerType::Halton);
+        (settings.max_depth, settings.integrator) = (5, Integrator::PathTracing);
+        settings.filter = Filter::new(FilterType::Box, 0.5);
+        settings.noise_threshold = 0.0;
+        (scene.camera.look_from, scene.camera.look_at) = (Point3::<f64>::origin(), Point3::<f64>::new(0.0, 0.0, 1.0));
+        scene.camera.focus_dist = 1e6;
+
+        let state = GraphicsState { transform: Matrix4::identity(), material: MaterialDescription::Lambertian { albedo: Color::repeat(0.5) },
+                                    named_materials: HashMap::new(), area_light: None, reverse_orientation: false };
+        Importer { directory: directory.to_path_buf(), scene, warnings: Vec::new(), reported: HashSet::new(), state, stack: Vec::new(),
+                   coordinate_systems: HashMap::new(), fov: 90.0 }
+    }
+
+    fn warn(&mut self, location: &str, message: String) {
+        if self.reported.insert(message.clone()) {
+            self.warnings.push(format!("{}: {}", location, message));
+        }
+    }
+
+    /// Imports the directives of a file, which is named in errors and warnings.
+    fn read(&mut self, file: &str, source: &str, depth: usize) -> Result<(), String> {
+        let directives = tokenize(source).and_then(directives).map_err(|(line, error)| format!("{}:{}: {}", file, line, error))?;
+        for directive in directives {
+            let location = format!("{}:{}", file, directive.line);
+            self.directive(&directive, &location, depth).map_err(|error| format!("{}: {}", location, error))?;
+        }
+        Ok(())
+    }
+
+    fn finish(mut self) -> (SceneDescription, Vec<String>) {
+        //pbrt's field of view is that of the shorter side of the image
+        let (width, height) = (self.scene.image_width as f64, self.scene.image_height as f64);
+        self.scene.camera.v_fov = if width >= height {
+            self.fov
+        } else {
+            2.0 * ((deg_to_rad(self.fov) / 2.0).tan() * height / width).atan().to_degrees()
+        };
+        (self.scene, self.warnings)
+    }
+
+    fn apply(&mut self, transform: Matrix4<f64>) {
+        self.state.transform *= transform;
+    }
+
+    fn push(&mut self, block: Block) {
+        self.stack.push((block, self.state.clone()));
+    }
+
+    fn pop(&mut self, block: Block, location: &str, directive: &str) {
+        match self.stack.pop() {
+            Some((popped, state)) => {
+                if popped != block {
+                    self.warn(location, format!("{} closes a {:?} block", directive, popped));
+                }
+                match popped {
+                    Block::Transform => self.state.transform = state.transform,
+                    _ => self.state = state
+                }
+            }
+            None => self.warn(location, format!("{} has no block to close", directive))
+        }
+    }
+
+    fn directive(&mut self, directive: &Directive, location: &str, depth: usize) -> Result<(), String> {
+        let (positional, params) = directive.split()?;
+        let name = directive.name.as_str();
+        let what = match positional.first() {
+            Some(Value::Text(kind)) => format!("{} \"{}\"", name, kind),
+            _ => String::from(name)
+        };
+        match name {
+            "Identity" => self.state.transform = Matrix4::identity(),
+            "Translate" => {
+                let offset = positional_numbers(name, &positional, 3)?;
+                self.apply(Matrix4::new_translation(&Vector3::<f64>::new(offset[0], offset[1], offset[2])));
+            }
+            "Scale" => {
+                let scale = positional_numbers(name, &positional, 3)?;
+                self.apply(Matrix4::new_nonuniform_scaling(&Vector3::<f64>::new(scale[0], scale[1], scale[2])));
+            }
+            "Rotate" => {
+                let rotation = positional_numbers(name, &positional, 4)?;
+                let axis = Vector3::<f64>::new(rotation[1], rotation[2], rotation[3]);
+                if axis.norm() == 0.0 {
+                    return Err(String::from("Rotate must be given an axis which is not zero"));
+                }
+                self.apply(Rotation3::from_axis_angle(&Unit::new_normalize(axis), deg_to_rad(rotation[0])).to_homogeneous());
+            }
+            "LookAt" => {
+                let v = positional_numbers(name, &positional, 9)?;
+                self.apply(look_at(Point3::<f64>::new(v[0], v[1], v[2]), Point3::<f64>::new(v[3], v[4], v[5]), Vector3::<f64>::new(v[6], v[7], v[8]))?);
+            }
+            //Matrices are written a column at a time
+            "ConcatTransform" => self.apply(Matrix4::from_column_slice(&positional_numbers(name, &positional, 16)?)),
+            "Transform" => self.state.transform = Matrix4::from_column_slice(&positional_numbers(name, &positional, 16)?),
+            "CoordinateSystem" => {
+                self.coordinate_systems.insert(positional_string(name, &positional)?, self.state.transform);
+            }
+            "CoordSysTransform" => {
+                let system = positional_string(name, &positional)?;
+                match self.coordinate_systems.get(&system) {
+                    Some(transform) => self.state.transform = *transform,
+                    None => self.warn(location, format!("Unknown coordinate system \"{}\"", system))
+                }
+            }
+            "ReverseOrientation" => self.state.reverse_orientation = !self.state.reverse_orientation,
+            "ActiveTransform" | "TransformTimes" => self.warn(location, format!("Motion blur is not supported, so {} is ignored", name)),
+            "Camera" => self.camera(&positional_string(name, &positional)?, &params, location)?,
+            "Film" => {
+                (self.scene.image_width, self.scene.image_height) = (params.number("xresolution", 640.0) as usize, params.number("yresolution", 480.0) as usize);
+                if self.scene.image_width == 0 || self.scene.image_height == 0 {
+                    return Err(String::from("The resolution of the film must be at least one pixel"));
+                }
+                //The image is saved wherever the renderer is told to save it
+                params.string("filename");
+            }
+            "Sampler" => self.sampler(&positional_string(name, &positional)?, &params, location),
+            "PixelFilter" => self.filter(&positional_string(name, &positional)?, &params, location),
+            "Integrator" => self.integrator(&positional_string(name, &positional)?, &params, location),
+            //Scenes are always built into a BVH
+            "Accelerator" => params.ignore(),
+            "WorldBegin" => {
+                self.state.transform = Matrix4::identity();
+                self.coordinate_systems.insert(String::from("world"), Matrix4::identity());
+            }
+            "WorldEnd" => (),
+            "AttributeBegin" => self.push(Block::Attribute),
+            "AttributeEnd" => self.pop(Block::Attribute, location, name),
+            "TransformBegin" => self.push(Block::Transform),
+            "TransformEnd" => self.pop(Block::Transform, location, name),
+            "Material" => self.state.material = self.material(&positional_string(name, &positional)?, &params, location),
+            "MakeNamedMaterial" => {
+                let material_name = positional_string(name, &positional)?;
+                let kind = params.string("type").ok_or_else(|| format!("The named material \"{}\" must have a type", material_name))?;
+                let material = self.material(&kind, &params, location);
+                self.state.named_materials.insert(material_name, material);
+            }
+            "NamedMaterial" => {
+                let material_name = positional_string(name, &positional)?;
+                match self.state.named_materials.get(&material_name) {
+                    Some(material) => self.state.material = *material,
+                    None => self.warn(location, format!("Unknown named material \"{}\"", material_name))
+                }
+            }
+            "Texture" => {
+                params.ignore();
+                self.warn(location, String::from("Textures are not supported, so materials use their constant colours"));
+            }
+            "AreaLightSource" => {
+                self.state.area_light = match positional_string(name, &positional)?.as_str() {
+                    "diffuse" => {
+                        //Lights emit from both sides of their surfaces, and are sampled by area
+                        params.boolean("twosided", false);
+                        params.number("samples", 1.0);
+                        params.number("nsamples", 1.0);
+                        Some(params.color("L", Color::repeat(1.0)).component_mul(&params.color("scale", Color::repeat(1.0))))
+                    }
+                    kind => {
+                        params.ignore();
+                        self.warn(location, format!("Unsupported area light \"{}\"", kind));
+                        None
+                    }
+                };
+            }
+            "LightSource" => match positional_string(name, &positional)?.as_str() {
+                "infinite" => {
+                    params.number("samples", 1.0);
+                    params.number("nsamples", 1.0);
+                    self.scene.background = params.color("L", Color::repeat(1.0)).component_mul(&params.color("scale", Color::repeat(1.0)));
+                }
+                kind => {
+                    params.ignore();
+                    self.warn(location, format!("Only area lights and constant infinite lights are supported, so the \"{}\" light is skipped", kind));
+                }
+            },
+            "Shape" => {
+                let kind = positional_string(name, &positional)?;
+                if self.stack.iter().any(|(block, _)| *block == Block::Object) {
+                    params.ignore();
+                } else {
+                    self.shape(&kind, &params, location)?;
+                }
+            }
+            "ObjectBegin" => {
+                self.push(Block::Object);
+                self.warn(location, format!("Object instancing is not supported, so the shapes of \"{}\" are skipped", positional_string(name, &positional)?));
+            }
+            "ObjectEnd" => self.pop(Block::Object, location, name),
+            "ObjectInstance" => (),
+            "MakeNamedMedium" | "MediumInterface" => {
+                params.ignore();
+                self.warn(location, format!("Participating media are not supported, so {} is ignored", name));
+            }
+            "Include" => {
+                if depth >= MAX_INCLUDE_DEPTH {
+                    return Err(String::from("Files are included too deeply, which may be because a file includes itself"));
+                }
+                let path = self.directory.join(positional_string(name, &positional)?);
+                let source = std::fs::read_to_string(&path).map_err(|error| format!("Could not read '{}': {}", path.display(), error))?;
+                self.read(&path.display().to_string(), &source, depth + 1)?;
+            }
+            _ => {
+                params.ignore();
+                self.warn(location, format!("Unknown directive {}", name));
+            }
+        }
+
+        let unused: Vec<String> = params.0.iter().filter(|parameter| !parameter.used.get())
+            .map(|parameter| format!("{} does not support the parameter \"{} {}\", so it is ignored", what, parameter.type_name, parameter.name)).collect();
+        for message in unused {
+            self.warn(location, message);
+        }
+        Ok(())
+    }
+
+    fn camera(&mut self, kind: &str, params: &ParamSet, location: &str) -> Result<(), String> {
+        if kind != "perspective" {
+            params.ignore();
+            self.warn(location, format!("Only perspective cameras are supported, so the \"{}\" camera is treated as one", kind));
+        }
+        let world_to_camera = self.state.transform;
+        let camera_to_world = world_to_camera.try_inverse().ok_or_else(|| String::from("The camera transform cannot be inverted"))?;
+        self.coordinate_systems.insert(String::from("camera"), camera_to_world);
+
+        let camera_to_world = mirror() * camera_to_world;
+        let look_from = camera_to_world.transform_point(&Point3::<f64>::origin());
+        let camera = &mut self.scene.camera;
+        camera.look_from = look_from;
+        camera.look_at = look_from + camera_to_world.transform_vector(&Vector3::<f64>::z()).normalize();
+        camera.v_up = camera_to_world.transform_vector(&Vector3::<f64>::y()).normalize();
+        camera.aperture = 2.0 * params.number("lensradius", 0.0);
+        camera.focus_dist = params.number("focaldistance", 1e6);
+        camera.shutter_open = params.number("shutteropen", 0.0);
+        camera.shutter_close = params.number("shutterclose", 1.0);
+        self.fov = params.number("fov", 90.0);
+        if !(self.fov > 0.0 && self.fov < 180.0) {
+            return Err(format!("Expected a field of view between 0 and 180 degrees, found {}", self.fov));
+        }
+        Ok(())
+    }
+
+    fn sampler(&mut self, kind: &str, params: &ParamSet, location: &str) {
+        let sampler = match kind {
+            "random" => SamplerType::Independent,
+            "stratified" => SamplerType::Stratified,
+            "halton" => SamplerType::Halton,
+            "sobol" => SamplerType::Sobol,
+            kind => {
+                self.warn(location, format!("Unsupported sampler \"{}\", using the Sobol sampler", kind));
+                SamplerType::Sobol
+            }
+        };
+        //The stratified sampler is given its strata rather than its number of samples
+        let samples_per_pixel = if kind == "stratified" {
+            params.boolean("jitter", true);
+            params.number("xsamples", 4.0) * params.number("ysamples", 4.0)
+        } else {
+            params.number("pixelsamples", 16.0)
+        };
+        let settings = &mut self.scene.raytrace_settings;
+        (settings.sampler, settings.samples_per_pixel) = (sampler, (samples_per_pixel as usize).max(1));
+    }
+
+    fn filter(&mut self, kind: &str, params: &ParamSet, location: &str) {
+        let (filter_type, default_radius) = match kind {
+            "box" => (FilterType::Box, 0.5),
+            "triangle" => (FilterType::Triangle, 2.0),
+            "gaussian" => (FilterType::Gaussian, 2.0),
+            "mitchell" => (FilterType::Mitchell, 2.0),
+            "sinc" => (FilterType::Lanczos, 4.0),
+            kind => {
+                self.warn(location, format!("Unsupported filter \"{}\", using a box filter", kind));
+                (FilterType::Box, 0.5)
+            }
+        };
+        let radius = params.number("xwidth", default_radius).max(params.number("ywidth", default_radius));
+        self.scene.raytrace_settings.filter = Filter::new(filter_type, radius);
+    }
+
+    fn integrator(&mut self, kind: &str, params: &ParamSet, location: &str) {
+        let integrator = match kind {
+            "path" | "volpath" => Integrator::PathTracing,
+            "bdpt" => Integrator::Bidirectional,
+            "sppm" => Integrator::ProgressivePhotonMapping,
+            "ambientocclusion" => Integrator::AmbientOcclusion,
+            kind => {
+                self.warn(location, format!("Unsupported integrator \"{}\", using the path tracer", kind));
+                Integrator::PathTracing
+            }
+        };
+        let settings = &mut self.scene.raytrace_settings;
+        (settings.integrator, settings.max_depth) = (integrator, params.number("maxdepth", 5.0) as i32);
+        match integrator {
+            Integrator::ProgressivePhotonMapping => {
+                settings.samples_per_pixel = (params.number("numiterations", 64.0) as usize).max(1);
+                settings.gather_radius = params.number("radius", 1.0);
+                //pbrt traces one photon per pixel in each iteration unless told otherwise
+                let photons = params.number("photonsperiteration", -1.0);
+                if photons > 0.0 {
+                    settings.photon_count = photons as usize;
+                }
+            }
+            Integrator::AmbientOcclusion => settings.ao_samples = (params.number("nsamples", 64.0) as usize).max(1),
+            _ => ()
+        }
+    }
+
+    fn material(&mut self, kind: &str, params: &ParamSet, location: &str) -> MaterialDescription {
+        match kind {
+            "matte" => MaterialDescription::Lambertian { albedo: params.color("Kd", Color::repeat(0.5)) },
+            "metal" => {
+                //The reflectance at normal incidence, from the complex index of refraction, which defaults to copper's
+                let eta = params.color("eta", Color::new(0.200, 0.924, 1.102));
+                let k = params.color("k", Color::new(3.912, 2.452, 2.142));
+                let albedo = Color::from_fn(|i, _| ((eta[i] - 1.0).powi(2) + k[i].powi(2)) / ((eta[i] + 1.0).powi(2) + k[i].powi(2)));
+                let roughness = params.number("roughness", 0.01);
+                let roughness = (params.number("uroughness", roughness) + params.number("vroughness", roughness)) / 2.0;
+                params.boolean("remaproughness", true);
+                MaterialDescription::Metal { albedo, fuzz: roughness.clamp(0.0, 1.0) }
+            }
+            "mirror" => MaterialDescription::Metal { albedo: params.color("Kr", Color::repeat(0.9)), fuzz: 0.0 },
+            "glass" => {
+                let index = params.number("index", params.number("eta", 1.5));
+                MaterialDescription::Dielectric { refractive_index: RefractiveIndex::Constant(index) }
+            }
+            "" | "none" | "interface" => MaterialDescription::Interface,
+            kind => {
+                let albedo = params.color("Kd", Color::repeat(0.5));
+                params.ignore();
+                self.warn(location, format!("Unsupported material \"{}\", using a matte material of its diffuse colour", kind));
+                MaterialDescription::Lambertian { albedo }
+            }
+        }
+    }
+
+    /// Returns the name of the given material in the scene, adding it if it is new.
+    fn material_name(&mut self, material: MaterialDescription) -> String {
+        if let Some((name, _)) = self.scene.materials.iter().find(|(_, existing)| *existing == material) {
+            return name.clone();
+        }
+        let kind = match material {
+            MaterialDescription::Lambertian { .. } => "matte",
+            MaterialDescription::Metal { .. } => "metal",
+            MaterialDescription::Dielectric { .. } => "glass",
+            MaterialDescription::Light { .. } => "light",
+            MaterialDescription::Interface => "interface"
+        };
+        let name = format!("{}_{}", kind, self.scene.materials.len() + 1);
+        self.scene.materials.push((name.clone(), material));
+        name
+    }
+
+    fn shape(&mut self, kind: &str, params: &ParamSet, location: &str) -> Result<(), String> {
+        let material = match self.state.area_light {
+            Some(color) => MaterialDescription::Light { color, light_group: 0 },
+            None => self.state.material
+        };
+        match kind {
+            "sphere" => {
+                let transform = mirror() * self.state.transform;
+                let linear: Matrix3<f64> = self.state.transform.fixed_slice::<3, 3>(0, 0).into_owned();
+                let scale = linear.determinant().abs().cbrt();
+                if (linear.transpose() * linear - Matrix3::identity() * scale * scale).norm() > 1e-6 * scale * scale {
+                    self.warn(location, String::from("Spheres can only be scaled uniformly, so their radii are scaled by the average scale"));
+                }
+                let center = transform.transform_point(&Point3::<f64>::origin());
+                let radius = params.number("radius", 1.0) * scale;
+                let material = self.material_name(material);
+                self.scene.shapes.push(ShapeDescription::Sphere { center, radius, material, medium: None });
+            }
+            "trianglemesh" => {
+                let positions: Vec<Point3<f64>> = params.numbers("P").ok_or_else(|| String::from("Triangle meshes must have positions \"P\""))?
+                    .chunks_exact(3).map(|p| Point3::<f64>::new(p[0], p[1], p[2])).collect();
+                let indices = match params.numbers("indices") {
+                    Some(indices) => indices,
+                    None if positions.len() == 3 => vec![0.0, 1.0, 2.0],
+                    None => return Err(String::from("Triangle meshes must have \"indices\""))
+                };
+                if indices.len() % 3 != 0 {
+                    return Err(String::from("The number of indices of a triangle mesh must be a multiple of three"));
+                }
+                if let Some(index) = indices.iter().find(|index| !(index.fract() == 0.0 && **index >= 0.0 && index.is_finite())) {
+                    return Err(format!("Invalid vertex index '{}'", index));
+                }
+                let triangles: Vec<[usize; 3]> = indices.chunks_exact(3).map(|i| [i[0] as usize, i[1] as usize, i[2] as usize]).collect();
+                if triangles.iter().flatten().any(|index| *index >= positions.len()) {
+                    return Err(String::from("A triangle refers to a vertex which does not exist"));
+                }
+                let mut normals: Option<Vec<Vector3<f64>>> = params.numbers("N").map(|n| n.chunks_exact(3).map(|n| Vector3::<f64>::new(n[0], n[1], n[2])).collect());
+                if normals.as_ref().is_some_and(|normals| normals.len() != positions.len()) {
+                    self.warn(location, String::from("Triangle meshes need a normal for every vertex, so the normals are ignored"));
+                    normals = None;
+                }
+                self.add_triangles(&positions, normals.as_deref(), &triangles, material, location);
+            }
+            "plymesh" => {
+                let file = params.string("filename").ok_or_else(|| String::from("PLY meshes must have a \"filename\""))?;
+                let mesh = ply::read(&self.directory.join(file))?;
+                self.add_triangles(&mesh.positions, mesh.normals.as_deref(), &mesh.triangles, material, location);
+            }
+            kind => {
+                params.ignore();
+                self.warn(location, format!("Unsupported shape \"{}\"", kind));
+            }
+        }
+        Ok(())
+    }
+
+    /// Adds the triangles of a mesh, moved into the world by the current transform. Triangles are wound so that their
+    /// normals face the way they would in pbrt.
+    fn add_triangles(&mut self, positions: &[Point3<f64>], normals: Option<&[Vector3<f64>]>, triangles: &[[usize; 3]], material: MaterialDescription, location: &str) {
+        let transform = mirror() * self.state.transform;
+        let linear: Matrix3<f64> = self.state.transform.fixed_slice::<3, 3>(0, 0).into_owned();
+        let normal_transform = mirror().fixed_slice::<3, 3>(0, 0) * linear.try_inverse().unwrap_or_else(Matrix3::zeros).transpose();
+        //pbrt's normals face the way of the winding, flipped by transforms which swap handedness. Mirroring the scene
+        //reverses the winding, so triangles are turned back unless pbrt would have flipped them.
+        let rewind = !(self.state.reverse_orientation ^ (linear.determinant() < 0.0));
+        let material = self.material_name(material);
+
+        let mut degenerate = 0;
+        for triangle in triangles {
+            let mut corners = *triangle;
+            if rewind {
+                corners.swap(1, 2);
+            }
+            let vertices = corners.map(|index| transform.transform_point(&positions[index]));
+            if (vertices[1] - vertices[0]).cross(&(vertices[2] - vertices[0])).norm() == 0.0 {
+                degenerate += 1;
+                continue;
+            }
+            let normals = normals.map(|normals| corners.map(|index| (normal_transform * normals[index]).normalize()));
+            self.scene.shapes.push(ShapeDescription::Triangle { vertices, normals, material: material.clone() });
+        }
+        if degenerate > 0 {
+            self.warn(location, format!("Skipped {} triangles which have no area", degenerate));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_import(){
+        let source = "# A small scene\n\
+                      LookAt 1 0 -5  1 0 0  0 1 0\n\
+                      Camera \"perspective\" \"float fov\" [ 30 ]\n\
+                      Film \"image\" \"integer xresolution\" [ 200 ] \"integer yresolution\" [ 100 ] \"string filename\" \"out.exr\"\n\
+                      Sampler \"halton\" \"integer pixelsamples\" 64\n\
+                      Integrator \"path\" \"integer maxdepth\" [ 8 ]\n\
+                      WorldBegin\n\
+                      LightSource \"infinite\" \"rgb L\" [ .1 .2 .3 ]\n\
+                      AttributeBegin\n\
+                          AreaLightSource \"diffuse\" \"rgb L\" [ 4 4 4 ]\n\
+                          Translate 2 3 0\n\
+                          Shape \"sphere\" \"float radius\" 0.5\n\
+                      AttributeEnd\n\
+                      Material \"glass\" \"float index\" 1.33\n\
+                      TransformBegin\n\
+                          Scale 2 2 2\n\
+                          Shape \"trianglemesh\" \"integer indices\" [ 0 1 2 ] \"point P\" [ 0 0 0  1 0 0  0 1 0 ]\n\
+                      TransformEnd\n\
+                      Shape \"sphere\" \"float radius\" 1 \"float zmax\" 0.5\n\
+                      Shape \"cylinder\"\n\
+                      Spin 3\n\
+                      WorldEnd\n";
+        let (scene, warnings) = import_source(source, Path::new("")).unwrap();
+
+        //Case 1: Settings are read, and the camera is mirrored along with the scene
+        assert_eq!((scene.image_width, scene.image_height), (200, 100));
+        let settings = &scene.raytrace_settings;
+        assert_eq!((settings.samples_per_pixel, settings.sampler, settings.max_depth), (64, SamplerType::Halton, 8));
+        assert_eq!(scene.camera.look_from, Point3::<f64>::new(-1.0, 0.0, -5.0));
+        assert!((scene.camera.look_at - Point3::<f64>::new(-1.0, 0.0, -4.0)).norm() < 1e-9);
+        assert_eq!(scene.camera.v_fov, 30.0);
+        assert_eq!(scene.background, Color::new(0.1, 0.2, 0.3));
+
+        //Case 2: Area lights and transforms only last until the end of their blocks
+        assert_eq!(scene.shapes.len(), 3);
+        match &scene.shapes[0] {
+            ShapeDescription::Sphere { center, radius, material, .. } => {
+                assert!((center - Point3::<f64>::new(-2.0, 3.0, 0.0)).norm() < 1e-9);
+                assert_eq!(*radius, 0.5);
+                assert_eq!(scene.material(material), Some(MaterialDescription::Light { color: Color::repeat(4.0), light_group: 0 }));
+            }
+            _ => panic!("Expected a sphere")
+        }
+        match &scene.shapes[1] {
+            ShapeDescription::Triangle { vertices, material, .. } => {
+                //Mirrored and rewound, so that the normal still faces along z
+                assert_eq!(vertices[1], Point3::<f64>::new(0.0, 2.0, 0.0));
+                assert_eq!(vertices[2], Point3::<f64>::new(-2.0, 0.0, 0.0));
+                assert!((vertices[1] - vertices[0]).cross(&(vertices[2] - vertices[0])).z > 0.0);
+                assert_eq!(scene.material(material), Some(MaterialDescription::Dielectric { refractive_index: RefractiveIndex::Constant(1.33) }));
+            }
+            _ => panic!("Expected a triangle")
+        }
+        assert!(matches!(&scene.shapes[2], ShapeDescription::Sphere { radius, .. } if *radius == 1.0));
+
+        //Case 3: Unsupported parts of the scene give warnings with their locations
+        assert_eq!(warnings.len(), 3);
+        assert_eq!(warnings[0], "<input>:19: Shape \"sphere\" does not support the parameter \"float zmax\", so it is ignored");
+        assert_eq!(warnings[1], "<input>:20: Unsupported shape \"cylinder\"");
+        assert_eq!(warnings[2], "<input>:21: Unknown directive Spin");
+    }
+
+    #[test]
+    fn test_errors(){
+        let error = |source: &str| import_source(source, Path::new("")).unwrap_err();
+
+        //Case 1: Syntax errors are reported with their lines
+        assert_eq!(error("WorldBegin\nTranslate 1 2"), "<input>:2: Translate expects 3 numbers, found 2 values");
+        assert_eq!(error("Shape \"sphere\" \"float radius\""), "<input>:1: Missing the value of the parameter \"float radius\"");
+        assert_eq!(error("\nShape \"sphere"), "<input>:2: Unterminated string");
+        assert_eq!(error("Shape \"sphere\" \"float radius\" [ 1"), "<input>:1: Unterminated '['");
+        assert_eq!(error("Film \"image\" \"integer xresolution\" [ 1.5 ]"), "<input>:1: Invalid value for the parameter \"integer xresolution\"");
+
+        //Case 2: Triangle meshes must only refer to vertices which exist
+        let mesh = |indices: &str| format!("WorldBegin\nShape \"trianglemesh\" \"point P\" [ 0 0 0  1 0 0  0 1 0 ] \"float indices\" [ {} ]", indices);
+        assert_eq!(error(&mesh("0 1 2.5")), "<input>:2: Invalid vertex index '2.5'");
+        assert_eq!(error(&mesh("0 -1 2")), "<input>:2: Invalid vertex index '-1'");
+        assert_eq!(error(&mesh("0 1 1e999")), "<input>:2: Invalid vertex index 'inf'");
+        assert_eq!(error(&mesh("0 1 3")), "<input>:2: A triangle refers to a vertex which does not exist");
+
+        //Case 3: Missing files are errors
+        assert!(error("Include \"missing.pbrt\"").starts_with("<input>:1: Could not read"));
+    }
+
+    #[test]
+    fn test_include(){
+        let directory = std::env::temp_dir().join(format!("pbrt_import_test_{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        std::fs::write(directory.join("main.pbrt"), "WorldBegin\nMaterial \"matte\" \"rgb Kd\" [ 1 0 0 ]\nInclude \"geometry.pbrt\"\nWorldEnd\n").unwrap();
+        std::fs::write(directory.join("geometry.pbrt"), "Shape \"plymesh\" \"string filename\" \"quad.ply\"\n").unwrap();
+        std::fs::write(directory.join("quad.ply"), "ply\nformat ascii 1.0\nelement vertex 4\nproperty float x\nproperty float y\nproperty float z\n\
+                                                    element face 1\nproperty list uchar int vertex_indices\nend_header\n\
+                                                    0 0 0\n1 0 0\n1 1 0\n0 1 0\n4 0 1 2 3\n").unwrap();
+        let imported = import(&directory.join("main.pbrt"));
+        std::fs::remove_dir_all(&directory).unwrap();
+
+        //Case 1: Included files are read relative to the main file, and share its graphics state
+        let (scene, warnings) = imported.unwrap();
+        assert!(warnings.is_empty());
+        assert_eq!(scene.shapes.len(), 2);
+        assert_eq!(scene.materials, vec![(String::from("matte_1"), MaterialDescription::Lambertian { albedo: Color::new(1.0, 0.0, 0.0) })]);
+    }
+}
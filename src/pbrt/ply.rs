@@ -0,0 +1,265 @@
+use crate::nalgebra::{Point3, Vector3};
+
+use std::path::Path;
+
+/// The triangles of a PLY mesh. Polygons with more than three sides are split into fans of triangles.
+#[derive (Clone, Debug, PartialEq)]
+pub struct PlyMesh {
+    pub positions: Vec<Point3<f64>>,
+    /// The normal at each vertex, if the file gives them.
+    pub normals: Option<Vec<Vector3<f64>>>,
+    pub triangles: Vec<[usize; 3]>
+}
+
+#[derive (Copy, Clone, Debug, PartialEq)]
+enum Format {
+    Ascii,
+    LittleEndian,
+    BigEndian
+}
+
+#[derive (Copy, Clone, Debug, PartialEq)]
+enum ScalarType {
+    I8, U8, I16, U16, I32, U32, F32, F64
+}
+
+impl ScalarType {
+    fn parse(name: &str) -> Result<ScalarType, String> {
+        match name {
+            "char" | "int8" => Ok(ScalarType::I8),
+            "uchar" | "uint8" => Ok(ScalarType::U8),
+            "short" | "int16" => Ok(ScalarType::I16),
+            "ushort" | "uint16" => Ok(ScalarType::U16),
+            "int" | "int32" => Ok(ScalarType::I32),
+            "uint" | "uint32" => Ok(ScalarType::U32),
+            "float" | "float32" => Ok(ScalarType::F32),
+            "double" | "float64" => Ok(ScalarType::F64),
+            _ => Err(format!("Unknown property type '{}'", name))
+        }
+    }
+
+    fn size(&self) -> usize {
+        match self {
+            ScalarType::I8 | ScalarType::U8 => 1,
+            ScalarType::I16 | ScalarType::U16 => 2,
+            ScalarType::I32 | ScalarType::U32 | ScalarType::F32 => 4,
+            ScalarType::F64 => 8
+        }
+    }
+}
+
+#[derive (Clone, Debug, PartialEq)]
+struct Property {
+    name: String,
+    /// The type of the length of a list property, or `None` for a single value.
+    count_type: Option<ScalarType>,
+    value_type: ScalarType
+}
+
+#[derive (Clone, Debug, PartialEq)]
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>
+}
+
+/// Reads the values of a file, in whichever format it is written.
+struct Reader<'a> {
+    format: Format,
+    bytes: &'a [u8],
+    position: usize
+}
+
+impl<'a> Reader<'a> {
+    fn read(&mut self, scalar_type: ScalarType) -> Result<f64, String> {
+        if self.format == Format::Ascii {
+            while self.bytes.get(self.position).is_some_and(|byte| byte.is_ascii_whitespace()) {
+                self.position += 1;
+            }
+            let start = self.position;
+            while self.bytes.get(self.position).is_some_and(|byte| !byte.is_ascii_whitespace()) {
+                self.position += 1;
+            }
+            let word = std::str::from_utf8(&self.bytes[start..self.position]).map_err(|_| String::from("Invalid text in PLY data"))?;
+            return word.parse().map_err(|_| if word.is_empty() { String::from("Unexpected end of PLY data") } else { format!("Invalid PLY value '{}'", word) });
+        }
+
+        let size = scalar_type.size();
+        let bytes = self.bytes.get(self.position..self.position + size).ok_or_else(|| String::from("Unexpected end of PLY data"))?;
+        self.position += size;
+        let mut buffer = [0u8; 8];
+        buffer[..size].copy_from_slice(bytes);
+        if self.format == Format::BigEndian {
+            buffer[..size].reverse();
+        }
+        Ok(match scalar_type {
+            ScalarType::I8 => buffer[0] as i8 as f64,
+            ScalarType::U8 => buffer[0] as f64,
+            ScalarType::I16 => i16::from_le_bytes([buffer[0], buffer[1]]) as f64,
+            ScalarType::U16 => u16::from_le_bytes([buffer[0], buffer[1]]) as f64,
+            ScalarType::I32 => i32::from_le_bytes([buffer[0], buffer[1], buffer[2], buffer[3]]) as f64,
+            ScalarType::U32 => u32::from_le_bytes([buffer[0], buffer[1], buffer[2], buffer[3]]) as f64,
+            ScalarType::F32 => f32::from_le_bytes([buffer[0], buffer[1], buffer[2], buffer[3]]) as f64,
+            ScalarType::F64 => f64::from_le_bytes(buffer)
+        })
+    }
+
+    /// Reads every value of a property, which is a single value unless the property is a list.
+    fn read_property(&mut self, property: &Property) -> Result<Vec<f64>, String> {
+        match property.count_type {
+            Some(count_type) => {
+                let count = self.read(count_type)? as usize;
+                (0..count).map(|_| self.read(property.value_type)).collect()
+            }
+            None => Ok(vec![self.read(property.value_type)?])
+        }
+    }
+}
+
+/// Reads the header of a PLY file, returning its format, its elements, and the length of the header in bytes.
+fn parse_header(bytes: &[u8]) -> Result<(Format, Vec<Element>, usize), String> {
+    let end = bytes.windows(10).position(|window| window == b"end_header").ok_or_else(|| String::from("Missing end_header"))?;
+    let data_start = bytes[end..].iter().position(|byte| *byte == b'\n').map_or(bytes.len(), |newline| end + newline + 1);
+    let header = std::str::from_utf8(&bytes[..end]).map_err(|_| String::from("Invalid text in PLY header"))?;
+
+    let mut lines = header.lines().map(str::trim);
+    if lines.next() != Some("ply") {
+        return Err(String::from("Not a PLY file"));
+    }
+    let mut format = None;
+    let mut elements: Vec<Element> = Vec::new();
+    for line in lines {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words[..] {
+            ["format", name, _] => format = Some(match name {
+                "ascii" => Format::Ascii,
+                "binary_little_endian" => Format::LittleEndian,
+                "binary_big_endian" => Format::BigEndian,
+                _ => return Err(format!("Unknown PLY format '{}'", name))
+            }),
+            ["element", name, count] => {
+                let count = count.parse().map_err(|_| format!("Invalid element count '{}'", count))?;
+                elements.push(Element { name: String::from(name), count, properties: Vec::new() });
+            }
+            ["property", "list", count_type, value_type, name] => {
+                let property = Property { name: String::from(name), count_type: Some(ScalarType::parse(count_type)?), value_type: ScalarType::parse(value_type)? };
+                elements.last_mut().ok_or_else(|| String::from("Property before any element"))?.properties.push(property);
+            }
+            ["property", value_type, name] => {
+                let property = Property { name: String::from(name), count_type: None, value_type: ScalarType::parse(value_type)? };
+                elements.last_mut().ok_or_else(|| String::from("Property before any element"))?.properties.push(property);
+            }
+            [] | ["comment", ..] | ["obj_info", ..] => (),
+            _ => return Err(format!("Invalid PLY header line '{}'", line))
+        }
+    }
+    //Only the first value of each property is read for a vertex, so lists would be silently cut short
+    let vertex_lists = elements.iter().filter(|element| element.name == "vertex").flat_map(|element| &element.properties)
+        .find(|property| property.count_type.is_some() && ["x", "y", "z", "nx", "ny", "nz"].contains(&property.name.as_str()));
+    if let Some(property) = vertex_lists {
+        return Err(format!("The vertex property '{}' must not be a list", property.name));
+    }
+    Ok((format.ok_or_else(|| String::from("Missing PLY format"))?, elements, data_start))
+}
+
+/// Reads a PLY mesh from its bytes.
+pub fn parse(bytes: &[u8]) -> Result<PlyMesh, String> {
+    let (format, elements, data_start) = parse_header(bytes)?;
+    let mut reader = Reader { format, bytes, position: data_start };
+    let mut mesh = PlyMesh { positions: Vec::new(), normals: None, triangles: Vec::new() };
+    let mut normals = Vec::new();
+
+    for element in &elements {
+        let index_of = |name: &str| element.properties.iter().position(|property| property.name == name);
+        for _ in 0..element.count {
+            let values = element.properties.iter().map(|property| reader.read_property(property)).collect::<Result<Vec<Vec<f64>>, String>>()?;
+            match element.name.as_str() {
+                "vertex" => {
+                    let coordinates = |names: [&str; 3]| -> Option<Vector3<f64>> {
+                        let indices = [index_of(names[0])?, index_of(names[1])?, index_of(names[2])?];
+                        Some(Vector3::<f64>::new(values[indices[0]][0], values[indices[1]][0], values[indices[2]][0]))
+                    };
+                    let position = coordinates(["x", "y", "z"]).ok_or_else(|| String::from("Vertices must have x, y and z properties"))?;
+                    mesh.positions.push(Point3::from(position));
+                    if let Some(normal) = coordinates(["nx", "ny", "nz"]) {
+                        normals.push(normal);
+                    }
+                }
+                "face" => {
+                    let indices = index_of("vertex_indices").or_else(|| index_of("vertex_index"))
+                        .ok_or_else(|| String::from("Faces must have a vertex_indices property"))?;
+                    let face = values[indices].iter().map(|index| match *index >= 0.0 && index.fract() == 0.0 {
+                        true => Ok(*index as usize),
+                        false => Err(format!("Invalid vertex index '{}'", index))
+                    }).collect::<Result<Vec<usize>, String>>()?;
+                    for corner in 2..face.len() {
+                        mesh.triangles.push([face[0], face[corner - 1], face[corner]]);
+                    }
+                }
+                _ => ()
+            }
+        }
+    }
+
+    if mesh.triangles.iter().flatten().any(|index| *index >= mesh.positions.len()) {
+        return Err(String::from("Face refers to a vertex which does not exist"));
+    }
+    if !normals.is_empty() && normals.len() == mesh.positions.len() {
+        mesh.normals = Some(normals);
+    }
+    Ok(mesh)
+}
+
+/// Reads the PLY mesh at the given path.
+pub fn read(path: &Path) -> Result<PlyMesh, String> {
+    let bytes = std::fs::read(path).map_err(|error| format!("Could not read '{}': {}", path.display(), error))?;
+    parse(&bytes).map_err(|error| format!("{}: {}", path.display(), error))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse(){
+        //Case 1: An ASCII file with normals, and a quad which is split into two triangles
+        let ascii = "ply\nformat ascii 1.0\ncomment A unit square\nelement vertex 4\nproperty float x\nproperty float y\nproperty float z\n\
+                     property float nx\nproperty float ny\nproperty float nz\nelement face 1\nproperty list uchar int vertex_indices\nend_header\n\
+                     0 0 0 0 0 1\n1 0 0 0 0 1\n1 1 0 0 0 1\n0 1 0 0 0 1\n4 0 1 2 3\n";
+        let mesh = parse(ascii.as_bytes()).unwrap();
+        assert_eq!(mesh.positions[2], Point3::<f64>::new(1.0, 1.0, 0.0));
+        assert_eq!(mesh.triangles, vec![[0, 1, 2], [0, 2, 3]]);
+        assert_eq!(mesh.normals.unwrap()[3], Vector3::<f64>::new(0.0, 0.0, 1.0));
+
+        //Case 2: A binary file, with an element which is skipped
+        let mut binary = b"ply\nformat binary_little_endian 1.0\nelement vertex 3\nproperty float x\nproperty float y\nproperty float z\n\
+                           element material 1\nproperty uchar red\nelement face 1\nproperty list uchar uint vertex_indices\nend_header\n".to_vec();
+        for value in [0.0f32, 0.0, 0.0, 2.0, 0.0, 0.0, 0.0, 0.5, -1.0] {
+            binary.extend_from_slice(&value.to_le_bytes());
+        }
+        binary.push(255);
+        binary.push(3);
+        for index in [0u32, 1, 2] {
+            binary.extend_from_slice(&index.to_le_bytes());
+        }
+        let mesh = parse(&binary).unwrap();
+        assert_eq!(mesh.positions[2], Point3::<f64>::new(0.0, 0.5, -1.0));
+        assert_eq!(mesh.triangles, vec![[0, 1, 2]]);
+        assert_eq!(mesh.normals, None);
+
+        //Case 3: Truncated data is reported rather than read past
+        assert!(parse(&binary[..binary.len() - 2]).is_err());
+
+        //Case 4: Vertex coordinates given as lists are rejected rather than read in part
+        let list = "ply\nformat ascii 1.0\nelement vertex 1\nproperty list uchar float x\nproperty float y\nproperty float z\nend_header\n2 0 1 0 0\n";
+        assert_eq!(parse(list.as_bytes()), Err(String::from("The vertex property 'x' must not be a list")));
+
+        //Case 5: Indices which are negative, fractional or not numbers are rejected rather than cast to a vertex
+        let header = "ply\nformat ascii 1.0\nelement vertex 3\nproperty float x\nproperty float y\nproperty float z\n\
+                      element face 1\nproperty list uchar int vertex_indices\nend_header\n0 0 0\n1 0 0\n0 1 0\n";
+        assert_eq!(parse(format!("{}3 0 1 2\n", header).as_bytes()).unwrap().triangles, vec![[0, 1, 2]]);
+        for (face, index) in [("3 0 -1 2", "-1"), ("3 0 1.5 2", "1.5"), ("3 0 1 nan", "NaN")] {
+            assert_eq!(parse(format!("{}{}\n", header, face).as_bytes()), Err(format!("Invalid vertex index '{}'", index)));
+        }
+    }
+}
@@ -1,3 +1,4 @@
+pub mod animated;
 pub mod bvh;
 pub mod rect;
 pub mod sphere;
@@ -7,11 +8,14 @@ use crate::primitives::triangle::*;
 use crate::primitives::sphere::*;
 use crate::primitives::rect::*;
 use crate::material::*;
+use crate::media::Medium;
 use crate::nalgebra::{Vector3, Point3};
 use crate::image::Color;
 use crate::primitives::bvh::*;
+use crate::primitives::animated::{AnimatedPrimitive, Motion};
 use crate::enum_dispatch::*;
 use crate::rasterizing::Rasterize;
+use crate::lights::{Sample, SurfaceSample};
 extern crate fastrand;
 
 use crate::camera::Camera;
@@ -24,6 +28,7 @@ use std::convert::TryFrom;
 
 #[enum_dispatch(Hit)]
 #[enum_dispatch(Rasterize)]
+#[enum_dispatch(Sample)]
 #[derive (Copy, Clone)]
 pub enum GeometricPrimitive {
     Triangle(Triangle),
@@ -40,16 +45,41 @@ impl GeometricPrimitive {
         GeometricPrimitive::Sphere(Sphere::new(cen, rad, mat))
     }
 
+    pub fn new_sphere_with_medium(cen: Point3<f64>, rad: f64, mat: Material, medium: Medium) -> GeometricPrimitive {
+        GeometricPrimitive::Sphere(Sphere::new_with_medium(cen, rad, mat, medium))
+    }
+
+    pub fn new_moving_sphere(cen0: Point3<f64>, cen1: Point3<f64>, time0: f64, time1: f64, rad: f64, mat: Material) -> GeometricPrimitive {
+        GeometricPrimitive::Sphere(Sphere::new_moving(cen0, cen1, time0, time1, rad, mat))
+    }
+
     pub fn new_rect(axes: RectAxes, axis1_min: f64, axis1_max: f64, axis2_min: f64, axis2_max: f64, k: f64, mat: Material) -> GeometricPrimitive {
         GeometricPrimitive::Rect(Rect::new(axes, axis1_min, axis1_max, axis2_min, axis2_max, k, mat))
     }
+
+    /// Returns true if the primitive moves while the shutter is open.
+    pub fn is_moving(&self) -> bool {
+        match self {
+            GeometricPrimitive::Sphere(sphere) => sphere.is_moving(),
+            _ => false
+        }
+    }
+
+    pub fn material(&self) -> Material {
+        match self {
+            GeometricPrimitive::Triangle(triangle) => triangle.material(),
+            GeometricPrimitive::Sphere(sphere) => sphere.material(),
+            GeometricPrimitive::Rect(rect) => rect.material()
+        }
+    }
 }
 
 #[enum_dispatch(Hit)]
 #[derive (Clone)]
 pub enum Primitive {
     GeometricPrimitive(GeometricPrimitive),
-    Bvh(BvhNode)
+    Bvh(BvhNode),
+    Animated(AnimatedPrimitive)
 }
 
 impl Primitive {
@@ -62,6 +92,14 @@ impl Primitive {
         Primitive::new_geometric_primitive(GeometricPrimitive::new_sphere(cen, rad, mat))
     }
 
+    pub fn new_sphere_with_medium(cen: Point3<f64>, rad: f64, mat: Material, medium: Medium) -> Primitive {
+        Primitive::new_geometric_primitive(GeometricPrimitive::new_sphere_with_medium(cen, rad, mat, medium))
+    }
+
+    pub fn new_moving_sphere(cen0: Point3<f64>, cen1: Point3<f64>, time0: f64, time1: f64, rad: f64, mat: Material) -> Primitive {
+        Primitive::new_geometric_primitive(GeometricPrimitive::new_moving_sphere(cen0, cen1, time0, time1, rad, mat))
+    }
+
     pub fn new_rect(axes: RectAxes, axis1_min: f64, axis1_max: f64, axis2_min: f64, axis2_max: f64, k: f64, mat: Material) -> Primitive {
         Primitive::new_geometric_primitive(GeometricPrimitive::new_rect(axes, axis1_min, axis1_max, axis2_min, axis2_max, k, mat))
     }
@@ -73,6 +111,11 @@ impl Primitive {
     pub fn new_bvh(bvh: BvhNode) -> Primitive {
         Primitive::Bvh(bvh)
     }
+
+    /// Returns the primitive moving rigidly through the motion. Animated primitives are raytraced, but not rasterized.
+    pub fn new_animated(primitive: Primitive, motion: Motion) -> Primitive {
+        Primitive::Animated(AnimatedPrimitive::new(primitive, motion))
+    }
 }
 
 
@@ -216,8 +259,9 @@ impl Hit for GeometricPrimitives{
         let mut closest_so_far = t_max;
         let mut hit_out: Option<(HitRecord, &Material)> = None;
 
-        for traceable in &self.list{
-            if let Some(hit_temp) = traceable.hit(r, t_min, closest_so_far){
+        for (index, traceable) in self.list.iter().enumerate(){
+            if let Some(mut hit_temp) = traceable.hit(r, t_min, closest_so_far){
+                hit_temp.0.primitive_id = index;
                 hit_out = Some(hit_temp);
                 closest_so_far = hit_temp.0.t;
             }
@@ -225,6 +269,10 @@ impl Hit for GeometricPrimitives{
         hit_out
     }
 
+    fn hit_debug(&self, r: &Ray, t_min: f64, t_max: f64) -> (i32, Option<(HitRecord, &Material)>) {
+        (self.len() as i32, self.hit(r, t_min, t_max))
+    }
+
     fn bounding_box(&self) -> Option<Aabb>{
         if self.empty(){
            None
@@ -377,8 +425,12 @@ impl Hit for Primitives{
         let mut closest_so_far = t_max;
         let mut hit_out: Option<(HitRecord, &Material)> = None;
 
-        for traceable in &self.list{
-            if let Some(hit_temp) = traceable.hit(r, t_min, closest_so_far){
+        for (index, traceable) in self.list.iter().enumerate(){
+            if let Some(mut hit_temp) = traceable.hit(r, t_min, closest_so_far){
+                //Primitives within an acceleration structure are identified by the structure itself
+                if let Primitive::GeometricPrimitive(_) = traceable {
+                    hit_temp.0.primitive_id = index;
+                }
                 hit_out = Some(hit_temp);
                 closest_so_far = hit_temp.0.t;
             }
@@ -386,6 +438,25 @@ impl Hit for Primitives{
         hit_out
     }
 
+    fn hit_debug(&self, r: &Ray, t_min: f64, t_max: f64) -> (i32, Option<(HitRecord, &Material)>) {
+        let mut closest_so_far = t_max;
+        let mut hit_out: Option<(HitRecord, &Material)> = None;
+        let mut nodes_visited = 0;
+
+        for (index, traceable) in self.list.iter().enumerate(){
+            let (nodes, hit) = traceable.hit_debug(r, t_min, closest_so_far);
+            nodes_visited += nodes;
+            if let Some(mut hit_temp) = hit{
+                if let Primitive::GeometricPrimitive(_) = traceable {
+                    hit_temp.0.primitive_id = index;
+                }
+                hit_out = Some(hit_temp);
+                closest_so_far = hit_temp.0.t;
+            }
+        }
+        (nodes_visited, hit_out)
+    }
+
     fn bounding_box(&self) -> Option<Aabb>{
         if self.empty(){
            None
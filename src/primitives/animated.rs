@@ -0,0 +1,161 @@
+use crate::material::Material;
+use crate::nalgebra::{Isometry3, Point3, Vector3};
+use crate::primitives::Primitive;
+use crate::primitives::bvh::Aabb;
+use crate::raytracing::{Hit, HitRecord, Ray};
+
+/// The number of steps at which a motion is sampled when bounding a primitive throughout it.
+const BOUND_STEPS: usize = 16;
+
+/// A rigid motion between two keyframes. The translation is interpolated linearly and the rotation spherically, from
+/// `start` at `time0` to `end` at `time1`. The transform is held at `start` before `time0`, and at `end` after `time1`.
+#[derive (Copy, Clone, Debug, PartialEq)]
+pub struct Motion {
+    pub start: Isometry3<f64>,
+    pub end: Isometry3<f64>,
+    pub time0: f64,
+    pub time1: f64
+}
+
+impl Motion {
+    pub fn new(start: Isometry3<f64>, end: Isometry3<f64>, time0: f64, time1: f64) -> Motion {
+        Motion { start, end, time0, time1 }
+    }
+
+    /// Returns the transform at the given time.
+    pub fn at(&self, time: f64) -> Isometry3<f64> {
+        if self.time1 <= self.time0 {
+            return self.start;
+        }
+        let fraction = ((time - self.time0) / (self.time1 - self.time0)).clamp(0.0, 1.0);
+        self.start.lerp_slerp(&self.end, fraction)
+    }
+
+    /// Returns a box which bounds the contents of `bb` at every moment of the motion. The box is transformed at evenly
+    /// spaced steps through the motion. Between steps, points move along arcs which may bulge out of the boxes, so the
+    /// result is padded by the furthest a point can stray from the chord between consecutive steps.
+    pub fn bound(&self, bb: &Aabb) -> Aabb {
+        let corners: Vec<Point3<f64>> = (0..8).map(|i| Point3::<f64>::new(if i & 1 == 0 { bb.min()[0] } else { bb.max()[0] },
+                                                                            if i & 2 == 0 { bb.min()[1] } else { bb.max()[1] },
+                                                                            if i & 4 == 0 { bb.min()[2] } else { bb.max()[2] })).collect();
+        let mut min = Point3::<f64>::new(f64::INFINITY, f64::INFINITY, f64::INFINITY);
+        let mut max = Point3::<f64>::new(f64::NEG_INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY);
+        for step in 0..=BOUND_STEPS {
+            let transform = self.at(self.time0 + (self.time1 - self.time0) * step as f64 / BOUND_STEPS as f64);
+            for corner in &corners {
+                let p = transform.transform_point(corner);
+                min = min.inf(&p);
+                max = max.sup(&p);
+            }
+        }
+
+        let step_angle = self.start.rotation.angle_to(&self.end.rotation) / BOUND_STEPS as f64;
+        let radius = corners.iter().map(|corner| corner.coords.norm()).fold(0.0, f64::max);
+        let padding = Vector3::<f64>::repeat(radius * (1.0 - (0.5 * step_angle).cos()));
+        Aabb::new(min - padding, max + padding)
+    }
+}
+
+/// A primitive which moves rigidly while the shutter is open. Rays are transformed into the space of the primitive at
+/// their own time, so the primitive is blurred along its path.
+#[derive (Clone)]
+pub struct AnimatedPrimitive {
+    primitive: Box<Primitive>,
+    motion: Motion
+}
+
+impl AnimatedPrimitive {
+    pub fn new(primitive: Primitive, motion: Motion) -> AnimatedPrimitive {
+        AnimatedPrimitive { primitive: Box::new(primitive), motion }
+    }
+
+    pub fn motion(&self) -> Motion {
+        self.motion
+    }
+
+    /// Returns the transform at the time of the ray, along with the ray in the space of the primitive.
+    fn to_local(&self, r: &Ray) -> (Isometry3<f64>, Ray) {
+        let transform = self.motion.at(r.time());
+        let local = Ray::new_at_time(transform.inverse_transform_point(&r.origin()), transform.inverse_transform_vector(&r.direction()), r.time());
+        (transform, local)
+    }
+
+    /// Moves a hit found in the space of the primitive back into world space.
+    fn to_world(transform: &Isometry3<f64>, mut rec: HitRecord) -> HitRecord {
+        let rotation = transform.rotation.to_rotation_matrix();
+        rec.p = transform.transform_point(&rec.p);
+        rec.normal = rotation * rec.normal;
+        rec.geometric_normal = rotation * rec.geometric_normal;
+        rec.p_err = rotation.matrix().abs() * rec.p_err;
+        rec
+    }
+}
+
+impl Hit for AnimatedPrimitive {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<(HitRecord, &Material)> {
+        let (transform, local) = self.to_local(r);
+        let (rec, mat) = self.primitive.hit(&local, t_min, t_max)?;
+        Some((AnimatedPrimitive::to_world(&transform, rec), mat))
+    }
+
+    fn hit_debug(&self, r: &Ray, t_min: f64, t_max: f64) -> (i32, Option<(HitRecord, &Material)>) {
+        let (transform, local) = self.to_local(r);
+        let (nodes, hit) = self.primitive.hit_debug(&local, t_min, t_max);
+        (nodes, hit.map(|(rec, mat)| (AnimatedPrimitive::to_world(&transform, rec), mat)))
+    }
+
+    /// The box covers the primitive throughout its motion.
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.primitive.bounding_box().map(|bb| self.motion.bound(&bb))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image::Color;
+    use crate::nalgebra::{Translation3, UnitQuaternion};
+    use std::f64::consts::PI;
+
+    #[test]
+    fn test_hit(){
+        let mat = Material::new_lambertian(Color::new(0.5, 0.5, 0.5));
+        let start = Isometry3::<f64>::identity();
+        let end = Isometry3::<f64>::from_parts(Translation3::new(0.0, 4.0, 0.0), UnitQuaternion::identity());
+        let animated = AnimatedPrimitive::new(Primitive::new_sphere(Point3::<f64>::new(0.0, 0.0, 0.0), 1.0, mat), Motion::new(start, end, 0.0, 1.0));
+
+        //Case 1: The primitive is found where it is at the time of the ray
+        let direction = Vector3::<f64>::new(1.0, 0.0, 0.0);
+        for (time, y) in [(0.0, 0.0), (0.5, 2.0), (1.0, 4.0), (2.0, 4.0)] {
+            let r = Ray::new_at_time(Point3::<f64>::new(-5.0, y, 0.0), direction, time);
+            let (rec, _) = animated.hit(&r, 0.001, f64::INFINITY).unwrap();
+            assert!((rec.p - Point3::<f64>::new(-1.0, y, 0.0)).norm() < 1e-9);
+            assert!((rec.normal - Vector3::<f64>::new(-1.0, 0.0, 0.0)).norm() < 1e-9);
+            assert_eq!(rec.time, time);
+        }
+
+        //Case 2: The primitive is missed where it was at another time
+        let r = Ray::new_at_time(Point3::<f64>::new(-5.0, 0.0, 0.0), direction, 1.0);
+        assert!(animated.hit(&r, 0.001, f64::INFINITY).is_none());
+
+        //Case 3: The bounding box covers the whole motion
+        let bb = animated.bounding_box().unwrap();
+        assert!((bb.min() - Point3::<f64>::new(-1.0, -1.0, -1.0)).norm() < 1e-9);
+        assert!((bb.max() - Point3::<f64>::new(1.0, 5.0, 1.0)).norm() < 1e-9);
+    }
+
+    #[test]
+    fn test_bound(){
+        //A box spinning half a turn about the z-axis sweeps out a disk, which the bound must contain at every moment
+        let end = Isometry3::<f64>::from_parts(Translation3::identity(), UnitQuaternion::from_axis_angle(&Vector3::z_axis(), 0.99 * PI));
+        let motion = Motion::new(Isometry3::<f64>::identity(), end, 0.0, 1.0);
+        let bb = Aabb::new(Point3::<f64>::new(2.0, -0.1, -0.1), Point3::<f64>::new(3.0, 0.1, 0.1));
+        let bound = motion.bound(&bb);
+        for i in 0..=1000 {
+            let p = motion.at(i as f64 / 1000.0).transform_point(&Point3::<f64>::new(3.0, 0.1, 0.1));
+            for axis in 0..3 {
+                assert!(bound.min()[axis] <= p[axis] && p[axis] <= bound.max()[axis]);
+            }
+        }
+    }
+}
@@ -28,7 +28,8 @@ pub struct BvhBranch{
 #[derive(Clone)]
 pub struct BvhRoot{
     traceable: GeometricPrimitive,
-    bb: Aabb
+    bb: Aabb,
+    id: usize
 }
 
 impl Aabb{
@@ -87,8 +88,8 @@ impl Aabb{
 }
 
 impl BvhBranch {
-    pub fn new(left: GeometricPrimitives, right: GeometricPrimitives, bb: Aabb) -> BvhNode{
-        BvhNode::Branch(BvhBranch{children: (Box::new(BvhNode::new(left)), Box::new(BvhNode::new(right))), bb})
+    pub fn new(left: Vec<(usize, GeometricPrimitive)>, right: Vec<(usize, GeometricPrimitive)>, bb: Aabb) -> BvhNode{
+        BvhNode::Branch(BvhBranch{children: (Box::new(BvhNode::build(left)), Box::new(BvhNode::build(right))), bb})
     }
 
     fn left(&self) -> &BvhNode{
@@ -105,56 +106,60 @@ impl BvhBranch {
 }
 
 impl BvhRoot{
-    pub fn new(traceable: GeometricPrimitive, bb: Aabb) -> BvhNode{
-        BvhNode::Root(BvhRoot{traceable, bb})
+    pub fn new(traceable: GeometricPrimitive, bb: Aabb, id: usize) -> BvhNode{
+        BvhNode::Root(BvhRoot{traceable, bb, id})
     }
 }
 
 impl BvhNode{
-    pub fn new(mut objects: GeometricPrimitives) -> BvhNode{
+    /// Builds a tree over the primitives. The primitives are identified in hit records by their index in `objects`.
+    pub fn new(objects: GeometricPrimitives) -> BvhNode{
+        BvhNode::build((0..objects.len()).map(|index| (index, objects.get(index))).collect())
+    }
+
+    fn build(mut objects: Vec<(usize, GeometricPrimitive)>) -> BvhNode{
         let object_span = objects.len();
         match object_span {
             1 => {
-                let traceable = objects.remove(0);
+                let (id, traceable) = objects.remove(0);
                 let bb = traceable.bounding_box().expect("A GeometricPrimitive within the TraceableList cannot be bound");
-                BvhRoot::new(traceable, bb)
+                BvhRoot::new(traceable, bb, id)
 
             } 
             
             _ => {
-                let axis = objects.get_largest_extent().expect("The TraceableList is empty") as i8;
-                objects.sort_by(|a, b| Aabb::box_compare(a, b, axis));
+                let axis = BvhNode::primitives(&objects).get_largest_extent().expect("The TraceableList is empty") as i8;
+                objects.sort_by(|a, b| Aabb::box_compare(&a.1, &b.1, axis));
                 let mid = object_span/2;
                 let right_objs = objects.split_off(mid);
                 let left_objs = objects;
-                let bb_left = left_objs.bounding_box().expect("A GeometricPrimitive within the TraceableList cannot be bound");
-                let bb_right = right_objs.bounding_box().expect("A GeometricPrimitive within the TraceableList cannot be bound");
+                let bb_left = BvhNode::primitives(&left_objs).bounding_box().expect("A GeometricPrimitive within the TraceableList cannot be bound");
+                let bb_right = BvhNode::primitives(&right_objs).bounding_box().expect("A GeometricPrimitive within the TraceableList cannot be bound");
                 let bb_surrounding = Aabb::surrounding_box(bb_left, bb_right);
                 BvhBranch::new(left_objs, right_objs, bb_surrounding)
             }
         }
     }
 
-    fn hit_debug(&self ,r: &Ray, t_min: f64, t_max: f64) -> (i32, Option<(HitRecord, &Material)>) {
-        match self{
-            BvhNode::Branch(x) => x.hit_debug(r, t_min, t_max),
-            BvhNode::Root(x) => x.hit_debug(r, t_min, t_max)
+    fn primitives(objects: &[(usize, GeometricPrimitive)]) -> GeometricPrimitives{
+        let mut primitives = GeometricPrimitives::new();
+        for (_, primitive) in objects {
+            primitives.add(*primitive);
         }
+        primitives
     }
 }
 
-impl BvhBranch{
-    pub fn hit_debug(&self, r: &Ray, t_min: f64, t_max: f64) -> (i32, Option<(HitRecord, &Material)>) {
+impl Hit for BvhBranch {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<(HitRecord, &Material)> {
         if !self.bb.hit(r, t_min, t_max){
-            return (0, None)
+            return None
         } 
 
-        let mut hit_left = self.left().hit_debug(r, t_min, t_max);
-        let mut hit_right = self.right().hit_debug(r, t_min, t_max);
-        hit_left.0 += hit_right.0;
-        hit_right.0 = hit_left.0;
-        match(hit_left.1, hit_right.1){
-            (None, None) => (hit_left.0, None),
+        let hit_left = self.left().hit(r, t_min, t_max);
+        let hit_right = self.right().hit(r, t_min, t_max);
+        match(hit_left, hit_right){
+            (None, None) => None,
             (Some(_), None) => hit_left,
             (None, Some(_)) => hit_right,
             (Some(left), Some(right)) =>  {
@@ -165,40 +170,29 @@ impl BvhBranch{
                 }
             }
         }
+        
     }
-}
 
-impl BvhRoot{
-    pub fn hit_debug(&self, r: &Ray, t_min: f64, t_max: f64) -> (i32, Option<(HitRecord, &Material)>) {
-        match self.traceable.hit(r, t_min, t_max) {
-            Some((rec, mat)) => {
-                (1, Some((rec, mat)))
-            }
-            None => (1, (None))
-        }
-    }
-}
-impl Hit for BvhBranch {
-    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<(HitRecord, &Material)> {
+    fn hit_debug(&self, r: &Ray, t_min: f64, t_max: f64) -> (i32, Option<(HitRecord, &Material)>) {
         if !self.bb.hit(r, t_min, t_max){
-            return None
+            return (1, None)
         } 
 
-        let hit_left = self.left().hit(r, t_min, t_max);
-        let hit_right = self.right().hit(r, t_min, t_max);
+        let (nodes_left, hit_left) = self.left().hit_debug(r, t_min, t_max);
+        let (nodes_right, hit_right) = self.right().hit_debug(r, t_min, t_max);
+        let nodes_visited = 1 + nodes_left + nodes_right;
         match(hit_left, hit_right){
-            (None, None) => None,
-            (Some(_), None) => hit_left,
-            (None, Some(_)) => hit_right,
+            (None, None) => (nodes_visited, None),
+            (Some(_), None) => (nodes_visited, hit_left),
+            (None, Some(_)) => (nodes_visited, hit_right),
             (Some(left), Some(right)) =>  {
                 if left.0.t <= right.0.t{
-                    hit_left
+                    (nodes_visited, hit_left)
                 } else {
-                    hit_right
+                    (nodes_visited, hit_right)
                 }
             }
         }
-        
     }
     
     fn bounding_box(&self) -> Option<Aabb> {
@@ -208,7 +202,9 @@ impl Hit for BvhBranch {
 
 impl Hit for BvhRoot {
     fn hit(&self ,r: &Ray, t_min: f64, t_max: f64) -> Option<(HitRecord, &Material)> {
-        self.traceable.hit(r, t_min, t_max)
+        let (mut rec, mat) = self.traceable.hit(r, t_min, t_max)?;
+        rec.primitive_id = self.id;
+        Some((rec, mat))
     }
     fn bounding_box(&self) -> Option<Aabb>{
         Some(self.bb)
@@ -222,6 +218,12 @@ impl Hit for BvhNode{
             BvhNode::Root(x) => x.hit(r, t_min, t_max)
         }
     }
+    fn hit_debug(&self ,r: &Ray, t_min: f64, t_max: f64) -> (i32, Option<(HitRecord, &Material)>) {
+        match self{
+            BvhNode::Branch(x) => x.hit_debug(r, t_min, t_max),
+            BvhNode::Root(x) => x.hit_debug(r, t_min, t_max)
+        }
+    }
     fn bounding_box(&self) -> Option<Aabb>{
         match self{
             BvhNode::Branch(x) => x.bounding_box(),
@@ -356,6 +358,51 @@ mod tests {
         assert!(hit.is_some());
         let (rec, _) = hit.unwrap();
         assert_eq!(rec.t, 3.0); 
+        assert_eq!(rec.primitive_id, 100);
         
     }    
+
+    #[test]
+    fn test_bvhnode_hit_debug(){
+        let mut list = GeometricPrimitives::new();
+        let mat = Material::Lambertian(Lambertian::default());
+        for i in 0..8{
+            list.add(GeometricPrimitive::new_sphere(Point3::<f64>::new(3.0 * i as f64, 0.0, 0.0), 1.0, mat));
+        }
+        let bvh = list.to_bvh();
+
+        //Case 1: A ray missing the whole tree only visits the root
+        let r = Ray::new(Point3::<f64>::new(0.0, 10.0, 0.0), Vector3::<f64>::new(1.0, 0.0, 0.0));
+        let (nodes, hit) = bvh.hit_debug(&r, 0.0, 100.0);
+        assert_eq!(nodes, 1);
+        assert!(hit.is_none());
+
+        //Case 2: A ray hitting a single sphere visits a path down the tree, and finds the same hit as `hit`
+        let r = Ray::new(Point3::<f64>::new(9.0, 10.0, 0.0), Vector3::<f64>::new(0.0, -1.0, 0.0));
+        let (nodes, hit) = bvh.hit_debug(&r, 0.0, 100.0);
+        let (rec, _) = hit.unwrap();
+        assert!(nodes > 1 && nodes < 15);
+        assert_eq!(rec.t, bvh.hit(&r, 0.0, 100.0).unwrap().0.t);
+        assert_eq!(rec.primitive_id, 3);
+    }
+
+    #[test]
+    fn test_bvhnode_hit_moving(){
+        //A moving sphere must be found at every point of its motion, wherever the tree puts it
+        let mut list = GeometricPrimitives::new();
+        let mat = Material::Lambertian(Lambertian::default());
+        for i in 0..8{
+            list.add(GeometricPrimitive::new_sphere(Point3::<f64>::new(3.0 * i as f64, 0.0, 0.0), 1.0, mat));
+        }
+        list.add(GeometricPrimitive::new_moving_sphere(Point3::<f64>::new(0.0, 10.0, 0.0), Point3::<f64>::new(21.0, 10.0, 0.0), 0.0, 1.0, 1.0, mat));
+        let bvh = list.to_bvh();
+
+        for i in 0..=10{
+            let time = i as f64 / 10.0;
+            let r = Ray::new_at_time(Point3::<f64>::new(21.0 * time, 20.0, 0.0), Vector3::<f64>::new(0.0, -1.0, 0.0), time);
+            let (rec, _) = bvh.hit(&r, 0.0, 100.0).unwrap();
+            assert_eq!(rec.primitive_id, 8);
+            assert!((rec.t - 9.0).abs() < 1e-9);
+        }
+    }
 }
\ No newline at end of file
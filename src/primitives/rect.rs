@@ -6,9 +6,10 @@ use crate::primitives::bvh::*;
 use crate::material::*;
 use crate::raytracing::{HitRecord, Hit, Ray};
 use crate::image::Color;
+use crate::lights::{Sample, SurfaceSample};
 
 
-#[derive (Copy, Clone)]
+#[derive (Copy, Clone, Debug, PartialEq)]
 pub enum RectAxes {
     XY,
     XZ,
@@ -101,6 +102,16 @@ impl Rect {
     pub fn corner(&self, index: usize) -> f64{
         self.corners[index]
     }
+
+    /// Returns the area of the rectangle
+    pub fn area(&self) -> f64 {
+        (self.corner(1) - self.corner(0)) * (self.corner(3) - self.corner(2))
+    }
+
+    /// Returns the material of the rectangle
+    pub fn material(&self) -> Material {
+        self.mat
+    }
 }
 
 impl Hit for Rect {
@@ -132,6 +143,45 @@ impl Hit for Rect {
     }
 }
 
+impl Sample for Rect {
+    fn sample(&self, origin: &Point3<f64>, u: [f64; 2]) -> Option<SurfaceSample> {
+        let SurfaceSample { p, normal, .. } = self.sample_area(u);
+
+        //Convert the density from area to solid angle
+        let wi = p - origin;
+        let cos_theta = normal.dot(&wi.normalize()).abs();
+        if cos_theta == 0.0 || !cos_theta.is_finite() {
+            return None;
+        }
+        let pdf = wi.norm_squared() / (cos_theta * self.area());
+        Some(SurfaceSample { p, normal, pdf })
+    }
+
+    fn sample_area(&self, u: [f64; 2]) -> SurfaceSample {
+        let indices = self.axes_indices();
+        let mut p = Point3::<f64>::default();
+        p[indices.0] = self.corner(0) + u[0] * (self.corner(1) - self.corner(0));
+        p[indices.1] = self.corner(2) + u[1] * (self.corner(3) - self.corner(2));
+        p[self.unused_axis_index()] = self.k;
+        SurfaceSample { p, normal: self.outward_normal(), pdf: 1.0 / self.area() }
+    }
+
+    fn area(&self) -> f64 {
+        Rect::area(self)
+    }
+
+    fn pdf(&self, origin: &Point3<f64>, direction: &Vector3<f64>) -> f64 {
+        let r = Ray::new(*origin, direction.normalize());
+        match self.hit(&r, 0.0, f64::INFINITY) {
+            Some((rec, _)) => {
+                let cos_theta = rec.normal.dot(&r.direction()).abs();
+                (rec.p - origin).norm_squared() / (cos_theta * self.area())
+            }
+            None => 0.0
+        }
+    }
+}
+
 impl Rasterize for Rect {
     fn outline(&self, cam: &Camera) -> Option<Vec<[usize; 2]>>{
         let lines = self.get_lines().to_vec();
@@ -255,4 +305,27 @@ mod tests {
         assert_eq!(bb.min(), Point3::<f64>::new(-0.0001, -5.0, 1.0));
         assert_eq!(bb.max(), Point3::<f64>::new(0.0001, -3.0, 3.0));
     }
+
+    #[test]
+    fn test_sample(){
+        let diff_light = Material::new_diffuse_light(Color::new(4.0,4.0,4.0));
+        let rect = Rect::new(RectAxes::XZ, -1.0, 1.0, -2.0, 2.0, 3.0, diff_light);
+        let origin = Point3::<f64>::new(0.0, 0.0, 0.0);
+
+        //Case 1: Sample directly above the origin
+        let sample = rect.sample(&origin, [0.5, 0.5]).unwrap();
+        assert_eq!(sample.p, Point3::<f64>::new(0.0, 3.0, 0.0));
+        assert_eq!(sample.pdf, 9.0 / 8.0);
+        assert_eq!(rect.pdf(&origin, &(sample.p - origin)), sample.pdf);
+
+        //Case 2: Sample at a corner
+        let sample = rect.sample(&origin, [1.0, 0.0]).unwrap();
+        assert_eq!(sample.p, Point3::<f64>::new(1.0, 3.0, -2.0));
+        let dist_squared: f64 = 14.0;
+        let cos_theta = 3.0 / dist_squared.sqrt();
+        assert!((sample.pdf - dist_squared / (cos_theta * 8.0)).abs() < 1e-9);
+
+        //Case 3: Directions which miss the rectangle have zero density
+        assert_eq!(rect.pdf(&origin, &Vector3::<f64>::new(0.0, -1.0, 0.0)), 0.0);
+    }
 }
\ No newline at end of file
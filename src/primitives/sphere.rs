@@ -8,28 +8,72 @@ use crate::geometry::plane::Plane;
 use crate::nalgebra::{Vector3, Point3};
 use crate::primitives::bvh::*;
 use crate::material::*;
+use crate::media::Medium;
 use crate::camera::*;
 use crate::raytracing::{HitRecord, Hit, Ray};
+use crate::lights::{Sample, SurfaceSample};
+use crate::sampler;
+use crate::vec::VecExtensionMethods;
 
 #[derive (Copy, Clone)]
 pub struct Sphere {
     center: Point3<f64>,
     radius: f64,
-    material: Material
+    material: Material,
+    medium: Option<Medium>,
+    /// The center of the sphere at `time1`. The sphere moves in a straight line from `center`, where it is at `time0`.
+    center1: Point3<f64>,
+    time0: f64,
+    time1: f64
 }
 
 impl Sphere{
 
     ///Initialises a new sphere
     pub fn new(cen: Point3<f64>, rad: f64, mat: Material) -> Sphere{
-        Sphere{center: cen, radius: rad, material: mat}
+        Sphere{center: cen, radius: rad, material: mat, medium: None, center1: cen, time0: 0.0, time1: 1.0}
     }
 
-    /// Returns the center of the sphere
+    ///Initialises a new sphere filled with a participating medium
+    pub fn new_with_medium(cen: Point3<f64>, rad: f64, mat: Material, medium: Medium) -> Sphere{
+        Sphere{center: cen, radius: rad, material: mat, medium: Some(medium), center1: cen, time0: 0.0, time1: 1.0}
+    }
+
+    ///Initialises a new sphere which moves from `cen0` at `time0` to `cen1` at `time1`. The sphere stays at `cen0` before
+    ///`time0`, and at `cen1` after `time1`.
+    pub fn new_moving(cen0: Point3<f64>, cen1: Point3<f64>, time0: f64, time1: f64, rad: f64, mat: Material) -> Sphere{
+        Sphere{center: cen0, radius: rad, material: mat, medium: None, center1: cen1, time0, time1}
+    }
+
+    /// Returns the center of the sphere at the time its motion starts
     pub fn center(&self) -> Point3<f64>{
         self.center
     }
 
+    /// Returns the center of the sphere at the given time
+    pub fn center_at(&self, time: f64) -> Point3<f64>{
+        if !self.is_moving() || self.time1 <= self.time0 {
+            return self.center;
+        }
+        let fraction = ((time - self.time0) / (self.time1 - self.time0)).clamp(0.0, 1.0);
+        self.center + fraction * (self.center1 - self.center)
+    }
+
+    /// Returns true if the sphere moves
+    pub fn is_moving(&self) -> bool{
+        self.center1 != self.center
+    }
+
+    /// Returns the material of the sphere
+    pub fn material(&self) -> Material{
+        self.material
+    }
+
+    /// Returns the medium inside the sphere
+    pub fn medium(&self) -> Option<Medium>{
+        self.medium
+    }
+
     /// Checks whether the sphere is at least partially in front of the plane. 
     /// 
     /// A sphere is defined as being in front of the plane if any point on its surface is in front of the plane.
@@ -90,7 +134,8 @@ impl Sphere{
 
 impl Hit for Sphere{
     fn hit(&self, r:&Ray, t_min: f64, t_max: f64) -> Option<(HitRecord, &Material)> {
-        let oc = r.origin() - self.center;
+        let center = self.center_at(r.time());
+        let oc = r.origin() - center;
         let a = r.direction().norm_squared();
         let half_b = oc.dot(&r.direction());
         let c = oc.norm_squared() - self.radius*self.radius;
@@ -109,16 +154,85 @@ impl Hit for Sphere{
 
             let t = root;
             let p = r.at(t);
-            let outward_normal = (p - self.center)/self.radius;
-            let new_rec = HitRecord::new(p, outward_normal, root, *r, Vector3::<f64>::default());
+            let outward_normal = (p - center)/self.radius;
+            let mut new_rec = HitRecord::new(p, outward_normal, root, *r, Vector3::<f64>::default());
+            new_rec.medium = self.medium;
             Some((new_rec, &self.material))
         }
     }
 
+    /// The box covers the sphere throughout its motion.
     fn bounding_box(&self) -> Option<Aabb> {
-        let output_box = Aabb::new(self.center - Vector3::<f64>::new(self.radius, self.radius, self.radius),
-                                   self.center + Vector3::<f64>::new(self.radius, self.radius, self.radius));
-        Some(output_box)
+        let extent = Vector3::<f64>::new(self.radius, self.radius, self.radius).abs();
+        let box_0 = Aabb::new(self.center - extent, self.center + extent);
+        let box_1 = Aabb::new(self.center1 - extent, self.center1 + extent);
+        Some(Aabb::surrounding_box(box_0, box_1))
+    }
+}
+
+impl Sample for Sphere {
+    fn sample(&self, origin: &Point3<f64>, u: [f64; 2]) -> Option<SurfaceSample> {
+        let radius = self.radius.abs();
+        let origin_center = self.center - origin;
+        let dist_squared = origin_center.norm_squared();
+
+        //If the origin lies inside the sphere, sample uniformly by area and convert to solid angle
+        if dist_squared <= radius * radius {
+            let SurfaceSample { p, normal, .. } = self.sample_area(u);
+            let wi = p - origin;
+            let cos_theta = normal.dot(&wi.normalize()).abs();
+            if cos_theta == 0.0 {
+                return None;
+            }
+            let pdf = wi.norm_squared() / (cos_theta * self.area());
+            return Some(SurfaceSample { p, normal, pdf });
+        }
+
+        //Otherwise, sample uniformly from the cone of directions subtended by the sphere
+        let dist = dist_squared.sqrt();
+        let w = origin_center / dist;
+        let (u_axis, v_axis) = w.coordinate_system();
+        let sin_theta_max_squared = radius * radius / dist_squared;
+        let cos_theta_max = (1.0 - sin_theta_max_squared).max(0.0).sqrt();
+        let local = sampler::uniform_cone(u, cos_theta_max);
+        let direction = local[0] * u_axis + local[1] * v_axis + local[2] * w;
+
+        //Find the nearest point on the sphere along the sampled direction
+        let cos_theta = local[2];
+        let sin_theta_squared = 1.0 - cos_theta * cos_theta;
+        let t = dist * cos_theta - (radius * radius - dist_squared * sin_theta_squared).max(0.0).sqrt();
+        let p = origin + t * direction;
+        let normal = (p - self.center) / radius;
+        let pdf = 1.0 / (2.0 * PI * (1.0 - cos_theta_max));
+        Some(SurfaceSample { p, normal, pdf })
+    }
+
+    fn sample_area(&self, u: [f64; 2]) -> SurfaceSample {
+        let normal = sampler::uniform_sphere(u);
+        let p = self.center + self.radius.abs() * normal;
+        SurfaceSample { p, normal, pdf: 1.0 / self.area() }
+    }
+
+    fn area(&self) -> f64 {
+        4.0 * PI * self.radius * self.radius
+    }
+
+    fn pdf(&self, origin: &Point3<f64>, direction: &Vector3<f64>) -> f64 {
+        let radius = self.radius.abs();
+        let dist_squared = (self.center - origin).norm_squared();
+        let r = Ray::new(*origin, direction.normalize());
+        let rec = match self.hit(&r, 0.0, f64::INFINITY) {
+            Some((rec, _)) => rec,
+            None => return 0.0
+        };
+
+        if dist_squared <= radius * radius {
+            let cos_theta = rec.normal.dot(&r.direction()).abs();
+            return (rec.p - origin).norm_squared() / (cos_theta * self.area());
+        }
+
+        let cos_theta_max = (1.0 - radius * radius / dist_squared).max(0.0).sqrt();
+        1.0 / (2.0 * PI * (1.0 - cos_theta_max))
     }
 }
 
@@ -216,4 +330,60 @@ mod tests {
         assert_eq!(bb.min(), Point3::<f64>::new(-5.0, -8.0, -3.0));
         assert_eq!(bb.max(), Point3::<f64>::new(5.0, 2.0, 7.0));
     } 
+
+    #[test]
+    fn test_moving(){
+        let mat = Material::Lambertian(Lambertian::default());
+        let s = Sphere::new_moving(Point3::<f64>::new(0.0, 0.0, 0.0), Point3::<f64>::new(0.0, 10.0, 0.0), 0.0, 2.0, 1.0, mat);
+        assert!(s.is_moving());
+
+        //Case 1: The center is interpolated between the two times, and held outside of them
+        assert_eq!(s.center_at(-1.0), Point3::<f64>::new(0.0, 0.0, 0.0));
+        assert_eq!(s.center_at(0.5), Point3::<f64>::new(0.0, 2.5, 0.0));
+        assert_eq!(s.center_at(3.0), Point3::<f64>::new(0.0, 10.0, 0.0));
+
+        //Case 2: Rays hit the sphere where it is at their own time
+        let r = Ray::new_at_time(Point3::<f64>::new(-10.0, 5.0, 0.0), Vector3::<f64>::new(1.0, 0.0, 0.0), 1.0);
+        let (rec, _) = s.hit(&r, 0.0, 100.0).unwrap();
+        assert_eq!(rec.p(), Point3::<f64>::new(-1.0, 5.0, 0.0));
+        assert_eq!(rec.normal(), Vector3::<f64>::new(-1.0, 0.0, 0.0));
+        assert_eq!(rec.time, 1.0);
+        let r = Ray::new_at_time(Point3::<f64>::new(-10.0, 5.0, 0.0), Vector3::<f64>::new(1.0, 0.0, 0.0), 0.0);
+        assert!(s.hit(&r, 0.0, 100.0).is_none());
+
+        //Case 3: The bounding box covers the whole motion
+        let bb = s.bounding_box().unwrap();
+        assert_eq!(bb.min(), Point3::<f64>::new(-1.0, -1.0, -1.0));
+        assert_eq!(bb.max(), Point3::<f64>::new(1.0, 11.0, 1.0));
+    }
+
+    #[test]
+    fn test_sample(){
+        let center = Point3::<f64>::new(0.0, 0.0, 0.0);
+        let radius = 2.0;
+        let mat = Material::Lambertian(Lambertian::default());
+        let s = Sphere::new(center, radius, mat);
+
+        //Case 1: Sampling from outside of the sphere
+        let origin = Point3::<f64>::new(-4.0, 0.0, 0.0);
+        let cos_theta_max = (3.0f64).sqrt() / 2.0;
+        for u in [[0.0, 0.0], [0.3, 0.7], [0.99, 0.2]] {
+            let sample = s.sample(&origin, u).unwrap();
+            assert!(((sample.p - center).norm() - radius).abs() < 1e-9);
+            assert!(sample.normal.dot(&(origin - sample.p)) >= -1e-9);
+            assert!((sample.pdf - 1.0 / (2.0 * PI * (1.0 - cos_theta_max))).abs() < 1e-9);
+            assert!((s.pdf(&origin, &(sample.p - origin)) - sample.pdf).abs() < 1e-9);
+        }
+
+        //Case 2: Sampling from inside of the sphere
+        let origin = Point3::<f64>::new(1.0, 0.0, 0.0);
+        let sample = s.sample(&origin, [0.5, 0.0]).unwrap();
+        assert_eq!(sample.p, Point3::<f64>::new(2.0, 0.0, 0.0));
+        assert!((sample.pdf - 1.0 / (16.0 * PI)).abs() < 1e-9);
+        assert!((s.pdf(&origin, &(sample.p - origin)) - sample.pdf).abs() < 1e-9);
+
+        //Case 3: Directions which miss the sphere have zero density
+        let origin = Point3::<f64>::new(-4.0, 0.0, 0.0);
+        assert_eq!(s.pdf(&origin, &Vector3::<f64>::new(0.0, 1.0, 0.0)), 0.0);
+    }
 }
\ No newline at end of file
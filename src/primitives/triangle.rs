@@ -7,6 +7,8 @@ use crate::material::*;
 use crate::util::*;
 use crate::rasterizing::*;
 use crate::raytracing::{HitRecord, Hit, Ray};
+use crate::lights::{Sample, SurfaceSample};
+use crate::sampler;
 
 #[derive (Copy, Clone)]
 pub struct Triangle {
@@ -28,6 +30,21 @@ impl Triangle{
         self.vertices[index]
     }
 
+    ///Returns the material of the triangle.
+    pub fn material(&self) -> Material{
+        self.material
+    }
+
+    ///Returns the (unnormalised) geometric normal of the triangle, whose length is twice the triangle's area.
+    pub fn geometric_normal(&self) -> Vector3<f64>{
+        (self.vertices[1] - self.vertices[0]).cross(&(self.vertices[2] - self.vertices[0]))
+    }
+
+    ///Returns the area of the triangle.
+    pub fn area(&self) -> f64{
+        0.5 * self.geometric_normal().norm()
+    }
+
     ///Determine where (0,0) lies with respect to the 
     ///oriented line connecting p0 to p1.
     ///
@@ -132,7 +149,9 @@ impl Hit for Triangle {
 
        let p_err = gamma(7) * Vector3::<f64>::new(x_err, y_err, z_err);
        let p = b0 * self.vertices[0] + b1 * self.vertices[1].coords + b2 * self.vertices[2].coords;
-       Some((HitRecord::new(p, norm, t, *r, p_err), &self.material))
+       let mut rec = HitRecord::new(p, norm, t, *r, p_err);
+       rec.geometric_normal = self.geometric_normal().normalize();
+       Some((rec, &self.material))
     }
 
     fn bounding_box(&self) -> Option<Aabb>{
@@ -148,6 +167,42 @@ impl Hit for Triangle {
     }
 }
 
+impl Sample for Triangle {
+    fn sample(&self, origin: &Point3<f64>, u: [f64; 2]) -> Option<SurfaceSample> {
+        let SurfaceSample { p, normal, .. } = self.sample_area(u);
+
+        //Convert the density from area to solid angle
+        let wi = p - origin;
+        let cos_theta = normal.dot(&wi.normalize()).abs();
+        if cos_theta == 0.0 || !cos_theta.is_finite() {
+            return None;
+        }
+        let pdf = wi.norm_squared() / (cos_theta * self.area());
+        Some(SurfaceSample { p, normal, pdf })
+    }
+
+    fn sample_area(&self, u: [f64; 2]) -> SurfaceSample {
+        let b = sampler::uniform_triangle(u);
+        let p = Point3::<f64>::from(b[0] * self.vertices[0].coords + b[1] * self.vertices[1].coords + b[2] * self.vertices[2].coords);
+        SurfaceSample { p, normal: self.geometric_normal().normalize(), pdf: 1.0 / self.area() }
+    }
+
+    fn area(&self) -> f64 {
+        Triangle::area(self)
+    }
+
+    fn pdf(&self, origin: &Point3<f64>, direction: &Vector3<f64>) -> f64 {
+        let r = Ray::new(*origin, direction.normalize());
+        match self.hit(&r, 0.0, f64::INFINITY) {
+            Some((rec, _)) => {
+                let cos_theta = self.geometric_normal().normalize().dot(&r.direction()).abs();
+                (rec.p - origin).norm_squared() / (cos_theta * self.area())
+            }
+            None => 0.0
+        }
+    }
+}
+
 impl Rasterize for Triangle {
     fn outline(&self, cam: &Camera) -> Option<Vec<[usize; 2]>> {
 
@@ -263,4 +318,27 @@ mod tests {
         let bb = result.unwrap();
         assert_eq!(bb, Aabb::new(Point3::<f64>::new(-0.001, -0.001, -0.001), Point3::<f64>::new(1.0 + 0.001, 2.0 + 0.001, 2.0 + 0.001)));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_sample(){
+        let mat = Material::new_diffuse_light(Vector3::<f64>::new(1.0, 1.0, 1.0));
+        let v0 = Point3::<f64>::new(-2.0, 2.0, 0.0);
+        let v1 = Point3::<f64>::new(2.0, 2.0, 0.0);
+        let v2 = Point3::<f64>::new(0.0, 4.0, 0.0);
+        let norm = [Vector3::<f64>::new(0.0, 0.0, 1.0); 3];
+        let t = Triangle::new([v0, v1, v2], norm, mat);
+        assert_eq!(t.area(), 4.0);
+
+        //Case 1: The sampled points lie on the triangle and match the density of the triangle in that direction
+        let origin = Point3::<f64>::new(0.0, 3.0, 4.0);
+        for u in [[0.0, 0.0], [0.25, 0.5], [0.9, 0.9]] {
+            let sample = t.sample(&origin, u).unwrap();
+            assert_eq!(sample.p[2], 0.0);
+            assert!(t.hit(&Ray::new(origin, sample.p - origin), 0.0, f64::INFINITY).is_some());
+            assert!((t.pdf(&origin, &(sample.p - origin)) - sample.pdf).abs() < 1e-9);
+        }
+
+        //Case 2: Directions which miss the triangle have zero density
+        assert_eq!(t.pdf(&origin, &Vector3::<f64>::new(0.0, 0.0, 1.0)), 0.0);
+    }
+}
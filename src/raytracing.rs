@@ -4,13 +4,25 @@ use crate::camera::Camera;
 use crate::geometry::plane::Plane;
 use crate::image::Color;
 use crate::image::Pixel;
-use crate::image::RaytracedImage;
-use crate::util::rand_double;
+use crate::aov::FirstHit;
+use crate::film::Film;
+use crate::lpe::{Event, LayerTracker};
 use crate::nalgebra::{Vector3, Point3};
 use crate::primitives::bvh::*;
 use crate::material::*;
 use crate::primitives::*;
+use crate::primitives::animated::AnimatedPrimitive;
 use crate::enum_dispatch::*;
+use crate::threads::RayTraceSettings;
+use crate::lights::Lights;
+use crate::media::{self, HenyeyGreenstein, Medium, MediumTracker};
+use crate::integrators::{Integrator, ambient_occlusion, bdpt, debug, photon_mapping};
+use crate::integrators::photon_mapping::PhotonMap;
+use crate::scenes::SceneData;
+use crate::spectra::{ColorMode, SampledWavelengths};
+use crate::sampler::{PixelSampler, Sampler};
+
+use std::f64::consts::PI;
 
 use std::f64::INFINITY;
 
@@ -22,20 +34,23 @@ pub struct HitRecord {
     pub t: f64,
     pub front_face: bool,
     pub p_err: Vector3<f64>,
+    /// The medium inside the surface that was hit, if the surface bounds one.
+    pub medium: Option<Medium>,
+    /// The true normal of the surface, pointing outwards. Unlike `normal`, it is neither interpolated across the 
+    /// surface nor flipped to face the ray.
+    pub geometric_normal: Vector3<f64>,
+    /// The index of the primitive that was hit, within the list of primitives it was found in.
+    pub primitive_id: usize,
+    /// The time of the ray which made the hit, at which any rays leaving the hit should also be traced.
+    pub time: f64,
 }
 
 
 
 
-pub enum TraceResult{
-    Missed,
-    Absorbed(Color),
-    Scattered((Color, Ray))
-}
-
 impl HitRecord{
     pub fn new(p: Point3<f64>, normal: Vector3<f64>, t: f64, r: Ray, p_err: Vector3<f64>) -> HitRecord{
-        let mut rec = HitRecord{p, normal, t, front_face: true, p_err};
+        let mut rec = HitRecord{p, normal, t, front_face: true, p_err, medium: None, geometric_normal: normal, primitive_id: 0, time: r.time};
         rec.set_face_normal(&r, &normal);
         rec      
     }
@@ -72,16 +87,10 @@ pub trait Hit: Send + Sync{
     fn hit(&self ,r: &Ray, t_min: f64, t_max: f64) -> Option<(HitRecord, &Material)>;
     fn bounding_box(&self) -> Option<Aabb>;
 
-    fn trace(&self, r: &Ray, t_min: f64, t_max: f64) -> TraceResult{
-        if let Some((hit_rec, mat)) = self.hit(r, t_min, t_max) {
-            if let Some((attenuation, scattered)) = mat.scatter(r, &hit_rec){
-                TraceResult::Scattered((mat.emit() + attenuation, scattered))
-            } else{
-                TraceResult::Absorbed(mat.emit())
-            }
-        } else{
-            TraceResult::Missed
-        }
+    /// Finds the closest hit, as `hit` does, and also counts the acceleration structure nodes visited along the way. 
+    /// Primitives outside of an acceleration structure count as a single node.
+    fn hit_debug(&self, r: &Ray, t_min: f64, t_max: f64) -> (i32, Option<(HitRecord, &Material)>) {
+        (1, self.hit(r, t_min, t_max))
     }
 }
 
@@ -89,6 +98,8 @@ pub trait Hit: Send + Sync{
 pub struct Ray{
     pub orig: Point3<f64>,
     pub dir: Vector3<f64>,
+    /// The moment at which the ray is traced, which determines where any moving primitives are.
+    pub time: f64,
 }
 
 pub enum RayPlaneIntersection {
@@ -99,7 +110,15 @@ pub enum RayPlaneIntersection {
 
 impl Ray{
     pub fn new(origin: Point3<f64>, direction: Vector3<f64>) -> Ray{
-        Ray{orig: origin, dir: direction}
+        Ray{orig: origin, dir: direction, time: 0.0}
+    }
+
+    pub fn new_at_time(origin: Point3<f64>, direction: Vector3<f64>, time: f64) -> Ray{
+        Ray{orig: origin, dir: direction, time}
+    }
+
+    pub fn time(&self) -> f64{
+        self.time
     }
 
     pub fn origin(&self) -> Point3<f64>{
@@ -120,7 +139,7 @@ impl Ray{
         if self.dir.dot(&norm) < 0.0{
             offset = -offset;
         }
-        Ray::new(self.orig + offset, self.dir)
+        Ray::new_at_time(self.orig + offset, self.dir, self.time)
     }
 
     pub fn plane_intersection(&self, plane: Plane) -> RayPlaneIntersection {
@@ -143,39 +162,324 @@ impl Ray{
     }
 }
 
-pub fn raytrace_pixel(mut image: RaytracedImage, cam: Camera, background: Color, primitives: &Primitives, max_depth: i32, pixel_position: (usize, usize))  -> RaytracedImage {
-    let image_width = image.image.image_width;
-    let image_height = image.image.image_height;
+/// Traces a single sample through the given pixel, and adds it to the film along with its render layers and light groups, 
+/// and the AOVs of the first surface the integrator found. 
+/// `photon_map` holds the photons traced for this pass over the image. The photon mapping integrators render black without one.
+pub fn raytrace_pixel(film: &mut Film, cam: Camera, scene: &SceneData, settings: &RayTraceSettings, photon_map: Option<&PhotonMap>, pixel_position: (usize, usize), sampler: &mut PixelSampler) {
+    let image_width = film.image_width;
+    let image_height = film.image_height;
     let i = pixel_position.0;
     let j = pixel_position.1;
 
-    let u = (rand_double(0.0, 1.0) + i as f64)/(image_width as f64 - 1.0);
-    let v = (rand_double(0.0, 1.0) + (image_height - j) as f64)/((image_height - 1) as f64);
-    let r = cam.get_ray(u,v);
-    let pixel_index = (j*image_width + i) as usize;
-    image.image.pixels[pixel_index] = Pixel::new(ray_color(&r, background, primitives, max_depth), 1.0);
-    
-    image
+    let jitter = sampler.get_2d();
+    let u = (jitter[0] + i as f64)/(image_width as f64);
+    let v = (jitter[1] + (image_height - 1 - j) as f64)/(image_height as f64);
+    let r = cam.get_ray(u, v, sampler);
+    let (background, primitives, lights) = (scene.background, &scene.raytracing_primitives, &scene.lights);
+    let mut first_hit = FirstHit::default();
+    let (color, layers, light_groups) = match settings.integrator {
+        Integrator::PathTracing => {
+            let mut layers = LayerTracker::new(&film.layers, film.image.light_groups.len());
+            match settings.color_mode {
+                ColorMode::Rgb => (ray_color(&r, background, scene.atmosphere, primitives, lights, settings, None, sampler, Some(&mut layers), Some(&mut first_hit)), layers.values, layers.light_groups),
+                ColorMode::Spectral => {
+                    let wavelengths = SampledWavelengths::sample_hero(sampler.get_1d());
+                    let radiance = ray_color(&r, background, scene.atmosphere, primitives, lights, settings, Some(&wavelengths), sampler, Some(&mut layers), Some(&mut first_hit));
+                    let to_rgb = |values: Vec<Color>| values.iter().map(|value| wavelengths.to_rgb(value)).collect();
+                    (wavelengths.to_rgb(&radiance), to_rgb(layers.values), to_rgb(layers.light_groups))
+                }
+            }
+        }
+        //Only the path tracer sorts light into render layers and light groups, see `Integrator::renders_light_groups`
+        Integrator::Bidirectional => (bdpt::li(&r, &cam, background, primitives, lights, settings, film, sampler, Some(&mut first_hit)), Vec::new(), Vec::new()),
+        Integrator::PhotonMapping | Integrator::ProgressivePhotonMapping => {
+            let color = photon_map.map_or(Color::zeros(), |photon_map| photon_mapping::li(&r, background, primitives, lights, settings, photon_map, sampler, Some(&mut first_hit)));
+            (color, Vec::new(), Vec::new())
+        }
+        Integrator::AmbientOcclusion => (ambient_occlusion::li(&r, primitives, settings, sampler, Some(&mut first_hit)), Vec::new(), Vec::new()),
+        Integrator::Debug(mode) => (debug::li(&r, &cam, primitives, mode, Some(&mut first_hit)), Vec::new(), Vec::new())
+    };
+
+    let pixel_index = j*image_width + i;
+    film.add_sample(pixel_index, (u, v), color, &layers, &light_groups);
+    if let Some(aovs) = first_hit.aovs(&cam) {
+        film.add_aovs(pixel_index, &aovs);
+    }
+}
+
+/// Heuristics for weighting samples drawn from several sampling strategies (multiple importance sampling).
+#[derive (Copy, Clone, Debug, PartialEq)]
+pub enum MisHeuristic {
+    Balance,
+    Power
+}
+
+impl MisHeuristic {
+    /// Returns the weight of a sample drawn with density `pdf`, when it could also have been drawn by a second
+    /// strategy with density `other_pdf`. The weights of the two strategies always sum to one.
+    pub fn weight(&self, pdf: f64, other_pdf: f64) -> f64 {
+        let (f, g) = match self {
+            MisHeuristic::Balance => (pdf, other_pdf),
+            MisHeuristic::Power => (pdf * pdf, other_pdf * other_pdf)
+        };
+        if f + g == 0.0 {
+            return 0.0;
+        }
+        f / (f + g)
+    }
 }
 
-pub fn ray_color<T>(r: &Ray, background: Color, world: &T, depth: i32) -> Color where T: Hit {
+/// Estimates the radiance arriving along the ray by iteratively tracing a path through the world.
+/// 
+/// The product of the attenuations along the path (the path throughput) is carried from bounce to bounce. 
+/// At each non-specular surface, a point on one of the lights is sampled and its contribution is added directly
+/// (next-event estimation). Emission subsequently found by the scattered ray can then be reached by both strategies,
+/// so both contributions are weighted by multiple importance sampling. Once the path is at least `rr_start_depth` 
+/// bounces long, it is terminated by Russian roulette with a probability based on its throughput. Surviving paths 
+/// are reweighted by the survival probability, so the estimate remains unbiased. Paths are always terminated after 
+/// `max_depth` bounces.
+/// 
+/// Rays travelling through a participating medium may be scattered within it, at a distance found by free-flight 
+/// sampling. The lights are sampled from the scattering point as they are from a surface. The path starts in the 
+/// `atmosphere`, and passes through surfaces which mark the boundaries of media without counting them as bounces.
+/// 
+/// If `wavelengths` are given, the path carries spectral radiance at those wavelengths rather than RGB radiance, and 
+/// every colour in the scene is upsampled to a spectrum before it is used. When the path meets a dispersive material,
+/// the secondary wavelengths are terminated and the path continues in the direction taken by the hero wavelength.
+/// 
+/// Every random number the path uses is drawn from `sampler`, which takes the same twelve dimensions on every bounce:
+/// three to scatter from a surface, two for the distance travelled through a medium, two to scatter within it, three
+/// to sample the lights, and one for Russian roulette.
+/// 
+/// If `layers` are given, the events along the path are followed, and every contribution to the radiance is also added
+/// to the render layers whose light path expressions match the path it arrived along, and to the group of the light
+/// it came from. If `first_hit` is given, the first surface the path reaches is recorded in it.
+#[allow(clippy::too_many_arguments)]
+pub fn ray_color<T, S>(r: &Ray, background: Color, atmosphere: Option<Medium>, world: &T, lights: &Lights, settings: &RayTraceSettings, wavelengths: Option<&SampledWavelengths>, sampler: &mut S, mut layers: Option<&mut LayerTracker>, mut first_hit: Option<&mut FirstHit>) -> Color where T: Hit, S: Sampler {
+    let reflectance = |rgb: Color| wavelengths.map_or(rgb, |wavelengths| wavelengths.reflectance(&rgb));
+    let illuminant = |rgb: Color| wavelengths.map_or(rgb, |wavelengths| wavelengths.illuminant(&rgb));
+    let mut radiance = Color::new(0.0, 0.0, 0.0);
+    let mut throughput = Color::new(1.0, 1.0, 1.0);
+    let mut ray = *r;
+    let mut lights_sampled = false;
+    let mut scattering_pdf = 0.0;
+    let mut scattering_point = r.origin();
+    let mut media = MediumTracker::new_with_wavelengths(atmosphere, wavelengths.copied());
+    let mut secondary_terminated = false;
+    let mut depth = 0;
+
+    //Rays leaving a surface are offset from it to avoid hitting it again, but rays scattered within a medium are not, 
+    //so that they cannot miss a nearby boundary and leak out of the medium
+    let mut t_min = 0.001;
+
+    while depth < settings.max_depth {
+        let hit = world.hit(&ray, t_min, f64::INFINITY);
+        t_min = 0.001;
+
+        //Draw the dimensions on every bounce, even if they go unused, so that each bounce always uses the same ones
+        let u_direction = sampler.get_2d();
+        let u_scatter = [u_direction[0], u_direction[1], sampler.get_1d()];
+        let u_distance = sampler.get_2d();
+        let u_phase = sampler.get_2d();
+        let u_light_position = sampler.get_2d();
+        let u_light = [sampler.get_1d(), u_light_position[0], u_light_position[1]];
+        let u_survival = sampler.get_1d();
+
+        //Sample the distance travelled through the current medium. If the ray is scattered before reaching the next
+        //surface, continue the path from the scattering point
+        let mut scattered = false;
+        if let Some(medium) = media.current {
+            let speed = ray.direction().norm();
+            //Measure to the hit point rather than scaling rec.t, so the medium ends exactly where the path continues
+            //from whatever parameterisation of t a primitive uses
+            let distance_max = hit.map_or(f64::INFINITY, |(rec, _)| (rec.p - ray.origin()).norm());
+            let sample = medium.sample(distance_max, u_distance);
+            throughput.component_mul_assign(&sample.weight);
+            if let Some(distance) = sample.distance {
+                let p = ray.at(distance / speed);
+                let wo = -ray.direction() / speed;
+                lights_sampled = !lights.is_empty();
+                if lights_sampled {
+                    let (direct, light_group) = sample_lights_in_medium(&wo, &p, ray.time(), &medium.phase, world, lights, &media, settings.mis_heuristic, wavelengths, u_light);
+                    let direct = throughput.component_mul(&direct);
+                    radiance += direct;
+                    add_to_layers(&mut layers, &[Event::Volume, Event::Light], Some(light_group), &direct);
+                }
+                let (wi, pdf) = medium.phase.sample(&wo, u_phase);
+                scatter_layers(&mut layers, Event::Volume);
+                if let Some(first_hit) = first_hit.as_mut() {
+                    first_hit.close();
+                }
+                scattering_pdf = pdf;
+                scattering_point = p;
+                ray = Ray::new_at_time(p, wi, ray.time());
+                t_min = 0.0;
+                scattered = true;
+            }
+        }
+
+        if !scattered {
+            let (rec, mat) = match hit {
+                Some(hit) => hit,
+                None => {
+                    let escaped = throughput.component_mul(&illuminant(background));
+                    radiance += escaped;
+                    add_to_layers(&mut layers, &[Event::Background], None, &escaped);
+                    break;
+                }
+            };
+            if let Some(first_hit) = first_hit.as_mut() {
+                first_hit.record(&rec, mat);
+            }
+
+            if mat.is_interface() {
+                media.cross(&rec, &ray.direction());
+                ray = Ray::new_at_time(rec.p, ray.direction(), ray.time());
+                continue;
+            }
+
+            let emission = mat.emit();
+            if emission != Color::new(0.0, 0.0, 0.0) {
+                let emission = illuminant(emission);
+                let weight = if lights_sampled {
+                    let light_pdf = lights.pdf(&scattering_point, &rec.p, rec.primitive_id);
+                    settings.mis_heuristic.weight(scattering_pdf, light_pdf)
+                } else {
+                    1.0
+                };
+                let emitted = throughput.component_mul(&emission) * weight;
+                radiance += emitted;
+                add_to_layers(&mut layers, &[Event::Light], mat.light_group(), &emitted);
+            }
 
-    //If we've exceeded the ray bounce limit, no more light is gathered.
-    if depth <= 0{
-        return Color::new(0.0,0.0,0.0)
+            let bsdf = match wavelengths {
+                Some(wavelengths) if mat.is_dispersive() => {
+                    if !secondary_terminated {
+                        throughput = wavelengths.terminate_secondary(&throughput);
+                        secondary_terminated = true;
+                    }
+                    mat.bsdf_at_wavelength(&rec, wavelengths.hero() as f64)
+                }
+                _ => mat.bsdf(&rec)
+            };
+            let wo = -ray.direction();
+            lights_sampled = bsdf.flags().is_non_specular() && !lights.is_empty();
+            if lights_sampled {
+                let (direct, light_group) = sample_lights(&wo, &rec, &bsdf, world, lights, &media, Some(settings.mis_heuristic), wavelengths, u_light);
+                let direct = throughput.component_mul(&direct);
+                radiance += direct;
+                add_to_layers(&mut layers, &[Event::from_flags(bsdf.flags()), Event::Light], Some(light_group), &direct);
+            }
+
+            match bsdf.sample(&wo, u_scatter) {
+                Some(sample) => {
+                    throughput.component_mul_assign(&(reflectance(sample.f) * (sample.wi.dot(&rec.normal).abs() / sample.pdf)));
+                    scattering_pdf = sample.pdf;
+                    scattering_point = rec.p;
+                    media.cross(&rec, &sample.wi);
+                    ray = Ray::new_at_time(rec.p, sample.wi, ray.time());
+                    scatter_layers(&mut layers, Event::from_flags(sample.flags));
+                }
+                None => break
+            }
+        }
+
+        if depth >= settings.rr_start_depth {
+            let survival_probability = throughput.max().min(1.0);
+            if u_survival >= survival_probability {
+                break;
+            }
+            throughput /= survival_probability;
+        }
+        depth += 1;
     }
+    radiance
+}
 
-    let result = world.trace(r, 0.001, INFINITY);
-    match result {
-        TraceResult::Scattered((attenuation, scattered)) => attenuation.component_mul(&ray_color(&scattered, background, world, depth-1)),
-        TraceResult::Absorbed(emitted) => emitted,
-        TraceResult::Missed => background      
+/// Adds a contribution to the radiance, which reached the path through the given events from the given light group,
+/// to the render layers and light groups.
+fn add_to_layers(layers: &mut Option<&mut LayerTracker>, events: &[Event], light_group: Option<usize>, radiance: &Color) {
+    if let Some(layers) = layers {
+        layers.add(events, light_group, radiance);
     }
 }
 
+/// Extends the path followed by the render layers by an event.
+fn scatter_layers(layers: &mut Option<&mut LayerTracker>, event: Event) {
+    if let Some(layers) = layers {
+        layers.scatter(event);
+    }
+}
+
+/// Estimates the radiance scattered back along `wo` due to light arriving directly from a sampled point on one of 
+/// the lights. A shadow ray is traced through the world to check that the sampled point is visible, and `media`, 
+/// holding the medium the path arrived through, is used to attenuate it. If a heuristic is given, the estimate is 
+/// weighted against the chance of the material scattering towards the same point. If `wavelengths` are given, the 
+/// spectral radiance at those wavelengths is returned instead of RGB radiance. The light is chosen by `u[0]`, and the
+/// point on it by `u[1]` and `u[2]`. The light group of the sampled light is returned alongside the estimate.
+#[allow(clippy::too_many_arguments)]
+pub fn sample_lights<T>(wo: &Vector3<f64>, rec: &HitRecord, bsdf: &Bsdf, world: &T, lights: &Lights, media: &MediumTracker, heuristic: Option<MisHeuristic>, wavelengths: Option<&SampledWavelengths>, u: [f64; 3]) -> (Color, usize) where T: Hit {
+    let (sample, emission, light_group, _) = match lights.sample(&rec.p, u[0], [u[1], u[2]]) {
+        Some(sample) => sample,
+        None => return (Color::new(0.0, 0.0, 0.0), 0)
+    };
+
+    let wi = (sample.p - rec.p).normalize();
+    let attenuation = bsdf.eval(wo, &wi) * wi.dot(&rec.normal).abs();
+    if attenuation == Color::new(0.0, 0.0, 0.0) || sample.pdf <= 0.0 {
+        return (Color::new(0.0, 0.0, 0.0), light_group);
+    }
+    let (attenuation, emission) = match wavelengths {
+        Some(wavelengths) => (wavelengths.reflectance(&attenuation), wavelengths.illuminant(&emission)),
+        None => (attenuation, emission)
+    };
+
+    let mut media = *media;
+    media.cross(rec, &wi);
+    let transmittance = media::transmittance(world, &rec.p, &sample.p, rec.time, media);
+    if transmittance == Color::new(0.0, 0.0, 0.0) {
+        return (Color::new(0.0, 0.0, 0.0), light_group);
+    }
+    let weight = match heuristic {
+        Some(heuristic) => heuristic.weight(sample.pdf, bsdf.pdf(wo, &wi)),
+        None => 1.0
+    };
+    (attenuation.component_mul(&emission).component_mul(&transmittance) * (weight / sample.pdf), light_group)
+}
+
+/// Estimates the radiance scattered along `wo` at a point `p` within a medium at the given time, due to light arriving
+/// directly from a sampled point on one of the lights. The estimate is weighted against the chance of the phase function scattering 
+/// towards the same point. If `wavelengths` are given, the spectral radiance at those wavelengths is returned instead.
+/// The light and the point on it are chosen by `u`, as for `sample_lights`. The light group of the sampled light is 
+/// returned alongside the estimate.
+#[allow(clippy::too_many_arguments)]
+pub fn sample_lights_in_medium<T>(wo: &Vector3<f64>, p: &Point3<f64>, time: f64, phase: &HenyeyGreenstein, world: &T, lights: &Lights, media: &MediumTracker, heuristic: MisHeuristic, wavelengths: Option<&SampledWavelengths>, u: [f64; 3]) -> (Color, usize) where T: Hit {
+    let (sample, emission, light_group, _) = match lights.sample(p, u[0], [u[1], u[2]]) {
+        Some(sample) => sample,
+        None => return (Color::new(0.0, 0.0, 0.0), 0)
+    };
+    if sample.pdf <= 0.0 {
+        return (Color::new(0.0, 0.0, 0.0), light_group);
+    }
+
+    let wi = (sample.p - p).normalize();
+    let emission = wavelengths.map_or(emission, |wavelengths| wavelengths.illuminant(&emission));
+    let transmittance = media::transmittance(world, p, &sample.p, time, *media);
+    let phase_pdf = phase.p(wo, &wi);
+    let weight = heuristic.weight(sample.pdf, phase_pdf);
+    (emission.component_mul(&transmittance) * (phase_pdf * weight / sample.pdf), light_group)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::primitives::rect::RectAxes;
+    use crate::material::dispersion::RefractiveIndex;
+    use crate::sampler::SamplerType;
+    use crate::lpe::RenderLayer;
+    use crate::film::Filter;
+    use crate::sampler::independent::IndependentSampler;
+    use crate::camera::CameraSettings;
     #[test]
     fn test_new(){
         let orig = Point3::<f64>::new(0.0, 0.0, 0.0);
@@ -210,4 +514,194 @@ mod tests {
         let t = 2.0;
         assert_eq!(ray.at(t), orig + 2.0 * dir);
     }
+
+    #[test]
+    fn test_ray_color(){
+        let settings = RayTraceSettings { rr_start_depth: 0, ..RayTraceSettings::for_test() };
+        let mut sampler = IndependentSampler::new(0);
+        let background = Color::new(0.7, 0.8, 1.0);
+        let mut world = Primitives::new();
+        let lights = Lights::new();
+        world.add(Primitive::new_sphere(Point3::<f64>::new(0.0, 0.0, 0.0), 1.0, Material::new_diffuse_light(Color::new(4.0, 4.0, 4.0))));
+
+        //Case 1: Ray misses, and so returns the background
+        let r = Ray::new(Point3::<f64>::new(-10.0, 5.0, 0.0), Vector3::<f64>::new(1.0, 0.0, 0.0));
+        assert_eq!(ray_color(&r, background, None, &world, &lights, &settings, None, &mut sampler, None, None), background);
+
+        //Case 2: Ray is absorbed by a light, and so returns its emission
+        let r = Ray::new(Point3::<f64>::new(-10.0, 0.0, 0.0), Vector3::<f64>::new(1.0, 0.0, 0.0));
+        assert_eq!(ray_color(&r, background, None, &world, &lights, &settings, None, &mut sampler, None, None), Color::new(4.0, 4.0, 4.0));
+
+        //Case 3: The path records the light as the first surface it reached, which the AOVs then describe
+        let mut first_hit = FirstHit::default();
+        ray_color(&r, background, None, &world, &lights, &settings, None, &mut sampler, None, Some(&mut first_hit));
+        let cam = Camera::new(CameraSettings { look_from: Point3::<f64>::new(-10.0, 0.0, 0.0), ..CameraSettings::default() });
+        let aovs = first_hit.aovs(&cam).unwrap();
+        assert!((aovs[3].1 - Color::new(-1.0, 0.0, 0.0)).norm() < 1e-9);
+
+        //Case 4: The bounce limit has been reached, so no light is gathered
+        let settings = RayTraceSettings { max_depth: 0, ..settings };
+        assert_eq!(ray_color(&r, background, None, &world, &lights, &settings, None, &mut sampler, None, None), Color::new(0.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn test_ray_color_media(){
+        let settings = RayTraceSettings { max_depth: 1000, rr_start_depth: 0, ..RayTraceSettings::for_test() };
+        let mut sampler = IndependentSampler::new(0);
+        let background = Color::new(1.0, 1.0, 1.0);
+        let lights = Lights::new();
+        let r = Ray::new(Point3::<f64>::new(0.0, 0.0, 10.0), Vector3::<f64>::new(0.0, 0.0, -2.0));
+
+        //Case 1: A medium which only scatters light loses none of it, so a uniform background is seen unchanged
+        let medium = Medium::new(Color::zeros(), Color::new(1.5, 1.5, 1.5), 0.5);
+        let mut world = Primitives::new();
+        world.add(Primitive::new_sphere_with_medium(Point3::<f64>::new(0.0, 0.0, 0.0), 1.0, Material::new_interface(), medium));
+        for _ in 0..100 {
+            assert!((ray_color(&r, background, None, &world, &lights, &settings, None, &mut sampler, None, None) - background).norm() < 1e-9);
+        }
+
+        //Case 2: A medium which only absorbs light attenuates the background by its transmittance
+        let medium = Medium::new(Color::new(0.5, 0.5, 0.5), Color::zeros(), 0.0);
+        let mut world = Primitives::new();
+        world.add(Primitive::new_sphere_with_medium(Point3::<f64>::new(0.0, 0.0, 0.0), 1.0, Material::new_interface(), medium));
+        let samples = 10000;
+        let mean = (0..samples).fold(Color::zeros(), |sum, _| sum + ray_color(&r, background, None, &world, &lights, &settings, None, &mut sampler, None, None)) / samples as f64;
+        assert!((mean - background * (-1.0f64).exp()).norm() < 0.03);
+
+        //Case 3: The atmosphere fills the space outside other media
+        let settings = RayTraceSettings { max_depth: 1, ..settings };
+        let mut world = Primitives::new();
+        world.add(Primitive::new_sphere(Point3::<f64>::new(0.0, 0.0, 0.0), 1.0, Material::new_diffuse_light(Color::new(4.0, 4.0, 4.0))));
+        let mean = (0..samples).fold(Color::zeros(), |sum, _| sum + ray_color(&r, background, Some(medium), &world, &lights, &settings, None, &mut sampler, None, None)) / samples as f64;
+        assert!((mean - Color::new(4.0, 4.0, 4.0) * (-4.5f64).exp()).norm() < 0.03);
+    }
+
+    #[test]
+    fn test_ray_color_spectral(){
+        let settings = RayTraceSettings { rr_start_depth: 50, color_mode: ColorMode::Spectral, ..RayTraceSettings::for_test() };
+        let mut sampler = IndependentSampler::new(0);
+        let background = Color::new(1.0, 1.0, 1.0);
+        let mut world = Primitives::new();
+        let lights = Lights::new();
+        world.add(Primitive::new_sphere(Point3::<f64>::new(0.0, 0.0, 0.0), 1.0, Material::new_lambertian(Color::new(0.5, 0.5, 0.5))));
+        let r = Ray::new(Point3::<f64>::new(-10.0, 0.0, 0.0), Vector3::<f64>::new(1.0, 0.0, 0.0));
+
+        //A convex grey sphere under a white sky reflects half of the light in RGB, and the same holds across the spectrum
+        assert!((ray_color(&r, background, None, &world, &lights, &settings, None, &mut sampler, None, None) - background * 0.5).norm() < 1e-12);
+        let n = 300;
+        let mean = (0..n).fold(Color::zeros(), |sum, i| {
+            let wavelengths = SampledWavelengths::sample_hero((i as f64 + 0.5) / n as f64);
+            sum + wavelengths.to_rgb(&ray_color(&r, background, None, &world, &lights, &settings, Some(&wavelengths), &mut sampler, None, None))
+        }) / n as f64;
+        assert!((mean - background * 0.5).norm() < 0.02);
+
+        //A dispersive glass sphere neither absorbs nor emits, so the sky seen through it is still white on average
+        let mut world = Primitives::new();
+        world.add(Primitive::new_sphere(Point3::<f64>::new(0.0, 0.0, 0.0), 1.0, Material::new_dispersive_dielectric(RefractiveIndex::DIAMOND)));
+        let r = Ray::new(Point3::<f64>::new(-10.0, 0.3, 0.0), Vector3::<f64>::new(1.0, 0.0, 0.0));
+        let mean = (0..n).fold(Color::zeros(), |sum, i| {
+            let wavelengths = SampledWavelengths::sample_hero((i as f64 + 0.5) / n as f64);
+            sum + wavelengths.to_rgb(&ray_color(&r, background, None, &world, &lights, &settings, Some(&wavelengths), &mut sampler, None, None))
+        }) / n as f64;
+        assert!((mean - background).norm() < 0.03);
+    }
+
+    #[test]
+    fn test_ray_color_layers(){
+        let settings = RayTraceSettings { max_depth: 20, rr_start_depth: 3, ..RayTraceSettings::for_test() };
+        let mut sampler = IndependentSampler::new(0);
+        let background = Color::new(0.2, 0.3, 0.4);
+        let mut geometric_primitives = GeometricPrimitives::new();
+        geometric_primitives.add(GeometricPrimitive::new_sphere(Point3::<f64>::new(0.0, -100.0, 0.0), 99.0, Material::new_lambertian(Color::new(0.5, 0.5, 0.5))));
+        geometric_primitives.add(GeometricPrimitive::new_sphere(Point3::<f64>::new(-2.0, 0.0, 0.0), 1.0, Material::new_metal(Color::new(0.8, 0.8, 0.8), 0.1)));
+        geometric_primitives.add(GeometricPrimitive::new_sphere(Point3::<f64>::new(2.0, 0.0, 0.0), 1.0, Material::new_dielectric(1.5)));
+        geometric_primitives.add(GeometricPrimitive::new_sphere(Point3::<f64>::new(0.0, 4.0, 0.0), 1.0, Material::new_diffuse_light(Color::new(4.0, 4.0, 4.0))));
+        geometric_primitives.add(GeometricPrimitive::new_sphere(Point3::<f64>::new(3.0, 3.0, 2.0), 0.5, Material::new_diffuse_light_in_group(Color::new(8.0, 2.0, 2.0), 1)));
+        let fog = Medium::new(Color::repeat(0.1), Color::repeat(0.5), 0.0);
+        geometric_primitives.add(GeometricPrimitive::new_sphere_with_medium(Point3::<f64>::new(0.0, 0.0, 5.0), 1.5, Material::new_interface(), fog));
+        let lights = Lights::from_primitives(&geometric_primitives);
+        let mut world = Primitives::new();
+        world.add(Primitive::new_bvh(geometric_primitives.to_bvh()));
+        let layers = RenderLayer::defaults();
+
+        //Case 1: The default layers, and the light groups, sum to the radiance of every path
+        let mut totals = vec![Color::zeros(); layers.len()];
+        let mut group_totals = [Color::zeros(); 3];
+        //Only a few rays reach the spheres behind the fog unscattered, so each direction is traced several times
+        for i in 0..8000 {
+            let i = i % 2000;
+            let direction = Vector3::<f64>::new((i % 40) as f64 / 10.0 - 2.0, (i / 40) as f64 / 25.0 - 1.0, -1.0);
+            let r = Ray::new(Point3::<f64>::new(0.0, 0.0, 8.0), direction);
+            let mut tracker = LayerTracker::new(&layers, 3);
+            let radiance = ray_color(&r, background, None, &world, &lights, &settings, None, &mut sampler, Some(&mut tracker), None);
+            let sum = tracker.values.iter().fold(Color::zeros(), |sum, value| sum + value);
+            assert!((sum - radiance).norm() <= 1e-9 * radiance.norm().max(1.0));
+            let group_sum = tracker.light_groups.iter().fold(Color::zeros(), |sum, value| sum + value);
+            assert!((group_sum - radiance).norm() <= 1e-9 * radiance.norm().max(1.0));
+            for (total, value) in totals.iter_mut().zip(tracker.values.iter()) {
+                *total += value;
+            }
+            for (total, value) in group_totals.iter_mut().zip(tracker.light_groups.iter()) {
+                *total += value;
+            }
+        }
+
+        //Case 2: Every kind of path, and every light group, is found in this scene
+        assert!(totals.iter().all(|total| total.max() > 0.0));
+        assert!(group_totals.iter().all(|total| total.max() > 0.0));
+        //Only the second group has a red light
+        assert!(group_totals[1][0] > 2.0 * group_totals[1][1]);
+
+        //Case 3: Custom layers may overlap the others
+        let layers = vec![RenderLayer::new("All", ".*").unwrap(), RenderLayer::new("Direct", "C . L").unwrap()];
+        let r = Ray::new(Point3::<f64>::new(0.0, 0.0, 8.0), Vector3::<f64>::new(0.0, -1.0, -1.0));
+        let mut tracker = LayerTracker::new(&layers, 0);
+        let radiance = ray_color(&r, background, None, &world, &lights, &settings, None, &mut sampler, Some(&mut tracker), None);
+        assert!((tracker.values[0] - radiance).norm() < 1e-12);
+        assert!(tracker.values[1].max() <= tracker.values[0].max());
+    }
+
+    #[test]
+    fn test_sample_lights(){
+        let albedo = Color::new(0.5, 0.5, 0.5);
+        let emission = Color::new(4.0, 4.0, 4.0);
+        let floor = GeometricPrimitive::new_rect(RectAxes::XZ, -10.0, 10.0, -10.0, 10.0, 0.0, Material::new_lambertian(albedo));
+        let light = GeometricPrimitive::new_rect(RectAxes::XZ, -0.5, 0.5, -0.5, 0.5, 2.0, Material::new_diffuse_light(emission));
+        let blocker = GeometricPrimitive::new_rect(RectAxes::XZ, -1.0, 1.0, -1.0, 1.0, 1.0, Material::new_lambertian(albedo));
+        let mut lights = Lights::new();
+        lights.add(1, light);
+
+        let r = Ray::new(Point3::<f64>::new(0.0, 5.0, 0.0), Vector3::<f64>::new(0.0, -1.0, 0.0));
+        let (rec, mat) = floor.hit(&r, 0.001, f64::INFINITY).unwrap();
+        let bsdf = mat.bsdf(&rec);
+
+        //Case 1: The light is visible, so the estimate is bounded by the irradiance from the light's solid angle
+        let mut world = GeometricPrimitives::new();
+        world.add(floor);
+        world.add(light);
+        let (radiance, _) = sample_lights(&-r.direction(), &rec, &bsdf, &world, &lights, &MediumTracker::default(), Some(MisHeuristic::Balance), None, [0.5, 0.3, 0.7]);
+        assert!(radiance[0] > 0.0);
+        assert!(radiance[0] <= albedo[0] * emission[0] * 1.0 / (PI * 4.0));
+
+        //Case 2: The light is occluded
+        world.add(blocker);
+        let (radiance, _) = sample_lights(&-r.direction(), &rec, &bsdf, &world, &lights, &MediumTracker::default(), Some(MisHeuristic::Balance), None, [0.5, 0.3, 0.7]);
+        assert_eq!(radiance, Color::new(0.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn test_mis_heuristic(){
+        //Case 1: The weights of both strategies sum to one
+        for heuristic in [MisHeuristic::Balance, MisHeuristic::Power] {
+            let weight = heuristic.weight(0.3, 1.2) + heuristic.weight(1.2, 0.3);
+            assert!((weight - 1.0).abs() < 1e-12);
+        }
+
+        //Case 2: Specific values
+        assert_eq!(MisHeuristic::Balance.weight(1.0, 3.0), 0.25);
+        assert_eq!(MisHeuristic::Power.weight(1.0, 3.0), 0.1);
+
+        //Case 3: Samples which neither strategy could have drawn have no weight
+        assert_eq!(MisHeuristic::Power.weight(0.0, 0.0), 0.0);
+    }
 }
\ No newline at end of file
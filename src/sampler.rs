@@ -1,34 +1,222 @@
-use nalgebra::Unit;
+pub mod halton;
+pub mod independent;
+pub mod sobol;
+pub mod stratified;
+
+use std::f64::consts::PI;
 
 use crate::nalgebra::{Vector3};
+use crate::enum_dispatch::*;
+use crate::sampler::halton::HaltonSampler;
+use crate::sampler::independent::IndependentSampler;
+use crate::sampler::sobol::SobolSampler;
+use crate::sampler::stratified::StratifiedSampler;
+
+/// Generates the random numbers used to build each sample of a pixel. Numbers are drawn one dimension at a time, and
+/// a sampler may arrange the values of each dimension across the samples of a pixel to cover [0, 1) more evenly than
+/// independent random numbers would. The same dimension should therefore be used for the same purpose in every sample.
+#[enum_dispatch]
+pub trait Sampler {
+    /// Starts generating the sample with the given index in the given pixel, from the given dimension. Each sample of a
+    /// pixel should have a different index.
+    fn start_pixel_sample(&mut self, pixel: (usize, usize), index: usize, dimension: usize);
+
+    /// Returns the dimension the next value of the sample will be drawn from.
+    fn dimension(&self) -> usize;
+
+    /// Continues the current sample from the given dimension, so that a value can be drawn from a fixed dimension 
+    /// however many were drawn before it. One- and two-dimensional values each take at most one dimension per number.
+    fn set_dimension(&mut self, dimension: usize);
+
+    /// Returns the value of the next dimension of the sample.
+    fn get_1d(&mut self) -> f64;
 
-//Generates random numbers between [min_inc, max_exc)
-pub fn rand_double(min_inc: f64, max_exc: f64) -> f64{
-    fastrand::f64()*(max_exc - min_inc) + min_inc
+    /// Returns the values of the next two dimensions of the sample, which are well distributed as a pair.
+    fn get_2d(&mut self) -> [f64; 2];
 }
 
-pub fn rand(min: f64, max:f64) -> Vector3<f64>{
-    Vector3::<f64>::new(rand_double(min, max), rand_double(min, max), rand_double(min, max))
+/// One of the samplers, chosen at run time.
+#[enum_dispatch(Sampler)]
+#[derive (Clone)]
+pub enum PixelSampler {
+    Independent(IndependentSampler),
+    Stratified(StratifiedSampler),
+    Halton(HaltonSampler),
+    Sobol(SobolSampler)
 }
 
-pub fn rand_in_unit_sphere() -> Vector3<f64>{
-    loop{
-        let p = rand(-1.0, 1.0);
-        if p.norm() < 1.0{
-            break(p)
+/// The kinds of sampler which can be chosen to render with.
+#[derive (Copy, Clone, Debug, PartialEq)]
+pub enum SamplerType {
+    /// Independent uniform random numbers.
+    Independent,
+    /// Random numbers jittered within strata, which are shuffled between the samples of each pixel.
+    Stratified,
+    /// The Halton sequence, randomly shifted in each pixel.
+    Halton,
+    /// The Sobol sequence, with the points of each pixel shuffled and Owen scrambled.
+    Sobol
+}
+
+impl SamplerType {
+    pub const ALL: [SamplerType; 4] = [SamplerType::Independent, SamplerType::Stratified, SamplerType::Halton, SamplerType::Sobol];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            SamplerType::Independent => "Independent",
+            SamplerType::Stratified => "Stratified",
+            SamplerType::Halton => "Halton",
+            SamplerType::Sobol => "Sobol"
+        }
+    }
+
+    /// Returns a sampler of this type. Samplers given the same seed generate the same samples.
+    pub fn sampler(&self, samples_per_pixel: usize, seed: u64) -> PixelSampler {
+        match self {
+            SamplerType::Independent => PixelSampler::Independent(IndependentSampler::new(seed)),
+            SamplerType::Stratified => PixelSampler::Stratified(StratifiedSampler::new(samples_per_pixel, seed)),
+            SamplerType::Halton => PixelSampler::Halton(HaltonSampler::new(seed)),
+            SamplerType::Sobol => PixelSampler::Sobol(SobolSampler::new(seed))
         }
     }
 }
 
-pub fn rand_in_unit_disk() -> Vector3<f64>{
-    loop{
-        let p = Vector3::<f64>::new(rand_double(-1.0, 1.0), rand_double(-1.0, 1.0), 0.0);
-        if p.norm() < 1.0{
-            break(p)
+/// Mixes the bits of a value, so that similar values give unrelated results.
+pub fn mix_bits(mut v: u64) -> u64 {
+    v ^= v >> 31;
+    v = v.wrapping_mul(0x7fb5_d329_728e_a185);
+    v ^= v >> 27;
+    v = v.wrapping_mul(0x81da_def4_bc2d_d44d);
+    v ^= v >> 33;
+    v
+}
+
+/// Hashes a list of values into a single value.
+pub fn hash(values: &[u64]) -> u64 {
+    values.iter().fold(0x9e37_79b9_7f4a_7c15, |h, v| mix_bits(h ^ mix_bits(v.wrapping_add(0x9e37_79b9_7f4a_7c15))))
+}
+
+/// Maps a hash to a uniform number in [0, 1).
+pub fn hash_to_unit(h: u64) -> f64 {
+    (h >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// Returns the element at index `i` of a random permutation of 0..`l`, chosen by the seed `p`. The permutation is
+/// computed without being stored, using Kensler's hashing method.
+pub fn permutation_element(mut i: u32, l: u32, p: u32) -> u32 {
+    let mut w = l.wrapping_sub(1);
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+    loop {
+        i ^= p;
+        i = i.wrapping_mul(0xe170_893d);
+        i ^= p >> 16;
+        i ^= (i & w) >> 4;
+        i ^= p >> 8;
+        i = i.wrapping_mul(0x0929_eb3f);
+        i ^= p >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | p >> 27);
+        i = i.wrapping_mul(0x6935_fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dc_b303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e50_1cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860_a3df);
+        i &= w;
+        i ^= i >> 5;
+        if i < l {
+            break;
         }
     }
+    (i.wrapping_add(p)) % l
+}
+
+/// Maps a pair of uniform random numbers in [0, 1) to a direction distributed uniformly over the unit sphere.
+pub fn uniform_sphere(u: [f64; 2]) -> Vector3<f64>{
+    let z = 1.0 - 2.0 * u[0];
+    let r = (1.0 - z * z).max(0.0).sqrt();
+    let phi = 2.0 * PI * u[1];
+    Vector3::<f64>::new(r * phi.cos(), r * phi.sin(), z)
+}
+
+/// Maps a pair of uniform random numbers in [0, 1) to a direction distributed uniformly over the cone
+/// about the z-axis with the given half-angle cosine.
+pub fn uniform_cone(u: [f64; 2], cos_theta_max: f64) -> Vector3<f64>{
+    let cos_theta = (1.0 - u[0]) + u[0] * cos_theta_max;
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+    let phi = 2.0 * PI * u[1];
+    Vector3::<f64>::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta)
+}
+
+/// Maps a pair of uniform random numbers in [0, 1) to barycentric coordinates distributed uniformly over a triangle.
+pub fn uniform_triangle(u: [f64; 2]) -> [f64; 3]{
+    let su0 = u[0].sqrt();
+    let b0 = 1.0 - su0;
+    let b1 = u[1] * su0;
+    [b0, b1, 1.0 - b0 - b1]
+}
+
+/// Maps three uniform random numbers in [0, 1) to a point distributed uniformly within the unit ball.
+pub fn uniform_ball(u: [f64; 3]) -> Vector3<f64>{
+    u[0].cbrt() * uniform_sphere([u[1], u[2]])
+}
+
+/// Maps a pair of uniform random numbers in [0, 1) to a point distributed uniformly within the unit disk.
+pub fn uniform_disk(u: [f64; 2]) -> Vector3<f64>{
+    let r = u[0].sqrt();
+    let phi = 2.0 * PI * u[1];
+    Vector3::<f64>::new(r * phi.cos(), r * phi.sin(), 0.0)
 }
 
-pub fn rand_unit_vec() -> Unit<Vector3<f64>>{
-    Unit::new_normalize(rand_in_unit_sphere())
-}
\ No newline at end of file
+/// Maps a pair of uniform random numbers in [0, 1) to a direction distributed about the z-axis with a density 
+/// proportional to the cosine of its angle to the axis.
+pub fn cosine_hemisphere(u: [f64; 2]) -> Vector3<f64>{
+    let d = uniform_disk(u);
+    let z = (1.0 - d[0] * d[0] - d[1] * d[1]).max(0.0).sqrt();
+    Vector3::<f64>::new(d[0], d[1], z)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_permutation_element(){
+        for l in [1, 7, 16, 100] {
+            for p in [0, 12345, 0xdead_beef] {
+                let mut seen = vec![false; l as usize];
+                for i in 0..l {
+                    let element = permutation_element(i, l, p) as usize;
+                    assert!(!seen[element]);
+                    seen[element] = true;
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_samplers(){
+        //Every sampler gives numbers in [0, 1), and the same numbers each time a sample is restarted
+        for sampler_type in SamplerType::ALL {
+            let mut sampler = sampler_type.sampler(16, 7);
+            let mut generate = |index: usize| {
+                sampler.start_pixel_sample((3, 5), index, 0);
+                let mut values: Vec<f64> = (0..40).map(|_| sampler.get_1d()).collect();
+                for _ in 0..40 {
+                    values.extend(sampler.get_2d());
+                }
+                values
+            };
+            for index in 0..16 {
+                let values = generate(index);
+                assert!(values.iter().all(|u| (0.0..1.0).contains(u)));
+                assert_eq!(values, generate(index));
+            }
+        }
+    }
+}
@@ -0,0 +1,87 @@
+use crate::sampler::{hash, hash_to_unit, Sampler};
+
+/// The bases of the dimensions of the Halton sequence.
+const PRIMES: [u64; 64] = [2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71, 73, 79, 83, 89, 97,
+                           101, 103, 107, 109, 113, 127, 131, 137, 139, 149, 151, 157, 163, 167, 173, 179, 181, 191, 193,
+                           197, 199, 211, 223, 227, 229, 233, 239, 241, 251, 257, 263, 269, 271, 277, 281, 283, 293, 307, 311];
+
+/// Generates the Halton sequence, in which each dimension is the radical inverse of the index of the sample in a 
+/// different prime base. The sequence is shifted by a random offset in each pixel and dimension, so that neighbouring 
+/// pixels do not share the same pattern. Dimensions beyond the table of primes are independent random numbers, since
+/// the radical inverses in large bases are poorly distributed.
+#[derive (Clone)]
+pub struct HaltonSampler {
+    seed: u64,
+    pixel: (usize, usize),
+    index: usize,
+    dimension: usize
+}
+
+impl HaltonSampler {
+    pub fn new(seed: u64) -> HaltonSampler {
+        HaltonSampler { seed, pixel: (0, 0), index: 0, dimension: 0 }
+    }
+}
+
+impl Sampler for HaltonSampler {
+    fn start_pixel_sample(&mut self, pixel: (usize, usize), index: usize, dimension: usize) {
+        self.pixel = pixel;
+        self.index = index;
+        self.dimension = dimension;
+    }
+
+    fn dimension(&self) -> usize {
+        self.dimension
+    }
+
+    fn set_dimension(&mut self, dimension: usize) {
+        self.dimension = dimension;
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        let h = hash(&[self.pixel.0 as u64, self.pixel.1 as u64, self.dimension as u64, self.seed]);
+        let u = match PRIMES.get(self.dimension) {
+            Some(base) => {
+                let shifted = radical_inverse(*base, self.index as u64) + hash_to_unit(h);
+                shifted - shifted.floor()
+            }
+            None => hash_to_unit(hash(&[h, self.index as u64]))
+        };
+        self.dimension += 1;
+
+        //Guard against the shifted value rounding up to one
+        u.min(1.0 - f64::EPSILON / 2.0)
+    }
+
+    fn get_2d(&mut self) -> [f64; 2] {
+        [self.get_1d(), self.get_1d()]
+    }
+}
+
+/// Reflects the digits of `a`, written in the given base, about the decimal point.
+pub fn radical_inverse(base: u64, mut a: u64) -> f64 {
+    let inverse_base = 1.0 / base as f64;
+    let mut reversed_digits = 0;
+    let mut inverse_base_n = 1.0;
+    while a > 0 {
+        let next = a / base;
+        let digit = a - next * base;
+        reversed_digits = reversed_digits * base + digit;
+        inverse_base_n *= inverse_base;
+        a = next;
+    }
+    (reversed_digits as f64 * inverse_base_n).min(1.0 - f64::EPSILON / 2.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_radical_inverse(){
+        assert_eq!(radical_inverse(2, 0), 0.0);
+        assert_eq!(radical_inverse(2, 1), 0.5);
+        assert_eq!(radical_inverse(2, 6), 0.375);
+        assert!((radical_inverse(3, 5) - 7.0 / 9.0).abs() < 1e-12);
+    }
+}
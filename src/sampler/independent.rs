@@ -0,0 +1,45 @@
+use crate::sampler::{hash, Sampler};
+
+/// Draws every dimension independently and uniformly at random. The random numbers of each sample are seeded from the
+/// pixel and the index of the sample, so that samples can be regenerated.
+#[derive (Clone)]
+pub struct IndependentSampler {
+    seed: u64,
+    pixel: (usize, usize),
+    index: usize,
+    dimension: usize,
+    rng: fastrand::Rng
+}
+
+impl IndependentSampler {
+    pub fn new(seed: u64) -> IndependentSampler {
+        IndependentSampler { seed, pixel: (0, 0), index: 0, dimension: 0, rng: fastrand::Rng::with_seed(seed) }
+    }
+}
+
+impl Sampler for IndependentSampler {
+    fn start_pixel_sample(&mut self, pixel: (usize, usize), index: usize, dimension: usize) {
+        self.pixel = pixel;
+        self.index = index;
+        self.set_dimension(dimension);
+    }
+
+    fn dimension(&self) -> usize {
+        self.dimension
+    }
+
+    fn set_dimension(&mut self, dimension: usize) {
+        self.dimension = dimension;
+        self.rng = fastrand::Rng::with_seed(hash(&[self.pixel.0 as u64, self.pixel.1 as u64, self.index as u64, dimension as u64, self.seed]));
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        self.dimension += 1;
+        self.rng.f64()
+    }
+
+    fn get_2d(&mut self) -> [f64; 2] {
+        self.dimension += 2;
+        [self.rng.f64(), self.rng.f64()]
+    }
+}
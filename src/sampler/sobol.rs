@@ -0,0 +1,121 @@
+use crate::sampler::{hash, Sampler};
+
+/// Generates the Sobol sequence, using its first two dimensions for every pair of dimensions of a sample. The points
+/// used for each pair are shuffled and Owen scrambled with seeds hashed from the pixel and the dimension, following 
+/// Burley's "Practical Hash-based Owen Scrambling". This keeps the excellent distribution of the first two dimensions
+/// of the sequence, without the correlations between higher dimensions.
+#[derive (Clone)]
+pub struct SobolSampler {
+    seed: u64,
+    pixel: (usize, usize),
+    index: usize,
+    dimension: usize
+}
+
+impl SobolSampler {
+    pub fn new(seed: u64) -> SobolSampler {
+        SobolSampler { seed, pixel: (0, 0), index: 0, dimension: 0 }
+    }
+
+    /// Returns the shuffled index of the current sample, along with a hash to scramble its point with.
+    fn next_point(&mut self) -> (u32, u64) {
+        let h = hash(&[self.pixel.0 as u64, self.pixel.1 as u64, self.dimension as u64, self.seed]);
+        self.dimension += 1;
+        (nested_uniform_scramble(self.index as u32, h as u32), h >> 32)
+    }
+}
+
+impl Sampler for SobolSampler {
+    fn start_pixel_sample(&mut self, pixel: (usize, usize), index: usize, dimension: usize) {
+        self.pixel = pixel;
+        self.index = index;
+        self.dimension = dimension;
+    }
+
+    fn dimension(&self) -> usize {
+        self.dimension
+    }
+
+    fn set_dimension(&mut self, dimension: usize) {
+        self.dimension = dimension;
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        let (index, h) = self.next_point();
+        to_unit(nested_uniform_scramble(sobol(index, 0), h as u32))
+    }
+
+    fn get_2d(&mut self) -> [f64; 2] {
+        let (index, h) = self.next_point();
+        let seeds = hash(&[h]);
+        [to_unit(nested_uniform_scramble(sobol(index, 0), seeds as u32)), to_unit(nested_uniform_scramble(sobol(index, 1), (seeds >> 32) as u32))]
+    }
+}
+
+/// Returns the given dimension, which must be zero or one, of the point of the Sobol sequence with the given index. The
+/// first dimension is the van der Corput sequence, and the direction numbers of the second are the rows of Pascal's
+/// triangle modulo two.
+pub fn sobol(index: u32, dimension: usize) -> u32 {
+    let mut result = 0;
+    let mut direction: u32 = 1 << 31;
+    let mut index = index;
+    while index != 0 {
+        if index & 1 == 1 {
+            result ^= direction;
+        }
+        index >>= 1;
+        direction = match dimension {
+            0 => direction >> 1,
+            _ => direction ^ (direction >> 1)
+        };
+    }
+    result
+}
+
+/// Owen scrambles the bits of `x`, by randomly flipping each bit based on the bits above it.
+pub fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+    laine_karras_permutation(x.reverse_bits(), seed).reverse_bits()
+}
+
+/// A hash in which each bit only depends on the bits below it.
+fn laine_karras_permutation(mut x: u32, seed: u32) -> u32 {
+    x = x.wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50_b47c);
+    x ^= x.wrapping_mul(0xb82f_1e52);
+    x ^= x.wrapping_mul(0xc7af_e638);
+    x ^= x.wrapping_mul(0x8d22_f6e6);
+    x
+}
+
+/// Maps 32 bits to a number in [0, 1).
+fn to_unit(x: u32) -> f64 {
+    x as f64 / (1u64 << 32) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sobol(){
+        //Case 1: The first points of the sequence
+        let points: Vec<(f64, f64)> = (0..4).map(|i| (to_unit(sobol(i, 0)), to_unit(sobol(i, 1)))).collect();
+        assert_eq!(points, vec![(0.0, 0.0), (0.5, 0.5), (0.25, 0.75), (0.75, 0.25)]);
+
+        //Case 2: Each of the first 16 samples of a pixel lies in a different cell of every 16-cell grid, as the
+        //scrambling preserves the stratification of the sequence
+        let mut sampler = SobolSampler::new(11);
+        let samples: Vec<[f64; 2]> = (0..16).map(|index| {
+            sampler.start_pixel_sample((4, 9), index, 0);
+            sampler.get_1d();
+            sampler.get_2d()
+        }).collect();
+        for (x_cells, y_cells) in [(1, 16), (2, 8), (4, 4), (8, 2), (16, 1)] {
+            let mut cells = [0; 16];
+            for u in &samples {
+                cells[(u[0] * x_cells as f64) as usize + x_cells * (u[1] * y_cells as f64) as usize] += 1;
+            }
+            assert!(cells.iter().all(|count| *count == 1));
+        }
+    }
+}
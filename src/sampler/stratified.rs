@@ -0,0 +1,81 @@
+use crate::sampler::{hash, hash_to_unit, permutation_element, Sampler};
+
+/// Divides each dimension into as many strata as there are samples per pixel, and places each sample at a random point
+/// within a different stratum. Pairs of dimensions are divided into a grid of strata instead. The strata are shuffled
+/// independently for each dimension, so that the dimensions are not correlated with each other.
+#[derive (Clone)]
+pub struct StratifiedSampler {
+    samples_per_pixel: usize,
+    seed: u64,
+    pixel: (usize, usize),
+    index: usize,
+    dimension: usize
+}
+
+impl StratifiedSampler {
+    pub fn new(samples_per_pixel: usize, seed: u64) -> StratifiedSampler {
+        StratifiedSampler { samples_per_pixel: samples_per_pixel.max(1), seed, pixel: (0, 0), index: 0, dimension: 0 }
+    }
+
+    /// Returns the stratum of the current sample, among the given number of strata, and a hash to jitter it with.
+    fn stratum(&mut self, strata: usize) -> (usize, u64) {
+        let h = hash(&[self.pixel.0 as u64, self.pixel.1 as u64, self.dimension as u64, self.seed]);
+        let stratum = permutation_element((self.index % strata) as u32, strata as u32, h as u32) as usize;
+        self.dimension += 1;
+        (stratum, hash(&[h, self.index as u64]))
+    }
+}
+
+impl Sampler for StratifiedSampler {
+    fn start_pixel_sample(&mut self, pixel: (usize, usize), index: usize, dimension: usize) {
+        self.pixel = pixel;
+        self.index = index;
+        self.dimension = dimension;
+    }
+
+    fn dimension(&self) -> usize {
+        self.dimension
+    }
+
+    fn set_dimension(&mut self, dimension: usize) {
+        self.dimension = dimension;
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        let (stratum, jitter) = self.stratum(self.samples_per_pixel);
+        (stratum as f64 + hash_to_unit(jitter)) / self.samples_per_pixel as f64
+    }
+
+    /// The pair is stratified over a grid which is as close to square as possible, and which has at least as many
+    /// strata as there are samples per pixel.
+    fn get_2d(&mut self) -> [f64; 2] {
+        let x_strata = ((self.samples_per_pixel as f64).sqrt().round() as usize).max(1);
+        let y_strata = self.samples_per_pixel.div_ceil(x_strata);
+        let (stratum, jitter) = self.stratum(x_strata * y_strata);
+        let (x, y) = (stratum % x_strata, stratum / x_strata);
+        [(x as f64 + hash_to_unit(jitter)) / x_strata as f64, (y as f64 + hash_to_unit(hash(&[jitter]))) / y_strata as f64]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stratification(){
+        let samples_per_pixel = 16;
+        let mut sampler = StratifiedSampler::new(samples_per_pixel, 3);
+        let mut strata_1d = vec![0; samples_per_pixel];
+        let mut strata_2d = vec![0; samples_per_pixel];
+        for index in 0..samples_per_pixel {
+            sampler.start_pixel_sample((1, 2), index, 0);
+            strata_1d[(sampler.get_1d() * 16.0) as usize] += 1;
+            let u = sampler.get_2d();
+            strata_2d[(u[0] * 4.0) as usize + 4 * (u[1] * 4.0) as usize] += 1;
+        }
+
+        //Each stratum holds exactly one sample
+        assert!(strata_1d.iter().all(|count| *count == 1));
+        assert!(strata_2d.iter().all(|count| *count == 1));
+    }
+}
@@ -0,0 +1,624 @@
+pub mod parser;
+
+use crate::camera::{Camera, CameraSettings};
+use crate::film::{Filter, FilterType};
+use crate::gltf_import;
+use crate::image::Color;
+use crate::integrators::Integrator;
+use crate::lights::Lights;
+use crate::lpe::RenderLayer;
+use crate::material::Material;
+use crate::material::dispersion::RefractiveIndex;
+use crate::media::Medium;
+use crate::primitives::{GeometricPrimitive, GeometricPrimitives, Primitive, Primitives};
+use crate::primitives::rect::RectAxes;
+use crate::raytracing::MisHeuristic;
+use crate::sampler::SamplerType;
+use crate::scenes::SceneData;
+use crate::spectra::ColorMode;
+use crate::threads::{GlobalSettings, ImageSettings, RayTraceSettings};
+use crate::util::deg_to_rad;
+use crate::nalgebra::{Point3, Similarity3, Translation3, Unit, UnitQuaternion, Vector3};
+
+use std::fmt;
+use std::path::Path;
+
+/// A scene as written in a scene file, along with the settings it is rendered with.
+///
+/// Scene files are made of statements, which are either a keyword followed by values, or a keyword followed by a block
+/// of properties in braces. Properties left out of a block keep their default values. Words are separated by
+/// whitespace, strings are written in double quotes, and comments run from a `#` to the end of the line:
+///
+/// ```text
+/// camera { look_from 13 2 3 look_at 0 0 0 up 0 1 0 fov 20 aperture 0.1 focus_distance 10 shutter 0 1 }
+/// image { width 800 height 533 }
+/// render { samples 100 max_depth 50 integrator path_tracing filter gaussian 1.5 }
+/// background 0.7 0.8 1
+/// light_group "Sun"
+/// material ground lambertian { albedo 0.5 0.5 0.5 }
+/// material sun light { color 4 4 4 group 0 }
+/// sphere { center 0 -1000 0 radius 1000 material ground }
+/// transform {
+///     rotate 0 1 0 45
+///     translate 0 4 0
+///     rect { axes xz min -1 -1 max 1 1 offset 0 material sun }
+/// }
+/// ```
+///
+/// Materials must be defined before the shapes which use them. Transforms apply their operations to the shapes inside
+/// them in the order they are written, and may be nested. Meshes are read from .obj, .gltf or .glb files, whose paths
+/// are relative to the scene file.
+///
+/// The description keeps the scene as it was written, rather than the primitives built from it, so that it can be
+/// written back out unchanged.
+#[derive (Clone, Debug, PartialEq)]
+pub struct SceneDescription {
+    pub camera: CameraDescription,
+    pub image_width: usize,
+    pub image_height: usize,
+    pub raytrace_settings: RayTraceSettings,
+    pub background: Color,
+    pub atmosphere: Option<Medium>,
+    /// The render layers, as pairs of their names and light path expressions.
+    pub layers: Vec<(String, String)>,
+    pub light_groups: Vec<String>,
+    pub materials: Vec<(String, MaterialDescription)>,
+    pub shapes: Vec<ShapeDescription>
+}
+
+/// The placement of the camera. The aspect ratio is that of the image.
+#[derive (Copy, Clone, Debug, PartialEq)]
+pub struct CameraDescription {
+    pub look_from: Point3<f64>,
+    pub look_at: Point3<f64>,
+    pub v_up: Vector3<f64>,
+    pub v_fov: f64,
+    pub aperture: f64,
+    pub focus_dist: f64,
+    pub shutter_open: f64,
+    pub shutter_close: f64
+}
+
+#[derive (Copy, Clone, Debug, PartialEq)]
+pub enum MaterialDescription {
+    Lambertian { albedo: Color },
+    Metal { albedo: Color, fuzz: f64 },
+    Dielectric { refractive_index: RefractiveIndex },
+    Light { color: Color, light_group: usize },
+    Interface
+}
+
+#[derive (Clone, Debug, PartialEq)]
+pub enum ShapeDescription {
+    Sphere { center: Point3<f64>, radius: f64, material: String, medium: Option<Medium> },
+    /// A rectangle lying in the plane of `axes`, at `offset` along the remaining axis.
+    Rect { axes: RectAxes, min: [f64; 2], max: [f64; 2], offset: f64, material: String },
+    /// A triangle, whose normals are those of its face unless given for each vertex.
+    Triangle { vertices: [Point3<f64>; 3], normals: Option<[Vector3<f64>; 3]>, material: String },
+    /// The triangles of an .obj or glTF file, which keep the materials of the file unless given one.
+    Mesh { file: String, material: Option<String> },
+    Transform { operations: Vec<TransformOperation>, shapes: Vec<ShapeDescription> }
+}
+
+#[derive (Copy, Clone, Debug, PartialEq)]
+pub enum TransformOperation {
+    Translate(Vector3<f64>),
+    /// A rotation about the given axis, by the given number of degrees.
+    Rotate(Vector3<f64>, f64),
+    /// A uniform scaling, which keeps spheres spherical.
+    Scale(f64)
+}
+
+impl Default for CameraDescription {
+    fn default() -> CameraDescription {
+        CameraDescription { look_from: Point3::<f64>::new(0.0, 0.0, 10.0), look_at: Point3::<f64>::origin(), v_up: Vector3::<f64>::new(0.0, 1.0, 0.0),
+                            v_fov: 20.0, aperture: 0.0, focus_dist: 10.0, shutter_open: 0.0, shutter_close: 1.0 }
+    }
+}
+
+impl Default for SceneDescription {
+    /// Returns an empty scene, rendered with the settings the window opens with.
+    fn default() -> SceneDescription {
+        let raytrace_settings = RayTraceSettings::default();
+        let layers = RenderLayer::defaults().into_iter().map(|layer| (layer.name, layer.source)).collect();
+        SceneDescription { camera: CameraDescription::default(), image_width: 800, image_height: 533, raytrace_settings, background: Color::zeros(),
+                           atmosphere: None, layers, light_groups: Vec::new(), materials: Vec::new(), shapes: Vec::new() }
+    }
+}
+
+impl MaterialDescription {
+    pub fn to_material(&self) -> Material {
+        match *self {
+            MaterialDescription::Lambertian { albedo } => Material::new_lambertian(albedo),
+            MaterialDescription::Metal { albedo, fuzz } => Material::new_metal(albedo, fuzz),
+            MaterialDescription::Dielectric { refractive_index } => Material::new_dispersive_dielectric(refractive_index),
+            MaterialDescription::Light { color, light_group } => Material::new_diffuse_light_in_group(color, light_group),
+            MaterialDescription::Interface => Material::new_interface()
+        }
+    }
+}
+
+impl TransformOperation {
+    pub fn to_similarity(&self) -> Similarity3<f64> {
+        match *self {
+            TransformOperation::Translate(offset) => Similarity3::from_parts(Translation3::from(offset), UnitQuaternion::identity(), 1.0),
+            TransformOperation::Rotate(axis, angle) => {
+                Similarity3::from_parts(Translation3::identity(), UnitQuaternion::from_axis_angle(&Unit::new_normalize(axis), deg_to_rad(angle)), 1.0)
+            }
+            TransformOperation::Scale(scale) => Similarity3::from_scaling(scale)
+        }
+    }
+}
+
+/// Reads the scene file at the given path.
+pub fn load(path: &Path) -> Result<SceneDescription, String> {
+    let source = std::fs::read_to_string(path).map_err(|error| format!("Could not read '{}': {}", path.display(), error))?;
+    parser::parse(&source).map_err(|error| format!("{}: {}", path.display(), error))
+}
+
+impl SceneDescription {
+    /// Returns the material with the given name.
+    pub fn material(&self, name: &str) -> Option<MaterialDescription> {
+        self.materials.iter().find(|(material_name, _)| material_name == name).map(|(_, material)| *material)
+    }
+
+    /// Builds the primitives of the scene. Meshes are read relative to the given directory.
+    pub fn primitives(&self, directory: &Path) -> Result<GeometricPrimitives, String> {
+        let mut primitives = GeometricPrimitives::new();
+        for shape in &self.shapes {
+            self.add_shape(&mut primitives, shape, &Similarity3::identity(), directory)?;
+        }
+        Ok(primitives)
+    }
+
+    fn add_shape(&self, primitives: &mut GeometricPrimitives, shape: &ShapeDescription, transform: &Similarity3<f64>, directory: &Path) -> Result<(), String> {
+        let material = |name: &str| self.material(name).map(|material| material.to_material()).ok_or_else(|| format!("Unknown material '{}'", name));
+        match shape {
+            ShapeDescription::Sphere { center, radius, material: name, medium } => {
+                let (center, radius, material) = (transform.transform_point(center), radius * transform.scaling(), material(name)?);
+                primitives.add(match medium {
+                    Some(medium) => GeometricPrimitive::new_sphere_with_medium(center, radius, material, *medium),
+                    None => GeometricPrimitive::new_sphere(center, radius, material)
+                });
+            }
+            ShapeDescription::Rect { axes, min, max, offset, material: name } => {
+                add_rect(primitives, *axes, *min, *max, *offset, material(name)?, transform);
+            }
+            ShapeDescription::Triangle { vertices, normals, material: name } => {
+                primitives.add(transformed_triangle(vertices, normals.as_ref(), material(name)?, transform));
+            }
+            ShapeDescription::Mesh { file, material: name } => {
+                let material = name.as_deref().map(material).transpose()?;
+                add_mesh(primitives, &directory.join(file), material, transform)?;
+            }
+            ShapeDescription::Transform { operations, shapes } => {
+                let transform = transform * operations.iter().fold(Similarity3::identity(), |local, operation| operation.to_similarity() * local);
+                for shape in shapes {
+                    self.add_shape(primitives, shape, &transform, directory)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Builds the scene and the settings it is rendered with. Meshes are read relative to the given directory.
+    pub fn to_settings(&self, directory: &Path) -> Result<GlobalSettings, String> {
+        self.settings_for(self.primitives(directory)?)
+    }
+
+    /// Builds the settings the scene is rendered with, using the given primitives in place of its shapes.
+    pub fn settings_for(&self, geometric_primitives: GeometricPrimitives) -> Result<GlobalSettings, String> {
+        let mut raytracing_primitives = Primitives::new();
+        if !geometric_primitives.empty() {
+            raytracing_primitives.add(Primitive::new_bvh(geometric_primitives.clone().to_bvh()));
+        }
+        let lights = Lights::from_primitives(&geometric_primitives);
+        let scene = SceneData { raytracing_primitives, rasterization_primitives: geometric_primitives, lights, background: self.background,
+                                atmosphere: self.atmosphere, light_groups: self.light_groups.clone() };
+
+        let (image_width, image_height) = (self.image_width, self.image_height);
+        let camera = &self.camera;
+        let camera = Camera::new(CameraSettings { look_from: camera.look_from, look_at: camera.look_at, v_up: camera.v_up, v_fov: camera.v_fov,
+                                                  aspect_ratio: image_width as f64 / image_height as f64, aperture: camera.aperture,
+                                                  focus_dist: camera.focus_dist, image_height, image_width, shutter_open: camera.shutter_open,
+                                                  shutter_close: camera.shutter_close });
+        let layers = self.layers.iter().map(|(name, source)| RenderLayer::new(name, source)).collect::<Result<Vec<RenderLayer>, String>>()?;
+        Ok(GlobalSettings { raytrace_settings: self.raytrace_settings, image_settings: ImageSettings { image_width, image_height }, camera, scene, layers, id: 1 })
+    }
+}
+
+/// Adds a rectangle to the primitives. Rectangles which are rotated out of line with the axes are split into a pair of
+/// triangles.
+fn add_rect(primitives: &mut GeometricPrimitives, axes: RectAxes, min: [f64; 2], max: [f64; 2], offset: f64, material: Material, transform: &Similarity3<f64>) {
+    let (first, second, normal) = match axes {
+        RectAxes::XY => (0, 1, 2),
+        RectAxes::XZ => (0, 2, 1),
+        RectAxes::YZ => (1, 2, 0)
+    };
+    //Rotations which should cancel out, such as by 360 degrees, leave a tiny angle behind from rounding
+    if transform.isometry.rotation.angle() < 1e-9 {
+        let (scale, translation) = (transform.scaling(), transform.isometry.translation.vector);
+        primitives.add(GeometricPrimitive::new_rect(axes, min[0] * scale + translation[first], max[0] * scale + translation[first],
+                                                    min[1] * scale + translation[second], max[1] * scale + translation[second],
+                                                    offset * scale + translation[normal], material));
+        return;
+    }
+
+    let corner = |a: f64, b: f64| {
+        let mut corner = Point3::<f64>::origin();
+        corner[first] = a;
+        corner[second] = b;
+        corner[normal] = offset;
+        corner
+    };
+    let mut face_normal = Vector3::<f64>::zeros();
+    face_normal[normal] = 1.0;
+    let corners = [corner(min[0], min[1]), corner(max[0], min[1]), corner(max[0], max[1]), corner(min[0], max[1])];
+    for vertices in [[corners[0], corners[1], corners[2]], [corners[0], corners[2], corners[3]]] {
+        primitives.add(transformed_triangle(&vertices, Some(&[face_normal; 3]), material, transform));
+    }
+}
+
+fn transformed_triangle(vertices: &[Point3<f64>; 3], normals: Option<&[Vector3<f64>; 3]>, material: Material, transform: &Similarity3<f64>) -> GeometricPrimitive {
+    let vertices = vertices.map(|vertex| transform.transform_point(&vertex));
+    let normals = match normals {
+        Some(normals) => normals.map(|normal| transform.transform_vector(&normal).normalize()),
+        None => [(vertices[1] - vertices[0]).cross(&(vertices[2] - vertices[0])).normalize(); 3]
+    };
+    GeometricPrimitive::new_triangle(vertices, normals, material)
+}
+
+/// Adds the triangles of the .obj or glTF file at the given path. Faces without normals take the normal of the face.
+fn add_mesh(primitives: &mut GeometricPrimitives, path: &Path, material: Option<Material>, transform: &Similarity3<f64>) -> Result<(), String> {
+    if gltf_import::is_gltf(path) {
+        return gltf_import::add_gltf_meshes(primitives, path, material, &transform.to_homogeneous());
+    }
+    let load_options = tobj::LoadOptions { single_index: true, triangulate: true, ignore_lines: true, ignore_points: true };
+    let (models, materials) = tobj::load_obj(path, &load_options).map_err(|error| format!("Could not load mesh '{}': {}", path.display(), error))?;
+    let materials = materials.unwrap_or_default();
+    for model in models {
+        let mesh = &model.mesh;
+        let material = material.unwrap_or_else(|| {
+            let diffuse = mesh.material_id.and_then(|id| materials.get(id)).map_or([0.5; 3], |material| material.diffuse);
+            Material::new_lambertian(Color::new(diffuse[0] as f64, diffuse[1] as f64, diffuse[2] as f64))
+        });
+        let point = |index: u32, values: &[f32]| {
+            let index = index as usize * 3;
+            Point3::<f64>::new(values[index] as f64, values[index + 1] as f64, values[index + 2] as f64)
+        };
+        for face in mesh.indices.chunks_exact(3) {
+            let vertices = [point(face[0], &mesh.positions), point(face[1], &mesh.positions), point(face[2], &mesh.positions)];
+            let normals = (!mesh.normals.is_empty()).then(|| [face[0], face[1], face[2]].map(|index| point(index, &mesh.normals).coords));
+            primitives.add(transformed_triangle(&vertices, normals.as_ref(), material, transform));
+        }
+    }
+    Ok(())
+}
+
+/// Returns the word an option is written as in scene files, which is its name in lower case with words joined by
+/// underscores.
+pub fn keyword(name: &str) -> String {
+    name.chars().map(|c| if c.is_alphanumeric() { c.to_ascii_lowercase() } else { '_' }).collect()
+}
+
+/// The integrators which can be written in scene files.
+pub fn integrators() -> Vec<Integrator> {
+    let mut integrators = Integrator::ALL.to_vec();
+    integrators.push(Integrator::AmbientOcclusion);
+    integrators
+}
+
+pub fn mis_heuristic_name(heuristic: &MisHeuristic) -> &'static str {
+    match heuristic {
+        MisHeuristic::Balance => "balance",
+        MisHeuristic::Power => "power"
+    }
+}
+
+pub fn color_mode_name(color_mode: &ColorMode) -> &'static str {
+    match color_mode {
+        ColorMode::Rgb => "rgb",
+        ColorMode::Spectral => "spectral"
+    }
+}
+
+pub fn rect_axes_name(axes: &RectAxes) -> &'static str {
+    match axes {
+        RectAxes::XY => "xy",
+        RectAxes::XZ => "xz",
+        RectAxes::YZ => "yz"
+    }
+}
+
+/// Writes a name as a bare word if it can be read back as one, and as a string otherwise.
+fn write_name(f: &mut fmt::Formatter, name: &str) -> fmt::Result {
+    if parser::is_word(name) {
+        write!(f, "{}", name)
+    } else {
+        write!(f, "\"{}\"", name.replace('\\', "\\\\").replace('"', "\\\""))
+    }
+}
+
+fn write_vector(f: &mut fmt::Formatter, vector: &Vector3<f64>) -> fmt::Result {
+    write!(f, "{} {} {}", vector[0], vector[1], vector[2])
+}
+
+fn write_medium(f: &mut fmt::Formatter, medium: &Medium) -> fmt::Result {
+    write!(f, "{{ absorption ")?;
+    write_vector(f, &medium.sigma_a)?;
+    write!(f, " scattering ")?;
+    write_vector(f, &medium.sigma_s)?;
+    write!(f, " g {} }}", medium.phase.g)
+}
+
+fn write_material(f: &mut fmt::Formatter, material: &MaterialDescription) -> fmt::Result {
+    match material {
+        MaterialDescription::Lambertian { albedo } => {
+            write!(f, "lambertian {{ albedo ")?;
+            write_vector(f, albedo)?;
+            write!(f, " }}")
+        }
+        MaterialDescription::Metal { albedo, fuzz } => {
+            write!(f, "metal {{ albedo ")?;
+            write_vector(f, albedo)?;
+            write!(f, " fuzz {} }}", fuzz)
+        }
+        MaterialDescription::Dielectric { refractive_index } => {
+            write!(f, "dielectric {{ ")?;
+            let preset = RefractiveIndex::PRESETS.iter().find(|(_, preset)| preset == refractive_index);
+            match (preset, refractive_index) {
+                (Some((name, _)), _) => write!(f, "glass {}", keyword(name))?,
+                (None, RefractiveIndex::Constant(index)) => write!(f, "ior {}", index)?,
+                (None, RefractiveIndex::Cauchy { a, b }) => write!(f, "cauchy {} {}", a, b)?,
+                (None, RefractiveIndex::Sellmeier { b, c }) => write!(f, "sellmeier {} {} {} {} {} {}", b[0], b[1], b[2], c[0], c[1], c[2])?
+            }
+            write!(f, " }}")
+        }
+        MaterialDescription::Light { color, light_group } => {
+            write!(f, "light {{ color ")?;
+            write_vector(f, color)?;
+            write!(f, " group {} }}", light_group)
+        }
+        MaterialDescription::Interface => write!(f, "interface {{ }}")
+    }
+}
+
+fn write_shape(f: &mut fmt::Formatter, shape: &ShapeDescription, indent: usize) -> fmt::Result {
+    write!(f, "{:indent$}", "", indent = indent)?;
+    match shape {
+        ShapeDescription::Sphere { center, radius, material, medium } => {
+            write!(f, "sphere {{ center ")?;
+            write_vector(f, &center.coords)?;
+            write!(f, " radius {} material ", radius)?;
+            write_name(f, material)?;
+            if let Some(medium) = medium {
+                write!(f, " medium ")?;
+                write_medium(f, medium)?;
+            }
+            writeln!(f, " }}")
+        }
+        ShapeDescription::Rect { axes, min, max, offset, material } => {
+            write!(f, "rect {{ axes {} min {} {} max {} {} offset {} material ", rect_axes_name(axes), min[0], min[1], max[0], max[1], offset)?;
+            write_name(f, material)?;
+            writeln!(f, " }}")
+        }
+        ShapeDescription::Triangle { vertices, normals, material } => {
+            write!(f, "triangle {{ vertices")?;
+            for vertex in vertices {
+                write!(f, " ")?;
+                write_vector(f, &vertex.coords)?;
+            }
+            if let Some(normals) = normals {
+                write!(f, " normals")?;
+                for normal in normals {
+                    write!(f, " ")?;
+                    write_vector(f, normal)?;
+                }
+            }
+            write!(f, " material ")?;
+            write_name(f, material)?;
+            writeln!(f, " }}")
+        }
+        ShapeDescription::Mesh { file, material } => {
+            write!(f, "mesh {{ file \"{}\"", file.replace('\\', "\\\\").replace('"', "\\\""))?;
+            if let Some(material) = material {
+                write!(f, " material ")?;
+                write_name(f, material)?;
+            }
+            writeln!(f, " }}")
+        }
+        ShapeDescription::Transform { operations, shapes } => {
+            writeln!(f, "transform {{")?;
+            for operation in operations {
+                write!(f, "{:indent$}", "", indent = indent + 4)?;
+                match operation {
+                    TransformOperation::Translate(offset) => {
+                        write!(f, "translate ")?;
+                        write_vector(f, offset)?;
+                    }
+                    TransformOperation::Rotate(axis, angle) => {
+                        write!(f, "rotate ")?;
+                        write_vector(f, axis)?;
+                        write!(f, " {}", angle)?;
+                    }
+                    TransformOperation::Scale(scale) => write!(f, "scale {}", scale)?
+                }
+                writeln!(f)?;
+            }
+            for shape in shapes {
+                write_shape(f, shape, indent + 4)?;
+            }
+            writeln!(f, "{:indent$}}}", "", indent = indent)
+        }
+    }
+}
+
+/// Writes the scene in the format of scene files, which reads back as the same scene.
+impl fmt::Display for SceneDescription {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let camera = &self.camera;
+        writeln!(f, "camera {{")?;
+        write!(f, "    look_from ")?;
+        write_vector(f, &camera.look_from.coords)?;
+        write!(f, "\n    look_at ")?;
+        write_vector(f, &camera.look_at.coords)?;
+        write!(f, "\n    up ")?;
+        write_vector(f, &camera.v_up)?;
+        writeln!(f, "\n    fov {}\n    aperture {}\n    focus_distance {}", camera.v_fov, camera.aperture, camera.focus_dist)?;
+        writeln!(f, "    shutter {} {}\n}}\n", camera.shutter_open, camera.shutter_close)?;
+
+        writeln!(f, "image {{\n    width {}\n    height {}\n}}\n", self.image_width, self.image_height)?;
+
+        let settings = &self.raytrace_settings;
+        writeln!(f, "render {{")?;
+        writeln!(f, "    samples {}\n    max_depth {}\n    rr_start_depth {}", settings.samples_per_pixel, settings.max_depth, settings.rr_start_depth)?;
+        writeln!(f, "    mis_heuristic {}\n    integrator {}", mis_heuristic_name(&settings.mis_heuristic), keyword(settings.integrator.name()))?;
+        writeln!(f, "    color_mode {}\n    photon_count {}\n    gather_radius {}", color_mode_name(&settings.color_mode), settings.photon_count, settings.gather_radius)?;
+        writeln!(f, "    ao_samples {}\n    ao_distance {}\n    noise_threshold {}", settings.ao_samples, settings.ao_distance, settings.noise_threshold)?;
+        writeln!(f, "    sampler {}\n    seed {}", keyword(settings.sampler.name()), settings.seed)?;
+        writeln!(f, "    filter {} {}\n}}\n", keyword(settings.filter.filter_type.name()), settings.filter.radius)?;
+
+        write!(f, "background ")?;
+        write_vector(f, &self.background)?;
+        writeln!(f)?;
+        if let Some(atmosphere) = &self.atmosphere {
+            write!(f, "atmosphere ")?;
+            write_medium(f, atmosphere)?;
+            writeln!(f)?;
+        }
+        writeln!(f)?;
+
+        for (name, source) in &self.layers {
+            write!(f, "layer ")?;
+            write_name(f, name)?;
+            write!(f, " ")?;
+            write_name(f, source)?;
+            writeln!(f)?;
+        }
+        for name in &self.light_groups {
+            write!(f, "light_group ")?;
+            write_name(f, name)?;
+            writeln!(f)?;
+        }
+        if !self.layers.is_empty() || !self.light_groups.is_empty() {
+            writeln!(f)?;
+        }
+
+        for (name, material) in &self.materials {
+            write!(f, "material ")?;
+            write_name(f, name)?;
+            write!(f, " ")?;
+            write_material(f, material)?;
+            writeln!(f)?;
+        }
+        if !self.materials.is_empty() {
+            writeln!(f)?;
+        }
+
+        for shape in &self.shapes {
+            write_shape(f, shape, 0)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::raytracing::Hit;
+
+    #[test]
+    fn test_round_trip(){
+        let scene = parser::parse(include_str!("../scenes/cornell_box.scene")).unwrap();
+
+        //Case 1: The file is read as written, with every setting it leaves out at its default
+        assert_eq!((scene.image_width, scene.image_height), (600, 600));
+        assert_eq!(scene.raytrace_settings.samples_per_pixel, 200);
+        assert_eq!(scene.raytrace_settings.noise_threshold, SceneDescription::default().raytrace_settings.noise_threshold);
+        assert_eq!(scene.material("glass"), Some(MaterialDescription::Dielectric { refractive_index: RefractiveIndex::BK7 }));
+        assert_eq!(scene.shapes.len(), 8);
+
+        //Case 2: Writing the scene out and reading it back gives the same scene, which is written out the same way
+        let written = scene.to_string();
+        let reread = parser::parse(&written).unwrap();
+        assert_eq!(reread, scene);
+        assert_eq!(reread.to_string(), written);
+
+        //Case 3: Names which are not words are written as strings, and every setting survives the round trip
+        let mut scene = scene;
+        scene.light_groups.push(String::from("Fill \"light\""));
+        scene.atmosphere = Some(Medium::new(Color::new(0.1, 0.2, 0.3), Color::new(0.01, 0.0, 0.5), -0.25));
+        scene.raytrace_settings.integrator = Integrator::ProgressivePhotonMapping;
+        scene.raytrace_settings.filter = Filter::new(FilterType::Mitchell, 2.0);
+        scene.materials.push((String::from("prism"), MaterialDescription::Dielectric { refractive_index: RefractiveIndex::Cauchy { a: 1.5, b: 0.004 } }));
+        scene.shapes.push(ShapeDescription::Mesh { file: String::from("meshes/car.obj"), material: None });
+        assert_eq!(parser::parse(&scene.to_string()), Ok(scene));
+    }
+
+    #[test]
+    fn test_parse_errors(){
+        let error = |source: &str| parser::parse(source).unwrap_err();
+
+        //Case 1: Errors give the line and column of the token they were found at
+        assert_eq!(error("camera {\n    fov abc\n}"), "Line 2, column 9: Expected an angle between 0 and 180, found 'abc'");
+        assert_eq!(error("image { width 0 }"), "Line 1, column 15: Expected a positive whole number, found '0'");
+        assert_eq!(error("\n  teapot { }"), "Line 2, column 3: Unknown statement 'teapot'");
+        assert_eq!(error("render { bounces 4 }"), "Line 1, column 10: Unknown property 'bounces'");
+        assert_eq!(error("sphere { material chrome }"), "Line 1, column 19: Unknown material 'chrome'");
+        assert_eq!(error("material a lambertian { }\nsphere { radius 2 }"), "Line 2, column 1: The sphere must have a material");
+        assert!(error("render { sampler random }").starts_with("Line 1, column 18: Expected a sampler (independent, stratified, halton, sobol)"));
+        assert_eq!(error("layer Glossy \"C G L\"\nlayer Glossy \"C G+ L\""), "Line 2, column 7: The layer 'Glossy' is already defined");
+
+        //Case 2: Errors at the end of the file are given just past its end
+        assert_eq!(error("background 1 1"), "Line 1, column 15: Expected a number, found the end of the file");
+        assert_eq!(error("layer \"Glossy\" \"C G\nmaterial"), "Line 1, column 16: Unterminated string");
+
+        //Case 3: Numbers must be finite, and triangles must not be flat
+        assert_eq!(error("background 1 NaN 1"), "Line 1, column 14: Expected a number, found 'NaN'");
+        assert_eq!(error("camera { look_from 0 0 inf }"), "Line 1, column 24: Expected a number, found 'inf'");
+        assert_eq!(error("rect { offset -inf }"), "Line 1, column 15: Expected a number, found '-inf'");
+        assert_eq!(error("triangle {\n    vertices 0 0 0  1 1 1  2 2 2\n}"), "Line 2, column 14: Triangles must not have zero area");
+        assert_eq!(error("triangle { vertices 0 0 0  0 0 0  1 0 0 }"), "Line 1, column 21: Triangles must not have zero area");
+
+        //Case 4: Rectangles must not be inside out, normals must not be zero, and the camera must have an orientation
+        assert_eq!(error("rect {\n    max 1 1\n    min 2 0\n}"), "Line 3, column 5: The minimum corner of a rectangle must be below its maximum corner");
+        assert_eq!(error("rect { min 1 -1 }"), "Line 1, column 8: The minimum corner of a rectangle must be below its maximum corner");
+        assert_eq!(error("triangle { vertices 0 0 0  1 0 0  0 1 0  normals 0 0 1  0 0 0  0 0 1 }"), "Line 1, column 57: Normals must not be zero");
+        assert_eq!(error("\ncamera { look_from 1 2 3 look_at 1 2 3 }"), "Line 2, column 1: The camera must not look at the point it is placed at");
+        assert_eq!(error("camera { look_from 0 0 0 look_at 0 5 0 up 0 1 0 }"), "Line 1, column 1: The camera's up direction must not be zero or parallel to the direction it looks in");
+        assert_eq!(error("camera { up 0 0 0 }"), "Line 1, column 1: The camera's up direction must not be zero or parallel to the direction it looks in");
+    }
+
+    #[test]
+    fn test_primitives(){
+        let scene = parser::parse("material white lambertian { albedo 1 1 1 }\n\
+                                   transform {\n\
+                                       scale 2\n\
+                                       translate 1 0 0\n\
+                                       sphere { center 1 0 0 radius 1 material white }\n\
+                                       rect { axes xy min 0 0 max 1 1 offset 0 material white }\n\
+                                       transform { rotate 0 0 1 90 rect { axes xy min 0 0 max 1 1 offset 0 material white } }\n\
+                                   }").unwrap();
+        let primitives = scene.primitives(Path::new("")).unwrap();
+
+        //Case 1: Transforms are applied in the order they are written, so the sphere is scaled before it is moved
+        match primitives.get(0) {
+            GeometricPrimitive::Sphere(sphere) => assert_eq!(sphere.center(), Point3::<f64>::new(3.0, 0.0, 0.0)),
+            _ => panic!("Expected a sphere")
+        }
+
+        //Case 2: Rectangles stay rectangles unless they are rotated, in which case they are split into triangles
+        assert!(matches!(primitives.get(1), GeometricPrimitive::Rect(_)));
+        assert_eq!(primitives.len(), 4);
+        assert!(matches!(primitives.get(3), GeometricPrimitive::Triangle(_)));
+        //The triangle's corners are turned about the z axis, then scaled and moved
+        let bounds = primitives.get(2).bounding_box().unwrap();
+        assert!((bounds.min().xy() - Point3::<f64>::new(-1.0, 0.0, 0.0).xy()).norm() < 1e-2);
+        assert!((bounds.max().xy() - Point3::<f64>::new(1.0, 2.0, 0.0).xy()).norm() < 1e-2);
+
+        //Case 3: Rotations which turn all the way round leave rectangles as they are
+        let scene = parser::parse("material white lambertian { }\n\
+                                   transform { rotate 1 1 0 360 rect { material white } }").unwrap();
+        assert!(matches!(scene.primitives(Path::new("")).unwrap().get(0), GeometricPrimitive::Rect(_)));
+    }
+}
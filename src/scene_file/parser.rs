@@ -0,0 +1,581 @@
+use super::*;
+
+use std::str::FromStr;
+
+#[derive (Clone, Debug, PartialEq)]
+enum TokenKind {
+    Word(String),
+    Str(String),
+    Open,
+    Close
+}
+
+/// A token of a scene file, along with the line and column it starts at, counting from one.
+#[derive (Clone, Debug, PartialEq)]
+struct Token {
+    kind: TokenKind,
+    line: usize,
+    column: usize
+}
+
+/// Returns true if the text can be written as a bare word in a scene file, rather than as a string.
+pub fn is_word(text: &str) -> bool {
+    !text.is_empty() && text.chars().all(is_word_char)
+}
+
+fn is_word_char(c: char) -> bool {
+    !c.is_whitespace() && !matches!(c, '{' | '}' | '"' | '#')
+}
+
+fn error_at(line: usize, column: usize, message: &str) -> String {
+    format!("Line {}, column {}: {}", line, column, message)
+}
+
+/// Splits a scene file into tokens, skipping whitespace and comments.
+fn tokenize(source: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = source.chars().peekable();
+    let (mut line, mut column) = (1, 1);
+    while let Some(&c) = chars.peek() {
+        let (start_line, start_column) = (line, column);
+        let mut advance = |chars: &mut std::iter::Peekable<std::str::Chars>| {
+            let c = chars.next();
+            if c == Some('\n') {
+                line += 1;
+                column = 1;
+            } else {
+                column += 1;
+            }
+            c
+        };
+        let kind = match c {
+            _ if c.is_whitespace() => {
+                advance(&mut chars);
+                continue;
+            }
+            '#' => {
+                while !matches!(chars.peek(), Some('\n') | None) {
+                    advance(&mut chars);
+                }
+                continue;
+            }
+            '{' => {
+                advance(&mut chars);
+                TokenKind::Open
+            }
+            '}' => {
+                advance(&mut chars);
+                TokenKind::Close
+            }
+            '"' => {
+                advance(&mut chars);
+                let mut text = String::new();
+                loop {
+                    match advance(&mut chars) {
+                        Some('"') => break,
+                        Some('\\') => match advance(&mut chars) {
+                            Some(escaped) => text.push(escaped),
+                            None => return Err(error_at(start_line, start_column, "Unterminated string"))
+                        },
+                        Some(c) => text.push(c),
+                        None => return Err(error_at(start_line, start_column, "Unterminated string"))
+                    }
+                }
+                TokenKind::Str(text)
+            }
+            _ => {
+                let mut text = String::new();
+                while let Some(&c) = chars.peek() {
+                    if !is_word_char(c) {
+                        break;
+                    }
+                    text.push(c);
+                    advance(&mut chars);
+                }
+                TokenKind::Word(text)
+            }
+        };
+        tokens.push(Token { kind, line: start_line, column: start_column });
+    }
+    Ok(tokens)
+}
+
+fn describe(token: &Token) -> String {
+    match &token.kind {
+        TokenKind::Word(word) => format!("'{}'", word),
+        TokenKind::Str(text) => format!("\"{}\"", text),
+        TokenKind::Open => String::from("'{'"),
+        TokenKind::Close => String::from("'}'")
+    }
+}
+
+/// Reads tokens one at a time, reporting errors at the position of the token they were found at.
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+    /// The line and column just past the end of the file.
+    end: (usize, usize)
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    /// Returns an error at the position of the next token.
+    fn error(&self, message: &str) -> String {
+        match self.peek() {
+            Some(token) => error_at(token.line, token.column, message),
+            None => error_at(self.end.0, self.end.1, message)
+        }
+    }
+
+    /// Returns an error describing what was expected, and what was found instead.
+    fn expected(&self, what: &str) -> String {
+        let found = self.peek().map_or(String::from("the end of the file"), describe);
+        self.error(&format!("Expected {}, found {}", what, found))
+    }
+
+    fn word(&mut self, what: &str) -> Result<String, String> {
+        match self.peek().map(|token| &token.kind) {
+            Some(TokenKind::Word(word)) => {
+                let word = word.clone();
+                self.position += 1;
+                Ok(word)
+            }
+            _ => Err(self.expected(what))
+        }
+    }
+
+    /// Reads a name, which may be written as a bare word or as a string.
+    fn name(&mut self, what: &str) -> Result<String, String> {
+        match self.peek().map(|token| &token.kind) {
+            Some(TokenKind::Word(text)) | Some(TokenKind::Str(text)) => {
+                let text = text.clone();
+                self.position += 1;
+                Ok(text)
+            }
+            _ => Err(self.expected(what))
+        }
+    }
+
+    fn string(&mut self, what: &str) -> Result<String, String> {
+        match self.peek().map(|token| &token.kind) {
+            Some(TokenKind::Str(text)) => {
+                let text = text.clone();
+                self.position += 1;
+                Ok(text)
+            }
+            _ => Err(self.expected(what))
+        }
+    }
+
+    fn number<T: FromStr>(&mut self, what: &str) -> Result<T, String> {
+        match self.peek().map(|token| &token.kind) {
+            Some(TokenKind::Word(word)) => match word.parse() {
+                Ok(number) => {
+                    self.position += 1;
+                    Ok(number)
+                }
+                Err(_) => Err(self.expected(what))
+            },
+            _ => Err(self.expected(what))
+        }
+    }
+
+    /// Moves back to the token at the given position, and returns an error there.
+    fn error_at(&mut self, position: usize, message: &str) -> String {
+        self.position = position;
+        self.error(message)
+    }
+
+    /// Reads a finite number, which must satisfy the given condition.
+    fn checked(&mut self, what: &str, condition: fn(f64) -> bool) -> Result<f64, String> {
+        let position = self.position;
+        let number: f64 = self.number(what)?;
+        if number.is_finite() && condition(number) {
+            Ok(number)
+        } else {
+            self.position = position;
+            Err(self.expected(what))
+        }
+    }
+
+    /// Reads a finite number. Rust parses words such as "nan" and "inf" as numbers, which are rejected here.
+    fn real(&mut self, what: &str) -> Result<f64, String> {
+        self.checked(what, |_| true)
+    }
+
+    fn vector(&mut self) -> Result<Vector3<f64>, String> {
+        Ok(Vector3::<f64>::new(self.real("a number")?, self.real("a number")?, self.real("a number")?))
+    }
+
+    fn point(&mut self) -> Result<Point3<f64>, String> {
+        Ok(Point3::from(self.vector()?))
+    }
+
+    /// Reads a word naming one of the given options.
+    fn choice<T: Copy>(&mut self, what: &str, options: &[T], name: fn(&T) -> String) -> Result<T, String> {
+        let names: Vec<String> = options.iter().map(name).collect();
+        let what = format!("{} ({})", what, names.join(", "));
+        let position = self.position;
+        let word = self.word(&what)?;
+        match names.iter().position(|name| *name == word) {
+            Some(index) => Ok(options[index]),
+            None => {
+                self.position = position;
+                Err(self.expected(&what))
+            }
+        }
+    }
+
+    fn open(&mut self) -> Result<(), String> {
+        match self.peek().map(|token| &token.kind) {
+            Some(TokenKind::Open) => {
+                self.position += 1;
+                Ok(())
+            }
+            _ => Err(self.expected("'{'"))
+        }
+    }
+
+    /// Reads the properties of a block, up to its closing brace. The reader is given the name of each property, and
+    /// reads its values.
+    fn block(&mut self, mut read: impl FnMut(&mut Parser, &str) -> Result<(), String>) -> Result<(), String> {
+        self.open()?;
+        loop {
+            if let Some(TokenKind::Close) = self.peek().map(|token| &token.kind) {
+                self.position += 1;
+                return Ok(());
+            }
+            let property_position = self.position;
+            let property = self.word("a property or '}'")?;
+            read(self, &property)?;
+            //Every property has values, so a property which read none was not recognised
+            if self.position == property_position + 1 {
+                return Err(self.error_at(property_position, &format!("Unknown property '{}'", property)));
+            }
+        }
+    }
+}
+
+fn medium(parser: &mut Parser) -> Result<Medium, String> {
+    let mut medium = Medium::default();
+    parser.block(|parser, property| {
+        match property {
+            "absorption" => medium.sigma_a = parser.vector()?,
+            "scattering" => medium.sigma_s = parser.vector()?,
+            "g" => medium.phase.g = parser.checked("a number between -1 and 1", |g| g.abs() < 1.0)?,
+            _ => ()
+        }
+        Ok(())
+    })?;
+    Ok(medium)
+}
+
+/// Reads the properties of the camera. The camera must look somewhere other than where it is placed, and its up
+/// direction must not be parallel to the direction it looks in, or it would have no orientation.
+fn camera(parser: &mut Parser, camera: &mut CameraDescription) -> Result<(), String> {
+    let start = parser.position - 1;
+    parser.block(|parser, property| {
+        match property {
+            "look_from" => camera.look_from = parser.point()?,
+            "look_at" => camera.look_at = parser.point()?,
+            "up" => camera.v_up = parser.vector()?,
+            "fov" => camera.v_fov = parser.checked("an angle between 0 and 180", |fov| fov > 0.0 && fov < 180.0)?,
+            "aperture" => camera.aperture = parser.checked("a non-negative number", |aperture| aperture >= 0.0)?,
+            "focus_distance" => camera.focus_dist = parser.checked("a positive number", |distance| distance > 0.0)?,
+            "shutter" => {
+                camera.shutter_open = parser.real("a number")?;
+                camera.shutter_close = parser.real("a number")?;
+            }
+            _ => ()
+        }
+        Ok(())
+    })?;
+    let direction = camera.look_at - camera.look_from;
+    if direction.norm() == 0.0 {
+        return Err(parser.error_at(start, "The camera must not look at the point it is placed at"));
+    }
+    if camera.v_up.cross(&direction).norm() == 0.0 {
+        return Err(parser.error_at(start, "The camera's up direction must not be zero or parallel to the direction it looks in"));
+    }
+    Ok(())
+}
+
+fn positive_count(parser: &mut Parser) -> Result<usize, String> {
+    let position = parser.position;
+    match parser.number("a positive whole number")? {
+        0 => Err(parser.error_at(position, "Expected a positive whole number, found '0'")),
+        count => Ok(count)
+    }
+}
+
+fn render_settings(parser: &mut Parser, settings: &mut RayTraceSettings) -> Result<(), String> {
+    parser.block(|parser, property| {
+        match property {
+            "samples" => settings.samples_per_pixel = positive_count(parser)?,
+            "max_depth" => settings.max_depth = parser.number("a whole number")?,
+            "rr_start_depth" => settings.rr_start_depth = parser.number("a whole number")?,
+            "mis_heuristic" => settings.mis_heuristic = parser.choice("a heuristic", &[MisHeuristic::Balance, MisHeuristic::Power],
+                                                                      |heuristic| String::from(mis_heuristic_name(heuristic)))?,
+            "integrator" => settings.integrator = parser.choice("an integrator", &integrators(), |integrator| keyword(integrator.name()))?,
+            "color_mode" => settings.color_mode = parser.choice("a colour mode", &[ColorMode::Rgb, ColorMode::Spectral],
+                                                                |color_mode| String::from(color_mode_name(color_mode)))?,
+            "photon_count" => settings.photon_count = parser.number("a whole number")?,
+            "gather_radius" => settings.gather_radius = parser.checked("a positive number", |radius| radius > 0.0)?,
+            "ao_samples" => settings.ao_samples = parser.number("a whole number")?,
+            "ao_distance" => settings.ao_distance = parser.checked("a positive number", |distance| distance > 0.0)?,
+            "noise_threshold" => settings.noise_threshold = parser.checked("a non-negative number", |threshold| threshold >= 0.0)?,
+            "sampler" => settings.sampler = parser.choice("a sampler", &SamplerType::ALL, |sampler| keyword(sampler.name()))?,
+            "seed" => settings.seed = parser.number("a whole number")?,
+            "filter" => {
+                let filter_type = parser.choice("a filter", &FilterType::ALL, |filter_type| keyword(filter_type.name()))?;
+                settings.filter = Filter::new(filter_type, parser.checked("a positive radius", |radius| radius > 0.0)?);
+            }
+            _ => ()
+        }
+        Ok(())
+    })
+}
+
+fn material(parser: &mut Parser) -> Result<MaterialDescription, String> {
+    let kinds = ["lambertian", "metal", "dielectric", "light", "interface"];
+    let kind = parser.choice("a kind of material", &kinds, |kind| String::from(*kind))?;
+    let mut albedo = Color::new(0.5, 0.5, 0.5);
+    let mut fuzz = 0.0;
+    let mut refractive_index = RefractiveIndex::Constant(1.5);
+    let mut color = Color::new(1.0, 1.0, 1.0);
+    let mut light_group = 0;
+    parser.block(|parser, property| {
+        match (kind, property) {
+            ("lambertian", "albedo") | ("metal", "albedo") => albedo = parser.vector()?,
+            ("metal", "fuzz") => fuzz = parser.checked("a number between 0 and 1", |fuzz| (0.0..=1.0).contains(&fuzz))?,
+            ("dielectric", "ior") => refractive_index = RefractiveIndex::Constant(parser.checked("a positive number", |index| index > 0.0)?),
+            ("dielectric", "glass") => refractive_index = parser.choice("a glass", &RefractiveIndex::PRESETS, |(name, _)| keyword(name))?.1,
+            ("dielectric", "cauchy") => refractive_index = RefractiveIndex::Cauchy { a: parser.real("a number")?, b: parser.real("a number")? },
+            ("dielectric", "sellmeier") => {
+                let b = [parser.real("a number")?, parser.real("a number")?, parser.real("a number")?];
+                let c = [parser.real("a number")?, parser.real("a number")?, parser.real("a number")?];
+                refractive_index = RefractiveIndex::Sellmeier { b, c };
+            }
+            ("light", "color") => color = parser.vector()?,
+            ("light", "group") => light_group = parser.number("a whole number")?,
+            _ => ()
+        }
+        Ok(())
+    })?;
+    Ok(match kind {
+        "lambertian" => MaterialDescription::Lambertian { albedo },
+        "metal" => MaterialDescription::Metal { albedo, fuzz },
+        "dielectric" => MaterialDescription::Dielectric { refractive_index },
+        "light" => MaterialDescription::Light { color, light_group },
+        _ => MaterialDescription::Interface
+    })
+}
+
+/// Reads the name of a material, which must already have been defined.
+fn material_reference(parser: &mut Parser, materials: &[(String, MaterialDescription)]) -> Result<String, String> {
+    let position = parser.position;
+    let name = parser.name("the name of a material")?;
+    if materials.iter().any(|(material, _)| *material == name) {
+        Ok(name)
+    } else {
+        Err(parser.error_at(position, &format!("Unknown material '{}'", name)))
+    }
+}
+
+/// Reads a shape, whose keyword has just been read, returning `None` if the keyword does not start one.
+fn shape(parser: &mut Parser, keyword: &str, materials: &[(String, MaterialDescription)]) -> Result<Option<ShapeDescription>, String> {
+    let start = parser.position - 1;
+    let mut material = None;
+    let mut read_material = |parser: &mut Parser| -> Result<(), String> {
+        material = Some(material_reference(parser, materials)?);
+        Ok(())
+    };
+    let shape = match keyword {
+        "sphere" => {
+            let (mut center, mut radius, mut medium_inside) = (Point3::<f64>::origin(), 1.0, None);
+            parser.block(|parser, property| {
+                match property {
+                    "center" => center = parser.point()?,
+                    "radius" => radius = parser.checked("a positive number", |radius| radius > 0.0)?,
+                    "material" => read_material(parser)?,
+                    "medium" => medium_inside = Some(medium(parser)?),
+                    _ => ()
+                }
+                Ok(())
+            })?;
+            material.map(|material| ShapeDescription::Sphere { center, radius, material, medium: medium_inside })
+        }
+        "rect" => {
+            let (mut axes, mut min, mut max, mut offset) = (RectAxes::XY, [-1.0, -1.0], [1.0, 1.0], 0.0);
+            //The corner read last is blamed if the corners are the wrong way round
+            let mut corner_position = start;
+            parser.block(|parser, property| {
+                match property {
+                    "axes" => axes = parser.choice("axes", &[RectAxes::XY, RectAxes::XZ, RectAxes::YZ], |axes| String::from(rect_axes_name(axes)))?,
+                    "min" => {
+                        corner_position = parser.position - 1;
+                        min = [parser.real("a number")?, parser.real("a number")?];
+                    }
+                    "max" => {
+                        corner_position = parser.position - 1;
+                        max = [parser.real("a number")?, parser.real("a number")?];
+                    }
+                    "offset" => offset = parser.real("a number")?,
+                    "material" => read_material(parser)?,
+                    _ => ()
+                }
+                Ok(())
+            })?;
+            if min[0] >= max[0] || min[1] >= max[1] {
+                return Err(parser.error_at(corner_position, "The minimum corner of a rectangle must be below its maximum corner"));
+            }
+            material.map(|material| ShapeDescription::Rect { axes, min, max, offset, material })
+        }
+        "triangle" => {
+            let (mut vertices, mut normals) = (None, None);
+            parser.block(|parser, property| {
+                match property {
+                    "vertices" => {
+                        let position = parser.position;
+                        let [a, b, c] = [parser.point()?, parser.point()?, parser.point()?];
+                        //Vertices in a line have no normal, so the triangle could never be hit
+                        if (b - a).cross(&(c - a)).norm() <= 1e-9 * (b - a).norm() * (c - a).norm() {
+                            return Err(parser.error_at(position, "Triangles must not have zero area"));
+                        }
+                        vertices = Some([a, b, c]);
+                    }
+                    "normals" => {
+                        let mut read = [Vector3::<f64>::zeros(); 3];
+                        for normal in read.iter_mut() {
+                            let position = parser.position;
+                            *normal = parser.vector()?;
+                            if normal.norm() == 0.0 {
+                                return Err(parser.error_at(position, "Normals must not be zero"));
+                            }
+                        }
+                        normals = Some(read);
+                    }
+                    "material" => read_material(parser)?,
+                    _ => ()
+                }
+                Ok(())
+            })?;
+            let vertices = match vertices {
+                Some(vertices) => vertices,
+                None => return Err(parser.error_at(start, "Triangles must have vertices"))
+            };
+            material.map(|material| ShapeDescription::Triangle { vertices, normals, material })
+        }
+        "mesh" => {
+            let mut file = None;
+            parser.block(|parser, property| {
+                match property {
+                    "file" => file = Some(parser.string("a file name in quotes")?),
+                    "material" => read_material(parser)?,
+                    _ => ()
+                }
+                Ok(())
+            })?;
+            match file {
+                Some(file) => return Ok(Some(ShapeDescription::Mesh { file, material })),
+                None => return Err(parser.error_at(start, "Meshes must have a file"))
+            }
+        }
+        "transform" => {
+            let (mut operations, mut shapes) = (Vec::new(), Vec::new());
+            parser.block(|parser, property| {
+                match property {
+                    "translate" => operations.push(TransformOperation::Translate(parser.vector()?)),
+                    "rotate" => {
+                        let axis = parser.vector()?;
+                        if axis.norm() == 0.0 {
+                            return Err(parser.error_at(parser.position - 3, "The axis of a rotation must not be zero"));
+                        }
+                        operations.push(TransformOperation::Rotate(axis, parser.real("an angle in degrees")?));
+                    }
+                    "scale" => operations.push(TransformOperation::Scale(parser.checked("a positive number", |scale| scale > 0.0)?)),
+                    _ => {
+                        if let Some(shape) = shape(parser, property, materials)? {
+                            shapes.push(shape);
+                        }
+                    }
+                }
+                Ok(())
+            })?;
+            return Ok(Some(ShapeDescription::Transform { operations, shapes }));
+        }
+        _ => return Ok(None)
+    };
+    match shape {
+        Some(shape) => Ok(Some(shape)),
+        None => Err(parser.error_at(start, &format!("The {} must have a material", keyword)))
+    }
+}
+
+/// Reads a scene file. Errors give the line and column at which they were found.
+pub fn parse(source: &str) -> Result<SceneDescription, String> {
+    let tokens = tokenize(source)?;
+    let last_line = source.rsplit('\n').next().unwrap_or("");
+    let end = (source.matches('\n').count() + 1, last_line.chars().count() + 1);
+    let mut parser = Parser { tokens, position: 0, end };
+    let mut scene = SceneDescription::default();
+    let mut layers_given = false;
+
+    while parser.peek().is_some() {
+        let statement_position = parser.position;
+        let statement = parser.word("a statement")?;
+        match statement.as_str() {
+            "camera" => camera(&mut parser, &mut scene.camera)?,
+            "image" => {
+                let (width, height) = (&mut scene.image_width, &mut scene.image_height);
+                parser.block(|parser, property| {
+                    match property {
+                        "width" => *width = positive_count(parser)?,
+                        "height" => *height = positive_count(parser)?,
+                        _ => ()
+                    }
+                    Ok(())
+                })?;
+            }
+            "render" => render_settings(&mut parser, &mut scene.raytrace_settings)?,
+            "background" => scene.background = parser.vector()?,
+            "atmosphere" => scene.atmosphere = Some(medium(&mut parser)?),
+            "layer" => {
+                let name = parser.name("the name of a layer")?;
+                if layers_given && scene.layers.iter().any(|(existing, _)| *existing == name) {
+                    return Err(parser.error_at(parser.position - 1, &format!("The layer '{}' is already defined", name)));
+                }
+                let error_position = parser.position;
+                let source = parser.name("a light path expression")?;
+                if let Err(error) = RenderLayer::new(&name, &source) {
+                    return Err(parser.error_at(error_position, &error));
+                }
+                if !layers_given {
+                    scene.layers.clear();
+                    layers_given = true;
+                }
+                scene.layers.push((name, source));
+            }
+            "light_group" => {
+                let name = parser.name("the name of a light group")?;
+                scene.light_groups.push(name);
+            }
+            "material" => {
+                let name = parser.name("the name of a material")?;
+                if scene.material(&name).is_some() {
+                    return Err(parser.error_at(parser.position - 1, &format!("The material '{}' is already defined", name)));
+                }
+                let material = material(&mut parser)?;
+                scene.materials.push((name, material));
+            }
+            _ => match shape(&mut parser, &statement, &scene.materials)? {
+                Some(shape) => scene.shapes.push(shape),
+                None => return Err(parser.error_at(statement_position, &format!("Unknown statement '{}'", statement)))
+            }
+        }
+    }
+    Ok(scene)
+}
@@ -1,5 +1,7 @@
 use crate::image::Color;
 use crate::primitives::{GeometricPrimitive, GeometricPrimitives, Primitives};
+use crate::lights::Lights;
+use crate::media::Medium;
 use crate::{material::*, sampler};
 use crate::primitives::rect::*;
 use crate::util::*;
@@ -9,15 +11,59 @@ use crate::nalgebra::{Vector3, Point3};
 
 /// Contains all information regarding the scene. The raytracing_primitives and the rasterization_primitives contain
 /// the same primtitives, but raytracing_primitives may contain acceleration structures designed to improve
-/// raytracing performance. The background color is the ambient color of the scene.
+/// raytracing performance. The lights are the emissive primitives, which are sampled directly when raytracing.
+/// The background color is the ambient color of the scene. The atmosphere is an optional medium filling all space 
+/// outside the primitives, in which the camera is assumed to sit. The light groups name the groups the emissive
+/// materials are sorted into, in order of their index.
 pub struct SceneData {
     pub raytracing_primitives: Primitives,
     pub rasterization_primitives: GeometricPrimitives,
+    pub lights: Lights,
     pub background: Color,   
+    pub atmosphere: Option<Medium>,
+    pub light_groups: Vec<String>
 }
 
-/// Returns a world filled with spheres.
-pub fn sphere_world() -> (GeometricPrimitives, Color, Point3<f64>, Point3<f64>) {
+impl SceneData {
+    /// Returns the name of every light group in the scene, followed by the background, which is treated as a group of
+    /// its own. Groups used by a light but left unnamed are named after their index.
+    pub fn light_group_names(&self) -> Vec<String> {
+        let primitives = &self.rasterization_primitives;
+        let used = (0..primitives.len()).map(|index| primitives.get(index).material())
+                                         .filter(|material| material.emit() != Color::zeros())
+                                         .filter_map(|material| material.light_group())
+                                         .map(|group| group + 1)
+                                         .max().unwrap_or(0);
+        let mut names: Vec<String> = (0..used.max(self.light_groups.len()))
+            .map(|group| self.light_groups.get(group).cloned().unwrap_or_else(|| format!("Group {}", group)))
+            .collect();
+        names.push(String::from("Background"));
+        names
+    }
+}
+
+/// The names of the scenes which can be looked up with `from_name`.
+pub const SCENE_NAMES: [&str; 7] = ["sphere_world", "light_test", "media_test", "dispersion_test", "motion_blur_test", "triangle_test", "mesh_test"];
+
+/// Returns the scene with the given name, along with its background and the points the camera looks from and at by
+/// default, or `None` if there is no scene with the name. The seed is only used by scenes which are placed randomly.
+pub fn from_name(name: &str, seed: u64) -> Option<(GeometricPrimitives, Color, Point3<f64>, Point3<f64>)> {
+    match name {
+        "sphere_world" => Some(sphere_world(seed)),
+        "light_test" => Some(light_test()),
+        "media_test" => Some(media_test()),
+        "dispersion_test" => Some(dispersion_test()),
+        "motion_blur_test" => Some(motion_blur_test()),
+        "triangle_test" => Some(triangle_test()),
+        "mesh_test" => Some(mesh_test()),
+        _ => None
+    }
+}
+
+/// Returns a world filled with spheres, which are placed randomly. The same seed always gives the same world.
+pub fn sphere_world(seed: u64) -> (GeometricPrimitives, Color, Point3<f64>, Point3<f64>) {
+    let rng = fastrand::Rng::with_seed(seed);
+    let rand_double = |min: f64, max: f64| rng.f64() * (max - min) + min;
     let mut world = GeometricPrimitives::new();
     let background = Color::new(0.7, 0.8, 1.0);
     let look_from = Point3::<f64>::new(13.0, 2.0, 3.0);
@@ -33,12 +79,12 @@ pub fn sphere_world() -> (GeometricPrimitives, Color, Point3<f64>, Point3<f64>)
             let center = Point3::<f64>::new(a as f64 + 0.9*rand_double(0.0, 1.0), 0.2, b as f64 + 0.9*rand_double(0.0, 1.0));
 
             if choose_mat < 0.6{
-                let albedo = sampler::rand(0.0, 1.0).component_mul(&sampler::rand(0.0, 1.0));
+                let albedo = Color::from_fn(|_, _| rand_double(0.0, 1.0) * rand_double(0.0, 1.0));
                 let sphere_material = Material::new_lambertian(albedo);
                 let sphere = GeometricPrimitive::new_sphere(center, 0.2, sphere_material);
                 world.add(sphere);
             } else if choose_mat < 0.9{
-                let albedo = sampler::rand(0.5, 1.0);
+                let albedo = Color::from_fn(|_, _| rand_double(0.5, 1.0));
                 let fuzz = rand_double(0.0, 0.5);
                 let sphere_material = Material::new_metal(albedo, fuzz);
                 let sphere = GeometricPrimitive::new_sphere(center, 0.2, sphere_material);
@@ -78,14 +124,79 @@ pub fn light_test() -> (GeometricPrimitives, Color, Point3<f64>, Point3<f64>) {
     let sphere = GeometricPrimitive::new_sphere(Point3::<f64>::new(0.0, 2.0, 0.0), 2.0, Material::new_lambertian(Color::new(0.8, 0.8, 0.8))); 
 
     let diff_light = Material::new_diffuse_light(Color::new(4.0,4.0,4.0));
-    let _rect = Box::new(Rect::new(RectAxes::XY, -1.0, 2.0, 1.0, 3.0, 4.0, diff_light));
+    let rect = GeometricPrimitive::new_rect(RectAxes::XY, -1.0, 2.0, 1.0, 3.0, 4.0, diff_light);
     world.add(ground);
     world.add(sphere);
+    world.add(rect);
     
     (world, background, look_from, look_at)
 
 }
 
+/// Returns a scene containing a sphere of smoke and a glass sphere filled with an absorbing liquid, lit by a single light.
+pub fn media_test() -> (GeometricPrimitives, Color, Point3<f64>, Point3<f64>) {
+    let mut world = GeometricPrimitives::new();
+    let background = Color::new(0.0, 0.0, 0.0);
+    let look_from = Point3::<f64>::new(0.0, 3.0, 20.0);
+    let look_at = Point3::<f64>::new(0.0, 1.5, 0.0);
+
+    let ground = GeometricPrimitive::new_sphere(Point3::<f64>::new(0.0, -1000.0, 0.0), 1000.0, Material::new_lambertian(Color::new(0.5, 0.5, 0.5)));
+    let smoke = Medium::new(Color::new(0.05, 0.05, 0.05), Color::new(0.8, 0.8, 0.8), 0.3);
+    let smoke_sphere = GeometricPrimitive::new_sphere_with_medium(Point3::<f64>::new(-2.5, 2.0, 0.0), 2.0, Material::new_interface(), smoke);
+    let liquid = Medium::new(Color::new(0.6, 0.2, 0.05), Color::new(0.0, 0.0, 0.0), 0.0);
+    let glass_sphere = GeometricPrimitive::new_sphere_with_medium(Point3::<f64>::new(2.5, 2.0, 0.0), 2.0, Material::new_dielectric(1.33), liquid);
+
+    let diff_light = Material::new_diffuse_light(Color::new(8.0, 8.0, 8.0));
+    let rect = GeometricPrimitive::new_rect(RectAxes::XZ, -2.0, 2.0, -2.0, 2.0, 8.0, diff_light);
+    world.add(ground);
+    world.add(smoke_sphere);
+    world.add(glass_sphere);
+    world.add(rect);
+
+    (world, background, look_from, look_at)
+}
+
+/// Returns a scene containing spheres of each of the glass presets, lit by a small, bright light so that their dispersion
+/// shows in spectral mode.
+pub fn dispersion_test() -> (GeometricPrimitives, Color, Point3<f64>, Point3<f64>) {
+    let mut world = GeometricPrimitives::new();
+    let background = Color::new(0.0, 0.0, 0.0);
+    let look_from = Point3::<f64>::new(0.0, 3.0, 20.0);
+    let look_at = Point3::<f64>::new(0.0, 1.5, 0.0);
+
+    let ground = GeometricPrimitive::new_sphere(Point3::<f64>::new(0.0, -1000.0, 0.0), 1000.0, Material::new_lambertian(Color::new(0.8, 0.8, 0.8)));
+    world.add(ground);
+    for (i, (_, refractive_index)) in dispersion::RefractiveIndex::PRESETS.iter().enumerate() {
+        let center = Point3::<f64>::new(-4.0 + 4.0 * i as f64, 1.5, 0.0);
+        world.add(GeometricPrimitive::new_sphere(center, 1.5, Material::new_dispersive_dielectric(*refractive_index)));
+    }
+
+    let diff_light = Material::new_diffuse_light(Color::new(40.0, 40.0, 40.0));
+    let rect = GeometricPrimitive::new_rect(RectAxes::XZ, -6.0, 6.0, -5.0, -4.5, 8.0, diff_light);
+    world.add(rect);
+
+    (world, background, look_from, look_at)
+}
+
+/// Returns a scene of spheres bouncing upwards while the shutter is open, to show motion blur.
+pub fn motion_blur_test() -> (GeometricPrimitives, Color, Point3<f64>, Point3<f64>) {
+    let mut world = GeometricPrimitives::new();
+    let background = Color::new(0.7, 0.8, 1.0);
+    let look_from = Point3::<f64>::new(0.0, 3.0, 20.0);
+    let look_at = Point3::<f64>::new(0.0, 1.5, 0.0);
+
+    let ground = GeometricPrimitive::new_sphere(Point3::<f64>::new(0.0, -1000.0, 0.0), 1000.0, Material::new_lambertian(Color::new(0.5, 0.5, 0.5)));
+    world.add(ground);
+    for i in 0..5 {
+        let center = Point3::<f64>::new(-6.0 + 3.0 * i as f64, 1.0, 0.0);
+        let bounce = Vector3::<f64>::new(0.0, 0.5 * i as f64, 0.0);
+        let mat = Material::new_lambertian(Color::new(0.8, 0.3 + 0.1 * i as f64, 0.1));
+        world.add(GeometricPrimitive::new_moving_sphere(center, center + bounce, 0.0, 1.0, 1.0, mat));
+    }
+
+    (world, background, look_from, look_at)
+}
+
 /// Returns a scene containing a single triangle.
 pub fn triangle_test() -> (GeometricPrimitives, Color, Point3<f64>, Point3<f64>) {
     let mut world = GeometricPrimitives::new();
@@ -7,11 +7,17 @@ use crate::image::Color;
 
 use constants::*;
 use std::iter::zip;
+use std::sync::OnceLock;
 
 const FIRST_WAVELENGTH: f32 = 400.0;
 const LAST_WAVELENGTH: f32 = 700.0;
 const SPECTRAL_SAMPLES: usize = 60;
 
+/// The number of wavelengths carried along each path in spectral mode. Each is carried in one channel of a Color.
+pub const HERO_WAVELENGTHS: usize = 3;
+
+static CONSTANT_SPECTRA: OnceLock<ConstantSpectra> = OnceLock::new();
+
 
 pub enum SpectrumType {
     Reflectance,
@@ -43,6 +49,8 @@ pub struct ConstantSpectra {
     rgb_illum_to_spect_red: ConstantSpectrum,
     rgb_illum_to_spect_green: ConstantSpectrum,
     rgb_illum_to_spect_blue: ConstantSpectrum,
+    /// Scales illuminants upsampled from RGB so that white light has a luminance of one.
+    illuminant_scale: f32
 }
 
 impl ConstantSpectra {
@@ -77,24 +85,32 @@ impl ConstantSpectra {
             y.coefficients[i] = average_samples(&CIE_Y.to_vec(), &CIE_LAMBDA.to_vec(), from_wavelength, to_wavelength);
             z.coefficients[i] = average_samples(&CIE_Z.to_vec(), &CIE_LAMBDA.to_vec(), from_wavelength, to_wavelength);
 
-            rgb_refl_to_spect_white.coefficients[i] = average_samples(&RGB_2_SPECT_LAMBDA.to_vec(), &RGB_REFL2_SPECT_WHITE.to_vec(), from_wavelength, to_wavelength);
-            rgb_refl_to_spect_cyan.coefficients[i] = average_samples(&RGB_2_SPECT_LAMBDA.to_vec(), &RGB_REFL2_SPECT_CYAN.to_vec(), from_wavelength, to_wavelength);
-            rgb_refl_to_spect_magenta.coefficients[i] = average_samples(&RGB_2_SPECT_LAMBDA.to_vec(), &RGB_REFL2_SPECT_MAGENTA.to_vec(), from_wavelength, to_wavelength);
-            rgb_refl_to_spect_yellow.coefficients[i] = average_samples(&RGB_2_SPECT_LAMBDA.to_vec(), &RGB_REFL2_SPECT_YELLOW.to_vec(), from_wavelength, to_wavelength);
-            rgb_refl_to_spect_red.coefficients[i] = average_samples(&RGB_2_SPECT_LAMBDA.to_vec(), &RGB_REFL2_SPECT_RED.to_vec(), from_wavelength, to_wavelength);
-            rgb_refl_to_spect_green.coefficients[i] = average_samples(&RGB_2_SPECT_LAMBDA.to_vec(), &RGB_REFL2_SPECT_GREEN.to_vec(), from_wavelength, to_wavelength);
-            rgb_refl_to_spect_blue.coefficients[i] = average_samples(&RGB_2_SPECT_LAMBDA.to_vec(), &RGB_REFL2_SPECT_BLUE.to_vec(), from_wavelength, to_wavelength);
+            rgb_refl_to_spect_white.coefficients[i] = average_samples(&RGB_REFL2_SPECT_WHITE.to_vec(), &RGB_2_SPECT_LAMBDA.to_vec(), from_wavelength, to_wavelength);
+            rgb_refl_to_spect_cyan.coefficients[i] = average_samples(&RGB_REFL2_SPECT_CYAN.to_vec(), &RGB_2_SPECT_LAMBDA.to_vec(), from_wavelength, to_wavelength);
+            rgb_refl_to_spect_magenta.coefficients[i] = average_samples(&RGB_REFL2_SPECT_MAGENTA.to_vec(), &RGB_2_SPECT_LAMBDA.to_vec(), from_wavelength, to_wavelength);
+            rgb_refl_to_spect_yellow.coefficients[i] = average_samples(&RGB_REFL2_SPECT_YELLOW.to_vec(), &RGB_2_SPECT_LAMBDA.to_vec(), from_wavelength, to_wavelength);
+            rgb_refl_to_spect_red.coefficients[i] = average_samples(&RGB_REFL2_SPECT_RED.to_vec(), &RGB_2_SPECT_LAMBDA.to_vec(), from_wavelength, to_wavelength);
+            rgb_refl_to_spect_green.coefficients[i] = average_samples(&RGB_REFL2_SPECT_GREEN.to_vec(), &RGB_2_SPECT_LAMBDA.to_vec(), from_wavelength, to_wavelength);
+            rgb_refl_to_spect_blue.coefficients[i] = average_samples(&RGB_REFL2_SPECT_BLUE.to_vec(), &RGB_2_SPECT_LAMBDA.to_vec(), from_wavelength, to_wavelength);
         
-            rgb_illum_to_spect_white.coefficients[i] = average_samples(&RGB_2_SPECT_LAMBDA.to_vec(), &RGB_ILLUM2_SPECT_WHITE.to_vec(), from_wavelength, to_wavelength);
-            rgb_illum_to_spect_cyan.coefficients[i] = average_samples(&RGB_2_SPECT_LAMBDA.to_vec(), &RGB_ILLUM2_SPECT_CYAN.to_vec(), from_wavelength, to_wavelength);
-            rgb_illum_to_spect_magenta.coefficients[i] = average_samples(&RGB_2_SPECT_LAMBDA.to_vec(), &RGB_ILLUM2_SPECT_MAGENTA.to_vec(), from_wavelength, to_wavelength);
-            rgb_illum_to_spect_yellow.coefficients[i] = average_samples(&RGB_2_SPECT_LAMBDA.to_vec(), &RGB_ILLUM2_SPECT_YELLOW.to_vec(), from_wavelength, to_wavelength);
-            rgb_illum_to_spect_red.coefficients[i] = average_samples(&RGB_2_SPECT_LAMBDA.to_vec(), &RGB_ILLUM2_SPECT_RED.to_vec(), from_wavelength, to_wavelength);
-            rgb_illum_to_spect_green.coefficients[i] = average_samples(&RGB_2_SPECT_LAMBDA.to_vec(), &RGB_ILLUM2_SPECT_GREEN.to_vec(), from_wavelength, to_wavelength);
-            rgb_illum_to_spect_blue.coefficients[i] = average_samples(&RGB_2_SPECT_LAMBDA.to_vec(), &RGB_ILLUM2_SPECT_BLUE.to_vec(), from_wavelength, to_wavelength);
+            rgb_illum_to_spect_white.coefficients[i] = average_samples(&RGB_ILLUM2_SPECT_WHITE.to_vec(), &RGB_2_SPECT_LAMBDA.to_vec(), from_wavelength, to_wavelength);
+            rgb_illum_to_spect_cyan.coefficients[i] = average_samples(&RGB_ILLUM2_SPECT_CYAN.to_vec(), &RGB_2_SPECT_LAMBDA.to_vec(), from_wavelength, to_wavelength);
+            rgb_illum_to_spect_magenta.coefficients[i] = average_samples(&RGB_ILLUM2_SPECT_MAGENTA.to_vec(), &RGB_2_SPECT_LAMBDA.to_vec(), from_wavelength, to_wavelength);
+            rgb_illum_to_spect_yellow.coefficients[i] = average_samples(&RGB_ILLUM2_SPECT_YELLOW.to_vec(), &RGB_2_SPECT_LAMBDA.to_vec(), from_wavelength, to_wavelength);
+            rgb_illum_to_spect_red.coefficients[i] = average_samples(&RGB_ILLUM2_SPECT_RED.to_vec(), &RGB_2_SPECT_LAMBDA.to_vec(), from_wavelength, to_wavelength);
+            rgb_illum_to_spect_green.coefficients[i] = average_samples(&RGB_ILLUM2_SPECT_GREEN.to_vec(), &RGB_2_SPECT_LAMBDA.to_vec(), from_wavelength, to_wavelength);
+            rgb_illum_to_spect_blue.coefficients[i] = average_samples(&RGB_ILLUM2_SPECT_BLUE.to_vec(), &RGB_2_SPECT_LAMBDA.to_vec(), from_wavelength, to_wavelength);
         }    
 
-        ConstantSpectra { x, y, z, rgb_refl_to_spect_white, rgb_refl_to_spect_cyan, rgb_refl_to_spect_magenta, rgb_refl_to_spect_yellow, rgb_refl_to_spect_red, rgb_refl_to_spect_green, rgb_refl_to_spect_blue, rgb_illum_to_spect_white, rgb_illum_to_spect_cyan, rgb_illum_to_spect_magenta, rgb_illum_to_spect_yellow, rgb_illum_to_spect_red, rgb_illum_to_spect_green, rgb_illum_to_spect_blue }
+        let mut constant_spectra = ConstantSpectra { x, y, z, rgb_refl_to_spect_white, rgb_refl_to_spect_cyan, rgb_refl_to_spect_magenta, rgb_refl_to_spect_yellow, rgb_refl_to_spect_red, rgb_refl_to_spect_green, rgb_refl_to_spect_blue, rgb_illum_to_spect_white, rgb_illum_to_spect_cyan, rgb_illum_to_spect_magenta, rgb_illum_to_spect_yellow, rgb_illum_to_spect_red, rgb_illum_to_spect_green, rgb_illum_to_spect_blue, illuminant_scale: 1.0 };
+        let white_luminance = SampledSpectrum::from_rgb(Color::new(1.0, 1.0, 1.0), SpectrumType::Illuminant, &constant_spectra).get_y();
+        constant_spectra.illuminant_scale = 1.0 / white_luminance;
+        constant_spectra
+    }
+
+    /// Returns a set of spectra shared by every thread, which is initialised the first time it is used.
+    pub fn global() -> &'static ConstantSpectra {
+        CONSTANT_SPECTRA.get_or_init(ConstantSpectra::init)
     }
 }
 
@@ -122,7 +138,7 @@ impl<'a> SampledSpectrum<'a> {
     ///     
     /// A reference to the matching curves, calculated by MatchingCurve::init_xyz, must be passed into the constructor.
     pub fn new(constant: f32, constant_spectra: &'a ConstantSpectra) -> SampledSpectrum<'a> {
-        let coefficients = SVector::<f32, SPECTRAL_SAMPLES>::repeat(0.0);
+        let coefficients = SVector::<f32, SPECTRAL_SAMPLES>::repeat(constant);
         SampledSpectrum { coefficients, constant_spectra } 
     }
     
@@ -150,15 +166,21 @@ impl<'a> SampledSpectrum<'a> {
                                                                          .map(|(a,b)| (*a, *b))
                                                                          .collect();
         //Sort the sample values from lowest wavelength to highest.
-        sample_dictionary.sort_by(|a,b| a.0.partial_cmp(&b.0).unwrap());
+        sample_dictionary.sort_by(|a,b| a.1.partial_cmp(&b.1).unwrap());
+        let (sample_values, sample_wavelengths): (Vec<f32>, Vec<f32>) = sample_dictionary.into_iter().unzip();
         for i in 0..SPECTRAL_SAMPLES {
-            let from_wavelength = lerp((i as f32) / (SPECTRAL_SAMPLES as f32), FIRST_WAVELENGTH, LAST_WAVELENGTH);
-            let to_wavelength = lerp(((i as f32) + 1.0) / (SPECTRAL_SAMPLES as f32), FIRST_WAVELENGTH, LAST_WAVELENGTH);
+            let from_wavelength = lerp(FIRST_WAVELENGTH, LAST_WAVELENGTH, (i as f32) / (SPECTRAL_SAMPLES as f32));
+            let to_wavelength = lerp(FIRST_WAVELENGTH, LAST_WAVELENGTH, ((i as f32) + 1.0) / (SPECTRAL_SAMPLES as f32));
             spectrum.coefficients[i] = average_samples(&sample_values, &sample_wavelengths, from_wavelength, to_wavelength);
         }
         spectrum
     }
 
+    /// Returns the value of the SPD at the given wavelength, taken from the sample whose range covers it.
+    pub fn value(&self, wavelength: f32) -> f32 {
+        self.coefficients[sample_index(wavelength)]
+    }
+
     pub fn from_coefficients(coefficients: SVector<f32, SPECTRAL_SAMPLES>, constant_spectra: &'a ConstantSpectra) -> SampledSpectrum<'a> {
         SampledSpectrum { coefficients, constant_spectra }
     }
@@ -314,6 +336,102 @@ impl<'a> SampledSpectrum<'a> {
     }
 }
 
+/// The quantities carried along each path by the path tracer.
+#[derive (Copy, Clone, Debug, PartialEq)]
+pub enum ColorMode {
+    /// Red, green and blue radiance, using the colours of the scene directly.
+    Rgb,
+    /// Spectral radiance at a few wavelengths sampled for each path. The colours of the scene are upsampled to spectra,
+    /// and each path's contribution is converted to XYZ and then to RGB.
+    Spectral
+}
+
+/// The wavelengths carried along a single path in spectral mode. A hero wavelength is sampled uniformly over the
+/// visible range, and the others are spaced evenly after it, wrapping around at the end of the range. Values at each
+/// wavelength are stored in the matching channel of a Color, so that they can be carried through the path tracer in
+/// place of RGB values.
+#[derive (Copy, Clone, Debug, PartialEq)]
+pub struct SampledWavelengths {
+    pub lambda: [f32; HERO_WAVELENGTHS]
+}
+
+impl SampledWavelengths {
+    /// Maps a uniform random number in [0, 1) to a set of wavelengths.
+    pub fn sample_hero(u: f64) -> SampledWavelengths {
+        let range = LAST_WAVELENGTH - FIRST_WAVELENGTH;
+        let hero = u as f32 * range;
+        let mut lambda = [0.0; HERO_WAVELENGTHS];
+        for (i, wavelength) in lambda.iter_mut().enumerate() {
+            *wavelength = FIRST_WAVELENGTH + (hero + i as f32 * range / HERO_WAVELENGTHS as f32) % range;
+        }
+        SampledWavelengths { lambda }
+    }
+
+    /// Returns the hero wavelength, which is carried in the first channel.
+    pub fn hero(&self) -> f32 {
+        self.lambda[0]
+    }
+
+    /// Drops the secondary wavelengths from the values carried along a path, for use once the path has taken a
+    /// direction which depends on wavelength. The value at the hero wavelength is scaled so that it alone remains an
+    /// unbiased estimate.
+    pub fn terminate_secondary(&self, values: &Color) -> Color {
+        Color::new(values[0] * HERO_WAVELENGTHS as f64, 0.0, 0.0)
+    }
+
+    /// The density with which each of the wavelengths is sampled.
+    pub fn pdf(&self) -> f32 {
+        1.0 / (LAST_WAVELENGTH - FIRST_WAVELENGTH)
+    }
+
+    /// Upsamples an RGB reflectance, and returns its value at each wavelength. The spectra are normalised so that white
+    /// reflects every wavelength fully.
+    pub fn reflectance(&self, rgb: &Color) -> Color {
+        let constant_spectra = ConstantSpectra::global();
+        let spectrum = SampledSpectrum::from_rgb(*rgb, SpectrumType::Reflectance, constant_spectra);
+        let white = &constant_spectra.rgb_refl_to_spect_white;
+        self.map(|wavelength| spectrum.value(wavelength) / white.coefficients[sample_index(wavelength)])
+    }
+
+    /// Upsamples an RGB illuminant, and returns its value at each wavelength. The spectra are normalised so that white
+    /// light has a luminance of one.
+    pub fn illuminant(&self, rgb: &Color) -> Color {
+        let constant_spectra = ConstantSpectra::global();
+        let spectrum = SampledSpectrum::from_rgb(*rgb, SpectrumType::Illuminant, constant_spectra);
+        self.map(|wavelength| spectrum.value(wavelength) * constant_spectra.illuminant_scale)
+    }
+
+    /// Estimates the XYZ coefficients of a spectrum from its values at each wavelength.
+    pub fn to_xyz(&self, values: &Color) -> [f32; 3] {
+        let constant_spectra = ConstantSpectra::global();
+        let mut xyz = [0.0; 3];
+        for (i, wavelength) in self.lambda.iter().enumerate() {
+            let index = sample_index(*wavelength);
+            let value = values[i] as f32 / (self.pdf() * CIE_Y_INTEGRAL * HERO_WAVELENGTHS as f32);
+            xyz[0] += constant_spectra.x.coefficients[index] * value;
+            xyz[1] += constant_spectra.y.coefficients[index] * value;
+            xyz[2] += constant_spectra.z.coefficients[index] * value;
+        }
+        xyz
+    }
+
+    /// Estimates the RGB coefficients of a spectrum from its values at each wavelength.
+    pub fn to_rgb(&self, values: &Color) -> Color {
+        let rgb = SampledSpectrum::xyz_to_rgb(self.to_xyz(values));
+        Color::new(rgb[0] as f64, rgb[1] as f64, rgb[2] as f64)
+    }
+
+    fn map<F>(&self, f: F) -> Color where F: Fn(f32) -> f32 {
+        Color::new(f(self.lambda[0]) as f64, f(self.lambda[1]) as f64, f(self.lambda[2]) as f64)
+    }
+}
+
+/// Returns the index of the spectral sample whose range of wavelengths covers the given wavelength.
+fn sample_index(wavelength: f32) -> usize {
+    let t = (wavelength - FIRST_WAVELENGTH) / (LAST_WAVELENGTH - FIRST_WAVELENGTH);
+    ((t * SPECTRAL_SAMPLES as f32) as usize).min(SPECTRAL_SAMPLES - 1)
+}
+
 /// Compute the average of the piecewise linear function over the range of wavelengths that each
 /// SPD sample is responsible for. The samples (submitted as two seperate vectors, containing the values for each wavelength)
 /// must be sorted.
@@ -338,12 +456,12 @@ fn average_samples(values: &Vec<f32>, wavelengths: &Vec<f32>, from_wavelength: f
     }
 
     if to_wavelength > *wavelengths.last().unwrap() {
-        sum += values.last().unwrap() * (from_wavelength - wavelengths.last().unwrap())
+        sum += values.last().unwrap() * (to_wavelength - wavelengths.last().unwrap())
     }
 
     // Advance to first relevant wavelength segment
     let mut i = 0;
-    while from_wavelength > values[i + 1] {
+    while from_wavelength > wavelengths[i + 1] {
         i += 1;
     } 
 
@@ -352,7 +470,7 @@ fn average_samples(values: &Vec<f32>, wavelengths: &Vec<f32>, from_wavelength: f
     };
 
     // Loop over wavelength segments and add contributions
-    while i + 1 <= wavelengths.len()  && to_wavelength >= wavelengths[i] {
+    while i + 1 < wavelengths.len()  && to_wavelength >= wavelengths[i] {
         let segment_start = from_wavelength.max(wavelengths[i]);
         let segment_end = to_wavelength.min(wavelengths[i + 1]);
         sum += 0.5 * (interp(segment_start, i) + interp(segment_end, i)) * (segment_end - segment_start);
@@ -361,3 +479,54 @@ fn average_samples(values: &Vec<f32>, wavelengths: &Vec<f32>, from_wavelength: f
 
     sum / (to_wavelength - from_wavelength)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_average_samples(){
+        let values = vec![0.0, 1.0];
+        let wavelengths = vec![400.0, 500.0];
+
+        //Case 1: The range covers the samples exactly
+        assert_eq!(average_samples(&values, &wavelengths, 400.0, 500.0), 0.5);
+
+        //Case 2: The range lies outside the samples, so the nearest sample is used
+        assert_eq!(average_samples(&values, &wavelengths, 300.0, 400.0), 0.0);
+        assert_eq!(average_samples(&values, &wavelengths, 500.0, 600.0), 1.0);
+
+        //Case 3: The range extends beyond the last sample
+        assert_eq!(average_samples(&values, &wavelengths, 450.0, 550.0), 0.875);
+    }
+
+    #[test]
+    fn test_from_rgb(){
+        let constant_spectra = ConstantSpectra::global();
+        let rgb = Color::new(0.2, 0.5, 0.8);
+        let spectrum = SampledSpectrum::from_rgb(rgb, SpectrumType::Illuminant, constant_spectra);
+        let round_trip = spectrum.get_rgb();
+        for i in 0..3 {
+            assert!((round_trip[i] as f64 - rgb[i]).abs() < 0.1);
+        }
+    }
+
+    #[test]
+    fn test_sampled_wavelengths(){
+        //Case 1: The wavelengths are evenly spaced, wrapping around the end of the range
+        let wavelengths = SampledWavelengths::sample_hero(0.5);
+        assert_eq!(wavelengths.lambda, [550.0, 650.0, 450.0]);
+
+        //Case 2: White surfaces reflect every wavelength, and black surfaces none
+        assert_eq!(wavelengths.reflectance(&Color::new(1.0, 1.0, 1.0)), Color::new(1.0, 1.0, 1.0));
+        assert_eq!(wavelengths.reflectance(&Color::new(0.0, 0.0, 0.0)), Color::new(0.0, 0.0, 0.0));
+
+        //Case 3: White light averaged over the spectrum is white
+        let n = 300;
+        let mean = (0..n).fold(Color::zeros(), |sum, i| {
+            let wavelengths = SampledWavelengths::sample_hero((i as f64 + 0.5) / n as f64);
+            sum + wavelengths.to_rgb(&wavelengths.illuminant(&Color::new(1.0, 1.0, 1.0)))
+        }) / n as f64;
+        assert!((mean - Color::new(1.0, 1.0, 1.0)).norm() < 0.02);
+    }
+}
@@ -1,14 +1,24 @@
+pub mod tiles;
+
 use crate::*;
 use crate::camera::Camera;
 use crate::image::CompositeImage;
 use crate::image::Raster;
-use crate::image::RaytracedImage;
 use crate::scenes::SceneData;
+use crate::raytracing::MisHeuristic;
+use crate::integrators::Integrator;
+use crate::spectra::ColorMode;
+use crate::sampler::{Sampler, SamplerType};
+use crate::film::{Film, Filter, FilterType};
+use crate::lpe::RenderLayer;
+
+use self::tiles::{TileRender, Work};
 
 use std::sync::Arc;
 use std::sync::Condvar;
 use std::sync::Mutex;
 use std::sync::RwLock;
+use std::sync::atomic::{AtomicI32, Ordering};
 use std::sync::mpsc::*;
 use std::thread;
 
@@ -18,11 +28,13 @@ pub struct ImageSettings {
     pub image_height: usize,
 }
 
+/// The rasterized images, along with the id of the settings they were rendered with. The raytraced image is held 
+/// separately, by the `TileRender` of the current settings, and is left empty here.
 #[derive (Clone)]
 pub struct TrackedCompositeImage {
     pub image: CompositeImage,
     pub id: i32,
-    pub rasterization_samples: usize,
+    pub rasterization_samples: usize
 }
 
 impl TrackedCompositeImage {
@@ -31,16 +43,16 @@ impl TrackedCompositeImage {
         TrackedCompositeImage { image, id, rasterization_samples: 0 }
     }
 
-    pub fn from_image(image: CompositeImage, rasterization_samples: usize, raytracing_samples: usize, id: i32) -> TrackedCompositeImage {
-        TrackedCompositeImage { image, id, rasterization_samples}
-    }
-
     pub fn is_finished_rasterizing(&self, desired_id: i32, desired_rasterization_samples: usize) -> bool{
         self.id >= desired_id && self.rasterization_samples >= desired_rasterization_samples
     }
 
-    pub fn is_finished_raytracing(&self, desired_id: i32, desired_raytracing_samples: usize) -> bool{
-        self.id >= desired_id && self.image.raytrace.samples >= desired_raytracing_samples
+    /// Discards the rasterized images, if they were rendered with older settings than those with the given id.
+    fn reset(&mut self, id: i32) {
+        if id > self.id {
+            self.rasterization_samples = 0;
+            self.id = id;
+        }
     }
 
     pub fn add_outline(&mut self, outline: Raster, id: i32) {
@@ -48,47 +60,75 @@ impl TrackedCompositeImage {
             panic!("Image dimensions do not match");
         }
 
-        if id > self.id {
-            self.rasterization_samples = 0;
-            self.image.raytrace.samples = 0;
-            self.id = id;
-        }
-
+        self.reset(id);
         if self.rasterization_samples == 0 {
             self.image.outline = outline;
             self.rasterization_samples = 1;
         }
     }
+}
 
-    pub fn add_raytraced_sample(&mut self, sample: RaytracedImage, id: i32, max_samples: usize) {
-        if self.image.image_height != sample.image.image_height || self.image.image_width != sample.image.image_width {
-            panic!("Image dimensions do not match");
-        }
 
-        if self.image.raytrace.samples >= max_samples {
-            return;
-        }
 
-        if id > self.id {
-            self.rasterization_samples = 0;
-            self.image.raytrace.samples = 1;
-            self.id = id;
-            self.image.raytrace = sample;
-        } else if self.image.raytrace.samples == 0 && id == self.id {
-            self.image.raytrace = sample;
-            self.image.raytrace.samples = 1;
-        } else if self.image.raytrace.samples > 0 && id == self.id {
-            self.image.raytrace += &sample;
-        }
-    }
+#[derive (Copy, Clone, Debug, PartialEq)]
+pub struct RayTraceSettings {
+    /// The maximum number of bounces a path may take before it is terminated.
+    pub max_depth: i32,
+    pub samples_per_pixel: usize,
+    /// The number of bounces after which paths become eligible for Russian roulette termination.
+    pub rr_start_depth: i32,
+    /// The heuristic used to combine light sampling with material sampling.
+    pub mis_heuristic: MisHeuristic,
+    /// The algorithm used to estimate the light arriving at each pixel.
+    pub integrator: Integrator,
+    /// Whether the path tracer carries RGB or spectral radiance. The other integrators always render in RGB.
+    pub color_mode: ColorMode,
+    /// The number of photons shot from the lights on each pass when photon mapping.
+    pub photon_count: usize,
+    /// The radius within which photons are gathered. Progressive photon mapping starts from this radius.
+    pub gather_radius: f64,
+    /// The number of occlusion rays cast from each surface seen by the camera when rendering ambient occlusion.
+    pub ao_samples: usize,
+    /// The distance beyond which surfaces no longer occlude each other when rendering ambient occlusion.
+    pub ao_distance: f64,
+    /// The estimated error, after gamma correction, below which a pixel stops being sampled. Pixels which stay noisier
+    /// are sampled up to `samples_per_pixel` times. Zero samples every pixel equally.
+    pub noise_threshold: f64,
+    /// The sampler which generates the camera ray of each sample, and the directions the path tracer scatters in.
+    pub sampler: SamplerType,
+    /// Seeds every random number used to render. Renders with the same settings and seed are identical, however many
+    /// threads render them.
+    pub seed: u64,
+    /// The filter which spreads each sample over the pixels around it.
+    pub filter: Filter
 }
 
+impl RayTraceSettings {
+    /// Returns true if pixels stop being sampled once they converge. Bidirectional path tracing splats light onto
+    /// every pixel on each pass, so it always samples every pixel.
+    pub fn is_adaptive(&self) -> bool {
+        self.noise_threshold > 0.0 && self.integrator != Integrator::Bidirectional
+    }
+}
 
+#[cfg(test)]
+impl RayTraceSettings {
+    /// Returns settings which take a single sample in every pixel, from an independent sampler through a box filter.
+    /// Tests change only the settings they depend on.
+    pub fn for_test() -> RayTraceSettings {
+        RayTraceSettings { samples_per_pixel: 1, noise_threshold: 0.0, sampler: SamplerType::Independent, filter: Filter::default(),
+                           ..RayTraceSettings::default() }
+    }
+}
 
-#[derive (Copy, Clone)]
-pub struct RayTraceSettings {
-    pub max_depth: i32,
-    pub samples_per_pixel: usize
+impl Default for RayTraceSettings {
+    /// Returns the settings the window opens with.
+    fn default() -> RayTraceSettings {
+        RayTraceSettings { max_depth: 50, samples_per_pixel: 1000, rr_start_depth: 5, mis_heuristic: MisHeuristic::Power,
+                           integrator: Integrator::PathTracing, color_mode: ColorMode::Rgb, photon_count: 100000,
+                           gather_radius: 0.1, ao_samples: 16, ao_distance: 1.0, noise_threshold: 0.01,
+                           sampler: SamplerType::Sobol, seed: 0, filter: Filter::new(FilterType::Gaussian, 1.5) }
+    }
 }
 
 #[derive (Clone)]
@@ -97,6 +137,8 @@ pub struct GlobalSettings {
     pub image_settings: ImageSettings,
     pub camera: Camera,
     pub scene: SceneData,
+    /// The render layers the path tracer sorts light into, alongside the raytraced image.
+    pub layers: Vec<RenderLayer>,
     pub id: i32
 }
 
@@ -108,7 +150,6 @@ pub struct LocalSettings {
 
 #[derive (Copy, Clone)]
 pub enum Instructions {
-    NewTask,
     Terminate
 }
 
@@ -117,7 +158,16 @@ pub struct ThreadCoordinator {
     pub gui_to_thread_txs: Vec<Sender<Instructions>>,
     pub global_settings: Arc<RwLock<GlobalSettings>>,
     pub local_settings: Vec<Arc<RwLock<LocalSettings>>>,
-    pub image: Arc<(Mutex<TrackedCompositeImage>, Condvar)>
+    pub image: Arc<(Mutex<TrackedCompositeImage>, Condvar)>,
+    /// The tiles of the raytraced image for the current settings, which are added to without taking the lock on `image`.
+    pub render: Arc<RwLock<Arc<TileRender>>>,
+    /// The id of the settings currently being rendered. Threads check it after every tile, and discard tiles which were
+    /// rendered with settings that have since changed.
+    pub epoch: Arc<AtomicI32>,
+    /// The number of threads which raytrace.
+    pub workers: usize,
+    /// The threads started by `spin_up`, which only stop on their own if they panic.
+    pub threads: Vec<thread::JoinHandle<()>>
 }
 
 impl ThreadCoordinator {
@@ -130,11 +180,19 @@ impl ThreadCoordinator {
         let image_height = initial_settings.image_settings.image_height;
         let image_width = initial_settings.image_settings.image_width;
         let image = Arc::new((Mutex::new(TrackedCompositeImage::new(image_width, image_height, 0)), Condvar::new()));
+        let workers = 1;
+        let render = Arc::new(RwLock::new(Arc::new(TileRender::new(&initial_settings, workers))));
+        let epoch = Arc::new(AtomicI32::new(initial_settings.id));
+        let threads = vec![];
 
-        ThreadCoordinator {gui_to_thread_txs, global_settings, local_settings, image }
+        ThreadCoordinator {gui_to_thread_txs, global_settings, local_settings, image, render, epoch, workers, threads }
     }
 
+    /// Starts the given number of threads. The first rasterizes, and the others raytrace.
     pub fn spin_up(&mut self, num_threads: usize) {
+        self.workers = num_threads.saturating_sub(1).max(1);
+        let settings = self.global_settings.read().unwrap().clone();
+        *self.render.write().unwrap() = Arc::new(TileRender::new(&settings, self.workers));
         
         for i in 0..num_threads {
             let mut raytracing = true;
@@ -149,80 +207,125 @@ impl ThreadCoordinator {
             let image = Arc::clone(&self.image);
             let global_settings = Arc::clone(&self.global_settings);
             let local_settings = self.local_settings[i].clone();
+            let render = Arc::clone(&self.render);
+            let epoch = Arc::clone(&self.epoch);
+            let worker = i.saturating_sub(1);
             self.gui_to_thread_txs.push(gui_to_thread_tx);
-            thread::spawn(move || run_thread(global_settings, local_settings, image,  gui_to_thread_rx));
+            self.threads.push(thread::spawn(move || run_thread(global_settings, local_settings, image, render, epoch, worker, gui_to_thread_rx)));
         }
     }
 
-    /// Returns the number of raytracing samples completed.
+    /// Returns an error if any thread has stopped before being told to terminate, which means that it panicked and the
+    /// render will never finish. The stopped thread is joined, and its panic message is included in the error.
+    pub fn check_threads(&mut self) -> Result<(), String> {
+        let stopped = match self.threads.iter().position(|thread| thread.is_finished()) {
+            Some(index) => self.threads.remove(index),
+            None => return Ok(())
+        };
+        let payload = match stopped.join() {
+            Ok(()) => return Err(String::from("A rendering thread stopped unexpectedly")),
+            Err(payload) => payload
+        };
+        let message = payload.downcast_ref::<&str>().map(|message| message.to_string())
+            .or_else(|| payload.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| String::from("unknown error"));
+        Err(format!("A rendering thread panicked: {}", message))
+    }
+
+    /// Returns the number of raytracing samples completed in every pixel.
     pub fn get_progress(&self) -> usize {
-        let image = self.image.0.lock().unwrap();
-        image.image.raytrace.samples
+        self.render.read().unwrap().progress()
     }
 
+    /// Sends the instructions to every thread, and wakes any which are waiting so that they read them.
     pub fn transmit_instructions(&mut self, instructions: Instructions) {
         let mut threads_to_remove = vec!();
         for (index, transmitter, ) in self.gui_to_thread_txs.iter().enumerate() {
-            let thread =  self.local_settings[index].read().unwrap();
             if transmitter.send(instructions).is_err() {
                 threads_to_remove.push(index);
             }
         }
-        for index in threads_to_remove {
+        for index in threads_to_remove.into_iter().rev() {
             self.gui_to_thread_txs.remove(index);
         }
+        let _image = self.image.0.lock().unwrap();
+        self.image.1.notify_all();
     }
 
+    /// Starts rendering the image again from scratch, with the same settings.
     pub fn refresh_image(&mut self) {
-        let mut settings = self.global_settings.read().unwrap().clone();
-        settings.id += 1;
-        self.transmit_instructions(Instructions::NewTask);
-        //let mut image = self.image.0.lock().unwrap();
-        //image.id += 1;
-        //image.rasterization_samples = 0;
-        //image.raytracing_samples = 0;
+        let settings = self.global_settings.read().unwrap().clone();
+        self.update_settings(settings);
     }
 
+    /// Changes the number of samples taken in each pixel. The image is kept when the number is raised, and rendered 
+    /// again when it is lowered.
     pub fn update_samples(&mut self, samples: usize) {
-        let settings = self.global_settings.read().unwrap().clone();
+        let mut settings = self.global_settings.read().unwrap().clone();
         if samples < settings.raytrace_settings.samples_per_pixel {
-            self.refresh_image();
-        } else {
-            let mut settings = self.global_settings.write().unwrap();
             settings.raytrace_settings.samples_per_pixel = samples;
+            self.update_settings(settings);
+        } else {
+            self.global_settings.write().unwrap().raytrace_settings.samples_per_pixel = samples;
+            //The count is raised after the settings are written, and under the lock, so a thread which finished with the
+            //old number of samples either sees the new count before it waits or is woken
+            let _image = self.image.0.lock().unwrap();
+            self.render.read().unwrap().samples_raised.fetch_add(1, Ordering::AcqRel);
+            self.image.1.notify_all();
         }
     }
 
+    /// Replaces the settings, and starts rendering the image with them. Threads finish the tile they are rendering 
+    /// before they move on.
     pub fn update_settings(&mut self, mut new_settings: GlobalSettings) {
         let mut settings = self.global_settings.write().unwrap();
         new_settings.id = settings.id + 1;
+        *self.render.write().unwrap() = Arc::new(TileRender::new(&new_settings, self.workers));
+        self.epoch.store(new_settings.id, Ordering::Release);
         *settings = new_settings;
+        drop(settings);
+
+        //The lock is taken so that no thread can miss the notification between checking the epoch and waiting
+        let _image = self.image.0.lock().unwrap();
         self.image.1.notify_all();
     }
 
     pub fn is_done(&self) -> bool {
-        let settings = self.global_settings.read().unwrap();
+        let settings = self.global_settings.read().unwrap().clone();
+        let render = Arc::clone(&self.render.read().unwrap());
         let image = self.image.0.lock().unwrap();
-        image.image.raytrace.samples >= settings.raytrace_settings.samples_per_pixel && image.rasterization_samples >= 1 && image.id == settings.id
+        let rasterized = image.rasterization_samples >= 1 && image.id == settings.id;
+        drop(image);
+        rasterized && render.id == settings.id && render.is_finished(&settings.raytrace_settings)
     }
 
+    /// Returns the rasterized images, along with the raytraced image gathered from its tiles.
     pub fn output_image(&self) -> CompositeImage {
-        let image = self.image.0.lock().unwrap();
-        image.image.clone()
+        let mut image = self.image.0.lock().unwrap().image.clone();
+        image.raytrace = self.render.read().unwrap().image();
+        image
     }
 }
 
 
- pub fn run_thread(global_settings: Arc<RwLock<GlobalSettings>>, local_settings: Arc<RwLock<LocalSettings>>, image_data: Arc<(Mutex<TrackedCompositeImage>, Condvar)>, coordinator_to_thread_rx: Receiver<Instructions>) {
+/// Renders whatever the settings currently require, until told to terminate. Raytracing threads take passes over 
+/// tiles from the current `TileRender`, using the queue with the given index as their own.
+pub fn run_thread(global_settings: Arc<RwLock<GlobalSettings>>, local_settings: Arc<RwLock<LocalSettings>>, image_data: Arc<(Mutex<TrackedCompositeImage>, Condvar)>,
+                  render: Arc<RwLock<Arc<TileRender>>>, epoch: Arc<AtomicI32>, worker: usize, coordinator_to_thread_rx: Receiver<Instructions>) {
 
     let mut terminated = false;
 
     while !terminated {
+        if let Ok(Instructions::Terminate) = coordinator_to_thread_rx.try_recv() {
+            terminated = true;
+            continue;
+        }
+        //Read before the settings, so that the number of samples being raised after they are read is noticed
+        let samples_raised = render.read().unwrap().samples_raised.load(Ordering::Acquire);
         let global_settings = global_settings.read().unwrap().clone();
         let settings_id = global_settings.id;
         let local_settings = local_settings.read().unwrap().clone();
         let cond_var = &image_data.1;
-        let desired_raytracing_samples = global_settings.raytrace_settings.samples_per_pixel;
         let desired_rasterization_samples = 1;
         let image = image_data.0.lock().unwrap();
         if !image.is_finished_rasterizing(settings_id, desired_rasterization_samples) && local_settings.rasterizing == true {
@@ -232,14 +335,42 @@ impl ThreadCoordinator {
                 image.add_outline(contribution, settings_id);
             }
             
-       } else if !image.is_finished_raytracing(settings_id, desired_raytracing_samples) && local_settings.raytracing == true {
+        } else if local_settings.raytracing == true {
             drop(image);
-            if let Some(contribution) = raytrace(global_settings, &coordinator_to_thread_rx) {
-                let mut image = image_data.0.lock().unwrap();
-                image.add_raytraced_sample(contribution, settings_id, desired_raytracing_samples);
+            let render = Arc::clone(&render.read().unwrap());
+            if render.id != settings_id {
+                continue;
+            }
+            let handed_back = render.handed_back.load(Ordering::Acquire);
+            match render.next_work(worker, &global_settings.raytrace_settings) {
+                Work::Tile(tile, pass, active_pixels) => {
+                    let film = raytrace_tile(&global_settings, &render, tile, pass, &active_pixels, &epoch);
+                    if epoch.load(Ordering::Acquire) == render.id {
+                        render.finish_pass(tile, pass, film, &global_settings.raytrace_settings);
+                    }
+                    render.queues.push(worker, tile);
+                    render.handed_back.fetch_add(1, Ordering::AcqRel);
+                    let _image = image_data.0.lock().unwrap();
+                    cond_var.notify_all();
+                }
+                Work::Waiting => {
+                    //Work can only appear when another thread hands a pass back, or the settings change, and both notify
+                    //while holding the lock, so neither can be missed once it is taken
+                    let image = image_data.0.lock().unwrap();
+                    if render.handed_back.load(Ordering::Acquire) == handed_back && epoch.load(Ordering::Acquire) == settings_id {
+                        drop(cond_var.wait(image));
+                    }
+                }
+                Work::Finished => {
+                    //More work can only appear when the number of samples is raised or the settings change
+                    let image = image_data.0.lock().unwrap();
+                    if render.samples_raised.load(Ordering::Acquire) == samples_raised && epoch.load(Ordering::Acquire) == settings_id {
+                        drop(cond_var.wait(image));
+                    }
+                }
             }
         } else {
-            cond_var.wait(image);
+            drop(cond_var.wait(image));
         }
     }
  }
@@ -255,24 +386,162 @@ impl ThreadCoordinator {
  }
 
 
-pub fn raytrace(settings: GlobalSettings, gui_to_thread_rx: &Receiver<Instructions>) -> Option<RaytracedImage> {
+/// Traces a single sample through every pixel of the tile with the given index which is marked in `active_pixels`, 
+/// and returns the film they were filtered onto, or `None` if no pixel was marked. `pass` is the index of the pass, 
+/// which is the index of the sample drawn in each pixel, and which is used to refine the gather radius of progressive
+/// photon mapping. Every random number used by a pixel is drawn from the sample of the pass in that pixel, from a 
+/// sampler seeded by the seed of the render, so that the pass is the same whichever thread renders it.
+/// 
+/// `epoch` is checked between rows, and the tile is abandoned with `None` as soon as the settings change, since its
+/// film would be discarded anyway.
+pub fn raytrace_tile(settings: &GlobalSettings, render: &TileRender, tile: usize, pass: usize, active_pixels: &[bool], epoch: &AtomicI32) -> Option<Film> {
+    if !active_pixels.iter().any(|active| *active) {
+        return None;
+    }
 
     let image_height = settings.image_settings.image_height;
     let image_width = settings.image_settings.image_width;
+    let (region, tile) = (render.region(tile), render.tile(tile));
 
-    let mut raytrace = RaytracedImage::new(image_width, image_height);
-    raytrace.samples = 1;
+    let mut film = Film::for_region(image_width, image_height, region, settings.raytrace_settings.filter, &settings.layers, render.light_groups());
     let cam = settings.camera;
-    for j in 0..image_height {
-        for i in 0..image_width {
-            if let Ok(instructions) = gui_to_thread_rx.try_recv() {
-                match instructions {
-                    Instructions::Terminate => return None,
-                    Instructions::NewTask => return None,
-                }
+    let photon_map = render.photon_map(pass, settings);
+    let mut sampler = settings.raytrace_settings.sampler.sampler(settings.raytrace_settings.samples_per_pixel, settings.raytrace_settings.seed);
+    for j in tile.y..tile.y + tile.height {
+        if epoch.load(Ordering::Acquire) != render.id {
+            return None;
+        }
+        for i in tile.x..tile.x + tile.width {
+            if !active_pixels[tile.local_index(i, j)] {
+                continue;
             }
-            raytrace = raytracing::raytrace_pixel(raytrace, cam, settings.scene.background, &settings.scene.raytracing_primitives, settings.raytrace_settings.max_depth, (i, j));
+            sampler.start_pixel_sample((i, j), pass, 0);
+            raytracing::raytrace_pixel(&mut film, cam, &settings.scene, &settings.raytrace_settings, photon_map.as_deref(), (i, j), &mut sampler);
         }
     }
-    Some(raytrace)
-}
\ No newline at end of file
+    Some(film)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::camera::CameraSettings;
+    use crate::film::FilterType;
+    use crate::image::RaytracedImage;
+    use crate::lights::Lights;
+    use crate::nalgebra::Vector3;
+    use crate::primitives::{Primitive, Primitives};
+    use crate::scenes;
+    use std::time::{Duration, Instant};
+
+    fn render(num_threads: usize, settings: &GlobalSettings) -> RaytracedImage {
+        let mut thread_coordinator = ThreadCoordinator::new(settings.clone());
+        thread_coordinator.spin_up(num_threads);
+        let deadline = Instant::now() + Duration::from_secs(120);
+        while !thread_coordinator.is_done() {
+            assert!(Instant::now() < deadline, "The render did not finish in time");
+            thread::sleep(Duration::from_millis(5));
+        }
+        thread_coordinator.transmit_instructions(Instructions::Terminate);
+        thread_coordinator.output_image().raytrace
+    }
+
+    /// Returns the settings of a small render of a randomly placed scene, which is wide enough to be split into two
+    /// tiles which filter samples onto each other.
+    fn settings(seed: u64) -> GlobalSettings {
+        let (image_width, image_height) = (36, 8);
+        let (geometric_primitives, background, look_from, look_at) = scenes::sphere_world(seed);
+        let mut raytracing_primitives = Primitives::new();
+        raytracing_primitives.add(Primitive::new_bvh(geometric_primitives.clone().to_bvh()));
+        let lights = Lights::from_primitives(&geometric_primitives);
+        let scene = SceneData { raytracing_primitives, rasterization_primitives: geometric_primitives, lights, background, atmosphere: None, light_groups: Vec::new() };
+        let camera = Camera::new(CameraSettings { look_from, look_at, aspect_ratio: 4.5, aperture: 0.1, image_height, image_width, ..CameraSettings::default() });
+        let raytrace_settings = RayTraceSettings { max_depth: 10, samples_per_pixel: 40, rr_start_depth: 3, noise_threshold: 0.05, seed, filter: Filter::new(FilterType::Mitchell, 2.0), ..RayTraceSettings::for_test() };
+        GlobalSettings { raytrace_settings, image_settings: ImageSettings { image_width, image_height }, camera, scene, layers: RenderLayer::defaults(), id: 1 }
+    }
+
+    #[test]
+    fn test_reproducible(){
+        //Case 1: The same seed renders the same image, with any number of raytracing threads
+        let single_thread = render(2, &settings(3));
+        let many_threads = render(5, &settings(3));
+        assert!(single_thread.image == many_threads.image);
+        assert_eq!(single_thread.pixel_samples, many_threads.pixel_samples);
+
+        //Case 2: Adaptive sampling stopped some pixels early
+        assert!(single_thread.pixel_samples.iter().any(|samples| *samples < 40));
+
+        //Case 3: A different seed renders a different image
+        let reseeded = render(2, &settings(4));
+        assert!(single_thread.image != reseeded.image);
+    }
+
+    #[test]
+    fn test_update_settings(){
+        //Case 1: Changing the settings part way through a render gives the same image as rendering the new settings from
+        //the start. There are more workers than tiles, so most of them have to steal their tiles from the others
+        let mut old = settings(3);
+        old.raytrace_settings.samples_per_pixel = 100000;
+        old.raytrace_settings.noise_threshold = 0.0;
+        let new = settings(4);
+        let mut thread_coordinator = ThreadCoordinator::new(old);
+        thread_coordinator.spin_up(6);
+        let deadline = Instant::now() + Duration::from_secs(120);
+        while thread_coordinator.get_progress() == 0 {
+            assert!(Instant::now() < deadline, "The first render did not start in time");
+            thread::sleep(Duration::from_millis(1));
+        }
+        thread_coordinator.update_settings(new.clone());
+        while !thread_coordinator.is_done() {
+            assert!(Instant::now() < deadline, "The render did not finish in time after the settings changed");
+            thread::sleep(Duration::from_millis(5));
+        }
+        thread_coordinator.transmit_instructions(Instructions::Terminate);
+        let changed = thread_coordinator.output_image().raytrace;
+        let expected = render(2, &new);
+        assert!(changed.image == expected.image);
+        assert_eq!(changed.pixel_samples, expected.pixel_samples);
+
+        //Case 2: A tile is abandoned once its settings are out of date
+        let tiles = TileRender::new(&new, 1);
+        let active_pixels = vec![true; tiles.tile(0).width * tiles.tile(0).height];
+        assert!(raytrace_tile(&new, &tiles, 0, 0, &active_pixels, &AtomicI32::new(new.id)).is_some());
+        assert!(raytrace_tile(&new, &tiles, 0, 0, &active_pixels, &AtomicI32::new(new.id + 1)).is_none());
+
+        //Case 3: Raising the number of samples once the render has finished wakes the threads, which carry on with it
+        let mut few = settings(5);
+        few.raytrace_settings.samples_per_pixel = 4;
+        few.raytrace_settings.noise_threshold = 0.0;
+        let mut thread_coordinator = ThreadCoordinator::new(few);
+        thread_coordinator.spin_up(3);
+        let deadline = Instant::now() + Duration::from_secs(120);
+        for samples in [4, 8] {
+            while !thread_coordinator.is_done() {
+                assert!(Instant::now() < deadline, "The render did not finish in time");
+                thread::sleep(Duration::from_millis(5));
+            }
+            assert_eq!(thread_coordinator.get_progress(), samples);
+            thread_coordinator.update_samples(8);
+        }
+        thread_coordinator.transmit_instructions(Instructions::Terminate);
+    }
+
+    #[test]
+    fn test_check_threads(){
+        //Case 1: No threads have stopped
+        let mut thread_coordinator = ThreadCoordinator::new(settings(3));
+        thread_coordinator.spin_up(2);
+        assert_eq!(thread_coordinator.check_threads(), Ok(()));
+
+        //Case 2: A thread which panicked is reported with its message, rather than being waited on forever
+        thread_coordinator.threads.push(thread::spawn(|| panic!("Out of memory")));
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while !thread_coordinator.threads.last().unwrap().is_finished() {
+            assert!(Instant::now() < deadline, "The thread did not stop in time");
+            thread::sleep(Duration::from_millis(5));
+        }
+        assert_eq!(thread_coordinator.check_threads(), Err(String::from("A rendering thread panicked: Out of memory")));
+        assert_eq!(thread_coordinator.threads.len(), 2);
+        thread_coordinator.transmit_instructions(Instructions::Terminate);
+    }
+}
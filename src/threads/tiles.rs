@@ -0,0 +1,339 @@
+use crate::film::{Film, Tile};
+use crate::image::{Color, RaytracedImage, MIN_ADAPTIVE_SAMPLES};
+use crate::integrators::Integrator;
+use crate::integrators::photon_mapping::PhotonMap;
+use super::{GlobalSettings, RayTraceSettings};
+
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// The width and height of the tiles the image is split into.
+pub const TILE_SIZE: usize = 32;
+
+/// Queues of tiles waiting to be rendered, one for each worker. Workers take tiles from the front of their own queue,
+/// and once it runs dry, steal them from the back of the others'.
+pub struct WorkQueues {
+    queues: Vec<Mutex<VecDeque<usize>>>
+}
+
+impl WorkQueues {
+    /// Deals the tiles out between the queues of the given number of workers in turn.
+    pub fn new(workers: usize, tile_count: usize) -> WorkQueues {
+        let workers = workers.max(1);
+        let mut queues = vec![VecDeque::new(); workers];
+        for tile in 0..tile_count {
+            queues[tile % workers].push_back(tile);
+        }
+        WorkQueues { queues: queues.into_iter().map(Mutex::new).collect() }
+    }
+
+    /// Takes the tile at the front of the worker's own queue, or if it is empty, steals the tile at the back of the next
+    /// queue which is not. Returns `None` if every queue is empty.
+    pub fn pop(&self, worker: usize) -> Option<usize> {
+        let count = self.queues.len();
+        (0..count).find_map(|offset| {
+            let mut queue = self.queues[(worker + offset) % count].lock().unwrap();
+            if offset == 0 { queue.pop_front() } else { queue.pop_back() }
+        })
+    }
+
+    /// Puts a tile at the back of the worker's own queue.
+    pub fn push(&self, worker: usize, tile: usize) {
+        self.queues[worker % self.queues.len()].lock().unwrap().push_back(tile);
+    }
+}
+
+/// The work handed to a raytracing thread.
+pub enum Work {
+    /// A pass over the tile with the given index, along with the pass and the pixels of the tile it should sample.
+    Tile(usize, usize, Vec<bool>),
+    /// Every tile with passes left is waiting for earlier passes, or being rendered by another thread.
+    Waiting,
+    /// Every pass of every tile has been handed out.
+    Finished
+}
+
+/// The passes which have been added to a tile.
+struct TileFilm {
+    image: RaytracedImage,
+    /// The number of passes over the tile which have been handed out, whether or not they have finished.
+    passes_started: usize,
+    /// The number of passes after which each pixel converged, or `usize::MAX` for pixels which still need sampling.
+    converged_at: Vec<usize>
+}
+
+/// A finished pass over a tile, which is kept until it has been added to every tile its samples reach.
+struct TileResult {
+    /// The light filtered onto the pixels around the tile, or `None` if the pass sampled none of its pixels.
+    image: Option<RaytracedImage>,
+    region: Tile,
+    /// Light splatted onto other tiles, by the index of the tile and of the pixel within it.
+    splats: BTreeMap<usize, Vec<(usize, Color)>>,
+    /// The number of tiles it has been added to.
+    consumed: AtomicUsize
+}
+
+struct TileState {
+    film: Mutex<TileFilm>,
+    /// Finished passes over the tile, by the index of the pass.
+    results: Mutex<BTreeMap<usize, Arc<TileResult>>>
+}
+
+/// The photon map of a pass, which is traced by whichever tile needs it first.
+type SharedPhotonMap = Arc<OnceLock<Arc<PhotonMap>>>;
+
+/// The raytraced image for one version of the settings, split into tiles which are rendered one pass at a time.
+///
+/// Each tile has a lock of its own, so that any number of tiles can be added to at once. The samples of a pass over a
+/// tile are filtered onto the pixels around it, and may be splatted anywhere when tracing paths from the lights, so a
+/// pass over a tile is only added to it once every tile which could reach it has finished the same pass. Passes are
+/// added in order, and the tiles reaching each tile are added in order of their index, so the image is the same
+/// however many threads rendered it, and in whichever order.
+///
+/// When sampling adaptively, which pixels of a tile a pass samples is decided at checkpoints every
+/// `MIN_ADAPTIVE_SAMPLES` passes. A pass past a checkpoint cannot start until every pass before the checkpoint has been
+/// added to the tile. Tiles which have converged still take empty passes, so that the tiles around them can go on.
+pub struct TileRender {
+    /// The id of the settings being rendered.
+    pub id: i32,
+    image_width: usize,
+    image_height: usize,
+    tiles: Vec<Tile>,
+    tiles_across: usize,
+    /// The number of pixels around a tile which the samples taken in it may be filtered onto.
+    margin: usize,
+    /// The tiles whose passes reach each tile, in order of their index.
+    sources: Vec<Vec<usize>>,
+    states: Vec<TileState>,
+    pub queues: WorkQueues,
+    /// The number of passes which threads have handed back, after finishing them or abandoning them. Threads waiting
+    /// for work compare it before and after they look, so that they do not sleep through a pass being handed back.
+    pub handed_back: AtomicUsize,
+    /// The number of times the number of samples has been raised, which carries on with the same render. Threads which
+    /// have finished compare it in the same way as `handed_back`, so that they do not sleep through a raise.
+    pub samples_raised: AtomicUsize,
+    light_groups: Vec<String>,
+    /// The photon map traced for each pass, along with the number of tiles yet to finish the pass.
+    photon_maps: Mutex<BTreeMap<usize, (SharedPhotonMap, usize)>>
+}
+
+impl TileRender {
+    pub fn new(settings: &GlobalSettings, workers: usize) -> TileRender {
+        let (image_width, image_height) = (settings.image_settings.image_width, settings.image_settings.image_height);
+        let tiles = Tile::split(image_width, image_height, TILE_SIZE);
+        let margin = Film::margin(&settings.raytrace_settings.filter);
+        let splats_anywhere = settings.raytrace_settings.integrator == Integrator::Bidirectional;
+        let sources = tiles.iter().map(|tile| {
+            (0..tiles.len()).filter(|source| splats_anywhere || tiles[*source].expand(margin, image_width, image_height).overlaps(tile)).collect()
+        }).collect();
+        let states = tiles.iter().map(|tile| {
+            let film = TileFilm { image: RaytracedImage::new(tile.width, tile.height), passes_started: 0, converged_at: vec![usize::MAX; tile.width * tile.height] };
+            TileState { film: Mutex::new(film), results: Mutex::new(BTreeMap::new()) }
+        }).collect();
+
+        TileRender { id: settings.id, image_width, image_height, tiles_across: image_width.div_ceil(TILE_SIZE), margin, sources, states,
+                     queues: WorkQueues::new(workers, tiles.len()), handed_back: AtomicUsize::new(0), samples_raised: AtomicUsize::new(0), light_groups: settings.scene.light_group_names(), photon_maps: Mutex::new(BTreeMap::new()), tiles }
+    }
+
+    pub fn tile(&self, index: usize) -> Tile {
+        self.tiles[index]
+    }
+
+    /// Returns the region of the image the samples taken in the tile with the given index may be filtered onto.
+    pub fn region(&self, index: usize) -> Tile {
+        self.tiles[index].expand(self.margin, self.image_width, self.image_height)
+    }
+
+    pub fn light_groups(&self) -> &[String] {
+        &self.light_groups
+    }
+
+    /// Takes tiles from the worker's queue until it finds one with a pass which can start, and hands the pass out.
+    /// Tiles which cannot start a pass are put back at the end of the queue.
+    pub fn next_work(&self, worker: usize, settings: &RayTraceSettings) -> Work {
+        let mut finished = true;
+        for _ in 0..self.tiles.len() {
+            let tile = match self.queues.pop(worker) {
+                Some(tile) => tile,
+                None => return Work::Waiting
+            };
+            let work = self.start_pass(tile, settings);
+            if let Work::Tile(..) = work {
+                return work;
+            }
+            finished &= matches!(work, Work::Finished);
+            self.queues.push(worker, tile);
+        }
+        if finished { Work::Finished } else { Work::Waiting }
+    }
+
+    /// Hands out the next pass over the tile with the given index, along with the pixels it should sample, unless it
+    /// must wait for earlier passes to be added before it can know which pixels have converged.
+    fn start_pass(&self, tile: usize, settings: &RayTraceSettings) -> Work {
+        let mut film = self.states[tile].film.lock().unwrap();
+        let pass = film.passes_started;
+        if pass >= settings.samples_per_pixel {
+            return Work::Finished;
+        }
+        let checkpoint = if settings.is_adaptive() { pass - pass % MIN_ADAPTIVE_SAMPLES } else { 0 };
+        if film.image.samples < checkpoint {
+            return Work::Waiting;
+        }
+        let active_pixels = film.converged_at.iter().map(|converged_at| *converged_at > checkpoint).collect();
+        film.passes_started += 1;
+        Work::Tile(tile, pass, active_pixels)
+    }
+
+    /// Returns the photon map for the given pass, tracing it if no other tile has yet. Returns `None` unless photon
+    /// mapping.
+    pub fn photon_map(&self, pass: usize, settings: &GlobalSettings) -> Option<Arc<PhotonMap>> {
+        if !matches!(settings.raytrace_settings.integrator, Integrator::PhotonMapping | Integrator::ProgressivePhotonMapping) {
+            return None;
+        }
+        let cell = {
+            let mut photon_maps = self.photon_maps.lock().unwrap();
+            Arc::clone(&photon_maps.entry(pass).or_insert_with(|| (Arc::new(OnceLock::new()), self.tiles.len())).0)
+        };
+        let scene = &settings.scene;
+        Some(Arc::clone(cell.get_or_init(|| Arc::new(PhotonMap::trace(&scene.raytracing_primitives, &scene.lights, &settings.raytrace_settings, pass)))))
+    }
+
+    /// Records that a tile has finished the given pass, and discards the photon map of the pass once every tile has.
+    fn release_photon_map(&self, pass: usize) {
+        let mut photon_maps = self.photon_maps.lock().unwrap();
+        let (_, remaining) = photon_maps.entry(pass).or_insert_with(|| (Arc::new(OnceLock::new()), self.tiles.len()));
+        *remaining -= 1;
+        if *remaining == 0 {
+            photon_maps.remove(&pass);
+        }
+    }
+
+    /// Stores a finished pass over the tile with the given index, whose film is `None` if it sampled no pixels, and
+    /// adds every pass which is now complete to the tiles it reaches.
+    pub fn finish_pass(&self, tile: usize, pass: usize, film: Option<Film>, settings: &RayTraceSettings) {
+        let mut splats: BTreeMap<usize, Vec<(usize, Color)>> = BTreeMap::new();
+        let image = film.map(|film| {
+            for (pixel_index, color) in film.outside_splats.iter() {
+                let (i, j) = (pixel_index % self.image_width, pixel_index / self.image_width);
+                let destination = (j / TILE_SIZE) * self.tiles_across + i / TILE_SIZE;
+                splats.entry(destination).or_default().push((self.tiles[destination].local_index(i, j), *color));
+            }
+            film.into_image()
+        });
+        let result = TileResult { image, region: self.region(tile), splats, consumed: AtomicUsize::new(0) };
+        self.states[tile].results.lock().unwrap().insert(pass, Arc::new(result));
+        self.release_photon_map(pass);
+
+        //The tiles reached by a tile are those which it is reached by
+        for destination in self.sources[tile].iter() {
+            self.add_passes(*destination, settings);
+        }
+    }
+
+    /// Adds passes to the tile with the given index, for as long as every tile reaching it has finished the next one.
+    fn add_passes(&self, tile: usize, settings: &RayTraceSettings) {
+        let mut film = self.states[tile].film.lock().unwrap();
+        while film.image.samples < film.passes_started {
+            let pass = film.image.samples;
+            let results: Option<Vec<(usize, Arc<TileResult>)>> = self.sources[tile].iter().map(|source| {
+                self.states[*source].results.lock().unwrap().get(&pass).map(|result| (*source, Arc::clone(result)))
+            }).collect();
+            let results = match results {
+                Some(results) => results,
+                None => break
+            };
+
+            let target = self.tiles[tile];
+            for (source, result) in results {
+                if let Some(image) = &result.image {
+                    film.image.add_at(image, (result.region.x as isize - target.x as isize, result.region.y as isize - target.y as isize));
+                }
+                for (pixel_index, color) in result.splats.get(&tile).into_iter().flatten() {
+                    film.image.splats[*pixel_index] += color;
+                    film.image.image.pixels[*pixel_index].alpha = 1.0;
+                }
+                if result.consumed.fetch_add(1, Ordering::AcqRel) + 1 == self.sources[source].len() {
+                    self.states[source].results.lock().unwrap().remove(&pass);
+                }
+            }
+            film.image.samples += 1;
+
+            let completed = film.image.samples;
+            if settings.is_adaptive() && completed.is_multiple_of(MIN_ADAPTIVE_SAMPLES) {
+                let active_pixels = film.image.active_pixels(settings.noise_threshold);
+                for (converged_at, active) in film.converged_at.iter_mut().zip(active_pixels) {
+                    if !active && *converged_at == usize::MAX {
+                        *converged_at = completed;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Returns true once every tile has received the desired number of passes, or when sampling adaptively, once every
+    /// pixel has converged.
+    pub fn is_finished(&self, settings: &RayTraceSettings) -> bool {
+        self.states.iter().all(|state| {
+            let film = state.film.lock().unwrap();
+            let converged = settings.is_adaptive() && film.converged_at.iter().all(|converged_at| *converged_at != usize::MAX);
+            film.image.samples >= settings.samples_per_pixel || converged
+        })
+    }
+
+    /// Returns the number of passes which have been added to every tile.
+    pub fn progress(&self) -> usize {
+        self.states.iter().map(|state| state.film.lock().unwrap().image.samples).min().unwrap_or(0)
+    }
+
+    /// Gathers the tiles into a single image. Tiles may have received different numbers of passes, so the image is
+    /// given the most passes of any tile, and the light splatted onto the others is scaled up to match.
+    pub fn image(&self) -> RaytracedImage {
+        let mut image = RaytracedImage::new(self.image_width, self.image_height);
+        let mut samples = Vec::with_capacity(self.tiles.len());
+        for (tile, state) in self.tiles.iter().zip(self.states.iter()) {
+            let film = state.film.lock().unwrap();
+            image.add_at(&film.image, (tile.x as isize, tile.y as isize));
+            samples.push(film.image.samples);
+        }
+
+        image.samples = samples.iter().copied().max().unwrap_or(0);
+        for (tile, tile_samples) in self.tiles.iter().zip(samples) {
+            if tile_samples > 0 && tile_samples < image.samples {
+                let scale = image.samples as f64 / tile_samples as f64;
+                for j in tile.y..tile.y + tile.height {
+                    for i in tile.x..tile.x + tile.width {
+                        image.splats[j * self.image_width + i] *= scale;
+                    }
+                }
+            }
+        }
+        image
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_work_queues(){
+        let queues = WorkQueues::new(2, 5);
+
+        //Case 1: Workers take the tiles dealt to them first
+        assert_eq!(queues.pop(0), Some(0));
+        assert_eq!(queues.pop(1), Some(1));
+
+        //Case 2: Once its own queue is empty, a worker steals from the back of another
+        assert_eq!(queues.pop(1), Some(3));
+        assert_eq!(queues.pop(1), Some(4));
+        assert_eq!(queues.pop(0), Some(2));
+        assert_eq!(queues.pop(1), None);
+
+        //Case 3: Tiles are put back at the end of the worker's own queue
+        queues.push(1, 4);
+        queues.push(1, 1);
+        assert_eq!(queues.pop(0), Some(1));
+        assert_eq!(queues.pop(1), Some(4));
+    }
+}
@@ -3,7 +3,7 @@ use core::cmp::Ordering;
 use std::ops::{Index, IndexMut};
 use impl_ops::*;
 
-use crate::{*, geometry::{lines::OutCode}, util::rand_double};
+use crate::{*, geometry::{lines::OutCode}};
 use crate::nalgebra::{Vector3, Point3};
 
 pub trait VecExtensionMethods {
@@ -12,6 +12,7 @@ pub trait VecExtensionMethods {
     fn offset_origin(origin: &Point3<f64>, dir: &Vector3<f64>,  p_err: &Vector3<f64>, norm: &Vector3<f64>) -> Point3<f64>;
     fn near_zero(&self) -> bool;
     fn swap(&mut self, i: usize, j: usize);
+    fn coordinate_system(&self) -> (Vector3<f64>, Vector3<f64>);
 }
 
 impl VecExtensionMethods for Vector3<f64> {
@@ -45,5 +46,15 @@ impl VecExtensionMethods for Vector3<f64> {
         self[j] = self[i];
         self[i] = temp;
     }
+
+    /// Constructs two unit vectors which, together with this (unit) vector, form an orthonormal basis.
+    fn coordinate_system(&self) -> (Vector3<f64>, Vector3<f64>) {
+        let sign = 1f64.copysign(self[2]);
+        let a = -1.0 / (sign + self[2]);
+        let b = self[0] * self[1] * a;
+        let s = Vector3::<f64>::new(1.0 + sign * self[0] * self[0] * a, sign * b, -sign * self[0]);
+        let t = Vector3::<f64>::new(b, sign + self[1] * self[1] * a, -self[1]);
+        (s, t)
+    }
 }
 